@@ -0,0 +1,40 @@
+//! Hot-path micro benchmarks: host lookup, `Host` parsing and backend URI construction.
+//! Run with `cargo bench`; use `examples/loadtest.rs` for end-to-end throughput.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use http::uri::PathAndQuery;
+use roost::serve::routing::{backend_authority, backend_uri, parse_host, HostTable};
+
+fn host_table() -> HostTable<u16> {
+    (0..100)
+        .map(|i| (format!("app{i}.test"), 5000 + i as u16))
+        .collect()
+}
+
+fn lookup(c: &mut Criterion) {
+    let table = host_table();
+    let mut group = c.benchmark_group("route_lookup");
+    group.bench_function("hit", |b| b.iter(|| table.get(black_box("app42.test"))));
+    group.bench_function("hit_mixed_case", |b| {
+        b.iter(|| table.get(black_box("App42.Test")))
+    });
+    group.bench_function("miss", |b| b.iter(|| table.get(black_box("other.test"))));
+    group.finish();
+}
+
+fn host_header(c: &mut Criterion) {
+    c.bench_function("parse_host", |b| {
+        b.iter(|| parse_host(black_box("app42.test:8443")))
+    });
+}
+
+fn uri(c: &mut Criterion) {
+    let authority = backend_authority(5001);
+    let pq = PathAndQuery::from_static("/api/users?page=2&sort=name");
+    c.bench_function("backend_uri", |b| {
+        b.iter(|| backend_uri(black_box(&authority), Some(black_box(&pq))))
+    });
+}
+
+criterion_group!(benches, lookup, host_header, uri);
+criterion_main!(benches);
@@ -0,0 +1,213 @@
+//! Local load test: keeps N connections busy for a fixed time and reports throughput and
+//! latency percentiles.
+//!
+//! By default it starts a small backend and an in-process proxy in front of it and measures
+//! requests through the proxy; `--direct` measures the backend alone for comparison, and
+//! `--url` targets an already running proxy or app over plain HTTP.
+//!
+//!     cargo run --release --example loadtest -- --concurrency 64 --duration 10
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use http::{Request, Response, Uri};
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use roost::config::{Config, RoostPaths};
+use roost::serve::config::{Mapping, Redirect, ServeConfig};
+use roost::serve::proxy::run_proxy_with;
+use tokio::sync::oneshot;
+
+const DOMAIN: &str = "loadtest.test";
+
+#[derive(Parser)]
+struct Args {
+    /// Concurrent connections.
+    #[arg(long, short, default_value_t = 32)]
+    concurrency: usize,
+    /// Seconds to run.
+    #[arg(long, short, default_value_t = 5)]
+    duration: u64,
+    /// Plain HTTP URL to load instead of the built-in backend and proxy.
+    #[arg(long, conflicts_with = "direct")]
+    url: Option<Uri>,
+    /// `Host` header to send with `--url`.
+    #[arg(long, requires = "url")]
+    host: Option<String>,
+    /// Load the built-in backend without the proxy in front.
+    #[arg(long)]
+    direct: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let _home;
+    let mut stop = None;
+    let (url, host) = match args.url {
+        Some(url) => (url, args.host),
+        None if args.direct => {
+            let port = backend().await?;
+            (format!("http://127.0.0.1:{port}/").parse()?, None)
+        }
+        None => {
+            let home = tempfile::tempdir()?;
+            let (port, tx) = start_proxy(&RoostPaths::for_test(home.path())).await?;
+            _home = home;
+            stop = Some(tx);
+            let url = format!("http://127.0.0.1:{port}/").parse()?;
+            (url, Some(DOMAIN.to_string()))
+        }
+    };
+
+    let client = Client::builder(TokioExecutor::new())
+        .pool_max_idle_per_host(args.concurrency)
+        .build_http::<Empty<Bytes>>();
+    let target = Arc::new(Target { url, host });
+    // Wait for the proxy to come up and fail early on a bad target
+    let mut ready = false;
+    for _ in 0..50 {
+        if client.request(target.request()).await.is_ok() {
+            ready = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::ensure!(ready, "{} is not answering", target.url);
+
+    println!(
+        "Loading {} with {} connections for {}s",
+        target.url, args.concurrency, args.duration
+    );
+    let deadline = Instant::now() + Duration::from_secs(args.duration);
+    let started = Instant::now();
+    let workers: Vec<_> = (0..args.concurrency)
+        .map(|_| {
+            let client = client.clone();
+            let target = target.clone();
+            tokio::spawn(async move {
+                let (mut latencies, mut errors) = (Vec::new(), 0u64);
+                while Instant::now() < deadline {
+                    let sent = Instant::now();
+                    match client.request(target.request()).await {
+                        Ok(response) if response.status().is_success() => {
+                            let _ = response.into_body().collect().await;
+                            latencies.push(sent.elapsed());
+                        }
+                        _ => errors += 1,
+                    }
+                }
+                (latencies, errors)
+            })
+        })
+        .collect();
+    let (mut latencies, mut errors) = (Vec::new(), 0);
+    for worker in workers {
+        let (l, e) = worker.await?;
+        latencies.extend(l);
+        errors += e;
+    }
+    let elapsed = started.elapsed();
+    if let Some(stop) = stop {
+        let _ = stop.send(());
+    }
+
+    latencies.sort_unstable();
+    let percentile = |p: f64| {
+        let i = ((latencies.len() as f64 * p) as usize).min(latencies.len().saturating_sub(1));
+        latencies.get(i).copied().unwrap_or_default()
+    };
+    println!("Requests: {} ({errors} errors)", latencies.len());
+    println!(
+        "Throughput: {:.0} req/s",
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "Latency: p50 {:?}, p99 {:?}, max {:?}",
+        percentile(0.50),
+        percentile(0.99),
+        latencies.last().copied().unwrap_or_default()
+    );
+    Ok(())
+}
+
+struct Target {
+    url: Uri,
+    host: Option<String>,
+}
+
+impl Target {
+    fn request(&self) -> Request<Empty<Bytes>> {
+        let mut req = Request::get(self.url.clone());
+        if let Some(host) = &self.host {
+            req = req.header(http::header::HOST, host);
+        }
+        req.body(Empty::new()).unwrap()
+    }
+}
+
+/// Backend answering every request with a short body; returns its port.
+async fn backend() -> Result<u16> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let _ = stream.set_nodelay(true);
+            tokio::spawn(async move {
+                let service = service_fn(|_req: Request<Incoming>| async {
+                    Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from_static(b"ok\n"))))
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    Ok(port)
+}
+
+/// Start the proxy in a throwaway roost home with a plain-HTTP mapping to the built-in
+/// backend. Returns the plain port and a sender that stops the proxy.
+async fn start_proxy(paths: &RoostPaths) -> Result<(u16, oneshot::Sender<()>)> {
+    roost::ca::create_ca(paths, "default")?;
+    let mut store = Config {
+        default_ca: "default".to_string(),
+        ..Default::default()
+    };
+    roost::domain::add_domain(paths, &mut store, DOMAIN, false, None)?;
+    store.save(paths)?;
+
+    let (tls_port, plain_port) = (free_port()?, free_port()?);
+    let mut mapping = Mapping::new(DOMAIN.into(), backend().await?);
+    mapping.plain_http = true;
+    let config = ServeConfig {
+        mappings: vec![mapping],
+        ports: vec![tls_port, plain_port],
+        redirects: vec![Redirect {
+            from: plain_port,
+            to: tls_port,
+        }],
+        ..Default::default()
+    };
+    let (tx, rx) = oneshot::channel::<()>();
+    let paths = paths.clone();
+    tokio::spawn(async move {
+        let shutdown = async {
+            let _ = rx.await;
+        };
+        if let Err(e) = run_proxy_with(&paths, &config, Vec::new(), shutdown).await {
+            eprintln!("proxy: {e:#}");
+        }
+    });
+    Ok((plain_port, tx))
+}
+
+fn free_port() -> Result<u16> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind")?;
+    Ok(listener.local_addr()?.port())
+}
@@ -0,0 +1,127 @@
+//! Flattened JWS requests (RFC 7515 / RFC 8555 §6.2) and JWK thumbprints (RFC 7638).
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// JWS in flattened JSON serialization, as sent in ACME POST bodies.
+#[derive(Debug, Deserialize)]
+pub struct Jws {
+    pub protected: String,
+    #[serde(default)]
+    pub payload: String,
+    pub signature: String,
+}
+
+/// Protected header fields ACME uses.
+#[derive(Debug, Deserialize)]
+pub struct Protected {
+    pub alg: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    pub url: String,
+    #[serde(default)]
+    pub jwk: Option<Value>,
+    #[serde(default)]
+    pub kid: Option<String>,
+}
+
+pub fn b64(data: impl AsRef<[u8]>) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+pub fn unb64(s: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(s.trim_end_matches('='))
+        .context("invalid base64url")
+}
+
+impl Jws {
+    pub fn parse(body: &[u8]) -> Result<(Jws, Protected)> {
+        let jws: Jws =
+            serde_json::from_slice(body).context("request body is not a flattened JWS")?;
+        let protected: Protected = serde_json::from_slice(&unb64(&jws.protected)?)
+            .context("invalid JWS protected header")?;
+        if protected.jwk.is_some() == protected.kid.is_some() {
+            anyhow::bail!("JWS must carry exactly one of 'jwk' and 'kid'");
+        }
+        Ok((jws, protected))
+    }
+
+    /// Decoded payload; empty for POST-as-GET.
+    pub fn payload(&self) -> Result<Vec<u8>> {
+        unb64(&self.payload)
+    }
+
+    /// Verify the signature with `jwk`.
+    pub fn verify(&self, alg: &str, jwk: &Value) -> Result<()> {
+        let signing_input = format!("{}.{}", self.protected, self.payload);
+        let sig = unb64(&self.signature)?;
+        let field = |name: &str| -> Result<Vec<u8>> {
+            unb64(
+                jwk.get(name)
+                    .and_then(Value::as_str)
+                    .with_context(|| format!("JWK is missing '{name}'"))?,
+            )
+        };
+        let kty = jwk.get("kty").and_then(Value::as_str).unwrap_or_default();
+        let crv = jwk.get("crv").and_then(Value::as_str).unwrap_or_default();
+        let verified = match (alg, kty, crv) {
+            ("ES256", "EC", "P-256") | ("ES384", "EC", "P-384") => {
+                let algorithm = if alg == "ES256" {
+                    &signature::ECDSA_P256_SHA256_FIXED
+                } else {
+                    &signature::ECDSA_P384_SHA384_FIXED
+                };
+                let mut point = vec![0x04];
+                point.extend(field("x")?);
+                point.extend(field("y")?);
+                UnparsedPublicKey::new(algorithm, point).verify(signing_input.as_bytes(), &sig)
+            }
+            ("RS256", "RSA", _) => RsaPublicKeyComponents {
+                n: field("n")?,
+                e: field("e")?,
+            }
+            .verify(
+                &signature::RSA_PKCS1_2048_8192_SHA256,
+                signing_input.as_bytes(),
+                &sig,
+            ),
+            ("EdDSA", "OKP", "Ed25519") => UnparsedPublicKey::new(&signature::ED25519, field("x")?)
+                .verify(signing_input.as_bytes(), &sig),
+            _ => anyhow::bail!("unsupported JWS algorithm {alg} for {kty} key"),
+        };
+        verified.map_err(|_| anyhow::anyhow!("JWS signature does not verify"))
+    }
+}
+
+/// RFC 7638 thumbprint: SHA-256 over the required members in lexicographic order.
+pub fn thumbprint(jwk: &Value) -> Result<String> {
+    let get = |name: &str| -> Result<&str> {
+        jwk.get(name)
+            .and_then(Value::as_str)
+            .with_context(|| format!("JWK is missing '{name}'"))
+    };
+    let canonical = match get("kty")? {
+        "EC" => format!(
+            r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+            get("crv")?,
+            get("x")?,
+            get("y")?
+        ),
+        "RSA" => format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, get("e")?, get("n")?),
+        "OKP" => format!(
+            r#"{{"crv":"{}","kty":"OKP","x":"{}"}}"#,
+            get("crv")?,
+            get("x")?
+        ),
+        other => anyhow::bail!("unsupported JWK key type {other}"),
+    };
+    Ok(b64(ring::digest::digest(
+        &ring::digest::SHA256,
+        canonical.as_bytes(),
+    )))
+}
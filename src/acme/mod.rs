@@ -0,0 +1,1232 @@
+//! Local ACME server (RFC 8555) that issues certs from a roost CA.
+//!
+//! Accounts are kept in `<config_dir>/acme/accounts.json` so clients can reuse them across
+//! restarts; orders, authorizations and issued certs live in memory until their order expires.
+
+pub mod jws;
+
+use anyhow::{Context, Result};
+use http::{Method, Request, Response, StatusCode};
+use http_body_util::{BodyExt, Empty, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rand::Rng;
+use rcgen::{
+    CertificateParams, CertificateSigningRequestParams, DistinguishedName, DnType, DnValue,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType, SerialNumber,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_rustls::TlsAcceptor;
+
+use self::jws::Jws;
+use crate::config::RoostPaths;
+use crate::domain::{validate_domain, validate_hostname};
+use crate::serve::config::DEFAULT_BIND;
+
+const NEW_NONCE: &str = "/acme/new-nonce";
+const NEW_ACCOUNT: &str = "/acme/new-account";
+const NEW_ORDER: &str = "/acme/new-order";
+
+/// Nonces remembered for replay protection; older ones are forgotten (and rejected).
+const MAX_NONCES: usize = 4096;
+/// Largest accepted request body.
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+/// Default lifetime of orders and authorizations.
+pub const DEFAULT_ORDER_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Time allowed to fetch an http-01 challenge response.
+const HTTP01_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings for `roost acme serve`.
+#[derive(Debug, Clone)]
+pub struct AcmeOptions {
+    /// CA that signs issued certs (and the server's own TLS cert).
+    pub ca: String,
+    pub port: u16,
+    pub bind: Vec<IpAddr>,
+    /// Allow non-loopback bind addresses.
+    pub lan: bool,
+    /// Extra names for the server's TLS cert besides localhost, 127.0.0.1 and ::1.
+    pub names: Vec<String>,
+    /// Mark names within the domain allowlist valid without a challenge.
+    pub auto_approve: bool,
+    /// Accept names outside the domain allowlist (they always need http-01).
+    pub allow_any_tld: bool,
+    /// Port the http-01 validator connects to (80 in the RFC).
+    pub http01_port: u16,
+    pub validity_days: u32,
+    /// Lifetime of orders and authorizations. Expired orders are forgotten, along with their
+    /// authorizations, challenges and cert, when the next order is created.
+    pub order_lifetime: Duration,
+}
+
+impl Default for AcmeOptions {
+    fn default() -> Self {
+        Self {
+            ca: "default".to_string(),
+            port: 14000,
+            bind: DEFAULT_BIND.to_vec(),
+            lan: false,
+            names: Vec::new(),
+            auto_approve: false,
+            allow_any_tld: false,
+            http01_port: 80,
+            validity_days: 90,
+            order_lifetime: DEFAULT_ORDER_LIFETIME,
+        }
+    }
+}
+
+impl AcmeOptions {
+    pub fn validate(&self) -> Result<()> {
+        crate::serve::config::require_loopback(
+            self.bind.iter().copied(),
+            self.lan,
+            "the ACME server",
+            "pass --lan to allow it",
+        )?;
+        if self.validity_days == 0 {
+            anyhow::bail!("validity must be at least one day");
+        }
+        Ok(())
+    }
+}
+
+/// ACME error document (RFC 8555 §6.7), sent as `application/problem+json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub detail: String,
+    #[serde(skip)]
+    pub status: StatusCode,
+}
+
+impl Problem {
+    fn new(kind: &str, status: StatusCode, detail: impl fmt::Display) -> Self {
+        Self {
+            kind: format!("urn:ietf:params:acme:error:{kind}"),
+            detail: detail.to_string(),
+            status,
+        }
+    }
+
+    fn malformed(detail: impl fmt::Display) -> Self {
+        Self::new("malformed", StatusCode::BAD_REQUEST, detail)
+    }
+
+    fn unauthorized(detail: impl fmt::Display) -> Self {
+        Self::new("unauthorized", StatusCode::FORBIDDEN, detail)
+    }
+
+    fn not_found() -> Self {
+        Self::new("malformed", StatusCode::NOT_FOUND, "no such resource")
+    }
+
+    fn bad_csr(detail: impl fmt::Display) -> Self {
+        Self::new("badCSR", StatusCode::BAD_REQUEST, detail)
+    }
+
+    fn rejected(detail: impl fmt::Display) -> Self {
+        Self::new("rejectedIdentifier", StatusCode::BAD_REQUEST, detail)
+    }
+
+    fn server(detail: impl fmt::Display) -> Self {
+        Self::new("serverInternal", StatusCode::INTERNAL_SERVER_ERROR, detail)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+    Deactivated,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Identifier {
+    #[serde(rename = "type")]
+    kind: String,
+    value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Account {
+    id: String,
+    jwk: Value,
+    thumbprint: String,
+    #[serde(default)]
+    contact: Vec<String>,
+    status: Status,
+    #[serde(skip)]
+    orders: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Order {
+    account: String,
+    status: Status,
+    expires: chrono::DateTime<chrono::Utc>,
+    identifiers: Vec<Identifier>,
+    authzs: Vec<String>,
+    cert: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Authz {
+    account: String,
+    /// Name without the `*.` prefix of wildcard identifiers.
+    domain: String,
+    wildcard: bool,
+    status: Status,
+    expires: chrono::DateTime<chrono::Utc>,
+    challenge: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Challenge {
+    authz: String,
+    token: String,
+    status: Status,
+    validated: Option<chrono::DateTime<chrono::Utc>>,
+    error: Option<Problem>,
+}
+
+#[derive(Debug)]
+struct IssuedCert {
+    account: String,
+    pem: String,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    nonces: HashSet<String>,
+    nonce_order: VecDeque<String>,
+    accounts: HashMap<String, Account>,
+    orders: HashMap<String, Order>,
+    authzs: HashMap<String, Authz>,
+    challenges: HashMap<String, Challenge>,
+    certs: HashMap<String, IssuedCert>,
+}
+
+impl State {
+    /// Drop orders past their expiry, finalized or not, with everything that belongs to them.
+    fn prune(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        let expired: Vec<String> = self
+            .orders
+            .iter()
+            .filter(|(_, o)| now > o.expires)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            let Some(order) = self.orders.remove(&id) else {
+                continue;
+            };
+            for authz in &order.authzs {
+                if let Some(challenge) = self.authzs.remove(authz).and_then(|a| a.challenge) {
+                    self.challenges.remove(&challenge);
+                }
+            }
+            if let Some(cert) = &order.cert {
+                self.certs.remove(cert);
+            }
+            if let Some(account) = self.accounts.get_mut(&order.account) {
+                account.orders.retain(|o| *o != id);
+            }
+        }
+    }
+
+    /// Move a pending order to ready/invalid once its authorizations settle.
+    fn refresh_order(&mut self, id: &str) {
+        let Some(order) = self.orders.get(id) else {
+            return;
+        };
+        if order.status != Status::Pending {
+            return;
+        }
+        let statuses: Vec<Status> = order
+            .authzs
+            .iter()
+            .filter_map(|a| self.authzs.get(a).map(|a| a.status))
+            .collect();
+        let next = if chrono::Utc::now() > order.expires
+            || statuses
+                .iter()
+                .any(|s| *s != Status::Pending && *s != Status::Valid)
+        {
+            Status::Invalid
+        } else if statuses.iter().all(|s| *s == Status::Valid) {
+            Status::Ready
+        } else {
+            Status::Pending
+        };
+        if let Some(order) = self.orders.get_mut(id) {
+            order.status = next;
+        }
+    }
+}
+
+/// Successful response body.
+enum Reply {
+    Json {
+        status: StatusCode,
+        body: Value,
+        location: Option<String>,
+        up: Option<String>,
+    },
+    Empty(StatusCode),
+    Pem(String),
+}
+
+impl Reply {
+    fn ok(body: Value) -> Self {
+        Reply::Json {
+            status: StatusCode::OK,
+            body,
+            location: None,
+            up: None,
+        }
+    }
+
+    fn created(body: Value, location: String) -> Self {
+        Reply::Json {
+            status: StatusCode::CREATED,
+            body,
+            location: Some(location),
+            up: None,
+        }
+    }
+}
+
+fn random_id() -> String {
+    format!("{:032x}", rand::rng().random::<u128>())
+}
+
+fn full(body: impl Into<Bytes>) -> Full<Bytes> {
+    Full::new(body.into())
+}
+
+/// ACME server state: accounts, orders and the signing CA.
+pub struct AcmeServer {
+    options: AcmeOptions,
+    accounts_path: PathBuf,
+    /// Responder base URL embedded in issued certs (`roost cert revocation-url`).
+    revocation_url: Option<String>,
+    ca_pem: String,
+    ca_key_pem: String,
+    state: Mutex<State>,
+    client: Client<HttpConnector, Empty<Bytes>>,
+}
+
+impl AcmeServer {
+    pub fn new(paths: &RoostPaths, options: AcmeOptions) -> Result<Arc<Self>> {
+        options.validate()?;
+        if !crate::ca::ca_exists(paths, &options.ca) {
+            anyhow::bail!(
+                "CA '{}' does not exist; run 'roost ca create {}' first",
+                options.ca,
+                options.ca
+            );
+        }
+        let (ca_pem, ca_key_pem) = crate::ca::load_ca(paths, &options.ca)?;
+        let accounts_path = paths.config_dir.join("acme").join("accounts.json");
+        let mut state = State::default();
+        if accounts_path.is_file() {
+            let s = std::fs::read_to_string(&accounts_path)
+                .with_context(|| format!("read {}", accounts_path.display()))?;
+            let accounts: Vec<Account> = serde_json::from_str(&s)
+                .with_context(|| format!("parse {}", accounts_path.display()))?;
+            state.accounts = accounts.into_iter().map(|a| (a.id.clone(), a)).collect();
+        }
+        let mut ca_pem = String::from_utf8(ca_pem).context("CA cert is not UTF-8")?;
+        if !ca_pem.ends_with('\n') {
+            ca_pem.push('\n');
+        }
+        let revocation_url = crate::store::load_config(paths)?.revocation_url;
+        Ok(Arc::new(Self {
+            options,
+            accounts_path,
+            revocation_url,
+            ca_pem,
+            ca_key_pem: String::from_utf8(ca_key_pem).context("CA key is not UTF-8")?,
+            state: Mutex::new(state),
+            client: Client::builder(TokioExecutor::new()).build(HttpConnector::new()),
+        }))
+    }
+
+    fn save_accounts(&self, state: &State) -> Result<()> {
+        if let Some(dir) = self.accounts_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut accounts: Vec<&Account> = state.accounts.values().collect();
+        accounts.sort_by(|a, b| a.id.cmp(&b.id));
+        std::fs::write(
+            &self.accounts_path,
+            serde_json::to_string_pretty(&accounts)?,
+        )
+        .with_context(|| format!("write {}", self.accounts_path.display()))
+    }
+
+    fn new_nonce(&self) -> String {
+        let nonce = jws::b64(rand::rng().random::<u128>().to_be_bytes());
+        let mut state = self.state.lock().unwrap();
+        state.nonces.insert(nonce.clone());
+        state.nonce_order.push_back(nonce.clone());
+        while state.nonce_order.len() > MAX_NONCES {
+            if let Some(old) = state.nonce_order.pop_front() {
+                state.nonces.remove(&old);
+            }
+        }
+        nonce
+    }
+
+    fn consume_nonce(&self, nonce: &str) -> bool {
+        self.state.lock().unwrap().nonces.remove(nonce)
+    }
+
+    /// Handle one ACME request. URLs in responses are built from the request's Host header,
+    /// so clients in containers can use whatever name reaches this machine.
+    pub async fn handle(self: &Arc<Self>, req: Request<Bytes>) -> Response<Full<Bytes>> {
+        let host = req
+            .headers()
+            .get(http::header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+            .unwrap_or_else(|| format!("localhost:{}", self.options.port));
+        let base = format!("https://{host}");
+        let path = req.uri().path().to_string();
+        let result = match (req.method(), path.as_str()) {
+            (&Method::GET, "/directory") => Ok(Reply::ok(self.directory(&base))),
+            (&Method::HEAD, NEW_NONCE) => Ok(Reply::Empty(StatusCode::OK)),
+            (&Method::GET, NEW_NONCE) => Ok(Reply::Empty(StatusCode::NO_CONTENT)),
+            (&Method::POST, _) => self.post(&base, &path, req.body()).await,
+            _ => Err(Problem::not_found()),
+        };
+
+        let mut builder = Response::builder()
+            .header("Replay-Nonce", self.new_nonce())
+            .header(http::header::CACHE_CONTROL, "no-store")
+            .header(
+                http::header::LINK,
+                format!("<{base}/directory>;rel=\"index\""),
+            );
+        let response = match result {
+            Ok(Reply::Json {
+                status,
+                body,
+                location,
+                up,
+            }) => {
+                if let Some(location) = location {
+                    builder = builder.header(http::header::LOCATION, location);
+                }
+                if let Some(up) = up {
+                    builder = builder.header(http::header::LINK, format!("<{up}>;rel=\"up\""));
+                }
+                builder
+                    .status(status)
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(full(serde_json::to_vec_pretty(&body).unwrap_or_default()))
+            }
+            Ok(Reply::Empty(status)) => builder.status(status).body(full(Bytes::new())),
+            Ok(Reply::Pem(pem)) => builder
+                .status(StatusCode::OK)
+                .header(
+                    http::header::CONTENT_TYPE,
+                    "application/pem-certificate-chain",
+                )
+                .body(full(pem)),
+            Err(problem) => builder
+                .status(problem.status)
+                .header(http::header::CONTENT_TYPE, "application/problem+json")
+                .body(full(serde_json::to_vec(&problem).unwrap_or_default())),
+        };
+        response.unwrap()
+    }
+
+    fn directory(&self, base: &str) -> Value {
+        json!({
+            "newNonce": format!("{base}{NEW_NONCE}"),
+            "newAccount": format!("{base}{NEW_ACCOUNT}"),
+            "newOrder": format!("{base}{NEW_ORDER}"),
+            "meta": {
+                "website": "https://github.com/itsbjoern/roost",
+                "externalAccountRequired": false,
+            },
+        })
+    }
+
+    async fn post(self: &Arc<Self>, base: &str, path: &str, body: &[u8]) -> Result<Reply, Problem> {
+        let (jws, protected) =
+            Jws::parse(body).map_err(|e| Problem::malformed(format!("{e:#}")))?;
+        let url = format!("{base}{path}");
+        if protected.url != url {
+            return Err(Problem::unauthorized(format!(
+                "JWS url {} does not match request URL {url}",
+                protected.url
+            )));
+        }
+        let nonce_ok = protected
+            .nonce
+            .as_deref()
+            .is_some_and(|n| self.consume_nonce(n));
+        if !nonce_ok {
+            return Err(Problem::new(
+                "badNonce",
+                StatusCode::BAD_REQUEST,
+                "missing, unknown or reused nonce",
+            ));
+        }
+        let bad_signature = |e: anyhow::Error| Problem::malformed(format!("{e:#}"));
+
+        if path == NEW_ACCOUNT {
+            let jwk = protected
+                .jwk
+                .as_ref()
+                .ok_or_else(|| Problem::malformed("newAccount requests must use 'jwk'"))?;
+            jws.verify(&protected.alg, jwk).map_err(bad_signature)?;
+            let payload = parse_payload(&jws)?.unwrap_or_else(|| json!({}));
+            return self.new_account(base, jwk, &payload);
+        }
+
+        let kid = protected
+            .kid
+            .as_deref()
+            .ok_or_else(|| Problem::malformed("requests must be signed with 'kid'"))?;
+        let account_id = kid
+            .rsplit_once("/acme/acct/")
+            .map(|(_, id)| id.to_string())
+            .ok_or_else(|| Problem::malformed(format!("invalid kid {kid}")))?;
+        let account = self
+            .state
+            .lock()
+            .unwrap()
+            .accounts
+            .get(&account_id)
+            .cloned()
+            .ok_or_else(|| {
+                Problem::new(
+                    "accountDoesNotExist",
+                    StatusCode::BAD_REQUEST,
+                    format!("no account {account_id}"),
+                )
+            })?;
+        jws.verify(&protected.alg, &account.jwk)
+            .map_err(bad_signature)?;
+        if account.status != Status::Valid {
+            return Err(Problem::unauthorized("account is deactivated"));
+        }
+        let payload = parse_payload(&jws)?;
+
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["acme", "new-order"] => self.new_order(base, &account, payload),
+            ["acme", "acct", id] if *id == account.id => {
+                self.update_account(base, &account, payload)
+            }
+            ["acme", "acct", id, "orders"] if *id == account.id => Ok(Reply::ok(json!({
+                "orders": account
+                    .orders
+                    .iter()
+                    .map(|o| format!("{base}/acme/order/{o}"))
+                    .collect::<Vec<_>>(),
+            }))),
+            ["acme", "acct", _] | ["acme", "acct", _, "orders"] => {
+                Err(Problem::unauthorized("kid does not match the account URL"))
+            }
+            ["acme", "order", id] => {
+                let mut state = self.state.lock().unwrap();
+                state.refresh_order(id);
+                let order = owned(state.orders.get(*id), &account)?;
+                Ok(Reply::ok(order_json(base, id, order)))
+            }
+            ["acme", "order", id, "finalize"] => self.finalize(base, &account, id, payload),
+            ["acme", "authz", id] => self.authz(base, &account, id, payload),
+            ["acme", "chall", id] => self.challenge(base, &account, id, payload),
+            ["acme", "cert", id] => {
+                let state = self.state.lock().unwrap();
+                let cert = state.certs.get(*id).ok_or_else(Problem::not_found)?;
+                if cert.account != account.id {
+                    return Err(Problem::unauthorized(
+                        "certificate belongs to another account",
+                    ));
+                }
+                Ok(Reply::Pem(cert.pem.clone()))
+            }
+            _ => Err(Problem::not_found()),
+        }
+    }
+
+    fn new_account(&self, base: &str, jwk: &Value, payload: &Value) -> Result<Reply, Problem> {
+        let thumbprint = jws::thumbprint(jwk).map_err(|e| Problem::malformed(format!("{e:#}")))?;
+        let mut state = self.state.lock().unwrap();
+        if let Some(existing) = state.accounts.values().find(|a| a.thumbprint == thumbprint) {
+            return Ok(Reply::Json {
+                status: StatusCode::OK,
+                body: account_json(base, existing),
+                location: Some(format!("{base}/acme/acct/{}", existing.id)),
+                up: None,
+            });
+        }
+        if payload["onlyReturnExisting"].as_bool() == Some(true) {
+            return Err(Problem::new(
+                "accountDoesNotExist",
+                StatusCode::BAD_REQUEST,
+                "no account for this key",
+            ));
+        }
+        let account = Account {
+            id: random_id(),
+            jwk: jwk.clone(),
+            thumbprint,
+            contact: contacts(payload),
+            status: Status::Valid,
+            orders: Vec::new(),
+        };
+        let reply = Reply::created(
+            account_json(base, &account),
+            format!("{base}/acme/acct/{}", account.id),
+        );
+        state.accounts.insert(account.id.clone(), account);
+        self.save_accounts(&state).map_err(Problem::server)?;
+        Ok(reply)
+    }
+
+    fn update_account(
+        &self,
+        base: &str,
+        account: &Account,
+        payload: Option<Value>,
+    ) -> Result<Reply, Problem> {
+        let mut state = self.state.lock().unwrap();
+        let stored = state
+            .accounts
+            .get_mut(&account.id)
+            .ok_or_else(Problem::not_found)?;
+        if let Some(payload) = payload {
+            if payload.get("contact").is_some() {
+                stored.contact = contacts(&payload);
+            }
+            if payload["status"] == "deactivated" {
+                stored.status = Status::Deactivated;
+            }
+        }
+        let body = account_json(base, stored);
+        self.save_accounts(&state).map_err(Problem::server)?;
+        Ok(Reply::ok(body))
+    }
+
+    /// Check an order identifier; returns whether it is approved without a challenge.
+    fn check_identifier(&self, identifier: &Identifier) -> Result<bool, Problem> {
+        if identifier.kind != "dns" {
+            return Err(Problem::new(
+                "unsupportedIdentifier",
+                StatusCode::BAD_REQUEST,
+                format!("identifier type {} is not supported", identifier.kind),
+            ));
+        }
+        let name = identifier.value.to_lowercase();
+        let (domain, wildcard) = match name.strip_prefix("*.") {
+            Some(rest) => (rest, true),
+            None => (name.as_str(), false),
+        };
+        validate_hostname(domain).map_err(|e| Problem::rejected(format!("{name}: {e}")))?;
+        let allowlisted = validate_domain(domain, false).is_ok();
+        if !allowlisted && !self.options.allow_any_tld {
+            return Err(Problem::rejected(format!(
+                "{name} is not in the domain allowlist; restart with --allow to accept any name"
+            )));
+        }
+        let approved = self.options.auto_approve && allowlisted;
+        if wildcard && !approved {
+            return Err(Problem::rejected(format!(
+                "{name}: wildcard names need --auto-approve (http-01 cannot validate them)"
+            )));
+        }
+        Ok(approved)
+    }
+
+    fn new_order(
+        &self,
+        base: &str,
+        account: &Account,
+        payload: Option<Value>,
+    ) -> Result<Reply, Problem> {
+        #[derive(Deserialize)]
+        struct NewOrder {
+            identifiers: Vec<Identifier>,
+        }
+        let request: NewOrder = serde_json::from_value(payload.unwrap_or_default())
+            .map_err(|e| Problem::malformed(format!("invalid newOrder payload: {e}")))?;
+        if request.identifiers.is_empty() {
+            return Err(Problem::malformed("order has no identifiers"));
+        }
+        let mut identifiers: Vec<Identifier> = Vec::new();
+        for mut identifier in request.identifiers {
+            identifier.value = identifier.value.to_lowercase();
+            if !identifiers.contains(&identifier) {
+                identifiers.push(identifier);
+            }
+        }
+        let approvals = identifiers
+            .iter()
+            .map(|i| self.check_identifier(i))
+            .collect::<Result<Vec<bool>, Problem>>()?;
+
+        let now = chrono::Utc::now();
+        let expires = chrono::Duration::from_std(self.options.order_lifetime)
+            .ok()
+            .and_then(|lifetime| now.checked_add_signed(lifetime))
+            .unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC);
+        let mut state = self.state.lock().unwrap();
+        state.prune(now);
+        let mut authzs = Vec::new();
+        for (identifier, approved) in identifiers.iter().zip(approvals) {
+            let authz_id = random_id();
+            let (domain, wildcard) = match identifier.value.strip_prefix("*.") {
+                Some(rest) => (rest.to_string(), true),
+                None => (identifier.value.clone(), false),
+            };
+            let challenge = (!approved).then(|| {
+                let id = random_id();
+                state.challenges.insert(
+                    id.clone(),
+                    Challenge {
+                        authz: authz_id.clone(),
+                        token: jws::b64(rand::rng().random::<[u8; 32]>()),
+                        status: Status::Pending,
+                        validated: None,
+                        error: None,
+                    },
+                );
+                id
+            });
+            state.authzs.insert(
+                authz_id.clone(),
+                Authz {
+                    account: account.id.clone(),
+                    domain,
+                    wildcard,
+                    status: if approved {
+                        Status::Valid
+                    } else {
+                        Status::Pending
+                    },
+                    expires,
+                    challenge,
+                },
+            );
+            authzs.push(authz_id);
+        }
+        let order_id = random_id();
+        state.orders.insert(
+            order_id.clone(),
+            Order {
+                account: account.id.clone(),
+                status: Status::Pending,
+                expires,
+                identifiers,
+                authzs,
+                cert: None,
+            },
+        );
+        if let Some(acct) = state.accounts.get_mut(&account.id) {
+            acct.orders.push(order_id.clone());
+        }
+        state.refresh_order(&order_id);
+        let order = &state.orders[&order_id];
+        Ok(Reply::created(
+            order_json(base, &order_id, order),
+            format!("{base}/acme/order/{order_id}"),
+        ))
+    }
+
+    fn authz(
+        &self,
+        base: &str,
+        account: &Account,
+        id: &str,
+        payload: Option<Value>,
+    ) -> Result<Reply, Problem> {
+        let mut state = self.state.lock().unwrap();
+        owned(state.authzs.get(id), account)?;
+        if payload.is_some_and(|p| p["status"] == "deactivated") {
+            if let Some(authz) = state.authzs.get_mut(id) {
+                authz.status = Status::Deactivated;
+            }
+        }
+        let authz = &state.authzs[id];
+        Ok(Reply::ok(authz_json(base, authz, &state)))
+    }
+
+    fn challenge(
+        self: &Arc<Self>,
+        base: &str,
+        account: &Account,
+        id: &str,
+        payload: Option<Value>,
+    ) -> Result<Reply, Problem> {
+        let mut state = self.state.lock().unwrap();
+        let challenge = state.challenges.get(id).ok_or_else(Problem::not_found)?;
+        let authz_id = challenge.authz.clone();
+        owned(state.authzs.get(&authz_id), account)?;
+        // Any non-empty payload (normally `{}`) asks the server to validate
+        if payload.is_some() && challenge.status == Status::Pending {
+            if let Some(c) = state.challenges.get_mut(id) {
+                c.status = Status::Processing;
+            }
+            let server = self.clone();
+            let id = id.to_string();
+            tokio::spawn(async move { server.validate_http01(&id).await });
+        }
+        let challenge = &state.challenges[id];
+        Ok(Reply::Json {
+            status: StatusCode::OK,
+            body: challenge_json(base, id, challenge),
+            location: None,
+            up: Some(format!("{base}/acme/authz/{authz_id}")),
+        })
+    }
+
+    /// Fetch the http-01 response from the name being validated and record the outcome.
+    async fn validate_http01(&self, challenge_id: &str) {
+        let (domain, token, thumbprint) = {
+            let state = self.state.lock().unwrap();
+            let Some(challenge) = state.challenges.get(challenge_id) else {
+                return;
+            };
+            let Some(authz) = state.authzs.get(&challenge.authz) else {
+                return;
+            };
+            let thumbprint = state
+                .accounts
+                .get(&authz.account)
+                .map(|a| a.thumbprint.clone())
+                .unwrap_or_default();
+            (authz.domain.clone(), challenge.token.clone(), thumbprint)
+        };
+        let expected = format!("{token}.{thumbprint}");
+        let outcome = self.fetch_http01(&domain, &token, &expected).await;
+
+        let mut state = self.state.lock().unwrap();
+        let Some(challenge) = state.challenges.get_mut(challenge_id) else {
+            return;
+        };
+        let authz_id = challenge.authz.clone();
+        let status = match outcome {
+            Ok(()) => {
+                challenge.validated = Some(chrono::Utc::now());
+                Status::Valid
+            }
+            Err(e) => {
+                eprintln!("http-01 validation for {domain} failed: {e:#}");
+                challenge.error = Some(Problem::new(
+                    "incorrectResponse",
+                    StatusCode::FORBIDDEN,
+                    format!("{e:#}"),
+                ));
+                Status::Invalid
+            }
+        };
+        challenge.status = status;
+        if let Some(authz) = state.authzs.get_mut(&authz_id) {
+            authz.status = status;
+        }
+        let orders: Vec<String> = state
+            .orders
+            .iter()
+            .filter(|(_, o)| o.authzs.contains(&authz_id))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for order in orders {
+            state.refresh_order(&order);
+        }
+    }
+
+    async fn fetch_http01(&self, domain: &str, token: &str, expected: &str) -> Result<()> {
+        let authority = match self.options.http01_port {
+            80 => domain.to_string(),
+            port => format!("{domain}:{port}"),
+        };
+        let uri: http::Uri = format!("http://{authority}/.well-known/acme-challenge/{token}")
+            .parse()
+            .context("build challenge URL")?;
+        let fetch = async {
+            let response = self
+                .client
+                .get(uri.clone())
+                .await
+                .with_context(|| format!("fetch {uri}"))?;
+            let status = response.status();
+            let body = Limited::new(response.into_body(), 8192)
+                .collect()
+                .await
+                .map_err(|e| anyhow::anyhow!("read {uri}: {e}"))?
+                .to_bytes();
+            anyhow::ensure!(status == StatusCode::OK, "{uri} returned {status}");
+            let body = String::from_utf8_lossy(&body);
+            anyhow::ensure!(
+                body.trim() == expected,
+                "{uri} returned {:?}, expected the key authorization",
+                body.trim()
+            );
+            Ok(())
+        };
+        tokio::time::timeout(HTTP01_TIMEOUT, fetch)
+            .await
+            .with_context(|| format!("timed out fetching {uri}"))?
+    }
+
+    fn finalize(
+        &self,
+        base: &str,
+        account: &Account,
+        id: &str,
+        payload: Option<Value>,
+    ) -> Result<Reply, Problem> {
+        let csr = payload
+            .as_ref()
+            .and_then(|p| p["csr"].as_str())
+            .ok_or_else(|| Problem::malformed("finalize payload needs 'csr'"))?;
+        let csr = jws::unb64(csr).map_err(|e| Problem::bad_csr(format!("{e:#}")))?;
+
+        let mut state = self.state.lock().unwrap();
+        state.refresh_order(id);
+        let order = owned(state.orders.get(id), account)?;
+        if order.status != Status::Ready {
+            return Err(Problem::new(
+                "orderNotReady",
+                StatusCode::FORBIDDEN,
+                format!("order is {:?}, not ready", order.status).to_lowercase(),
+            ));
+        }
+        let names: Vec<String> = order.identifiers.iter().map(|i| i.value.clone()).collect();
+        let pem = self.issue(&names, &csr)?;
+        let cert_id = random_id();
+        state.certs.insert(
+            cert_id.clone(),
+            IssuedCert {
+                account: account.id.clone(),
+                pem,
+            },
+        );
+        let order = state.orders.get_mut(id).ok_or_else(Problem::not_found)?;
+        order.status = Status::Valid;
+        order.cert = Some(cert_id);
+        Ok(Reply::Json {
+            status: StatusCode::OK,
+            body: order_json(base, id, order),
+            location: Some(format!("{base}/acme/order/{id}")),
+            up: None,
+        })
+    }
+
+    /// Sign the CSR's key for exactly the order's names; returns leaf + CA as PEM.
+    fn issue(&self, names: &[String], csr_der: &[u8]) -> Result<String, Problem> {
+        let csr = CertificateSigningRequestParams::from_der(&csr_der.to_vec().into())
+            .map_err(|e| Problem::bad_csr(format!("cannot parse CSR: {e}")))?;
+        let mut requested = BTreeSet::new();
+        for san in &csr.params.subject_alt_names {
+            match san {
+                SanType::DnsName(name) => {
+                    requested.insert(name.as_str().to_lowercase());
+                }
+                _ => return Err(Problem::bad_csr("CSR may only request DNS names")),
+            }
+        }
+        if let Some(cn) = csr.params.distinguished_name.get(&DnType::CommonName) {
+            let cn = match cn {
+                DnValue::Utf8String(s) => s.clone(),
+                DnValue::PrintableString(s) => s.as_str().to_string(),
+                DnValue::Ia5String(s) => s.as_str().to_string(),
+                _ => String::new(),
+            };
+            if !cn.is_empty() {
+                requested.insert(cn.to_lowercase());
+            }
+        }
+        let ordered: BTreeSet<String> = names.iter().cloned().collect();
+        if requested != ordered {
+            return Err(Problem::bad_csr(format!(
+                "CSR names [{}] do not match order identifiers [{}]",
+                requested.into_iter().collect::<Vec<_>>().join(", "),
+                ordered.into_iter().collect::<Vec<_>>().join(", ")
+            )));
+        }
+
+        let mut params = CertificateParams::new(names.to_vec()).map_err(Problem::server)?;
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, DnValue::Utf8String(names[0].clone()));
+        params.is_ca = IsCa::NoCa;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        let now = time::OffsetDateTime::now_utc();
+        params.not_before = now - time::Duration::hours(1);
+        params.not_after = now + time::Duration::days(self.options.validity_days as i64);
+        // Positive serial: clear the top bit
+        let mut serial = rand::rng().random::<[u8; 16]>();
+        serial[0] &= 0x7f;
+        params.serial_number = Some(SerialNumber::from_slice(&serial));
+        if let Some(url) = &self.revocation_url {
+            crate::revoke::add_revocation_urls(&mut params, url, &self.options.ca);
+        }
+
+        let issuer_params =
+            CertificateParams::from_ca_cert_pem(&self.ca_pem).map_err(Problem::server)?;
+        let issuer_key = KeyPair::from_pem(&self.ca_key_pem).map_err(Problem::server)?;
+        let issuer_cert = issuer_params
+            .self_signed(&issuer_key)
+            .map_err(Problem::server)?;
+        let cert = CertificateSigningRequestParams {
+            params,
+            public_key: csr.public_key,
+        }
+        .signed_by(&issuer_cert, &issuer_key)
+        .map_err(|e| Problem::bad_csr(format!("cannot sign CSR: {e}")))?;
+        Ok(format!("{}{}", cert.pem(), self.ca_pem))
+    }
+
+    /// TLS config for the ACME endpoint: a fresh cert from the CA for localhost and `names`.
+    fn tls_config(&self) -> Result<rustls::ServerConfig> {
+        let mut names = vec![
+            "localhost".to_string(),
+            "127.0.0.1".to_string(),
+            "::1".to_string(),
+        ];
+        names.extend(self.options.names.iter().cloned());
+        let issuer_params =
+            CertificateParams::from_ca_cert_pem(&self.ca_pem).context("parse CA cert")?;
+        let issuer_key = KeyPair::from_pem(&self.ca_key_pem).context("parse CA key")?;
+        let issuer_cert = issuer_params
+            .self_signed(&issuer_key)
+            .context("reconstruct issuer cert")?;
+        let key = KeyPair::generate().context("generate server key")?;
+        let mut params = CertificateParams::new(names).context("server cert names")?;
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(
+            DnType::CommonName,
+            DnValue::Utf8String("Roost ACME server".to_string()),
+        );
+        let cert = params
+            .signed_by(&key, &issuer_cert, &issuer_key)
+            .context("sign server cert")?;
+        let key_der = rustls::pki_types::PrivateKeyDer::try_from(key.serialize_der())
+            .map_err(|e| anyhow::anyhow!("server key: {e}"))?;
+        let mut config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.der().clone()], key_der)
+            .context("build TLS config")?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+fn parse_payload(jws: &Jws) -> Result<Option<Value>, Problem> {
+    let payload = jws
+        .payload()
+        .map_err(|e| Problem::malformed(format!("{e:#}")))?;
+    if payload.is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_slice(&payload)
+        .map(Some)
+        .map_err(|e| Problem::malformed(format!("payload is not JSON: {e}")))
+}
+
+fn contacts(payload: &Value) -> Vec<String> {
+    payload["contact"]
+        .as_array()
+        .map(|c| {
+            c.iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Resources carry their owning account; other accounts get `unauthorized`.
+trait Owned {
+    fn account(&self) -> &str;
+}
+
+impl Owned for Order {
+    fn account(&self) -> &str {
+        &self.account
+    }
+}
+
+impl Owned for Authz {
+    fn account(&self) -> &str {
+        &self.account
+    }
+}
+
+fn owned<'a, T: Owned>(resource: Option<&'a T>, account: &Account) -> Result<&'a T, Problem> {
+    let resource = resource.ok_or_else(Problem::not_found)?;
+    if resource.account() != account.id {
+        return Err(Problem::unauthorized("resource belongs to another account"));
+    }
+    Ok(resource)
+}
+
+fn account_json(base: &str, account: &Account) -> Value {
+    json!({
+        "status": account.status,
+        "contact": account.contact,
+        "orders": format!("{base}/acme/acct/{}/orders", account.id),
+    })
+}
+
+fn order_json(base: &str, id: &str, order: &Order) -> Value {
+    let mut body = json!({
+        "status": order.status,
+        "expires": order.expires.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        "identifiers": order.identifiers,
+        "authorizations": order
+            .authzs
+            .iter()
+            .map(|a| format!("{base}/acme/authz/{a}"))
+            .collect::<Vec<_>>(),
+        "finalize": format!("{base}/acme/order/{id}/finalize"),
+    });
+    if let Some(cert) = &order.cert {
+        body["certificate"] = json!(format!("{base}/acme/cert/{cert}"));
+    }
+    body
+}
+
+fn authz_json(base: &str, authz: &Authz, state: &State) -> Value {
+    let challenges: Vec<Value> = authz
+        .challenge
+        .iter()
+        .filter_map(|c| {
+            state
+                .challenges
+                .get(c)
+                .map(|ch| challenge_json(base, c, ch))
+        })
+        .collect();
+    let mut body = json!({
+        "identifier": { "type": "dns", "value": authz.domain },
+        "status": authz.status,
+        "expires": authz.expires.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        "challenges": challenges,
+    });
+    if authz.wildcard {
+        body["wildcard"] = json!(true);
+    }
+    body
+}
+
+fn challenge_json(base: &str, id: &str, challenge: &Challenge) -> Value {
+    let mut body = json!({
+        "type": "http-01",
+        "url": format!("{base}/acme/chall/{id}"),
+        "token": challenge.token,
+        "status": challenge.status,
+    });
+    if let Some(validated) = challenge.validated {
+        body["validated"] = json!(validated.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+    }
+    if let Some(error) = &challenge.error {
+        body["error"] = json!(error);
+    }
+    body
+}
+
+/// Run the ACME server until Ctrl-C.
+pub async fn serve(paths: &RoostPaths, options: AcmeOptions) -> Result<()> {
+    let server = AcmeServer::new(paths, options.clone())?;
+    let acceptor = TlsAcceptor::from(Arc::new(server.tls_config()?));
+
+    let mut listeners = Vec::new();
+    for ip in &options.bind {
+        let addr = SocketAddr::new(*ip, options.port);
+        match crate::serve::proxy::bind_listener(addr) {
+            Ok(l) => listeners.push(l),
+            Err(e) => eprintln!("Warning: could not bind {addr}: {e:#}"),
+        }
+    }
+    if listeners.is_empty() {
+        anyhow::bail!("could not bind port {}", options.port);
+    }
+    for listener in listeners {
+        eprintln!(
+            "ACME server listening on https://{}",
+            listener.local_addr()?
+        );
+        let server = server.clone();
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        eprintln!("accept error: {e}");
+                        continue;
+                    }
+                };
+                let server = server.clone();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(tls) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let service = service_fn(move |req: Request<Incoming>| {
+                        let server = server.clone();
+                        async move {
+                            let (parts, body) = req.into_parts();
+                            let body = match Limited::new(body, MAX_BODY_BYTES).collect().await {
+                                Ok(b) => b.to_bytes(),
+                                Err(e) => {
+                                    return Ok::<_, anyhow::Error>(
+                                        Response::builder()
+                                            .status(StatusCode::PAYLOAD_TOO_LARGE)
+                                            .body(full(format!("{e}\n")))
+                                            .unwrap(),
+                                    )
+                                }
+                            };
+                            Ok(server.handle(Request::from_parts(parts, body)).await)
+                        }
+                    });
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(tls), service)
+                        .await;
+                });
+            }
+        });
+    }
+    let host = options
+        .names
+        .first()
+        .map(String::as_str)
+        .unwrap_or("localhost");
+    println!("ACME directory: https://{host}:{}/directory", options.port);
+    println!(
+        "Issuing from CA '{}'{}",
+        options.ca,
+        if options.auto_approve {
+            "; names in the domain allowlist are approved without a challenge"
+        } else {
+            ""
+        }
+    );
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
@@ -4,11 +4,21 @@ use anyhow::{Context, Result};
 use rcgen::{CertificateParams, KeyPair};
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use x509_parser::prelude::FromDer;
 
 use crate::config::RoostPaths;
 
+/// Key algorithm of a domain leaf certificate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LeafKey {
+    /// ECDSA P-256 (the default for all domain certs).
+    #[default]
+    Ecdsa,
+    /// RSA 2048, kept alongside the ECDSA cert for clients that need it.
+    Rsa,
+}
+
 /// Generate domain cert; SANs = [domain, *.domain] or [domain] if exact.
 pub fn generate_domain_cert(
     domain: &str,
@@ -16,30 +26,97 @@ pub fn generate_domain_cert(
     ca_key_pem: &[u8],
     exact: bool,
 ) -> Result<(Vec<u8>, Vec<u8>)> {
-    let ca_str = String::from_utf8(ca_pem.to_vec())?;
-    let ca_key_str = String::from_utf8(ca_key_pem.to_vec())?;
+    build_domain_cert(
+        domain,
+        ca_pem,
+        ca_key_pem,
+        exact,
+        LeafKey::Ecdsa,
+        None,
+        None,
+    )
+}
 
-    let issuer_params =
-        CertificateParams::from_ca_cert_pem(&ca_str).context("parse CA cert")?;
-    let issuer_key = KeyPair::from_pem(&ca_key_str).context("parse CA key")?;
-    let issuer_cert = issuer_params.self_signed(&issuer_key).context("reconstruct issuer cert")?;
+/// Generate domain cert that expires in `validity_days` days. For testing renewal.
+#[doc(hidden)]
+pub fn generate_domain_cert_with_validity(
+    domain: &str,
+    ca_pem: &[u8],
+    ca_key_pem: &[u8],
+    exact: bool,
+    validity_days: u32,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    build_domain_cert(
+        domain,
+        ca_pem,
+        ca_key_pem,
+        exact,
+        LeafKey::Ecdsa,
+        Some(validity_days),
+        None,
+    )
+}
+
+/// Issue a domain cert from `ca_name`, embedding CRL/OCSP URLs when a revocation URL is configured.
+pub fn issue_domain_cert(
+    paths: &RoostPaths,
+    domain: &str,
+    ca_name: &str,
+    exact: bool,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    issue_domain_cert_with_key(paths, domain, ca_name, exact, LeafKey::Ecdsa)
+}
+
+/// Like [`issue_domain_cert`], with the given leaf key algorithm.
+pub fn issue_domain_cert_with_key(
+    paths: &RoostPaths,
+    domain: &str,
+    ca_name: &str,
+    exact: bool,
+    key: LeafKey,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let (ca_pem, ca_key_pem) = crate::ca::load_ca(paths, ca_name)?;
+    let config = crate::store::load_config(paths)?;
+    let revocation = config.revocation_url.as_deref().map(|url| (url, ca_name));
+    build_domain_cert(domain, &ca_pem, &ca_key_pem, exact, key, None, revocation)
+}
 
-    let subject_key = KeyPair::generate().context("generate domain key")?;
+fn build_domain_cert(
+    domain: &str,
+    ca_pem: &[u8],
+    ca_key_pem: &[u8],
+    exact: bool,
+    key: LeafKey,
+    validity_days: Option<u32>,
+    revocation: Option<(&str, &str)>,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let (issuer_cert, issuer_key) = load_issuer(ca_pem, ca_key_pem)?;
+
+    let subject_key = match key {
+        LeafKey::Ecdsa => KeyPair::generate(),
+        LeafKey::Rsa => {
+            KeyPair::generate_rsa_for(&rcgen::PKCS_RSA_SHA256, rcgen::RsaKeySize::_2048)
+        }
+    }
+    .context("generate domain key")?;
 
+    // IP SANs can't have wildcards
+    let exact = exact || domain.parse::<std::net::IpAddr>().is_ok();
     let subject_alt_names: Vec<String> = if exact {
         vec![domain.to_string()]
     } else {
         vec![domain.to_string(), format!("*.{}", domain)]
     };
 
-    let mut params =
-        CertificateParams::new(subject_alt_names).context("create cert params")?;
-    params.distinguished_name = rcgen::DistinguishedName::new();
-    params.distinguished_name.push(
-        rcgen::DnType::CommonName,
-        rcgen::DnValue::Utf8String(domain.to_string()),
-    );
-    params.is_ca = rcgen::IsCa::NoCa;
+    let mut params = leaf_params(subject_alt_names, domain)?;
+
+    if let Some(validity_days) = validity_days {
+        let now = time::OffsetDateTime::now_utc();
+        params.not_after = now.saturating_add(time::Duration::days(validity_days as i64));
+    }
+    if let Some((base_url, ca_name)) = revocation {
+        crate::revoke::add_revocation_urls(&mut params, base_url, ca_name);
+    }
 
     let cert = params
         .signed_by(&subject_key, &issuer_cert, &issuer_key)
@@ -51,15 +128,8 @@ pub fn generate_domain_cert(
     Ok((cert_pem.into_bytes(), key_pem.into_bytes()))
 }
 
-/// Generate domain cert that expires in `validity_days` days. For testing renewal.
-#[doc(hidden)]
-pub fn generate_domain_cert_with_validity(
-    domain: &str,
-    ca_pem: &[u8],
-    ca_key_pem: &[u8],
-    exact: bool,
-    validity_days: u32,
-) -> Result<(Vec<u8>, Vec<u8>)> {
+/// Rebuild the CA certificate and key for signing.
+fn load_issuer(ca_pem: &[u8], ca_key_pem: &[u8]) -> Result<(rcgen::Certificate, KeyPair)> {
     let ca_str = String::from_utf8(ca_pem.to_vec())?;
     let ca_key_str = String::from_utf8(ca_key_pem.to_vec())?;
 
@@ -67,35 +137,117 @@ pub fn generate_domain_cert_with_validity(
         CertificateParams::from_ca_cert_pem(&ca_str).context("parse CA cert")?;
     let issuer_key = KeyPair::from_pem(&ca_key_str).context("parse CA key")?;
     let issuer_cert = issuer_params.self_signed(&issuer_key).context("reconstruct issuer cert")?;
+    Ok((issuer_cert, issuer_key))
+}
 
-    let subject_key = KeyPair::generate().context("generate domain key")?;
-
-    let subject_alt_names: Vec<String> = if exact {
-        vec![domain.to_string()]
-    } else {
-        vec![domain.to_string(), format!("*.{}", domain)]
-    };
-
+/// Leaf cert params with the given SANs and CN.
+fn leaf_params(subject_alt_names: Vec<String>, common_name: &str) -> Result<CertificateParams> {
     let mut params =
         CertificateParams::new(subject_alt_names).context("create cert params")?;
     params.distinguished_name = rcgen::DistinguishedName::new();
     params.distinguished_name.push(
         rcgen::DnType::CommonName,
-        rcgen::DnValue::Utf8String(domain.to_string()),
+        rcgen::DnValue::Utf8String(common_name.to_string()),
     );
     params.is_ca = rcgen::IsCa::NoCa;
+    Ok(params)
+}
 
-    let now = time::OffsetDateTime::now_utc();
-    params.not_after = now.saturating_add(time::Duration::days(validity_days as i64));
+/// CA cert params (for the badssl untrusted root and intermediate).
+fn ca_params(common_name: &str) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params.distinguished_name.push(
+        rcgen::DnType::CommonName,
+        rcgen::DnValue::Utf8String(common_name.to_string()),
+    );
+    params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        rcgen::KeyUsagePurpose::KeyCertSign,
+        rcgen::KeyUsagePurpose::CrlSign,
+    ];
+    params
+}
 
-    let cert = params
-        .signed_by(&subject_key, &issuer_cert, &issuer_key)
-        .context("sign domain cert")?;
+/// How a badssl test cert is broken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrokenCert {
+    /// Signed by the CA, but expired.
+    Expired,
+    /// Signed by the CA for another host ([`WRONG_HOST_SAN`]).
+    WrongHost,
+    /// Self-signed leaf.
+    SelfSigned,
+    /// Signed by a throwaway root that no trust store knows; the root is sent in the chain.
+    UntrustedRoot,
+    /// Signed by an intermediate of the CA that is left out of the chain.
+    IncompleteChain,
+    /// Signed by the CA and revoked (listed in its CRL and OCSP responses).
+    Revoked,
+}
 
-    let cert_pem = cert.pem();
-    let key_pem = subject_key.serialize_pem();
+/// The only SAN of [`BrokenCert::WrongHost`] certs.
+pub const WRONG_HOST_SAN: &str = "wrong-host.invalid";
 
-    Ok((cert_pem.into_bytes(), key_pem.into_bytes()))
+/// Issue a deliberately broken cert for `domain`. Returns (chain PEM, key PEM).
+/// [`BrokenCert::Revoked`] certs are revoked under `ca_name` before returning.
+pub fn issue_broken_cert(
+    paths: &RoostPaths,
+    domain: &str,
+    ca_name: &str,
+    kind: BrokenCert,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let (ca_pem, ca_key_pem) = crate::ca::load_ca(paths, ca_name)?;
+    let (issuer_cert, issuer_key) = load_issuer(&ca_pem, &ca_key_pem)?;
+    let key = KeyPair::generate().context("generate domain key")?;
+    let mut params = leaf_params(vec![domain.to_string()], domain)?;
+
+    let chain_pem = match kind {
+        BrokenCert::Expired => {
+            let now = time::OffsetDateTime::now_utc();
+            params.not_before = now - time::Duration::days(90);
+            params.not_after = now - time::Duration::days(1);
+            params.signed_by(&key, &issuer_cert, &issuer_key)?.pem()
+        }
+        BrokenCert::WrongHost => {
+            let params = leaf_params(vec![WRONG_HOST_SAN.to_string()], WRONG_HOST_SAN)?;
+            params.signed_by(&key, &issuer_cert, &issuer_key)?.pem()
+        }
+        BrokenCert::SelfSigned => params.self_signed(&key)?.pem(),
+        BrokenCert::UntrustedRoot => {
+            let root_key = KeyPair::generate().context("generate root key")?;
+            let root = ca_params("Roost untrusted root").self_signed(&root_key)?;
+            let leaf = params.signed_by(&key, &root, &root_key)?;
+            leaf.pem() + &root.pem()
+        }
+        BrokenCert::IncompleteChain => {
+            let intermediate_key = KeyPair::generate().context("generate intermediate key")?;
+            let intermediate = ca_params("Roost intermediate (not sent)").signed_by(
+                &intermediate_key,
+                &issuer_cert,
+                &issuer_key,
+            )?;
+            params.signed_by(&key, &intermediate, &intermediate_key)?.pem()
+        }
+        BrokenCert::Revoked => {
+            let config = crate::store::load_config(paths)?;
+            if let Some(base_url) = config.revocation_url.as_deref() {
+                crate::revoke::add_revocation_urls(&mut params, base_url, ca_name);
+            }
+            let pem = params.signed_by(&key, &issuer_cert, &issuer_key)?.pem();
+            let serial = crate::revoke::cert_serial(pem.as_bytes())?;
+            crate::revoke::revoke(
+                paths,
+                ca_name,
+                &serial,
+                crate::revoke::Reason::KeyCompromise,
+                Some(domain),
+            )?;
+            pem
+        }
+    };
+
+    Ok((chain_pem.into_bytes(), key.serialize_pem().into_bytes()))
 }
 
 /// Save domain cert and key to store.
@@ -106,8 +258,7 @@ pub fn save_domain_cert(
     key_pem: &[u8],
 ) -> Result<()> {
     crate::store::ensure_dirs(paths)?;
-    let cert_path = paths.certs_dir.join(format!("{domain}.pem"));
-    let key_path = paths.certs_dir.join(format!("{domain}-key.pem"));
+    let (cert_path, key_path) = domain_cert_paths(paths, domain);
 
     let mut f = fs::File::create(&cert_path)?;
     f.write_all(cert_pem)?;
@@ -120,8 +271,7 @@ pub fn save_domain_cert(
 
 /// Load domain cert and key.
 pub fn load_domain_cert(paths: &RoostPaths, domain: &str) -> Result<(Vec<u8>, Vec<u8>)> {
-    let cert_path = paths.certs_dir.join(format!("{domain}.pem"));
-    let key_path = paths.certs_dir.join(format!("{domain}-key.pem"));
+    let (cert_path, key_path) = domain_cert_paths(paths, domain);
 
     let cert = fs::read(&cert_path)
         .with_context(|| format!("read cert: {}", cert_path.display()))?;
@@ -159,7 +309,7 @@ pub fn ensure_cert_valid(
     ca_name: &str,
     exact: bool,
 ) -> Result<()> {
-    let cert_path = paths.certs_dir.join(format!("{domain}.pem"));
+    let (cert_path, _) = domain_cert_paths(paths, domain);
 
     let needs_regen = if cert_path.is_file() {
         cert_expires_within_days(&cert_path, 30)?
@@ -168,10 +318,113 @@ pub fn ensure_cert_valid(
     };
 
     if needs_regen {
-        let (ca_pem, ca_key_pem) = crate::ca::load_ca(paths, ca_name)?;
-        let (cert_pem, key_pem) = generate_domain_cert(domain, &ca_pem, &ca_key_pem, exact)?;
+        let (cert_pem, key_pem) = issue_domain_cert(paths, domain, ca_name, exact)?;
         save_domain_cert(paths, domain, &cert_pem, &key_pem)?;
     }
 
     Ok(())
 }
+
+/// File name stem for a domain's cert and key. Colons in IPv6 addresses become `_`, since
+/// Windows doesn't allow them in file names.
+fn file_stem(domain: &str) -> String {
+    domain.replace(':', "_")
+}
+
+/// Paths of a domain's cert and key: `certs/<domain>.pem` and `-key.pem`.
+pub fn domain_cert_paths(paths: &RoostPaths, domain: &str) -> (PathBuf, PathBuf) {
+    let stem = file_stem(domain);
+    (
+        paths.certs_dir.join(format!("{stem}.pem")),
+        paths.certs_dir.join(format!("{stem}-key.pem")),
+    )
+}
+
+/// Paths of a domain's RSA cert and key: `certs/rsa/<domain>.pem` and `-key.pem`.
+pub fn rsa_cert_paths(paths: &RoostPaths, domain: &str) -> (PathBuf, PathBuf) {
+    let dir = paths.certs_dir.join("rsa");
+    let stem = file_stem(domain);
+    (
+        dir.join(format!("{stem}.pem")),
+        dir.join(format!("{stem}-key.pem")),
+    )
+}
+
+/// Ensure the domain's RSA cert exists and is not expiring; issued with the same SANs as
+/// the ECDSA cert. RSA certs are only created for mappings whose TLS policy asks for them.
+pub fn ensure_rsa_cert_valid(paths: &RoostPaths, domain: &str, ca_name: &str) -> Result<()> {
+    let (cert_path, key_path) = rsa_cert_paths(paths, domain);
+    if cert_path.is_file() && key_path.is_file() && !cert_expires_within_days(&cert_path, 30)? {
+        return Ok(());
+    }
+    let exact = match load_domain_cert(paths, domain) {
+        Ok((pem, _)) => !has_wildcard_san(&pem, domain)?,
+        Err(_) => false,
+    };
+    let (cert_pem, key_pem) =
+        issue_domain_cert_with_key(paths, domain, ca_name, exact, LeafKey::Rsa)?;
+    if let Some(dir) = cert_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&cert_path, cert_pem)?;
+    fs::write(&key_path, key_pem)?;
+    Ok(())
+}
+
+/// Delete a domain's RSA cert; it is reissued the next time the proxy needs it.
+pub fn remove_rsa_cert(paths: &RoostPaths, domain: &str) {
+    let (cert_path, key_path) = rsa_cert_paths(paths, domain);
+    let _ = fs::remove_file(cert_path);
+    let _ = fs::remove_file(key_path);
+}
+
+/// Name in the proxy's fallback cert, served for unknown SNI values.
+pub const FALLBACK_CERT_NAME: &str = "roost-fallback.invalid";
+
+/// Paths of the fallback cert and key: `certs/fallback/<name>.pem` and `-key.pem`.
+pub fn fallback_cert_paths(paths: &RoostPaths) -> (PathBuf, PathBuf) {
+    let dir = paths.certs_dir.join("fallback");
+    (
+        dir.join(format!("{FALLBACK_CERT_NAME}.pem")),
+        dir.join(format!("{FALLBACK_CERT_NAME}-key.pem")),
+    )
+}
+
+/// Ensure the fallback cert exists and is not expiring. It lets handshakes for unmapped
+/// hosts complete so the proxy can answer with its landing page; clients still see a
+/// name mismatch.
+pub fn ensure_fallback_cert(paths: &RoostPaths, ca_name: &str) -> Result<()> {
+    let (cert_path, key_path) = fallback_cert_paths(paths);
+    if cert_path.is_file() && key_path.is_file() && !cert_expires_within_days(&cert_path, 30)? {
+        return Ok(());
+    }
+    let (cert_pem, key_pem) = issue_domain_cert(paths, FALLBACK_CERT_NAME, ca_name, true)?;
+    if let Some(dir) = cert_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&cert_path, cert_pem)?;
+    fs::write(&key_path, key_pem)?;
+    Ok(())
+}
+
+/// Whether the cert has a `*.<domain>` SAN (i.e. was not issued with `--exact`).
+pub(crate) fn has_wildcard_san(cert_pem: &[u8], domain: &str) -> Result<bool> {
+    use x509_parser::extensions::GeneralName;
+    let der = rustls_pemfile::certs(&mut &cert_pem[..])
+        .next()
+        .and_then(|r| r.ok())
+        .context("parse cert PEM")?;
+    let (_, cert) = x509_parser::prelude::X509Certificate::from_der(der.as_ref())
+        .map_err(|e| anyhow::anyhow!("parse X.509: {e:?}"))?;
+    let wildcard = format!("*.{domain}");
+    Ok(cert
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .is_some_and(|san| {
+            san.value
+                .general_names
+                .iter()
+                .any(|n| matches!(n, GeneralName::DNSName(d) if *d == wildcard))
+        }))
+}
@@ -41,6 +41,98 @@ pub enum Commands {
 
     /// Check configuration health (CA, hosts, certs, trust store)
     Doctor,
+
+    /// Revoke certs and serve CRL/OCSP (revoke, revoked, crl, ocsp, revocation-url)
+    Cert {
+        #[command(subcommand)]
+        cmd: CertCmd,
+    },
+
+    /// Run a local ACME server that issues certs from a roost CA
+    Acme {
+        #[command(subcommand)]
+        cmd: AcmeCmd,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CertCmd {
+    /// Revoke a domain's current cert (and issue a fresh one) or a cert by serial
+    Revoke {
+        /// Registered domain, or hex serial of any cert signed by the CA
+        target: String,
+        /// CA that issued the serial (default: the domain's CA or the default CA)
+        #[arg(long)]
+        ca: Option<String>,
+        #[arg(long, value_enum, default_value_t = crate::revoke::Reason::Unspecified)]
+        reason: crate::revoke::Reason,
+        /// Do not issue a replacement cert when revoking a domain
+        #[arg(long)]
+        no_reissue: bool,
+    },
+    /// List revoked serials of a CA
+    Revoked {
+        #[arg(long)]
+        ca: Option<String>,
+    },
+    /// Regenerate a CA's CRL and print its path
+    Crl {
+        #[arg(long)]
+        ca: Option<String>,
+    },
+    /// Run the CRL and OCSP responder for all CAs (until Ctrl-C)
+    Ocsp {
+        #[arg(long, default_value_t = crate::ocsp::DEFAULT_PORT)]
+        port: u16,
+        /// Bind addresses (default 127.0.0.1 and ::1)
+        #[arg(long)]
+        bind: Vec<std::net::IpAddr>,
+        /// Allow non-loopback bind addresses
+        #[arg(long)]
+        lan: bool,
+    },
+    /// Set the responder URL embedded (as CRL and OCSP URLs) in newly issued certs
+    RevocationUrl {
+        /// Base URL, e.g. http://127.0.0.1:8889; omit to show the current setting
+        url: Option<String>,
+        /// Stop embedding revocation URLs in new certs
+        #[arg(long, conflicts_with = "url")]
+        off: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AcmeCmd {
+    /// Serve an RFC 8555 ACME directory over HTTPS (until Ctrl-C)
+    Serve {
+        /// CA that signs issued certs (default: the configured default CA)
+        #[arg(long)]
+        ca: Option<String>,
+        /// HTTPS port for the ACME directory
+        #[arg(long, default_value_t = 14000)]
+        port: u16,
+        /// Bind addresses (default 127.0.0.1 and ::1)
+        #[arg(long)]
+        bind: Vec<std::net::IpAddr>,
+        /// Allow non-loopback bind addresses
+        #[arg(long)]
+        lan: bool,
+        /// Extra names for the server's own cert (e.g. host.docker.internal)
+        #[arg(long = "name")]
+        names: Vec<String>,
+        /// Approve names in the domain allowlist without an http-01 challenge
+        #[arg(long)]
+        auto_approve: bool,
+        /// Accept names outside the domain allowlist (always validated with http-01)
+        #[arg(long)]
+        allow: bool,
+        /// Port the http-01 validator connects to
+        #[arg(long, default_value_t = 80)]
+        http_port: u16,
+        /// Validity of issued certs in days
+        #[arg(long, default_value_t = 90)]
+        days: u32,
+    },
 }
 
 #[derive(Subcommand)]
@@ -112,6 +204,74 @@ pub enum ServeCmd {
         #[command(subcommand)]
         cmd: ServeDaemonCmd,
     },
+    /// Inject latency and faults into a running proxy (set, clear, list)
+    Chaos {
+        #[command(subcommand)]
+        cmd: ServeChaosCmd,
+    },
+    /// Manage the response cache of a running proxy (purge)
+    Cache {
+        #[command(subcommand)]
+        cmd: ServeCacheCmd,
+    },
+    /// Manage the certificates of a running proxy (reload)
+    Certs {
+        #[command(subcommand)]
+        cmd: ServeCertsCmd,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ServeCertsCmd {
+    /// Serve the certs on disk for a mapping, or for all mappings without a domain
+    Reload { domain: Option<String> },
+}
+
+#[derive(Subcommand)]
+pub enum ServeCacheCmd {
+    /// Drop cached responses for a mapping, or for all mappings without a domain
+    Purge {
+        domain: Option<String>,
+        /// Only drop responses whose path starts with this prefix
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ServeChaosCmd {
+    /// Set runtime chaos for a mapping (replaces .roostrc settings until cleared); no flags disables chaos
+    Set {
+        domain: String,
+        /// Only affect request paths starting with this prefix
+        #[arg(long)]
+        path_prefix: Option<String>,
+        /// Fixed added latency in milliseconds
+        #[arg(long, default_value_t = 0)]
+        latency: u64,
+        /// Extra random latency of up to this many milliseconds
+        #[arg(long, default_value_t = 0)]
+        jitter: u64,
+        /// Limit response throughput to this many bytes per second
+        #[arg(long)]
+        bandwidth: Option<u64>,
+        /// Percentage of requests answered with --error-status
+        #[arg(long, default_value_t = 0.0)]
+        error_rate: f64,
+        /// Status code for injected errors, 400-599 (default 503)
+        #[arg(long)]
+        error_status: Option<u16>,
+        /// Percentage of requests whose connection is dropped
+        #[arg(long, default_value_t = 0.0)]
+        drop_rate: f64,
+        /// Percentage of responses whose body is truncated
+        #[arg(long, default_value_t = 0.0)]
+        truncate_rate: f64,
+    },
+    /// Clear runtime chaos for a mapping (falls back to .roostrc settings)
+    Clear { domain: String },
+    /// List chaos settings in effect on the running proxy
+    List,
 }
 
 #[derive(Subcommand)]
@@ -138,6 +298,296 @@ pub enum ServeConfigCmd {
         #[command(subcommand)]
         cmd: ServePortsCmd,
     },
+    /// Manage plain HTTP ports that redirect to a TLS port (e.g. 8080 -> 8443)
+    Redirect {
+        #[command(subcommand)]
+        cmd: ServeRedirectCmd,
+    },
+    /// Manage HTTP basic auth users for a mapping
+    Auth {
+        #[command(subcommand)]
+        cmd: ServeAuthCmd,
+    },
+    /// Manage client IP/CIDR allowlist for a mapping
+    Allow {
+        #[command(subcommand)]
+        cmd: ServeAllowCmd,
+    },
+    /// Enable gzip/brotli response compression for a mapping
+    Compress {
+        domain: String,
+        /// Disable compression instead
+        #[arg(long)]
+        off: bool,
+        /// Skip responses smaller than this many bytes (default 1024)
+        #[arg(long)]
+        min_size: Option<u64>,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Serve Prometheus metrics at http://<addr>/metrics (a bare port binds 127.0.0.1)
+    Metrics {
+        #[arg(value_parser = parse_metrics_addr, required_unless_present = "off")]
+        addr: Option<std::net::SocketAddr>,
+        /// Disable the metrics endpoint instead
+        #[arg(long, conflicts_with = "addr")]
+        off: bool,
+        /// Allow a non-loopback address (sets lan = true; needs --global)
+        #[arg(long)]
+        lan: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Set TLS versions, cipher suites, ALPN and key type for a mapping
+    Tls {
+        #[arg(required_unless_present = "list_ciphers")]
+        domain: Option<String>,
+        /// Lowest TLS version to accept (1.2 or 1.3)
+        #[arg(long)]
+        min_version: Option<crate::serve::tls::TlsVersion>,
+        /// Highest TLS version to accept (1.2 or 1.3)
+        #[arg(long)]
+        max_version: Option<crate::serve::tls::TlsVersion>,
+        /// Allowed cipher suites, comma-separated (see --list-ciphers)
+        #[arg(long, value_delimiter = ',')]
+        ciphers: Option<Vec<String>>,
+        /// ALPN protocols to offer, comma-separated, in preference order
+        #[arg(long, value_delimiter = ',', conflicts_with = "no_alpn")]
+        alpn: Option<Vec<String>>,
+        /// Offer no ALPN protocols
+        #[arg(long)]
+        no_alpn: bool,
+        /// Leaf certificate to serve
+        #[arg(long, value_enum)]
+        key_type: Option<crate::serve::tls::KeyType>,
+        /// Clear the mapping's TLS settings (back to [serve.tls] and rustls defaults)
+        #[arg(long)]
+        reset: bool,
+        /// List supported cipher suite names
+        #[arg(long)]
+        list_ciphers: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Send a copy of each request for a mapping to a shadow backend; its responses are discarded
+    Mirror {
+        domain: String,
+        /// Port of the shadow backend on localhost
+        #[arg(required_unless_present = "off")]
+        port: Option<u16>,
+        /// Log status and latency differences between the primary and the shadow
+        #[arg(long)]
+        compare: bool,
+        /// Latency difference in milliseconds that --compare reports (default 100)
+        #[arg(long, requires = "compare")]
+        latency_threshold_ms: Option<u64>,
+        /// Stop mirroring instead
+        #[arg(long, conflicts_with_all = ["port", "compare"])]
+        off: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Ask an auth endpoint about each request before proxying it (like nginx auth_request)
+    ForwardAuth {
+        domain: String,
+        /// Auth endpoint, e.g. http://localhost:9091/api/verify
+        #[arg(required_unless_present = "off")]
+        url: Option<String>,
+        /// Copy this header from a 2xx auth response onto the upstream request
+        #[arg(long = "header", value_name = "NAME")]
+        headers: Vec<String>,
+        /// Stop calling the auth endpoint instead
+        #[arg(long, conflicts_with_all = ["url", "headers"])]
+        off: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Let a roost-upstream cookie or header send a mapping's requests to an alternate backend
+    Upstream {
+        domain: String,
+        /// Allow selecting these ports by number (5005 or 5000-5010); replaces the list
+        #[arg(long = "ports", value_name = "RANGE")]
+        ports: Vec<String>,
+        /// Add or update a named alternate backend
+        #[arg(long = "alternate", value_name = "NAME=PORT", value_parser = parse_alternate)]
+        alternates: Vec<(String, u16)>,
+        /// Remove a named alternate
+        #[arg(long = "remove-alternate", value_name = "NAME")]
+        remove_alternates: Vec<String>,
+        /// Remove the override settings instead
+        #[arg(long, conflicts_with_all = ["ports", "alternates", "remove_alternates"])]
+        off: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Cache a mapping's responses like a CDN, following Cache-Control; replaces its settings
+    Cache {
+        domain: String,
+        /// Memory budget in bytes (default 64 MiB); least recently used responses go first
+        #[arg(long)]
+        max_bytes: Option<u64>,
+        /// Don't store responses larger than this many bytes (default 8 MiB)
+        #[arg(long)]
+        max_entry_bytes: Option<u64>,
+        /// Also keep cached responses on disk so they survive restarts
+        #[arg(long)]
+        disk: bool,
+        /// Stop caching instead
+        #[arg(long, conflicts_with_all = ["max_bytes", "max_entry_bytes", "disk"])]
+        off: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Let a port in the Host header (app.test:5173) pick the backend port for a mapping
+    PortPassthrough {
+        domain: String,
+        /// Route only to the mapped port again
+        #[arg(long)]
+        off: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Proxy requests for unmapped hosts to this port instead of showing the landing page
+    DefaultBackend {
+        #[arg(required_unless_present = "off")]
+        port: Option<u16>,
+        /// Show the landing page for unmapped hosts again
+        #[arg(long, conflicts_with = "port")]
+        off: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Serve this domain's cert to clients that send no SNI (https://192.168.1.20)
+    DefaultCert {
+        #[arg(required_unless_present = "off")]
+        domain: Option<String>,
+        /// Fail handshakes without SNI again
+        #[arg(long, conflicts_with = "domain")]
+        off: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Serve deliberately broken TLS test endpoints (expired.badssl.test, revoked.badssl.test, ...)
+    Badssl {
+        /// Stop serving them and remove their hosts entries and certs
+        #[arg(long)]
+        off: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Configure trace context propagation and OTLP span export; no flags shows the settings
+    Tracing {
+        /// Export a span per request to this OTLP/HTTP collector (e.g. http://127.0.0.1:4318)
+        #[arg(long, conflicts_with = "no_otlp")]
+        otlp: Option<String>,
+        /// Stop exporting spans
+        #[arg(long)]
+        no_otlp: bool,
+        /// service.name of exported spans (default roost)
+        #[arg(long)]
+        service_name: Option<String>,
+        /// Add traceparent/tracestate and X-Request-Id headers to backend requests (default true)
+        #[arg(long)]
+        propagate: Option<bool>,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+}
+
+/// Parse a named alternate upstream, "NAME=PORT".
+fn parse_alternate(s: &str) -> Result<(String, u16), String> {
+    let (name, port) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=PORT, got {s:?}"))?;
+    let port = port
+        .parse()
+        .map_err(|_| format!("invalid port in {s:?}"))?;
+    Ok((name.to_string(), port))
+}
+
+/// Parse "host:port" or a bare port (bound on 127.0.0.1).
+fn parse_metrics_addr(s: &str) -> Result<std::net::SocketAddr, String> {
+    if let Ok(port) = s.parse::<u16>() {
+        return Ok((std::net::Ipv4Addr::LOCALHOST, port).into());
+    }
+    s.parse()
+        .map_err(|_| format!("expected a port or address:port, got {s:?}"))
+}
+
+#[derive(Subcommand)]
+pub enum ServeRedirectCmd {
+    /// Listen for plain HTTP on <from> and redirect to HTTPS on <to>
+    Add {
+        from: u16,
+        to: u16,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Remove the redirect from a plain HTTP port
+    Remove {
+        from: u16,
+        /// Remove from global .roostrc instead of project
+        #[arg(long)]
+        global: bool,
+    },
+    /// List redirects in effect (project + global)
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum ServeAuthCmd {
+    /// Add or update a user (password is stored as a bcrypt hash)
+    Add {
+        domain: String,
+        user: String,
+        /// Read the password from the first line of stdin instead of prompting
+        #[arg(long)]
+        password_stdin: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Remove a user
+    Remove {
+        domain: String,
+        user: String,
+        /// Remove from global .roostrc instead of project
+        #[arg(long)]
+        global: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ServeAllowCmd {
+    /// Allow a client IP or CIDR range (e.g. 192.168.1.0/24)
+    Add {
+        domain: String,
+        cidr: String,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
+    /// Remove a client IP or CIDR range
+    Remove {
+        domain: String,
+        cidr: String,
+        /// Remove from global .roostrc instead of project
+        #[arg(long)]
+        global: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -164,6 +614,17 @@ pub enum ServePortsCmd {
         #[arg(long)]
         global: bool,
     },
+    /// Set bind addresses for a port (default 127.0.0.1 and ::1); no addresses resets
+    Bind {
+        port: u16,
+        addrs: Vec<std::net::IpAddr>,
+        /// Allow non-loopback addresses (sets lan = true; needs --global)
+        #[arg(long)]
+        lan: bool,
+        /// Write to global .roostrc instead of project .roostrc
+        #[arg(long)]
+        global: bool,
+    },
     /// List configured listen ports
     List,
 }
@@ -191,6 +652,8 @@ pub fn run() -> Result<()> {
         Commands::Domain { cmd } => cmd_domain(&paths, cmd),
         Commands::Serve { cmd } => cmd_serve(&paths, cmd),
         Commands::Doctor => cmd_doctor(&paths),
+        Commands::Cert { cmd } => cmd_cert(&paths, cmd),
+        Commands::Acme { cmd } => cmd_acme(&paths, cmd),
     }
 }
 
@@ -222,6 +685,165 @@ fn cmd_init(paths: &RoostPaths) -> Result<()> {
     Ok(())
 }
 
+/// Named CA, else the configured default CA.
+fn ca_or_default(paths: &RoostPaths, ca: Option<String>) -> Result<String> {
+    if let Some(ca) = ca {
+        return Ok(ca);
+    }
+    let config = store::load_config(paths)?;
+    Ok(if config.default_ca.is_empty() {
+        "default".to_string()
+    } else {
+        config.default_ca
+    })
+}
+
+fn cmd_cert(paths: &RoostPaths, cmd: CertCmd) -> Result<()> {
+    match cmd {
+        CertCmd::Revoke {
+            target,
+            ca,
+            reason,
+            no_reissue,
+        } => {
+            let config = store::load_config(paths)?;
+            if let Some(domain_ca) = config.domains.get(&target) {
+                let ca_name = ca.unwrap_or_else(|| domain_ca.clone());
+                let serial =
+                    crate::revoke::revoke_domain(paths, &target, &ca_name, reason, !no_reissue)?;
+                println!("Revoked cert for {target} (serial {serial}, CA {ca_name})");
+                if !no_reissue {
+                    println!("Issued new cert for {target}");
+                    if crate::serve::daemon::daemon_status(paths)?.is_some() {
+                        let _ = crate::serve::daemon::reload_daemon(paths);
+                    }
+                }
+            } else {
+                if crate::revoke::normalize_serial(&target).is_err() {
+                    anyhow::bail!("'{target}' is neither a registered domain nor a hex serial");
+                }
+                let ca_name = ca_or_default(paths, ca)?;
+                let serial = crate::revoke::normalize_serial(&target)?;
+                if crate::revoke::revoke(paths, &ca_name, &serial, reason, None)? {
+                    println!("Revoked serial {serial} (CA {ca_name})");
+                } else {
+                    println!("Serial {serial} is already revoked (CA {ca_name})");
+                }
+            }
+            Ok(())
+        }
+        CertCmd::Revoked { ca } => {
+            let ca_name = ca_or_default(paths, ca)?;
+            for r in crate::revoke::load(paths, &ca_name)?.revoked {
+                let reason = crate::revoke::Reason::to_possible_value(&r.reason)
+                    .map(|v| v.get_name().to_string())
+                    .unwrap_or_default();
+                println!(
+                    "{}\t{}\t{reason}\t{}",
+                    r.serial,
+                    r.revoked_at,
+                    r.domain.unwrap_or_default()
+                );
+            }
+            Ok(())
+        }
+        CertCmd::Crl { ca } => {
+            let ca_name = ca_or_default(paths, ca)?;
+            if !crate::ca::ca_exists(paths, &ca_name) {
+                anyhow::bail!("CA '{ca_name}' does not exist");
+            }
+            println!("{}", crate::revoke::write_crl(paths, &ca_name)?.display());
+            Ok(())
+        }
+        CertCmd::Ocsp { port, bind, lan } => {
+            let bind = if bind.is_empty() {
+                crate::serve::config::DEFAULT_BIND.to_vec()
+            } else {
+                bind
+            };
+            crate::serve::config::require_loopback(
+                bind.iter().copied(),
+                lan,
+                "the OCSP responder",
+                "pass --lan to allow it",
+            )?;
+            let config = store::load_config(paths)?;
+            match &config.revocation_url {
+                Some(url) => println!("Certs issued from now on point at {url}"),
+                None => println!(
+                    "New certs carry no revocation URLs; run 'roost cert revocation-url http://127.0.0.1:{port}' to embed them"
+                ),
+            }
+            let rt = tokio::runtime::Runtime::new()?;
+            rt.block_on(crate::ocsp::serve(paths, &bind, port))
+        }
+        CertCmd::RevocationUrl { url, off } => {
+            let mut config = store::load_config(paths)?;
+            if off {
+                config.revocation_url = None;
+                store::save_config(paths, &config)?;
+                println!("New certs will not carry revocation URLs");
+            } else if let Some(url) = url {
+                let parsed: http::Uri = url.parse()?;
+                if !matches!(parsed.scheme_str(), Some("http") | Some("https")) {
+                    anyhow::bail!("revocation URL must start with http:// or https://");
+                }
+                let url = url.trim_end_matches('/').to_string();
+                let ca_name = ca_or_default(paths, None)?;
+                println!(
+                    "New certs will carry CRL {} and OCSP {}",
+                    crate::revoke::crl_url(&url, &ca_name),
+                    crate::revoke::ocsp_url(&url, &ca_name)
+                );
+                println!("Existing certs keep their URLs until reissued");
+                config.revocation_url = Some(url);
+                store::save_config(paths, &config)?;
+            } else {
+                match config.revocation_url {
+                    Some(url) => println!("{url}"),
+                    None => println!("(none)"),
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn cmd_acme(paths: &RoostPaths, cmd: AcmeCmd) -> Result<()> {
+    match cmd {
+        AcmeCmd::Serve {
+            ca,
+            port,
+            bind,
+            lan,
+            names,
+            auto_approve,
+            allow,
+            http_port,
+            days,
+        } => {
+            let ca = ca_or_default(paths, ca)?;
+            let mut options = crate::acme::AcmeOptions {
+                ca,
+                port,
+                lan,
+                names,
+                auto_approve,
+                allow_any_tld: allow,
+                http01_port: http_port,
+                validity_days: days,
+                ..Default::default()
+            };
+            if !bind.is_empty() {
+                options.bind = bind;
+            }
+            options.validate()?;
+            let rt = tokio::runtime::Runtime::new()?;
+            rt.block_on(crate::acme::serve(paths, options))
+        }
+    }
+}
+
 fn cmd_doctor(paths: &RoostPaths) -> Result<()> {
     let cwd = std::env::current_dir()?;
     let results = crate::doctor::run_checks(paths, &cwd)?;
@@ -315,6 +937,11 @@ fn cmd_domain(paths: &RoostPaths, cmd: DomainCmd) -> Result<()> {
             crate::domain::set_ca(paths, &mut config, &domain, &ca_name)?;
             store::save_config(paths, &config)?;
             println!("Set CA for {domain}: {ca_name}");
+            // A running proxy would notice the new files; this switches right away
+            let request = crate::serve::control::ControlRequest::CertReload {
+                domain: Some(domain),
+            };
+            let _ = crate::serve::control::send_control(paths, request);
             Ok(())
         }
         DomainCmd::Path {
@@ -359,21 +986,111 @@ fn serve_config_path(paths: &RoostPaths, cwd: &std::path::Path, global: bool) ->
     }
 }
 
+/// Check the bind addresses in `serve_cfg` (loaded from `rc_path`). Non-loopback ones need
+/// `lan = true`, which only counts in the global .roostrc; `--lan` sets it there, so it is
+/// refused for a project .roostrc.
+fn check_binds(
+    paths: &RoostPaths,
+    rc_path: &std::path::Path,
+    serve_cfg: &mut ServeConfig,
+    lan: bool,
+) -> Result<()> {
+    if rc_path == paths.roostrc_global {
+        if lan && !serve_cfg.lan {
+            serve_cfg.lan = true;
+            println!("Enabled LAN exposure (lan = true) in {}", rc_path.display());
+        }
+        return serve_cfg.validate_binds();
+    }
+    let global = ServeConfig::load(&paths.roostrc_global)?;
+    if lan && !global.lan {
+        anyhow::bail!(
+            "--lan needs --global: LAN exposure applies to every project on this machine. \
+             Set 'lan = true' under [serve] in {} first",
+            paths.roostrc_global.display()
+        );
+    }
+    let mut effective = serve_cfg.clone();
+    effective.lan = global.lan;
+    effective.validate_binds()
+}
+
+/// Load .roostrc, apply `f` to the domain's mapping, save and reload a running daemon.
+fn update_mapping(
+    paths: &RoostPaths,
+    rc_path: &std::path::Path,
+    domain: &str,
+    f: impl FnOnce(&mut crate::serve::config::Mapping) -> Result<()>,
+) -> Result<()> {
+    let mut serve_cfg = ServeConfig::load(rc_path)?;
+    let mapping = serve_cfg.mapping_mut(domain).ok_or_else(|| {
+        anyhow::anyhow!("no mapping for {domain} in {}; add it with 'roost serve config add'", rc_path.display())
+    })?;
+    f(mapping)?;
+    serve_cfg.save(rc_path)?;
+    if crate::serve::daemon::daemon_status(paths)?.is_some() {
+        let _ = crate::serve::daemon::reload_daemon(paths);
+    }
+    Ok(())
+}
+
+/// Password from the first line of stdin (`--password-stdin`), else prompted for on the
+/// terminal without echo. Never taken from argv, where shell history and `ps` would see it.
+fn read_password(from_stdin: bool) -> Result<String> {
+    use std::io::IsTerminal;
+    let read_line = || -> Result<String> {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+    let password = if from_stdin {
+        read_line()?
+    } else {
+        if !std::io::stdin().is_terminal() {
+            anyhow::bail!("no terminal to prompt for the password; use --password-stdin");
+        }
+        eprint!("Password: ");
+        let password = without_echo(read_line)?;
+        eprintln!();
+        password
+    };
+    if password.is_empty() {
+        anyhow::bail!("empty password");
+    }
+    Ok(password)
+}
+
+/// Run `f` with terminal echo on stdin turned off.
+#[cfg(unix)]
+fn without_echo<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    use std::os::fd::AsRawFd;
+    let fd = std::io::stdin().as_raw_fd();
+    let mut original = std::mem::MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: tcgetattr fills the termios struct on success
+    if unsafe { libc::tcgetattr(fd, original.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let original = unsafe { original.assume_init() };
+    let mut silent = original;
+    silent.c_lflag &= !libc::ECHO;
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    let result = f();
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    result
+}
+
+#[cfg(not(unix))]
+fn without_echo<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    f()
+}
+
 fn cmd_serve(paths: &RoostPaths, cmd: Option<ServeCmd>) -> Result<()> {
     match cmd {
         None => {
             let cwd = std::env::current_dir()?;
-            let project_path = project_roostrc(&cwd);
-            let project = project_path
-                .as_ref()
-                .map(|p| ServeConfig::load(p))
-                .transpose()?
-                .unwrap_or_default();
-            let global = ServeConfig::load(&paths.roostrc_global)?;
-            let mappings = crate::serve::config::merge_configs(&project, &global);
-            let ports = crate::serve::config::merge_ports(&project, &global);
+            let config = crate::serve::config::load_merged(paths, &cwd)?;
             let rt = tokio::runtime::Runtime::new()?;
-            rt.block_on(crate::serve::proxy::run_proxy(paths, mappings, ports))?;
+            rt.block_on(crate::serve::proxy::run_proxy(paths, &config))?;
             Ok(())
         }
         Some(ServeCmd::Config { cmd }) => {
@@ -471,23 +1188,536 @@ fn cmd_serve(paths: &RoostPaths, cmd: Option<ServeCmd>) -> Result<()> {
                         println!("Ports updated");
                         Ok(())
                     }
+                    ServePortsCmd::Bind {
+                        port,
+                        addrs,
+                        lan,
+                        global,
+                    } => {
+                        let rc_path = serve_config_path(paths, &cwd, global)?;
+                        let mut serve_cfg = ServeConfig::load(&rc_path)?;
+                        serve_cfg.set_bind(port, addrs);
+                        check_binds(paths, &rc_path, &mut serve_cfg, lan)?;
+                        serve_cfg.save(&rc_path)?;
+                        if crate::serve::daemon::daemon_status(paths)?.is_some() {
+                            let _ = crate::serve::daemon::reload_daemon(paths);
+                        }
+                        let bind: Vec<String> =
+                            serve_cfg.bind_addrs(port).iter().map(|ip| ip.to_string()).collect();
+                        println!("Port {port} binds {}", bind.join(", "));
+                        Ok(())
+                    }
                     ServePortsCmd::List => {
-                        let project_path = project_roostrc(&cwd);
-                        let project = project_path
-                            .as_ref()
-                            .map(|p| ServeConfig::load(p))
-                            .transpose()?
-                            .unwrap_or_default();
-                        let global = ServeConfig::load(&paths.roostrc_global)?;
-                        let ports = crate::serve::config::merge_ports(&project, &global);
+                        let merged = crate::serve::config::load_merged(paths, &cwd)?;
+                        let redirects = merged.effective_redirects();
+                        let mut ports = merged.tls_ports();
+                        ports.extend(redirects.iter().map(|r| r.from));
+                        ports.sort();
+                        ports.dedup();
                         for p in ports {
-                            println!("{p}");
+                            let bind: Vec<String> =
+                                merged.bind_addrs(p).iter().map(|ip| ip.to_string()).collect();
+                            match redirects.iter().find(|r| r.from == p) {
+                                Some(r) => println!("{p}\t{}\thttp -> {}", bind.join(", "), r.to),
+                                None => println!("{p}\t{}\thttps", bind.join(", ")),
+                            }
                         }
                         Ok(())
                     }
                 },
+                ServeConfigCmd::Redirect { cmd } => match cmd {
+                    ServeRedirectCmd::Add { from, to, global } => {
+                        let rc_path = serve_config_path(paths, &cwd, global)?;
+                        let mut serve_cfg = ServeConfig::load(&rc_path)?;
+                        serve_cfg.set_redirect(from, to)?;
+                        serve_cfg.save(&rc_path)?;
+                        if crate::serve::daemon::daemon_status(paths)?.is_some() {
+                            let _ = crate::serve::daemon::reload_daemon(paths);
+                        }
+                        println!("Redirecting http port {from} -> https port {to}");
+                        Ok(())
+                    }
+                    ServeRedirectCmd::Remove { from, global } => {
+                        let rc_path = serve_config_path(paths, &cwd, global)?;
+                        let mut serve_cfg = ServeConfig::load(&rc_path)?;
+                        if !serve_cfg.remove_redirect(from) {
+                            anyhow::bail!("no redirect from port {from} in {}", rc_path.display());
+                        }
+                        serve_cfg.save(&rc_path)?;
+                        if crate::serve::daemon::daemon_status(paths)?.is_some() {
+                            let _ = crate::serve::daemon::reload_daemon(paths);
+                        }
+                        println!("Removed redirect from port {from}");
+                        Ok(())
+                    }
+                    ServeRedirectCmd::List => {
+                        let merged = crate::serve::config::load_merged(paths, &cwd)?;
+                        for r in merged.effective_redirects() {
+                            println!("{}\t-> {}", r.from, r.to);
+                        }
+                        Ok(())
+                    }
+                },
+                ServeConfigCmd::Auth { cmd } => match cmd {
+                    ServeAuthCmd::Add {
+                        domain,
+                        user,
+                        password_stdin,
+                        global,
+                    } => {
+                        if user.is_empty() || user.contains(':') {
+                            anyhow::bail!("invalid user name {user:?}");
+                        }
+                        let password = read_password(password_stdin)?;
+                        let hash = crate::serve::access::hash_password(&password)?;
+                        let rc_path = serve_config_path(paths, &cwd, global)?;
+                        update_mapping(paths, &rc_path, &domain, |m| {
+                            m.basic_auth
+                                .get_or_insert_with(Default::default)
+                                .users
+                                .insert(user.clone(), hash);
+                            Ok(())
+                        })?;
+                        println!("Added user {user} for {domain}");
+                        Ok(())
+                    }
+                    ServeAuthCmd::Remove {
+                        domain,
+                        user,
+                        global,
+                    } => {
+                        let rc_path = serve_config_path(paths, &cwd, global)?;
+                        update_mapping(paths, &rc_path, &domain, |m| {
+                            if let Some(auth) = m.basic_auth.as_mut() {
+                                auth.users.remove(&user);
+                                if auth.users.is_empty() {
+                                    m.basic_auth = None;
+                                }
+                            }
+                            Ok(())
+                        })?;
+                        println!("Removed user {user} for {domain}");
+                        Ok(())
+                    }
+                },
+                ServeConfigCmd::Allow { cmd } => match cmd {
+                    ServeAllowCmd::Add {
+                        domain,
+                        cidr,
+                        global,
+                    } => {
+                        crate::serve::access::parse_allow_entry(&cidr)?;
+                        let rc_path = serve_config_path(paths, &cwd, global)?;
+                        update_mapping(paths, &rc_path, &domain, |m| {
+                            if !m.allow.contains(&cidr) {
+                                m.allow.push(cidr.clone());
+                            }
+                            Ok(())
+                        })?;
+                        println!("Allowed {cidr} for {domain}");
+                        Ok(())
+                    }
+                    ServeAllowCmd::Remove {
+                        domain,
+                        cidr,
+                        global,
+                    } => {
+                        let rc_path = serve_config_path(paths, &cwd, global)?;
+                        update_mapping(paths, &rc_path, &domain, |m| {
+                            m.allow.retain(|c| c != &cidr);
+                            Ok(())
+                        })?;
+                        println!("Removed {cidr} from allowlist for {domain}");
+                        Ok(())
+                    }
+                },
+                ServeConfigCmd::Compress {
+                    domain,
+                    off,
+                    min_size,
+                    global,
+                } => {
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    update_mapping(paths, &rc_path, &domain, |m| {
+                        if off {
+                            m.compression = None;
+                        } else {
+                            let c = m.compression.get_or_insert_with(Default::default);
+                            if let Some(n) = min_size {
+                                c.min_size = n;
+                            }
+                        }
+                        Ok(())
+                    })?;
+                    if off {
+                        println!("Disabled compression for {domain}");
+                    } else {
+                        println!("Enabled compression for {domain}");
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::Metrics {
+                    addr,
+                    off,
+                    lan,
+                    global,
+                } => {
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    let mut serve_cfg = ServeConfig::load(&rc_path)?;
+                    serve_cfg.metrics = if off { None } else { addr };
+                    check_binds(paths, &rc_path, &mut serve_cfg, lan)?;
+                    serve_cfg.save(&rc_path)?;
+                    if crate::serve::daemon::daemon_status(paths)?.is_some() {
+                        let _ = crate::serve::daemon::reload_daemon(paths);
+                    }
+                    match serve_cfg.metrics {
+                        Some(addr) => println!("Metrics on http://{addr}/metrics"),
+                        None => println!("Disabled metrics endpoint"),
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::Tls {
+                    domain,
+                    min_version,
+                    max_version,
+                    ciphers,
+                    alpn,
+                    no_alpn,
+                    key_type,
+                    reset,
+                    list_ciphers,
+                    global,
+                } => {
+                    if list_ciphers {
+                        for name in crate::serve::tls::cipher_suite_names() {
+                            println!("{name}");
+                        }
+                        return Ok(());
+                    }
+                    let domain = domain.unwrap_or_default();
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    let mut policy = None;
+                    update_mapping(paths, &rc_path, &domain, |m| {
+                        let tls = &mut m.tls;
+                        if reset {
+                            *tls = Default::default();
+                        }
+                        if min_version.is_some() {
+                            tls.min_version = min_version;
+                        }
+                        if max_version.is_some() {
+                            tls.max_version = max_version;
+                        }
+                        if ciphers.is_some() {
+                            tls.ciphers = ciphers;
+                        }
+                        if no_alpn {
+                            tls.alpn = Some(Vec::new());
+                        } else if alpn.is_some() {
+                            tls.alpn = alpn;
+                        }
+                        if key_type.is_some() {
+                            tls.key_type = key_type;
+                        }
+                        tls.validate()?;
+                        policy = Some(tls.clone());
+                        Ok(())
+                    })?;
+                    let policy = policy.unwrap_or_default();
+                    if policy.is_empty() {
+                        println!("{domain}: default TLS settings");
+                    } else {
+                        println!("{domain}: {}", toml::to_string(&policy)?.trim().replace('\n', ", "));
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::Mirror {
+                    domain,
+                    port,
+                    compare,
+                    latency_threshold_ms,
+                    off,
+                    global,
+                } => {
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    update_mapping(paths, &rc_path, &domain, |m| {
+                        m.mirror = match port {
+                            Some(port) if !off => {
+                                let mirror = crate::serve::mirror::Mirror {
+                                    port,
+                                    compare,
+                                    latency_threshold_ms,
+                                };
+                                mirror.validate()?;
+                                Some(mirror)
+                            }
+                            _ => None,
+                        };
+                        Ok(())
+                    })?;
+                    match port.filter(|_| !off) {
+                        Some(port) => println!(
+                            "Mirroring {domain} to localhost:{port}{}",
+                            if compare { " (comparing responses)" } else { "" }
+                        ),
+                        None => println!("Stopped mirroring {domain}"),
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::ForwardAuth {
+                    domain,
+                    url,
+                    headers,
+                    off,
+                    global,
+                } => {
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    update_mapping(paths, &rc_path, &domain, |m| {
+                        m.forward_auth = match url.clone().filter(|_| !off) {
+                            Some(url) => {
+                                let auth = crate::serve::forward_auth::ForwardAuth {
+                                    url,
+                                    response_headers: headers.clone(),
+                                };
+                                auth.validate()?;
+                                Some(auth)
+                            }
+                            None => None,
+                        };
+                        Ok(())
+                    })?;
+                    match url.filter(|_| !off) {
+                        Some(url) => println!("{domain} requests are checked by {url}"),
+                        None => println!("Removed forward auth for {domain}"),
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::Upstream {
+                    domain,
+                    ports,
+                    alternates,
+                    remove_alternates,
+                    off,
+                    global,
+                } => {
+                    use crate::serve::upstream::ENDPOINT;
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    let mut upstream = None;
+                    update_mapping(paths, &rc_path, &domain, |m| {
+                        if off {
+                            m.upstream = None;
+                            return Ok(());
+                        }
+                        let u = m.upstream.get_or_insert_with(Default::default);
+                        if !ports.is_empty() {
+                            u.ports = ports.clone();
+                        }
+                        u.alternates.extend(alternates.iter().cloned());
+                        for name in &remove_alternates {
+                            u.alternates.remove(name);
+                        }
+                        if u.ports.is_empty() && u.alternates.is_empty() {
+                            m.upstream = None;
+                        } else {
+                            u.validate()?;
+                            upstream = Some(u.clone());
+                        }
+                        Ok(())
+                    })?;
+                    match upstream {
+                        Some(u) => {
+                            for (name, port) in &u.alternates {
+                                println!("{domain}: alternate {name} -> localhost:{port}");
+                            }
+                            if !u.ports.is_empty() {
+                                println!("{domain}: allowed ports {}", u.ports.join(", "));
+                            }
+                            println!("Select one at https://{domain}{ENDPOINT}");
+                        }
+                        None => println!("Removed upstream override for {domain}"),
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::Cache {
+                    domain,
+                    max_bytes,
+                    max_entry_bytes,
+                    disk,
+                    off,
+                    global,
+                } => {
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    update_mapping(paths, &rc_path, &domain, |m| {
+                        m.cache = if off {
+                            None
+                        } else {
+                            let settings = crate::serve::cache::CacheSettings {
+                                max_bytes,
+                                max_entry_bytes,
+                                disk,
+                            };
+                            settings.validate()?;
+                            Some(settings)
+                        };
+                        Ok(())
+                    })?;
+                    if off {
+                        println!("Stopped caching {domain}");
+                    } else {
+                        println!(
+                            "Caching {domain} responses{}",
+                            if disk { " (in memory and on disk)" } else { " (in memory)" }
+                        );
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::PortPassthrough {
+                    domain,
+                    off,
+                    global,
+                } => {
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    update_mapping(paths, &rc_path, &domain, |m| {
+                        m.port_passthrough = !off;
+                        Ok(())
+                    })?;
+                    if off {
+                        println!("{domain} routes only to its mapped port");
+                    } else {
+                        println!("{domain} forwards to the port in the Host header");
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::DefaultBackend { port, off, global } => {
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    let mut serve_cfg = ServeConfig::load(&rc_path)?;
+                    serve_cfg.default_backend = if off { None } else { port };
+                    serve_cfg.save(&rc_path)?;
+                    if crate::serve::daemon::daemon_status(paths)?.is_some() {
+                        let _ = crate::serve::daemon::reload_daemon(paths);
+                    }
+                    match serve_cfg.default_backend {
+                        Some(port) => println!("Unmapped hosts -> localhost:{port}"),
+                        None => println!("Unmapped hosts get the landing page"),
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::DefaultCert {
+                    domain,
+                    off,
+                    global,
+                } => {
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    let domain = domain.filter(|_| !off).map(|d| d.to_lowercase());
+                    if let Some(domain) = &domain {
+                        // Auto-add domain if not registered
+                        let mut config = store::load_config(paths)?;
+                        if !config.domains.contains_key(domain) {
+                            crate::domain::validate_domain(domain, false)?;
+                            let editor = crate::platform::default_hosts_editor();
+                            crate::domain::add_domain(
+                                paths,
+                                &mut config,
+                                domain,
+                                false,
+                                Some(editor.as_ref()),
+                            )?;
+                            store::save_config(paths, &config)?;
+                        }
+                    }
+                    let mut serve_cfg = ServeConfig::load(&rc_path)?;
+                    serve_cfg.default_cert = domain;
+                    serve_cfg.save(&rc_path)?;
+                    if crate::serve::daemon::daemon_status(paths)?.is_some() {
+                        let _ = crate::serve::daemon::reload_daemon(paths);
+                    }
+                    match &serve_cfg.default_cert {
+                        Some(domain) => println!("Clients without SNI get the cert for {domain}"),
+                        None => println!("Handshakes without SNI fail"),
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::Badssl { off, global } => {
+                    use crate::serve::badssl;
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    let mut serve_cfg = ServeConfig::load(&rc_path)?;
+                    let editor = crate::platform::default_hosts_editor();
+                    if off {
+                        for host in badssl::hosts() {
+                            crate::hosts::remove_domain_from_hosts(editor.as_ref(), &host)?;
+                        }
+                        badssl::remove_certs(paths);
+                    } else {
+                        badssl::ensure_certs(paths)?;
+                        for host in badssl::hosts() {
+                            crate::hosts::add_domain_to_hosts(editor.as_ref(), &host)?;
+                        }
+                    }
+                    serve_cfg.badssl = !off;
+                    serve_cfg.save(&rc_path)?;
+                    if crate::serve::daemon::daemon_status(paths)?.is_some() {
+                        let _ = crate::serve::daemon::reload_daemon(paths);
+                    }
+                    if off {
+                        println!("Disabled badssl endpoints");
+                    } else {
+                        println!("Serving badssl endpoints (index: https://{}/):", badssl::BASE_DOMAIN);
+                        for endpoint in badssl::ENDPOINTS {
+                            println!("  {}\t{}", endpoint.host(), endpoint.description);
+                        }
+                    }
+                    Ok(())
+                }
+                ServeConfigCmd::Tracing {
+                    otlp,
+                    no_otlp,
+                    service_name,
+                    propagate,
+                    global,
+                } => {
+                    let rc_path = serve_config_path(paths, &cwd, global)?;
+                    let mut serve_cfg = ServeConfig::load(&rc_path)?;
+                    let changed =
+                        otlp.is_some() || no_otlp || service_name.is_some() || propagate.is_some();
+                    if changed {
+                        let tracing = &mut serve_cfg.tracing;
+                        if no_otlp {
+                            tracing.otlp_endpoint = None;
+                        }
+                        if otlp.is_some() {
+                            tracing.otlp_endpoint = otlp;
+                        }
+                        if service_name.is_some() {
+                            tracing.service_name = service_name;
+                        }
+                        if propagate.is_some() {
+                            tracing.propagate = propagate;
+                        }
+                        tracing.traces_url()?;
+                        serve_cfg.save(&rc_path)?;
+                        if crate::serve::daemon::daemon_status(paths)?.is_some() {
+                            let _ = crate::serve::daemon::reload_daemon(paths);
+                        }
+                    }
+                    let tracing = &serve_cfg.tracing;
+                    println!(
+                        "Propagate traceparent and X-Request-Id: {}",
+                        if tracing.propagate() { "yes" } else { "no" }
+                    );
+                    match tracing.traces_url()? {
+                        Some(url) => println!(
+                            "Exporting spans to {url} as service {}",
+                            tracing.service_name()
+                        ),
+                        None => println!("Span export: off"),
+                    }
+                    Ok(())
+                }
             }
         }
+        Some(ServeCmd::Chaos { cmd }) => cmd_serve_chaos(paths, cmd),
+        Some(ServeCmd::Cache { cmd }) => cmd_serve_cache(paths, cmd),
+        Some(ServeCmd::Certs { cmd }) => cmd_serve_certs(paths, cmd),
         Some(ServeCmd::Daemon { cmd }) => match cmd {
             ServeDaemonCmd::Start => {
                 crate::serve::daemon::start_daemon(paths)?;
@@ -518,3 +1748,89 @@ fn cmd_serve(paths: &RoostPaths, cmd: Option<ServeCmd>) -> Result<()> {
         },
     }
 }
+
+fn cmd_serve_cache(paths: &RoostPaths, cmd: ServeCacheCmd) -> Result<()> {
+    use crate::serve::control::{send_control, ControlRequest};
+
+    match cmd {
+        ServeCacheCmd::Purge { domain, prefix } => {
+            let request = ControlRequest::CachePurge {
+                domain: domain.clone(),
+                path_prefix: prefix.clone(),
+            };
+            let data = send_control(paths, request)?;
+            let purged = data["purged"].as_u64().unwrap_or_default();
+            let scope = domain.unwrap_or_else(|| "all mappings".to_string());
+            let under = prefix.map(|p| format!(" under {p}")).unwrap_or_default();
+            println!("Purged {purged} cached responses{under} for {scope}");
+            Ok(())
+        }
+    }
+}
+
+fn cmd_serve_certs(paths: &RoostPaths, cmd: ServeCertsCmd) -> Result<()> {
+    use crate::serve::control::{send_control, ControlRequest};
+
+    match cmd {
+        ServeCertsCmd::Reload { domain } => {
+            let data = send_control(paths, ControlRequest::CertReload { domain })?;
+            for domain in data["reloaded"].as_array().into_iter().flatten() {
+                println!("Reloaded cert for {}", domain.as_str().unwrap_or_default());
+            }
+            Ok(())
+        }
+    }
+}
+
+fn cmd_serve_chaos(paths: &RoostPaths, cmd: ServeChaosCmd) -> Result<()> {
+    use crate::serve::chaos::Chaos;
+    use crate::serve::control::{send_control, ControlRequest};
+
+    match cmd {
+        ServeChaosCmd::Set {
+            domain,
+            path_prefix,
+            latency,
+            jitter,
+            bandwidth,
+            error_rate,
+            error_status,
+            drop_rate,
+            truncate_rate,
+        } => {
+            let chaos = Chaos {
+                path_prefix,
+                latency_ms: latency,
+                jitter_ms: jitter,
+                bandwidth_bps: bandwidth,
+                error_rate,
+                error_status,
+                drop_rate,
+                truncate_rate,
+            };
+            chaos.validate()?;
+            send_control(paths, ControlRequest::ChaosSet { domain: domain.clone(), chaos })?;
+            println!("Chaos set for {domain}");
+            Ok(())
+        }
+        ServeChaosCmd::Clear { domain } => {
+            send_control(paths, ControlRequest::ChaosClear { domain: domain.clone() })?;
+            println!("Chaos cleared for {domain}");
+            Ok(())
+        }
+        ServeChaosCmd::List => {
+            let data = send_control(paths, ControlRequest::ChaosList)?;
+            let mut entries = data.as_array().cloned().unwrap_or_default();
+            entries.sort_by_key(|e| e["domain"].as_str().unwrap_or_default().to_string());
+            for e in entries {
+                println!(
+                    "{}\t({})\t{}",
+                    e["domain"].as_str().unwrap_or_default(),
+                    e["source"].as_str().unwrap_or_default(),
+                    e["chaos"]
+                );
+            }
+            Ok(())
+        }
+    }
+}
@@ -59,6 +59,10 @@ pub struct Config {
     pub default_ca: String,
     #[serde(default)]
     pub domains: HashMap<String, String>,
+    /// Base URL of the revocation responder (`roost cert ocsp`); when set, new certs
+    /// carry its CRL and OCSP URLs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revocation_url: Option<String>,
 }
 
 /// Path to config.toml (respects ROOST_HOME).
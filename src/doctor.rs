@@ -58,27 +58,29 @@ pub fn run_checks(paths: &RoostPaths, cwd: &std::path::Path) -> Result<Vec<Check
             crate::serve::config::MappingSource::Global => "global",
         };
 
-        // 2a. Domain in hosts file
-        match crate::hosts::domain_in_hosts(hosts_editor.as_ref(), domain) {
-            Ok(true) => {
-                results.push(CheckResult {
-                    ok: true,
-                    message: format!("[{domain}] ({source}) in hosts file"),
-                });
-            }
-            Ok(false) => {
-                results.push(CheckResult {
-                    ok: false,
-                    message: format!(
-                        "[{domain}] ({source}) not in hosts file. Run 'roost domain add {domain}'."
-                    ),
-                });
-            }
-            Err(e) => {
-                results.push(CheckResult {
-                    ok: false,
-                    message: format!("[{domain}] ({source}) cannot read hosts file: {e}"),
-                });
+        // 2a. Domain in hosts file (IP addresses and localhost resolve without one)
+        if crate::domain::needs_hosts_entry(domain) {
+            match crate::hosts::domain_in_hosts(hosts_editor.as_ref(), domain) {
+                Ok(true) => {
+                    results.push(CheckResult {
+                        ok: true,
+                        message: format!("[{domain}] ({source}) in hosts file"),
+                    });
+                }
+                Ok(false) => {
+                    results.push(CheckResult {
+                        ok: false,
+                        message: format!(
+                            "[{domain}] ({source}) not in hosts file. Run 'roost domain add {domain}'."
+                        ),
+                    });
+                }
+                Err(e) => {
+                    results.push(CheckResult {
+                        ok: false,
+                        message: format!("[{domain}] ({source}) cannot read hosts file: {e}"),
+                    });
+                }
             }
         }
 
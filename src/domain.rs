@@ -1,6 +1,7 @@
 //! Domain validation, add/remove, set-ca.
 
 use anyhow::Result;
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 use crate::cert;
@@ -14,8 +15,15 @@ pub const TLD_ALLOWLIST: &[&str] = &[
     "localdomain", "corp", "private", "docker", "dev",
 ];
 
-/// Validate domain against TLD allowlist.
+/// Validate domain against TLD allowlist. IP addresses are accepted when they are
+/// loopback, private or link-local (any address with `allow_any_tld`).
 pub fn validate_domain(domain: &str, allow_any_tld: bool) -> Result<()> {
+    if let Ok(ip) = domain.parse::<IpAddr>() {
+        if allow_any_tld || is_local_ip(ip) {
+            return Ok(());
+        }
+        anyhow::bail!("{ip} is not a loopback, private or link-local address; use --allow to override");
+    }
     if allow_any_tld {
         return validate_hostname(domain);
     }
@@ -38,9 +46,6 @@ pub fn validate_hostname(domain: &str) -> Result<()> {
     if domain.contains("..") {
         anyhow::bail!("invalid hostname: consecutive dots");
     }
-    if domain == "localhost" {
-        anyhow::bail!("bare localhost not allowed");
-    }
     for label in domain.split('.') {
         if label.is_empty() {
             anyhow::bail!("invalid hostname: empty label");
@@ -57,6 +62,23 @@ pub fn validate_hostname(domain: &str) -> Result<()> {
     Ok(())
 }
 
+/// Whether `ip` is only reachable from this machine or the local network.
+pub fn is_local_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_loopback() || v4.is_private() || v4.is_link_local(),
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            // fc00::/7 unique local, fe80::/10 link-local
+            v6.is_loopback() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// Whether `domain` needs a hosts entry to resolve: IP addresses and `localhost` don't.
+pub fn needs_hosts_entry(domain: &str) -> bool {
+    domain != "localhost" && domain.parse::<IpAddr>().is_err()
+}
+
 /// Add domain to config, create cert, and optionally update hosts.
 pub fn add_domain(
     paths: &RoostPaths,
@@ -78,7 +100,7 @@ pub fn add_domain(
     cert::ensure_cert_valid(paths, domain, &ca_name, exact)?;
 
     // Update hosts before config so we don't leave partial state on failure
-    if let Some(editor) = hosts_editor {
+    if let Some(editor) = hosts_editor.filter(|_| needs_hosts_entry(domain)) {
         hosts::add_domain_to_hosts(editor, domain)?;
     }
 
@@ -96,14 +118,14 @@ pub fn remove_domain(
 ) -> Result<()> {
     config.domains.remove(domain);
 
-    if let Some(editor) = hosts_editor {
+    if let Some(editor) = hosts_editor.filter(|_| needs_hosts_entry(domain)) {
         hosts::remove_domain_from_hosts(editor, domain)?;
     }
 
-    let cert_path = paths.certs_dir.join(format!("{domain}.pem"));
-    let key_path = paths.certs_dir.join(format!("{domain}-key.pem"));
+    let (cert_path, key_path) = cert::domain_cert_paths(paths, domain);
     let _ = std::fs::remove_file(&cert_path);
     let _ = std::fs::remove_file(&key_path);
+    cert::remove_rsa_cert(paths, domain);
 
     Ok(())
 }
@@ -119,9 +141,9 @@ pub fn set_ca(paths: &RoostPaths, config: &mut Config, domain: &str, ca_name: &s
 
     config.domains.insert(domain.to_string(), ca_name.to_string());
     // Always regenerate when CA changes (don't use ensure_cert_valid which skips if cert exists)
-    let (ca_pem, ca_key_pem) = crate::ca::load_ca(paths, ca_name)?;
-    let (cert_pem, key_pem) = cert::generate_domain_cert(domain, &ca_pem, &ca_key_pem, false)?;
+    let (cert_pem, key_pem) = cert::issue_domain_cert(paths, domain, ca_name, false)?;
     cert::save_domain_cert(paths, domain, &cert_pem, &key_pem)?;
+    cert::remove_rsa_cert(paths, domain);
 
     Ok(())
 }
@@ -135,8 +157,5 @@ pub fn list_domains(config: &Config) -> Vec<(String, String)> {
 
 /// Get cert and key paths for domain.
 pub fn get_cert_paths(paths: &RoostPaths, domain: &str) -> (PathBuf, PathBuf) {
-    (
-        paths.certs_dir.join(format!("{domain}.pem")),
-        paths.certs_dir.join(format!("{domain}-key.pem")),
-    )
+    cert::domain_cert_paths(paths, domain)
 }
@@ -1,5 +1,6 @@
 //! Roost - local HTTPS reverse proxy with signed domains.
 
+pub mod acme;
 pub mod ca;
 pub mod cert;
 pub mod cli;
@@ -7,7 +8,9 @@ pub mod doctor;
 pub mod config;
 pub mod domain;
 pub mod hosts;
+pub mod ocsp;
 pub mod platform;
+pub mod revoke;
 pub mod serve;
 pub mod store;
 pub mod trust;
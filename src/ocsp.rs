@@ -0,0 +1,457 @@
+//! Local revocation responder: OCSP (RFC 6960) and CRL download over plain HTTP.
+//!
+//! Responses are signed directly by the CA, so clients need no delegated responder cert.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use http::{Method, Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use ring::digest;
+use ring::signature::{
+    EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, ECDSA_P384_SHA384_ASN1_SIGNING,
+};
+use std::net::{IpAddr, SocketAddr};
+use x509_parser::prelude::FromDer;
+use yasna::models::{GeneralizedTime, ObjectIdentifier};
+use yasna::Tag;
+
+use crate::config::RoostPaths;
+use crate::revoke::{self, RevocationList};
+
+/// Default port for `roost cert ocsp`.
+pub const DEFAULT_PORT: u16 = 8889;
+
+const OID_OCSP_BASIC: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1, 1];
+const OID_OCSP_NONCE: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1, 2];
+/// id-pe-authorityInfoAccess, where leaf certs name the OCSP responder
+const OID_AUTHORITY_INFO_ACCESS: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 1];
+const OID_SHA1: &[u64] = &[1, 3, 14, 3, 2, 26];
+const OID_SHA256: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+const OID_ECDSA_SHA256: &[u64] = &[1, 2, 840, 10045, 4, 3, 2];
+const OID_ECDSA_SHA384: &[u64] = &[1, 2, 840, 10045, 4, 3, 3];
+
+/// OCSPResponseStatus malformedRequest
+const MALFORMED_REQUEST: i64 = 1;
+/// OCSPResponseStatus internalError
+const INTERNAL_ERROR: i64 = 2;
+
+/// A parsed OCSP request: raw CertIDs plus the nonce extension to echo back.
+struct OcspRequest {
+    cert_ids: Vec<Vec<u8>>,
+    nonce: Option<Vec<u8>>,
+}
+
+struct CertId {
+    hash: &'static digest::Algorithm,
+    issuer_name_hash: Vec<u8>,
+    issuer_key_hash: Vec<u8>,
+    serial: String,
+}
+
+enum CertStatus {
+    Good,
+    Revoked(time::OffsetDateTime, revoke::Reason),
+    /// Not issued by this CA.
+    Unknown,
+}
+
+fn oid(components: &[u64]) -> ObjectIdentifier {
+    ObjectIdentifier::from_slice(components)
+}
+
+fn parse_request(der: &[u8]) -> yasna::ASN1Result<OcspRequest> {
+    yasna::parse_der(der, |r| {
+        r.read_sequence(|r| {
+            let request = r.next().read_sequence(|r| {
+                r.read_optional(|r| r.read_tagged(Tag::context(0), |r| r.read_u8()))?;
+                r.read_optional(|r| r.read_tagged(Tag::context(1), |r| r.read_der()))?;
+                let cert_ids = r.next().collect_sequence_of(|r| {
+                    r.read_sequence(|r| {
+                        let cert_id = r.next().read_der()?;
+                        r.read_optional(|r| r.read_tagged(Tag::context(0), |r| r.read_der()))?;
+                        Ok(cert_id)
+                    })
+                })?;
+                let extensions = r.read_optional(|r| {
+                    r.read_tagged(Tag::context(2), |r| r.collect_sequence_of(|r| r.read_der()))
+                })?;
+                let nonce = extensions
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|ext| extension_oid(ext).is_some_and(|o| o == oid(OID_OCSP_NONCE)));
+                Ok(OcspRequest { cert_ids, nonce })
+            })?;
+            // optionalSignature is ignored: anyone may ask about any serial
+            r.read_optional(|r| r.read_tagged(Tag::context(0), |r| r.read_der()))?;
+            Ok(request)
+        })
+    })
+}
+
+fn extension_oid(der: &[u8]) -> Option<ObjectIdentifier> {
+    yasna::parse_der(der, |r| {
+        r.read_sequence(|r| {
+            let oid = r.next().read_oid()?;
+            r.read_optional(|r| r.read_bool())?;
+            r.next().read_bytes()?;
+            Ok(oid)
+        })
+    })
+    .ok()
+}
+
+fn parse_cert_id(der: &[u8]) -> yasna::ASN1Result<Option<CertId>> {
+    yasna::parse_der(der, |r| {
+        r.read_sequence(|r| {
+            let alg = r.next().read_sequence(|r| {
+                let alg = r.next().read_oid()?;
+                r.read_optional(|r| r.read_null())?;
+                Ok(alg)
+            })?;
+            let issuer_name_hash = r.next().read_bytes()?;
+            let issuer_key_hash = r.next().read_bytes()?;
+            let (serial, _) = r.next().read_bigint_bytes()?;
+            let hash = if alg == oid(OID_SHA1) {
+                &digest::SHA1_FOR_LEGACY_USE_ONLY
+            } else if alg == oid(OID_SHA256) {
+                &digest::SHA256
+            } else {
+                return Ok(None);
+            };
+            Ok(Some(CertId {
+                hash,
+                issuer_name_hash,
+                issuer_key_hash,
+                serial: revoke::serial_hex(&serial),
+            }))
+        })
+    })
+}
+
+fn generalized_time(t: time::OffsetDateTime) -> GeneralizedTime {
+    // DER GeneralizedTime has no fractional seconds
+    GeneralizedTime::from_datetime(t.replace_nanosecond(0).unwrap_or(t))
+}
+
+fn simple_response(status: i64) -> Vec<u8> {
+    yasna::construct_der(|w| w.write_sequence(|w| w.next().write_enum(status)))
+}
+
+/// CA material needed to answer OCSP requests.
+struct Issuer {
+    ca_der: Vec<u8>,
+    key_pkcs8: Vec<u8>,
+    revoked: RevocationList,
+}
+
+impl Issuer {
+    fn load(paths: &RoostPaths, ca_name: &str) -> Result<Self> {
+        let (ca_pem, ca_key_pem) = crate::ca::load_ca(paths, ca_name)?;
+        let ca_der = rustls_pemfile::certs(&mut ca_pem.as_slice())
+            .next()
+            .and_then(|r| r.ok())
+            .context("parse CA cert PEM")?
+            .to_vec();
+        let key =
+            rcgen::KeyPair::from_pem(&String::from_utf8(ca_key_pem)?).context("parse CA key")?;
+        Ok(Self {
+            ca_der,
+            key_pkcs8: key.serialize_der(),
+            revoked: revoke::load(paths, ca_name)?,
+        })
+    }
+
+    fn status(&self, id: &CertId) -> Result<CertStatus> {
+        let (_, ca) = x509_parser::prelude::X509Certificate::from_der(&self.ca_der)
+            .map_err(|e| anyhow::anyhow!("parse CA cert: {e:?}"))?;
+        let name_hash = digest::digest(id.hash, ca.subject().as_raw());
+        let key_hash = digest::digest(id.hash, &ca.public_key().subject_public_key.data);
+        if name_hash.as_ref() != id.issuer_name_hash || key_hash.as_ref() != id.issuer_key_hash {
+            return Ok(CertStatus::Unknown);
+        }
+        // Serials are not tracked at issuance, so anything not revoked is good
+        Ok(match self.revoked.find(&id.serial) {
+            Some(r) => CertStatus::Revoked(r.revoked_time()?, r.reason),
+            None => CertStatus::Good,
+        })
+    }
+
+    fn sign(&self, tbs: &[u8]) -> Result<(ObjectIdentifier, Vec<u8>)> {
+        let rng = ring::rand::SystemRandom::new();
+        for (alg, alg_oid) in [
+            (&ECDSA_P256_SHA256_ASN1_SIGNING, OID_ECDSA_SHA256),
+            (&ECDSA_P384_SHA384_ASN1_SIGNING, OID_ECDSA_SHA384),
+        ] {
+            if let Ok(key) = EcdsaKeyPair::from_pkcs8(alg, &self.key_pkcs8, &rng) {
+                let sig = key
+                    .sign(&rng, tbs)
+                    .map_err(|_| anyhow::anyhow!("sign OCSP response"))?;
+                return Ok((oid(alg_oid), sig.as_ref().to_vec()));
+            }
+        }
+        anyhow::bail!("OCSP signing supports ECDSA P-256/P-384 CA keys only")
+    }
+
+    fn responder_key_hash(&self) -> Result<Vec<u8>> {
+        let (_, ca) = x509_parser::prelude::X509Certificate::from_der(&self.ca_der)
+            .map_err(|e| anyhow::anyhow!("parse CA cert: {e:?}"))?;
+        Ok(digest::digest(
+            &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            &ca.public_key().subject_public_key.data,
+        )
+        .as_ref()
+        .to_vec())
+    }
+
+    fn respond(&self, request: &OcspRequest) -> Result<Vec<u8>> {
+        let mut entries = Vec::new();
+        for raw in &request.cert_ids {
+            let status = match parse_cert_id(raw)? {
+                Some(id) => self.status(&id)?,
+                None => CertStatus::Unknown,
+            };
+            entries.push((raw.as_slice(), status));
+        }
+        let now = time::OffsetDateTime::now_utc();
+        let next = now + time::Duration::days(revoke::CRL_VALIDITY_DAYS);
+        let key_hash = self.responder_key_hash()?;
+
+        let tbs = yasna::construct_der(|w| {
+            w.write_sequence(|w| {
+                // responderID byKey
+                w.next()
+                    .write_tagged(Tag::context(2), |w| w.write_bytes(&key_hash));
+                w.next().write_generalized_time(&generalized_time(now));
+                w.next().write_sequence(|w| {
+                    for (cert_id, status) in &entries {
+                        w.next().write_sequence(|w| {
+                            w.next().write_der(cert_id);
+                            match status {
+                                CertStatus::Good => w
+                                    .next()
+                                    .write_tagged_implicit(Tag::context(0), |w| w.write_null()),
+                                CertStatus::Revoked(at, reason) => {
+                                    w.next().write_tagged_implicit(Tag::context(1), |w| {
+                                        w.write_sequence(|w| {
+                                            w.next().write_generalized_time(&generalized_time(*at));
+                                            w.next().write_tagged(Tag::context(0), |w| {
+                                                w.write_enum(reason.code() as i64)
+                                            });
+                                        })
+                                    })
+                                }
+                                CertStatus::Unknown => w
+                                    .next()
+                                    .write_tagged_implicit(Tag::context(2), |w| w.write_null()),
+                            }
+                            w.next().write_generalized_time(&generalized_time(now));
+                            w.next().write_tagged(Tag::context(0), |w| {
+                                w.write_generalized_time(&generalized_time(next))
+                            });
+                        });
+                    }
+                });
+                if let Some(nonce) = &request.nonce {
+                    w.next().write_tagged(Tag::context(1), |w| {
+                        w.write_sequence(|w| w.next().write_der(nonce))
+                    });
+                }
+            })
+        });
+        let (sig_alg, signature) = self.sign(&tbs)?;
+        let basic = yasna::construct_der(|w| {
+            w.write_sequence(|w| {
+                w.next().write_der(&tbs);
+                w.next().write_sequence(|w| w.next().write_oid(&sig_alg));
+                w.next().write_bitvec_bytes(&signature, signature.len() * 8);
+            })
+        });
+        Ok(yasna::construct_der(|w| {
+            w.write_sequence(|w| {
+                w.next().write_enum(0);
+                w.next().write_tagged(Tag::context(0), |w| {
+                    w.write_sequence(|w| {
+                        w.next().write_oid(&oid(OID_OCSP_BASIC));
+                        w.next().write_bytes(&basic);
+                    })
+                });
+            })
+        }))
+    }
+}
+
+/// OCSP response to staple in TLS handshakes for `cert_der`, or `None` when the cert names
+/// no OCSP responder (issued without a revocation URL) or was not issued by `ca_name`.
+/// Generated locally, so stapling works without `roost cert ocsp` running.
+pub fn staple(paths: &RoostPaths, ca_name: &str, cert_der: &[u8]) -> Result<Option<Vec<u8>>> {
+    let (_, leaf) = x509_parser::prelude::X509Certificate::from_der(cert_der)
+        .map_err(|e| anyhow::anyhow!("parse cert: {e:?}"))?;
+    let names_responder = leaf.extensions().iter().any(|ext| {
+        ext.oid
+            .iter()
+            .is_some_and(|o| o.eq(OID_AUTHORITY_INFO_ACCESS.iter().copied()))
+    });
+    if !names_responder {
+        return Ok(None);
+    }
+    let issuer = Issuer::load(paths, ca_name)?;
+    let (_, ca) = x509_parser::prelude::X509Certificate::from_der(&issuer.ca_der)
+        .map_err(|e| anyhow::anyhow!("parse CA cert: {e:?}"))?;
+    if leaf.issuer().as_raw() != ca.subject().as_raw() {
+        return Ok(None);
+    }
+    let hash = &digest::SHA1_FOR_LEGACY_USE_ONLY;
+    let cert_id = yasna::construct_der(|w| {
+        w.write_sequence(|w| {
+            w.next().write_sequence(|w| {
+                w.next().write_oid(&oid(OID_SHA1));
+                w.next().write_null();
+            });
+            w.next()
+                .write_bytes(digest::digest(hash, ca.subject().as_raw()).as_ref());
+            w.next().write_bytes(
+                digest::digest(hash, &ca.public_key().subject_public_key.data).as_ref(),
+            );
+            w.next().write_bigint_bytes(leaf.raw_serial(), true);
+        })
+    });
+    let request = OcspRequest {
+        cert_ids: vec![cert_id],
+        nonce: None,
+    };
+    issuer.respond(&request).map(Some)
+}
+
+/// Answer a DER-encoded OCSP request for `ca_name`. Always returns an OCSPResponse;
+/// unparseable requests get `malformedRequest`.
+pub fn respond(paths: &RoostPaths, ca_name: &str, request_der: &[u8]) -> Vec<u8> {
+    let request = match parse_request(request_der) {
+        Ok(r) if !r.cert_ids.is_empty() => r,
+        _ => return simple_response(MALFORMED_REQUEST),
+    };
+    match Issuer::load(paths, ca_name).and_then(|issuer| issuer.respond(&request)) {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("OCSP response for CA '{ca_name}' failed: {e:#}");
+            simple_response(INTERNAL_ERROR)
+        }
+    }
+}
+
+fn bytes_response(status: StatusCode, content_type: &str, body: Vec<u8>) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, content_type)
+        .header(http::header::CACHE_CONTROL, "no-cache")
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+}
+
+fn not_found(msg: String) -> Response<Full<Bytes>> {
+    bytes_response(
+        StatusCode::NOT_FOUND,
+        "text/plain; charset=utf-8",
+        format!("{msg}\n").into_bytes(),
+    )
+}
+
+/// Route one responder request: `GET /crl/<ca>.crl`, `POST /ocsp/<ca>`, `GET /ocsp/<ca>/<base64>`.
+pub fn handle(paths: &RoostPaths, req: Request<Bytes>) -> Response<Full<Bytes>> {
+    let path = req.uri().path().to_string();
+    let segments: Vec<&str> = path.trim_start_matches('/').splitn(3, '/').collect();
+    let ca_known = |ca: &str| crate::ca::ca_exists(paths, ca);
+    match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["crl", file]) => {
+            let Some(ca) = file.strip_suffix(".crl").filter(|ca| ca_known(ca)) else {
+                return not_found(format!("no CRL at {path}"));
+            };
+            match revoke::generate_crl(paths, ca) {
+                Ok(der) => bytes_response(StatusCode::OK, "application/pkix-crl", der),
+                Err(e) => bytes_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "text/plain; charset=utf-8",
+                    format!("{e:#}\n").into_bytes(),
+                ),
+            }
+        }
+        (&Method::POST, ["ocsp", ca]) if ca_known(ca) => bytes_response(
+            StatusCode::OK,
+            "application/ocsp-response",
+            respond(paths, ca, req.body()),
+        ),
+        (&Method::GET, ["ocsp", ca, encoded]) if ca_known(ca) => {
+            // RFC 6960 Appendix A.1: base64 of the DER request, URL-encoded
+            let encoded = encoded
+                .replace("%2B", "+")
+                .replace("%2F", "/")
+                .replace("%3D", "=");
+            let body = match base64::engine::general_purpose::STANDARD.decode(encoded) {
+                Ok(der) => respond(paths, ca, &der),
+                Err(_) => simple_response(MALFORMED_REQUEST),
+            };
+            bytes_response(StatusCode::OK, "application/ocsp-response", body)
+        }
+        _ => not_found(format!("no responder at {path}")),
+    }
+}
+
+/// Serve CRLs and OCSP for every CA until Ctrl-C.
+pub async fn serve(paths: &RoostPaths, bind: &[IpAddr], port: u16) -> Result<()> {
+    let mut listeners = Vec::new();
+    for ip in bind {
+        let addr = SocketAddr::new(*ip, port);
+        match crate::serve::proxy::bind_listener(addr) {
+            Ok(l) => listeners.push(l),
+            Err(e) => eprintln!("Warning: could not bind {addr}: {e:#}"),
+        }
+    }
+    if listeners.is_empty() {
+        anyhow::bail!("could not bind port {port}");
+    }
+    for listener in listeners {
+        eprintln!(
+            "Revocation responder listening on http://{}",
+            listener.local_addr()?
+        );
+        let paths = paths.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        eprintln!("accept error: {e}");
+                        continue;
+                    }
+                };
+                let paths = paths.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: Request<Incoming>| {
+                        let paths = paths.clone();
+                        async move {
+                            let (parts, body) = req.into_parts();
+                            let body = match Limited::new(body, 64 * 1024).collect().await {
+                                Ok(b) => b.to_bytes(),
+                                Err(_) => {
+                                    return Ok::<_, anyhow::Error>(bytes_response(
+                                        StatusCode::PAYLOAD_TOO_LARGE,
+                                        "text/plain; charset=utf-8",
+                                        b"request too large\n".to_vec(),
+                                    ))
+                                }
+                            };
+                            Ok(handle(&paths, Request::from_parts(parts, body)))
+                        }
+                    });
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+    }
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
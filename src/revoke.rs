@@ -0,0 +1,302 @@
+//! Certificate revocation: per-CA revoked list, CRL generation, and the
+//! CRL distribution point / AIA OCSP extensions embedded in new leaf certs.
+
+use anyhow::{Context, Result};
+use rcgen::{
+    CertificateParams, CertificateRevocationListParams, CrlDistributionPoint, CustomExtension,
+    KeyIdMethod, KeyPair, RevocationReason, RevokedCertParams, SerialNumber,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use x509_parser::prelude::FromDer;
+
+use crate::config::RoostPaths;
+
+/// How long a generated CRL (and OCSP response) stays valid.
+pub const CRL_VALIDITY_DAYS: i64 = 7;
+
+/// id-pe-authorityInfoAccess
+const OID_AUTHORITY_INFO_ACCESS: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 1];
+/// id-ad-ocsp
+const OID_AD_OCSP: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1];
+
+/// CRL reason codes (RFC 5280 §5.3.1) accepted by `roost cert revoke`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    Unspecified,
+    KeyCompromise,
+    CaCompromise,
+    AffiliationChanged,
+    Superseded,
+    CessationOfOperation,
+}
+
+impl Reason {
+    pub fn code(self) -> RevocationReason {
+        match self {
+            Reason::Unspecified => RevocationReason::Unspecified,
+            Reason::KeyCompromise => RevocationReason::KeyCompromise,
+            Reason::CaCompromise => RevocationReason::CaCompromise,
+            Reason::AffiliationChanged => RevocationReason::AffiliationChanged,
+            Reason::Superseded => RevocationReason::Superseded,
+            Reason::CessationOfOperation => RevocationReason::CessationOfOperation,
+        }
+    }
+}
+
+/// One revoked certificate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Revoked {
+    /// Lowercase hex, no leading zero bytes (see `normalize_serial`).
+    pub serial: String,
+    /// RFC 3339 timestamp.
+    pub revoked_at: String,
+    pub reason: Reason,
+    /// Domain the cert was issued for, when revoked by domain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+}
+
+/// Revocation state of a CA, stored as `ca/<name>/revoked.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RevocationList {
+    /// Number of the last CRL written; increases with every revocation.
+    #[serde(default)]
+    pub crl_number: u64,
+    #[serde(default)]
+    pub revoked: Vec<Revoked>,
+}
+
+impl Revoked {
+    pub fn revoked_time(&self) -> Result<time::OffsetDateTime> {
+        let at = chrono::DateTime::parse_from_rfc3339(&self.revoked_at)
+            .with_context(|| format!("invalid revocation time for serial {}", self.serial))?;
+        Ok(time::OffsetDateTime::from_unix_timestamp(at.timestamp())?)
+    }
+}
+
+impl RevocationList {
+    pub fn find(&self, serial: &str) -> Option<&Revoked> {
+        self.revoked.iter().find(|r| r.serial == serial)
+    }
+}
+
+fn revoked_path(paths: &RoostPaths, ca_name: &str) -> PathBuf {
+    paths.ca_dir.join(ca_name).join("revoked.json")
+}
+
+/// Path of the CA's PEM-encoded CRL (rewritten on every revocation).
+pub fn crl_path(paths: &RoostPaths, ca_name: &str) -> PathBuf {
+    paths.ca_dir.join(ca_name).join("crl.pem")
+}
+
+/// Load the CA's revocation list (empty if nothing was revoked yet).
+pub fn load(paths: &RoostPaths, ca_name: &str) -> Result<RevocationList> {
+    let path = revoked_path(paths, ca_name);
+    if !path.is_file() {
+        return Ok(RevocationList::default());
+    }
+    let s = fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+    serde_json::from_str(&s).with_context(|| format!("parse {}", path.display()))
+}
+
+fn save(paths: &RoostPaths, ca_name: &str, list: &RevocationList) -> Result<()> {
+    let path = revoked_path(paths, ca_name);
+    fs::write(&path, serde_json::to_string_pretty(list)?)
+        .with_context(|| format!("write {}", path.display()))
+}
+
+/// Normalize a serial given as hex (optionally `0x`-prefixed or colon-separated).
+pub fn normalize_serial(serial: &str) -> Result<String> {
+    let hex: String = serial
+        .trim()
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| *c != ':')
+        .collect::<String>()
+        .to_lowercase();
+    if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("invalid serial '{serial}': expected hex digits");
+    }
+    let hex = hex.trim_start_matches('0');
+    // Keep whole bytes so the serial round-trips through DER
+    Ok(match hex.len() {
+        0 => "00".to_string(),
+        n if n % 2 == 1 => format!("0{hex}"),
+        _ => hex.to_string(),
+    })
+}
+
+/// Serial bytes to the normalized hex form.
+pub fn serial_hex(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    normalize_serial(&hex).unwrap_or(hex)
+}
+
+fn serial_bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .filter_map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Serial of the first certificate in a PEM file.
+pub fn cert_serial(cert_pem: &[u8]) -> Result<String> {
+    let der = rustls_pemfile::certs(&mut &cert_pem[..])
+        .next()
+        .and_then(|r| r.ok())
+        .context("parse cert PEM")?;
+    let (_, cert) = x509_parser::prelude::X509Certificate::from_der(der.as_ref())
+        .map_err(|e| anyhow::anyhow!("parse X.509: {e:?}"))?;
+    Ok(serial_hex(cert.raw_serial()))
+}
+
+/// Revoke `serial` under `ca_name` and rewrite the CA's CRL.
+/// Returns false if the serial was already revoked.
+pub fn revoke(
+    paths: &RoostPaths,
+    ca_name: &str,
+    serial: &str,
+    reason: Reason,
+    domain: Option<&str>,
+) -> Result<bool> {
+    if !crate::ca::ca_exists(paths, ca_name) {
+        anyhow::bail!("CA '{ca_name}' does not exist");
+    }
+    let serial = normalize_serial(serial)?;
+    let mut list = load(paths, ca_name)?;
+    if list.find(&serial).is_some() {
+        return Ok(false);
+    }
+    list.revoked.push(Revoked {
+        serial,
+        revoked_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        reason,
+        domain: domain.map(String::from),
+    });
+    list.crl_number += 1;
+    save(paths, ca_name, &list)?;
+    write_crl(paths, ca_name)?;
+    Ok(true)
+}
+
+/// Build a signed CRL (DER) for the CA from its current revocation list.
+pub fn generate_crl(paths: &RoostPaths, ca_name: &str) -> Result<Vec<u8>> {
+    let list = load(paths, ca_name)?;
+    let (ca_pem, ca_key_pem) = crate::ca::load_ca(paths, ca_name)?;
+    let ca_str = String::from_utf8(ca_pem)?;
+    let issuer_params = CertificateParams::from_ca_cert_pem(&ca_str).context("parse CA cert")?;
+    let issuer_key = KeyPair::from_pem(&String::from_utf8(ca_key_pem)?).context("parse CA key")?;
+    let issuer_cert = issuer_params
+        .self_signed(&issuer_key)
+        .context("reconstruct issuer cert")?;
+
+    let mut revoked_certs = Vec::new();
+    for r in &list.revoked {
+        revoked_certs.push(RevokedCertParams {
+            serial_number: SerialNumber::from_slice(&serial_bytes(&r.serial)),
+            revocation_time: r.revoked_time()?,
+            reason_code: Some(r.reason.code()),
+            invalidity_date: None,
+        });
+    }
+    let now = time::OffsetDateTime::now_utc();
+    let crl = CertificateRevocationListParams {
+        this_update: now,
+        next_update: now + time::Duration::days(CRL_VALIDITY_DAYS),
+        crl_number: SerialNumber::from(list.crl_number.max(1)),
+        issuing_distribution_point: None,
+        revoked_certs,
+        key_identifier_method: KeyIdMethod::Sha256,
+    }
+    .signed_by(&issuer_cert, &issuer_key)
+    .context("sign CRL")?;
+    Ok(crl.der().to_vec())
+}
+
+/// Regenerate the CA's CRL file and return its path.
+pub fn write_crl(paths: &RoostPaths, ca_name: &str) -> Result<PathBuf> {
+    let der = generate_crl(paths, ca_name)?;
+    let path = crl_path(paths, ca_name);
+    let pem = pem_encode("X509 CRL", &der);
+    fs::write(&path, pem).with_context(|| format!("write {}", path.display()))?;
+    Ok(path)
+}
+
+fn pem_encode(label: &str, der: &[u8]) -> String {
+    use base64::Engine;
+    let b64 = base64::engine::general_purpose::STANDARD.encode(der);
+    let mut out = format!("-----BEGIN {label}-----\n");
+    for chunk in b64.as_bytes().chunks(64) {
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {label}-----\n"));
+    out
+}
+
+/// CRL URL for `ca_name` under the responder base URL.
+pub fn crl_url(base_url: &str, ca_name: &str) -> String {
+    format!("{}/crl/{ca_name}.crl", base_url.trim_end_matches('/'))
+}
+
+/// OCSP URL for `ca_name` under the responder base URL.
+pub fn ocsp_url(base_url: &str, ca_name: &str) -> String {
+    format!("{}/ocsp/{ca_name}", base_url.trim_end_matches('/'))
+}
+
+/// Point a leaf cert at the responder: CRL distribution point and AIA OCSP URL.
+pub fn add_revocation_urls(params: &mut CertificateParams, base_url: &str, ca_name: &str) {
+    params.crl_distribution_points = vec![CrlDistributionPoint {
+        uris: vec![crl_url(base_url, ca_name)],
+    }];
+    let ocsp = ocsp_url(base_url, ca_name);
+    // AuthorityInfoAccessSyntax ::= SEQUENCE OF AccessDescription
+    let aia = yasna::construct_der(|w| {
+        w.write_sequence(|w| {
+            w.next().write_sequence(|w| {
+                w.next()
+                    .write_oid(&yasna::models::ObjectIdentifier::from_slice(OID_AD_OCSP));
+                // GeneralName uniformResourceIdentifier [6] IMPLICIT IA5String
+                w.next()
+                    .write_tagged_implicit(yasna::Tag::context(6), |w| w.write_ia5_string(&ocsp));
+            });
+        });
+    });
+    params
+        .custom_extensions
+        .push(CustomExtension::from_oid_content(
+            OID_AUTHORITY_INFO_ACCESS,
+            aia,
+        ));
+}
+
+/// Revoke the current cert of a registered domain. With `reissue`, a fresh cert (new key)
+/// replaces it, keeping the wildcard SAN if the old cert had one. Returns the revoked serial.
+pub fn revoke_domain(
+    paths: &RoostPaths,
+    domain: &str,
+    ca_name: &str,
+    reason: Reason,
+    reissue: bool,
+) -> Result<String> {
+    let (cert_pem, _) = crate::cert::load_domain_cert(paths, domain)?;
+    let serial = cert_serial(&cert_pem)?;
+    revoke(paths, ca_name, &serial, reason, Some(domain))?;
+    // The RSA cert (see `cert::ensure_rsa_cert_valid`) shares the domain; revoke it too and
+    // let the proxy issue a new one.
+    let (rsa_path, _) = crate::cert::rsa_cert_paths(paths, domain);
+    if let Ok(rsa_pem) = fs::read(&rsa_path) {
+        let rsa_serial = cert_serial(&rsa_pem)?;
+        revoke(paths, ca_name, &rsa_serial, reason, Some(domain))?;
+        crate::cert::remove_rsa_cert(paths, domain);
+    }
+    if reissue {
+        let exact = !crate::cert::has_wildcard_san(&cert_pem, domain)?;
+        let (cert_pem, key_pem) = crate::cert::issue_domain_cert(paths, domain, ca_name, exact)?;
+        crate::cert::save_domain_cert(paths, domain, &cert_pem, &key_pem)?;
+    }
+    Ok(serial)
+}
@@ -0,0 +1,200 @@
+//! Per-mapping access control: client IP allowlists and HTTP basic auth.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use ipnet::IpNet;
+use ring::hmac;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::serve::config::Mapping;
+
+/// Outcome of an access check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessDecision {
+    Allow,
+    /// Client address not in the allowlist (403).
+    Forbidden,
+    /// Missing or wrong credentials (401 with the given realm).
+    Unauthorized {
+        realm: String,
+    },
+}
+
+/// Verified `Authorization` headers remembered per mapping, least recently used dropped first.
+const VERIFIED_CAPACITY: usize = 256;
+/// How long a verified header is accepted before its password is checked again.
+const VERIFIED_TTL: Duration = Duration::from_secs(10 * 60);
+/// Checked for unknown users so they take as long to reject as a wrong password.
+const DUMMY_HASH: &str = "$2b$12$VU57HLa6iX0BhFS4BCsOH.Db3LiJrX4vst1EttjXtEojcm4p2vR7e";
+
+/// Recently verified `Authorization` headers, by HMAC under a per-process key so the
+/// credentials themselves are never kept.
+#[derive(Debug)]
+struct Verified {
+    key: hmac::Key,
+    /// Tag -> (last use, when verified).
+    entries: HashMap<Vec<u8>, (u64, Instant)>,
+    tick: u64,
+}
+
+impl Verified {
+    fn new() -> Result<Self> {
+        let key = hmac::Key::generate(hmac::HMAC_SHA256, &ring::rand::SystemRandom::new())
+            .map_err(|_| anyhow::anyhow!("generate credential cache key"))?;
+        Ok(Self {
+            key,
+            entries: HashMap::new(),
+            tick: 0,
+        })
+    }
+
+    fn tag(&self, header: &str) -> Vec<u8> {
+        hmac::sign(&self.key, header.as_bytes()).as_ref().to_vec()
+    }
+
+    fn contains(&mut self, tag: &[u8]) -> bool {
+        self.tick += 1;
+        let tick = self.tick;
+        match self.entries.get_mut(tag) {
+            Some((last_used, verified_at)) if verified_at.elapsed() < VERIFIED_TTL => {
+                *last_used = tick;
+                true
+            }
+            Some(_) => {
+                self.entries.remove(tag);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, tag: Vec<u8>) {
+        self.tick += 1;
+        self.entries.insert(tag, (self.tick, Instant::now()));
+        while self.entries.len() > VERIFIED_CAPACITY {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(tag, _)| tag.clone())
+            else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// Compiled access rules for one mapping.
+#[derive(Debug)]
+pub struct AccessPolicy {
+    allow: Vec<IpNet>,
+    realm: String,
+    users: BTreeMap<String, String>,
+    /// Authorization headers already verified; bcrypt is too slow to run per request.
+    verified: Mutex<Verified>,
+}
+
+/// Parse "10.0.0.1", "::1" or "192.168.0.0/16" into a network.
+pub fn parse_allow_entry(s: &str) -> Result<IpNet> {
+    let s = s.trim();
+    if let Ok(net) = s.parse::<IpNet>() {
+        return Ok(net);
+    }
+    let ip: IpAddr = s
+        .parse()
+        .with_context(|| format!("invalid IP or CIDR in allow list: {s:?}"))?;
+    Ok(IpNet::from(ip))
+}
+
+/// Hash a password for storage in `.roostrc`.
+pub fn hash_password(password: &str) -> Result<String> {
+    bcrypt::hash(password, bcrypt::DEFAULT_COST).context("hash password")
+}
+
+impl AccessPolicy {
+    /// Build policy from mapping; None when the mapping has no restrictions.
+    pub fn from_mapping(mapping: &Mapping) -> Result<Option<Self>> {
+        let allow = mapping
+            .allow
+            .iter()
+            .map(|s| parse_allow_entry(s))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("mapping {}", mapping.domain))?;
+        let (realm, users) = match &mapping.basic_auth {
+            Some(auth) if !auth.users.is_empty() => (
+                auth.realm.clone().unwrap_or_else(|| mapping.domain.clone()),
+                auth.users.clone(),
+            ),
+            _ => (mapping.domain.clone(), BTreeMap::new()),
+        };
+        if allow.is_empty() && users.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            allow,
+            realm,
+            users,
+            verified: Mutex::new(Verified::new()?),
+        }))
+    }
+
+    /// Check client address and `Authorization` header value.
+    /// Address is checked first so blocked clients never get an auth prompt.
+    pub fn check(&self, peer: IpAddr, authorization: Option<&str>) -> AccessDecision {
+        let peer = peer.to_canonical();
+        if !self.allow.is_empty() && !self.allow.iter().any(|net| net.contains(&peer)) {
+            return AccessDecision::Forbidden;
+        }
+        if self.users.is_empty() || self.credentials_valid(authorization) {
+            AccessDecision::Allow
+        } else {
+            AccessDecision::Unauthorized {
+                realm: self.realm.clone(),
+            }
+        }
+    }
+
+    fn credentials_valid(&self, authorization: Option<&str>) -> bool {
+        let Some(header) = authorization.map(str::trim) else {
+            return false;
+        };
+        let tag = {
+            let mut verified = self.verified.lock().unwrap();
+            let tag = verified.tag(header);
+            if verified.contains(&tag) {
+                return true;
+            }
+            tag
+        };
+        let Some((scheme, encoded)) = header.split_once(' ') else {
+            return false;
+        };
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return false;
+        }
+        let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(encoded.trim()) else {
+            return false;
+        };
+        let Ok(decoded) = String::from_utf8(decoded) else {
+            return false;
+        };
+        let Some((user, password)) = decoded.split_once(':') else {
+            return false;
+        };
+        let ok = match self.users.get(user) {
+            Some(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            None => {
+                let _ = bcrypt::verify(password, DUMMY_HASH);
+                false
+            }
+        };
+        if ok {
+            self.verified.lock().unwrap().insert(tag);
+        }
+        ok
+    }
+}
@@ -0,0 +1,151 @@
+//! Deliberately broken TLS test endpoints under `badssl.test`, like badssl.com but local.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::cert::{self, BrokenCert};
+use crate::config::RoostPaths;
+
+/// Parent domain of the endpoints; it serves an index page with a valid cert.
+pub const BASE_DOMAIN: &str = "badssl.test";
+
+/// A test endpoint: `<name>.badssl.test`, served with a broken cert.
+#[derive(Debug, Clone, Copy)]
+pub struct Endpoint {
+    pub name: &'static str,
+    pub cert: BrokenCert,
+    pub description: &'static str,
+}
+
+impl Endpoint {
+    pub fn host(&self) -> String {
+        format!("{}.{BASE_DOMAIN}", self.name)
+    }
+}
+
+pub const ENDPOINTS: &[Endpoint] = &[
+    Endpoint {
+        name: "expired",
+        cert: BrokenCert::Expired,
+        description: "Signed by your roost CA, but expired yesterday.",
+    },
+    Endpoint {
+        name: "wrong-host",
+        cert: BrokenCert::WrongHost,
+        description: "Signed by your roost CA for wrong-host.invalid only.",
+    },
+    Endpoint {
+        name: "self-signed",
+        cert: BrokenCert::SelfSigned,
+        description: "Self-signed leaf certificate.",
+    },
+    Endpoint {
+        name: "untrusted-root",
+        cert: BrokenCert::UntrustedRoot,
+        description: "Signed by a root CA that is in no trust store.",
+    },
+    Endpoint {
+        name: "incomplete-chain",
+        cert: BrokenCert::IncompleteChain,
+        description: "Signed by an intermediate of your roost CA that the server does not send.",
+    },
+    Endpoint {
+        name: "revoked",
+        cert: BrokenCert::Revoked,
+        description: "Signed by your roost CA and revoked (see its CRL and OCSP responder).",
+    },
+];
+
+/// All host names served in badssl mode: the index and each endpoint.
+pub fn hosts() -> Vec<String> {
+    std::iter::once(BASE_DOMAIN.to_string())
+        .chain(ENDPOINTS.iter().map(Endpoint::host))
+        .collect()
+}
+
+/// Cert and key paths for a badssl host: `certs/badssl/<host>.pem` and `-key.pem`.
+pub fn cert_paths(paths: &RoostPaths, host: &str) -> (PathBuf, PathBuf) {
+    let dir = paths.certs_dir.join("badssl");
+    (
+        dir.join(format!("{host}.pem")),
+        dir.join(format!("{host}-key.pem")),
+    )
+}
+
+/// Issue missing badssl certs from the default CA. Existing certs are kept, so the
+/// revoked cert is only revoked once.
+pub fn ensure_certs(paths: &RoostPaths) -> Result<()> {
+    let config = crate::store::load_config(paths)?;
+    let ca_name = if config.default_ca.is_empty() {
+        "default"
+    } else {
+        config.default_ca.as_str()
+    };
+    if !crate::ca::ca_exists(paths, ca_name) {
+        anyhow::bail!("CA '{ca_name}' does not exist; run 'roost ca create {ca_name}' first");
+    }
+    let missing = |host: &str| {
+        let (cert_path, key_path) = cert_paths(paths, host);
+        !cert_path.is_file() || !key_path.is_file()
+    };
+    let write = |host: &str, (cert_pem, key_pem): (Vec<u8>, Vec<u8>)| -> Result<()> {
+        let (cert_path, key_path) = cert_paths(paths, host);
+        if let Some(dir) = cert_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&cert_path, cert_pem)
+            .with_context(|| format!("write {}", cert_path.display()))?;
+        fs::write(&key_path, key_pem).with_context(|| format!("write {}", key_path.display()))
+    };
+
+    if missing(BASE_DOMAIN) {
+        write(
+            BASE_DOMAIN,
+            cert::issue_domain_cert(paths, BASE_DOMAIN, ca_name, true)?,
+        )?;
+    }
+    for endpoint in ENDPOINTS {
+        let host = endpoint.host();
+        if missing(&host) {
+            let pems = cert::issue_broken_cert(paths, &host, ca_name, endpoint.cert)
+                .with_context(|| format!("issue cert for {host}"))?;
+            write(&host, pems)?;
+        }
+    }
+    Ok(())
+}
+
+/// Delete the badssl certs; they are reissued the next time badssl mode is enabled.
+pub fn remove_certs(paths: &RoostPaths) {
+    let _ = fs::remove_dir_all(paths.certs_dir.join("badssl"));
+}
+
+/// HTML page for a badssl host, or None if `host` is not one.
+pub fn page(host: &str) -> Option<String> {
+    if host == BASE_DOMAIN {
+        let items: String = ENDPOINTS
+            .iter()
+            .map(|e| {
+                format!(
+                    "<li><a href=\"https://{host}/\">{host}</a>: {}</li>\n",
+                    e.description,
+                    host = e.host()
+                )
+            })
+            .collect();
+        return Some(format!(
+            "<!doctype html>\n<title>{BASE_DOMAIN}</title>\n<h1>{BASE_DOMAIN}</h1>\n\
+             <p>Each endpoint below is served with a deliberately broken certificate. \
+             A client that validates certificates correctly must refuse to load it.</p>\n\
+             <ul>\n{items}</ul>\n"
+        ));
+    }
+    let endpoint = ENDPOINTS.iter().find(|e| e.host() == host)?;
+    Some(format!(
+        "<!doctype html>\n<title>{host}</title>\n<h1>{host}</h1>\n<p>{}</p>\n\
+         <p>If you can read this over HTTPS, your client accepted a broken certificate.</p>\n\
+         <p><a href=\"https://{BASE_DOMAIN}/\">All endpoints</a></p>\n",
+        endpoint.description
+    ))
+}
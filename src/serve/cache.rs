@@ -0,0 +1,746 @@
+//! Per-mapping HTTP response cache that behaves like a shared cache (a CDN) under
+//! RFC 9111: freshness from `s-maxage`, `max-age` and `Expires`, `no-store`/`private`/
+//! `no-cache`, `Vary`, revalidation with `ETag`/`Last-Modified`, and invalidation by
+//! unsafe methods. Responses get `X-Cache: HIT`, `MISS`, `REVALIDATED` or `BYPASS`.
+
+use anyhow::{Context, Result};
+use http::header::{
+    AGE, AUTHORIZATION, CACHE_CONTROL, CONNECTION, CONTENT_LENGTH, DATE, ETAG, EXPIRES,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, PRAGMA, SET_COOKIE, TRANSFER_ENCODING, VARY,
+};
+use http::response::Parts;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::RoostPaths;
+use crate::serve::proxy::{BoxError, ProxyBody};
+
+/// Memory budget per mapping when `max_bytes` is unset.
+pub const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Largest response stored when `max_entry_bytes` is unset.
+pub const DEFAULT_MAX_ENTRY_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Response header telling whether the cache answered.
+pub const X_CACHE: &str = "x-cache";
+
+/// Statuses stored without explicit freshness (RFC 9110 §15.1); here only when they
+/// carry a validator, so they are revalidated on every use.
+const CACHEABLE_BY_DEFAULT: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// Directory holding a mapping's on-disk cache.
+pub fn dir(paths: &RoostPaths, domain: &str) -> PathBuf {
+    paths.config_dir.join("cache").join(domain.to_lowercase())
+}
+
+/// Cache settings for a mapping (`[serve.mappings.cache]`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheSettings {
+    /// Total size of stored responses (default 64 MiB); least recently used go first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    /// Larger responses are not stored (default 8 MiB, or `max_bytes` if smaller).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_entry_bytes: Option<u64>,
+    /// Also keep responses under `cache/<domain>/` in the data directory, so they
+    /// survive restarts.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disk: bool,
+}
+
+impl CacheSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_bytes == Some(0) || self.max_entry_bytes == Some(0) {
+            anyhow::bail!("cache sizes must be greater than 0");
+        }
+        if self.max_entry_bytes.is_some_and(|max| max > self.max_bytes()) {
+            anyhow::bail!("max_entry_bytes must not exceed max_bytes");
+        }
+        Ok(())
+    }
+
+    fn max_bytes(&self) -> u64 {
+        self.max_bytes.unwrap_or(DEFAULT_MAX_BYTES)
+    }
+
+    fn max_entry_bytes(&self) -> u64 {
+        let max = self.max_entry_bytes.unwrap_or(DEFAULT_MAX_ENTRY_BYTES);
+        max.min(self.max_bytes())
+    }
+}
+
+/// `Cache-Control` directives the cache acts on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub public: bool,
+    pub must_revalidate: bool,
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+}
+
+impl CacheControl {
+    pub fn parse(headers: &HeaderMap) -> Self {
+        let mut cc = Self::default();
+        let values = headers
+            .get_all(CACHE_CONTROL)
+            .into_iter()
+            .filter_map(|v| v.to_str().ok());
+        for directive in values.flat_map(|v| v.split(',')) {
+            let (name, arg) = match directive.split_once('=') {
+                Some((name, arg)) => (name, Some(arg.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            // An invalid delta-seconds makes the response stale (RFC 9111 §1.2.2)
+            let seconds = || Some(arg.and_then(|a| a.parse().ok()).unwrap_or(0));
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => cc.no_store = true,
+                "no-cache" => cc.no_cache = true,
+                "private" => cc.private = true,
+                "public" => cc.public = true,
+                "must-revalidate" | "proxy-revalidate" => cc.must_revalidate = true,
+                "max-age" => cc.max_age = seconds(),
+                "s-maxage" => cc.s_maxage = seconds(),
+                _ => {}
+            }
+        }
+        cc
+    }
+}
+
+/// Whether a shared cache may store this response to a GET with `request` headers.
+pub fn storable(request: &HeaderMap, status: StatusCode, headers: &HeaderMap) -> bool {
+    let req_cc = CacheControl::parse(request);
+    let cc = CacheControl::parse(headers);
+    if req_cc.no_store || cc.no_store || cc.private {
+        return false;
+    }
+    if status.is_informational()
+        || status == StatusCode::PARTIAL_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+    {
+        return false;
+    }
+    // Per-user responses; CDNs don't store them either
+    if headers.contains_key(SET_COOKIE) || vary_names(headers).iter().any(|n| n == "*") {
+        return false;
+    }
+    if request.contains_key(AUTHORIZATION)
+        && !(cc.public || cc.s_maxage.is_some() || cc.must_revalidate)
+    {
+        return false;
+    }
+    let explicit = cc.s_maxage.is_some() || cc.max_age.is_some() || headers.contains_key(EXPIRES);
+    let validator = headers.contains_key(ETAG) || headers.contains_key(LAST_MODIFIED);
+    explicit || (CACHEABLE_BY_DEFAULT.contains(&status.as_u16()) && validator)
+}
+
+/// Lowercased header names listed in `Vary`.
+fn vary_names(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(VARY)
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|n| n.trim().to_ascii_lowercase())
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+/// All values of a request header, joined as one.
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<&str> = headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+fn http_date(headers: &HeaderMap, name: HeaderName) -> Option<SystemTime> {
+    let value = headers.get(name)?.to_str().ok()?;
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(SystemTime::from(date))
+}
+
+/// A stored response.
+#[derive(Debug, Clone)]
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: SystemTime,
+    /// Request header values named by the response's `Vary`.
+    vary: Vec<(String, Option<String>)>,
+}
+
+impl Entry {
+    /// Backend `Age` plus time spent in this cache.
+    fn age(&self, now: SystemTime) -> Duration {
+        let initial = self
+            .headers
+            .get(AGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        Duration::from_secs(initial) + now.duration_since(self.stored_at).unwrap_or_default()
+    }
+
+    fn lifetime(&self) -> Duration {
+        let cc = CacheControl::parse(&self.headers);
+        if let Some(secs) = cc.s_maxage.or(cc.max_age) {
+            return Duration::from_secs(secs);
+        }
+        let Some(expires) = http_date(&self.headers, EXPIRES) else {
+            return Duration::ZERO;
+        };
+        let date = http_date(&self.headers, DATE).unwrap_or(self.stored_at);
+        expires.duration_since(date).unwrap_or_default()
+    }
+
+    fn is_fresh(&self, now: SystemTime) -> bool {
+        !CacheControl::parse(&self.headers).no_cache && self.lifetime() > self.age(now)
+    }
+
+    fn has_validator(&self) -> bool {
+        self.headers.contains_key(ETAG) || self.headers.contains_key(LAST_MODIFIED)
+    }
+
+    fn matches(&self, request: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| header_value(request, name) == *value)
+    }
+
+    fn size(&self) -> u64 {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(n, v)| n.as_str().len() + v.len())
+            .sum();
+        (self.body.len() + headers) as u64
+    }
+
+    /// Whether the client's own `If-None-Match` / `If-Modified-Since` matches.
+    fn not_modified(&self, request: &HeaderMap) -> bool {
+        if let Some(inm) = request.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+            let Some(etag) = self.headers.get(ETAG).and_then(|v| v.to_str().ok()) else {
+                return false;
+            };
+            let weak = |t: &str| t.trim().trim_start_matches("W/").to_string();
+            return inm
+                .split(',')
+                .any(|t| t.trim() == "*" || weak(t) == weak(etag));
+        }
+        match (
+            http_date(request, IF_MODIFIED_SINCE),
+            http_date(&self.headers, LAST_MODIFIED),
+        ) {
+            (Some(since), Some(modified)) => modified <= since,
+            _ => false,
+        }
+    }
+
+    fn response(&self, x_cache: &'static str, request: &HeaderMap) -> Response<ProxyBody> {
+        let mut headers = self.headers.clone();
+        headers.insert(AGE, self.age(SystemTime::now()).as_secs().into());
+        headers.insert(X_CACHE, HeaderValue::from_static(x_cache));
+        let (status, body) = if self.not_modified(request) {
+            headers.remove(CONTENT_LENGTH);
+            (StatusCode::NOT_MODIFIED, Bytes::new())
+        } else {
+            if self.status != StatusCode::NO_CONTENT {
+                headers.insert(CONTENT_LENGTH, self.body.len().into());
+            }
+            (self.status, self.body.clone())
+        };
+        let mut response = Response::new(Full::new(body).map_err(|never| match never {}).boxed());
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+        response
+    }
+}
+
+struct Slot {
+    entry: Arc<Entry>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Key -> one slot per `Vary` variant.
+    entries: HashMap<String, Vec<Slot>>,
+    bytes: u64,
+    tick: u64,
+}
+
+/// A mapping's cache.
+pub struct Cache {
+    settings: CacheSettings,
+    dir: Option<PathBuf>,
+    inner: Mutex<Inner>,
+}
+
+impl Cache {
+    /// Create a cache; with `dir`, responses stored there earlier are loaded.
+    pub fn new(settings: CacheSettings, dir: Option<PathBuf>) -> Result<Self> {
+        let cache = Self {
+            settings,
+            dir,
+            inner: Mutex::default(),
+        };
+        if let Some(dir) = &cache.dir {
+            std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+            for (key, entry) in load_dir(dir)? {
+                cache.insert(key, entry, false);
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Cache key for a request to backend `port`; `target` is the path and query.
+    pub fn key(port: u16, target: &str) -> String {
+        format!("{port} {target}")
+    }
+
+    /// Start handling a request: look up a stored response for it.
+    pub fn begin(self: &Arc<Self>, key: String, method: &Method, request: &HeaderMap) -> Exchange {
+        let req_cc = CacheControl::parse(request);
+        let state = if !(method == Method::GET || method == Method::HEAD) || req_cc.no_store {
+            State::Bypass
+        } else {
+            match self.lookup(&key, request) {
+                None => State::Miss,
+                Some(entry) => {
+                    let now = SystemTime::now();
+                    let no_cache = req_cc.no_cache
+                        || (!request.contains_key(CACHE_CONTROL)
+                            && request.get(PRAGMA).is_some_and(|p| p == "no-cache"));
+                    let acceptable = req_cc
+                        .max_age
+                        .is_none_or(|max| entry.age(now) <= Duration::from_secs(max));
+                    if !no_cache && acceptable && entry.is_fresh(now) {
+                        State::Hit(entry)
+                    } else if entry.has_validator() {
+                        State::Revalidate(entry)
+                    } else {
+                        State::Miss
+                    }
+                }
+            }
+        };
+        Exchange {
+            cache: self.clone(),
+            key,
+            method: method.clone(),
+            request: request.clone(),
+            state,
+        }
+    }
+
+    /// Remove stored responses whose path starts with `prefix` (all without one).
+    /// Returns how many were removed.
+    pub fn purge(&self, prefix: Option<&str>) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let keys: Vec<String> = inner
+            .entries
+            .keys()
+            .filter(|key| {
+                let target = key.split_once(' ').map_or(key.as_str(), |(_, t)| t);
+                prefix.is_none_or(|p| target.starts_with(p))
+            })
+            .cloned()
+            .collect();
+        let mut removed = 0;
+        for key in keys {
+            removed += self.remove_key(&mut inner, &key);
+        }
+        removed
+    }
+
+    /// Number of stored responses.
+    pub fn len(&self) -> usize {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .map(Vec::len)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lookup(&self, key: &str, request: &HeaderMap) -> Option<Arc<Entry>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let slot = inner
+            .entries
+            .get_mut(key)?
+            .iter_mut()
+            .find(|s| s.entry.matches(request))?;
+        slot.last_used = tick;
+        Some(slot.entry.clone())
+    }
+
+    fn insert(&self, key: String, entry: Entry, persist: bool) {
+        if entry.body.len() as u64 > self.settings.max_entry_bytes() {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        // A new response replaces the stored one for the same variant
+        let variant = entry.vary.clone();
+        if let Some(slots) = inner.entries.get_mut(&key) {
+            if let Some(i) = slots.iter().position(|s| s.entry.vary == variant) {
+                let old = slots.remove(i);
+                inner.bytes -= old.entry.size();
+            }
+        }
+        if persist {
+            if let Some(dir) = &self.dir {
+                if let Err(e) = save_entry(dir, &key, &entry) {
+                    eprintln!("Warning: could not write cache entry for {key}: {e:#}");
+                }
+            }
+        }
+        inner.tick += 1;
+        inner.bytes += entry.size();
+        let slot = Slot {
+            entry: Arc::new(entry),
+            last_used: inner.tick,
+        };
+        inner.entries.entry(key).or_default().push(slot);
+        while inner.bytes > self.settings.max_bytes() {
+            let Some((key, i)) = inner
+                .entries
+                .iter()
+                .flat_map(|(k, slots)| slots.iter().enumerate().map(move |(i, s)| (k, i, s)))
+                .min_by_key(|(_, _, s)| s.last_used)
+                .map(|(k, i, _)| (k.clone(), i))
+            else {
+                break;
+            };
+            self.remove_slot(&mut inner, &key, i);
+        }
+    }
+
+    /// Replace a stale entry's headers with those of a 304 and restart its freshness.
+    fn refresh(&self, key: &str, stale: &Entry, not_modified: &HeaderMap) -> Arc<Entry> {
+        let mut headers = stale.headers.clone();
+        for name in not_modified.keys() {
+            if *name == CONTENT_LENGTH || is_hop_by_hop(name) {
+                continue;
+            }
+            headers.remove(name);
+            for value in not_modified.get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+        let entry = Entry {
+            status: stale.status,
+            headers,
+            body: stale.body.clone(),
+            stored_at: SystemTime::now(),
+            vary: stale.vary.clone(),
+        };
+        self.insert(key.to_string(), entry.clone(), true);
+        Arc::new(entry)
+    }
+
+    /// Drop all variants of `key` (after an unsafe method changed the resource).
+    fn invalidate(&self, key: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        self.remove_key(&mut inner, key);
+    }
+
+    fn remove_key(&self, inner: &mut Inner, key: &str) -> usize {
+        let count = inner.entries.get(key).map_or(0, Vec::len);
+        for i in (0..count).rev() {
+            self.remove_slot(inner, key, i);
+        }
+        count
+    }
+
+    fn remove_slot(&self, inner: &mut Inner, key: &str, i: usize) {
+        let Some(slots) = inner.entries.get_mut(key) else {
+            return;
+        };
+        let slot = slots.remove(i);
+        if slots.is_empty() {
+            inner.entries.remove(key);
+        }
+        inner.bytes -= slot.entry.size();
+        if let Some(dir) = &self.dir {
+            let stem = file_stem(key, &slot.entry.vary);
+            let _ = std::fs::remove_file(dir.join(format!("{stem}.json")));
+            let _ = std::fs::remove_file(dir.join(format!("{stem}.body")));
+        }
+    }
+}
+
+fn is_hop_by_hop(name: &HeaderName) -> bool {
+    *name == CONNECTION || *name == TRANSFER_ENCODING || name.as_str() == "keep-alive"
+}
+
+/// Hop-by-hop headers don't belong in a stored response.
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    headers.remove(CONNECTION);
+    headers.remove(TRANSFER_ENCODING);
+    headers.remove("keep-alive");
+}
+
+enum State {
+    /// Not cacheable (method or `no-store`); unsafe methods invalidate the key.
+    Bypass,
+    Miss,
+    Hit(Arc<Entry>),
+    Revalidate(Arc<Entry>),
+}
+
+/// One request's interaction with the cache, from [`Cache::begin`] to [`Exchange::finish`].
+pub struct Exchange {
+    cache: Arc<Cache>,
+    key: String,
+    method: Method,
+    request: HeaderMap,
+    state: State,
+}
+
+impl Exchange {
+    /// The stored response, when it is fresh enough to serve without the backend.
+    pub fn hit(&self) -> Option<Response<ProxyBody>> {
+        match &self.state {
+            State::Hit(entry) => Some(entry.response("HIT", &self.request)),
+            _ => None,
+        }
+    }
+
+    /// Ask the backend whether a stale response is still valid, using its validators
+    /// instead of the client's.
+    pub fn prepare(&self, headers: &mut HeaderMap) {
+        let State::Revalidate(entry) = &self.state else {
+            return;
+        };
+        headers.remove(IF_NONE_MATCH);
+        headers.remove(IF_MODIFIED_SINCE);
+        if let Some(etag) = entry.headers.get(ETAG) {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(modified) = entry.headers.get(LAST_MODIFIED) {
+            headers.insert(IF_MODIFIED_SINCE, modified.clone());
+        }
+    }
+
+    /// Handle the backend's response: serve a revalidated entry on 304, otherwise mark
+    /// it and store it once its body has been read in full.
+    pub fn finish(self, mut parts: Parts, body: ProxyBody) -> (Parts, ProxyBody) {
+        let x_cache = match &self.state {
+            State::Hit(_) => return (parts, body),
+            State::Revalidate(entry) if parts.status == StatusCode::NOT_MODIFIED => {
+                let entry = self.cache.refresh(&self.key, entry, &parts.headers);
+                return entry.response("REVALIDATED", &self.request).into_parts();
+            }
+            State::Bypass => {
+                let changed = parts.status.is_success() || parts.status.is_redirection();
+                if changed && !self.method.is_safe() {
+                    self.cache.invalidate(&self.key);
+                }
+                "BYPASS"
+            }
+            State::Miss | State::Revalidate(_) => "MISS",
+        };
+        let store = self.method == Method::GET
+            && storable(&self.request, parts.status, &parts.headers)
+            && body
+                .size_hint()
+                .exact()
+                .is_none_or(|len| len <= self.cache.settings.max_entry_bytes());
+        let body = if store {
+            let mut headers = parts.headers.clone();
+            strip_hop_by_hop(&mut headers);
+            let vary = vary_names(&parts.headers)
+                .into_iter()
+                .map(|name| {
+                    let value = header_value(&self.request, &name);
+                    (name, value)
+                })
+                .collect();
+            let pending = Entry {
+                status: parts.status,
+                headers,
+                body: Bytes::new(),
+                stored_at: SystemTime::now(),
+                vary,
+            };
+            let limit = self.cache.settings.max_entry_bytes();
+            CaptureBody {
+                inner: body,
+                buf: Vec::new(),
+                limit,
+                pending: Some((self.cache, self.key, pending)),
+            }
+            .boxed()
+        } else {
+            body
+        };
+        parts
+            .headers
+            .insert(X_CACHE, HeaderValue::from_static(x_cache));
+        (parts, body)
+    }
+}
+
+/// Passes a response body through while keeping a copy; the copy is stored when the
+/// body ends, and dropped if it grows past the limit, fails, or is not read to the end.
+struct CaptureBody {
+    inner: ProxyBody,
+    buf: Vec<u8>,
+    limit: u64,
+    pending: Option<(Arc<Cache>, String, Entry)>,
+}
+
+impl CaptureBody {
+    fn complete(&mut self) {
+        if let Some((cache, key, mut entry)) = self.pending.take() {
+            entry.body = Bytes::from(std::mem::take(&mut self.buf));
+            cache.insert(key, entry, true);
+        }
+    }
+}
+
+impl Body for CaptureBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    if (self.buf.len() + data.len()) as u64 > self.limit {
+                        self.pending = None;
+                        self.buf = Vec::new();
+                    } else if self.pending.is_some() {
+                        self.buf.extend_from_slice(data);
+                    }
+                }
+                // hyper stops polling once the body says it has ended
+                if self.inner.is_end_stream() {
+                    self.complete();
+                }
+            }
+            Poll::Ready(Some(Err(_))) => self.pending = None,
+            Poll::Ready(None) => self.complete(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// An entry's metadata on disk; the body is stored next to it.
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    key: String,
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    stored_at: u64,
+    vary: Vec<(String, Option<String>)>,
+}
+
+fn file_stem(key: &str, vary: &[(String, Option<String>)]) -> String {
+    let mut input = key.to_string();
+    for (name, value) in vary {
+        input.push_str(&format!("\n{name}: {}", value.as_deref().unwrap_or("")));
+    }
+    ring::digest::digest(&ring::digest::SHA256, input.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn save_entry(dir: &Path, key: &str, entry: &Entry) -> Result<()> {
+    let stem = file_stem(key, &entry.vary);
+    let meta = StoredEntry {
+        key: key.to_string(),
+        status: entry.status.as_u16(),
+        headers: entry
+            .headers
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.as_bytes().to_vec()))
+            .collect(),
+        stored_at: entry
+            .stored_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        vary: entry.vary.clone(),
+    };
+    std::fs::write(dir.join(format!("{stem}.body")), &entry.body)?;
+    std::fs::write(dir.join(format!("{stem}.json")), serde_json::to_vec(&meta)?)?;
+    Ok(())
+}
+
+fn load_dir(dir: &Path) -> Result<Vec<(String, Entry)>> {
+    let mut entries = Vec::new();
+    for file in std::fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+        let path = file?.path();
+        if path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+        let loaded = std::fs::read(&path)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<StoredEntry>(&json).ok())
+            .and_then(|meta| {
+                let body = std::fs::read(path.with_extension("body")).ok()?;
+                let mut headers = HeaderMap::new();
+                for (name, value) in meta.headers {
+                    headers.append(
+                        HeaderName::from_bytes(name.as_bytes()).ok()?,
+                        HeaderValue::from_bytes(&value).ok()?,
+                    );
+                }
+                let entry = Entry {
+                    status: StatusCode::from_u16(meta.status).ok()?,
+                    headers,
+                    body: Bytes::from(body),
+                    stored_at: UNIX_EPOCH + Duration::from_secs(meta.stored_at),
+                    vary: meta.vary,
+                };
+                Some((meta.key, entry))
+            });
+        match loaded {
+            Some(loaded) => entries.push(loaded),
+            // Unreadable leftovers from an older or interrupted write
+            None => {
+                let _ = std::fs::remove_file(&path);
+                let _ = std::fs::remove_file(path.with_extension("body"));
+            }
+        }
+    }
+    Ok(entries)
+}
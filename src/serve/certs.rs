@@ -0,0 +1,442 @@
+//! Certificates served by the proxy: a per-domain map that can be swapped while the proxy
+//! runs. Renewed or re-signed certs (e.g. after `roost domain set-ca`) are picked up from
+//! disk by a watcher or on request over the control channel. Only new handshakes see the
+//! change; open connections keep the cert they started with.
+//!
+//! Clients connecting by IP address send no SNI; they get the configured default cert
+//! (`default_cert` in `[serve]`), usually one issued for that IP.
+
+use anyhow::{Context, Result};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::CertificateDer;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::SignatureScheme;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio_util::sync::CancellationToken;
+
+use crate::cert;
+use crate::config::RoostPaths;
+use crate::ocsp;
+use crate::serve::badssl;
+use crate::serve::metrics::Metrics;
+use crate::serve::tls::{sni_host, KeyType, TlsPolicy};
+
+/// How often cert files are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+/// Age at which OCSP staples are regenerated; responses are valid for a week.
+const STAPLE_REFRESH: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Modification time and size of a cert and its key, to notice rewrites.
+type FileStamp = [Option<(SystemTime, u64)>; 2];
+
+fn file_stamp(cert_path: &Path, key_path: &Path) -> FileStamp {
+    [cert_path, key_path].map(|path| {
+        let meta = std::fs::metadata(path).ok()?;
+        Some((meta.modified().ok()?, meta.len()))
+    })
+}
+
+/// Leaf certs for one domain and which to serve.
+struct DomainCerts {
+    ecdsa: Arc<CertifiedKey>,
+    rsa: Option<Arc<CertifiedKey>>,
+    key_type: KeyType,
+    /// Files the certs were loaded from, for mapped domains (badssl certs are fixed).
+    stamp: Option<FileStamp>,
+    /// When the OCSP staples were generated, if the certs carry any.
+    stapled_at: Option<Instant>,
+}
+
+impl DomainCerts {
+    fn select(&self, client_hello: &ClientHello<'_>) -> Arc<CertifiedKey> {
+        let rsa = self.rsa.as_ref().unwrap_or(&self.ecdsa);
+        match self.key_type {
+            KeyType::Ecdsa => self.ecdsa.clone(),
+            KeyType::Rsa => rsa.clone(),
+            KeyType::Both => {
+                let ecdsa_ok = client_hello.signature_schemes().iter().any(|s| {
+                    matches!(
+                        s,
+                        SignatureScheme::ECDSA_NISTP256_SHA256
+                            | SignatureScheme::ECDSA_NISTP384_SHA384
+                            | SignatureScheme::ECDSA_NISTP521_SHA512
+                    )
+                });
+                if ecdsa_ok { &self.ecdsa } else { rsa }.clone()
+            }
+        }
+    }
+}
+
+/// Certs by lowercase domain, resolved from the ClientHello's SNI.
+pub struct CertStore {
+    certs: ArcSwap<HashMap<String, Arc<DomainCerts>>>,
+    /// Served for unknown SNI values so unmapped hosts reach the landing page.
+    fallback: Option<Arc<CertifiedKey>>,
+    /// Domain whose certs are served to clients that send no SNI.
+    default: ArcSwapOption<String>,
+    /// Key type per mapped domain and the default cert domain; also serializes updates to
+    /// `certs` and `default`.
+    domains: Mutex<HashMap<String, KeyType>>,
+    /// Files that failed to load, so the watcher reports each broken version once.
+    failed: Mutex<HashMap<String, FileStamp>>,
+    paths: RoostPaths,
+    provider: Arc<CryptoProvider>,
+    metrics: Arc<Metrics>,
+}
+
+impl fmt::Debug for CertStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertStore")
+            .field("domains", &self.certs.load().keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let Some(server_name) = client_hello.server_name() else {
+            let default = self.default.load();
+            return self
+                .certs
+                .load()
+                .get(default.as_deref()?)
+                .map(|c| c.select(&client_hello));
+        };
+        let host = sni_host(server_name)?;
+        self.certs
+            .load()
+            .get(&host)
+            .map(|c| c.select(&client_hello))
+            .or_else(|| self.fallback.clone())
+    }
+}
+
+impl CertStore {
+    /// Load certs for the mapped domains in `policies` (effective policy per lowercase
+    /// domain) and `default_cert`, plus the badssl certs when enabled.
+    pub fn new(
+        paths: &RoostPaths,
+        policies: &HashMap<String, TlsPolicy>,
+        default_cert: Option<&str>,
+        with_badssl: bool,
+        metrics: &Arc<Metrics>,
+    ) -> Result<Arc<Self>> {
+        let provider = rustls::ServerConfig::builder().crypto_provider().clone();
+        let default_ca = default_ca(paths);
+        let fallback = cert::ensure_fallback_cert(paths, &default_ca)
+            .and_then(|()| {
+                let (cert_path, key_path) = cert::fallback_cert_paths(paths);
+                load_certified_key(&cert_path, &key_path, &provider)
+            })
+            .map(|(key, _)| key)
+            .inspect_err(|e| eprintln!("Warning: no fallback cert for unknown domains: {e:#}"))
+            .ok();
+        let store = Self {
+            certs: ArcSwap::default(),
+            fallback,
+            default: ArcSwapOption::from_pointee(default_cert.map(str::to_lowercase)),
+            domains: Mutex::new(key_types(policies, default_cert)),
+            failed: Mutex::default(),
+            paths: paths.clone(),
+            provider,
+            metrics: metrics.clone(),
+        };
+
+        let mut certs = HashMap::new();
+        for (domain, key_type) in store.domains.lock().unwrap().iter() {
+            if let Some(loaded) = store.load(domain, *key_type)? {
+                certs.insert(domain.clone(), Arc::new(loaded));
+            }
+        }
+        if with_badssl {
+            badssl::ensure_certs(paths).context("issue badssl certs")?;
+            for host in badssl::hosts() {
+                let (cert_path, key_path) = badssl::cert_paths(paths, &host);
+                let (ecdsa, _) = load_certified_key(&cert_path, &key_path, &store.provider)
+                    .with_context(|| format!("load cert for {host}"))?;
+                let (ecdsa, stapled_at) = with_ocsp_staple(ecdsa, paths, &default_ca);
+                certs.entry(host).or_insert(Arc::new(DomainCerts {
+                    ecdsa,
+                    rsa: None,
+                    key_type: KeyType::Ecdsa,
+                    stamp: None,
+                    stapled_at,
+                }));
+            }
+        }
+        store.warn_missing_default(&certs);
+        if certs.is_empty() {
+            let mut names: Vec<&str> = policies.keys().map(String::as_str).collect();
+            names.sort();
+            anyhow::bail!(
+                "no domain certs found (mappings: {}); run 'roost serve config add <domain> <port>' to create certs",
+                names.join(", ")
+            );
+        }
+        store.certs.store(Arc::new(certs));
+        Ok(Arc::new(store))
+    }
+
+    fn warn_missing_default(&self, certs: &HashMap<String, Arc<DomainCerts>>) {
+        if let Some(default) = self.default.load().as_deref() {
+            if !certs.contains_key(default) {
+                eprintln!("Warning: no cert for default cert {default}; clients without SNI will fail the handshake");
+            }
+        }
+    }
+
+    /// Load a mapped domain's certs from disk; `None` when it has no cert yet.
+    fn load(&self, domain: &str, key_type: KeyType) -> Result<Option<DomainCerts>> {
+        let paths = &self.paths;
+        let (cert_path, key_path) = cert::domain_cert_paths(paths, domain);
+        if !cert_path.is_file() || !key_path.is_file() {
+            return Ok(None);
+        }
+        let stamp = file_stamp(&cert_path, &key_path);
+        let (ecdsa, not_after) = load_certified_key(&cert_path, &key_path, &self.provider)
+            .with_context(|| format!("load cert for {domain}"))?;
+        if let Some(not_after) = not_after {
+            self.metrics.set_cert_expiry(domain, not_after);
+        }
+        let ca_name = domain_ca(paths, domain);
+        let (ecdsa, mut stapled_at) = with_ocsp_staple(ecdsa, paths, &ca_name);
+        let rsa = if key_type.needs_rsa() {
+            cert::ensure_rsa_cert_valid(paths, domain, &ca_name)
+                .with_context(|| format!("issue RSA cert for {domain}"))?;
+            let (cert_path, key_path) = cert::rsa_cert_paths(paths, domain);
+            let (rsa, _) = load_certified_key(&cert_path, &key_path, &self.provider)
+                .with_context(|| format!("load RSA cert for {domain}"))?;
+            let (rsa, rsa_stapled_at) = with_ocsp_staple(rsa, paths, &ca_name);
+            stapled_at = stapled_at.or(rsa_stapled_at);
+            Some(rsa)
+        } else {
+            None
+        };
+        Ok(Some(DomainCerts {
+            ecdsa,
+            rsa,
+            key_type,
+            stamp: Some(stamp),
+            stapled_at,
+        }))
+    }
+
+    /// Reload certs from disk for `domain`, or for every mapped domain. Returns the domains
+    /// now served with a cert from disk.
+    pub fn reload(&self, domain: Option<&str>) -> Result<Vec<String>> {
+        let domains = self.domains.lock().unwrap();
+        let selected: Vec<(&String, &KeyType)> = match domain {
+            Some(domain) => {
+                let domain = domain.to_lowercase();
+                let entry = domains
+                    .get_key_value(&domain)
+                    .with_context(|| format!("no mapping for {domain}"))?;
+                vec![entry]
+            }
+            None => domains.iter().collect(),
+        };
+        let mut certs = HashMap::clone(&self.certs.load());
+        let mut reloaded = Vec::new();
+        for (name, key_type) in selected {
+            match self.load(name, *key_type)? {
+                Some(loaded) => {
+                    certs.insert(name.clone(), Arc::new(loaded));
+                    reloaded.push(name.clone());
+                }
+                // Reloading everything skips mappings that have no cert yet
+                None if domain.is_none() => {}
+                None => anyhow::bail!("no cert for {name} in {}", self.paths.certs_dir.display()),
+            }
+        }
+        self.certs.store(Arc::new(certs));
+        reloaded.sort();
+        Ok(reloaded)
+    }
+
+    /// Switch to a new set of mapped domains and default cert (after a config reload): load
+    /// certs for added domains or changed key types, and stop serving certs for removed ones.
+    pub fn set_domains(&self, policies: &HashMap<String, TlsPolicy>, default_cert: Option<&str>) {
+        let mut domains = self.domains.lock().unwrap();
+        let new = key_types(policies, default_cert);
+        let mut certs = HashMap::clone(&self.certs.load());
+        certs.retain(|domain, c| c.stamp.is_none() || new.contains_key(domain));
+        for (domain, key_type) in &new {
+            if domains.get(domain) == Some(key_type) && certs.contains_key(domain) {
+                continue;
+            }
+            match self.load(domain, *key_type) {
+                Ok(Some(loaded)) => {
+                    certs.insert(domain.clone(), Arc::new(loaded));
+                }
+                Ok(None) => {
+                    certs.remove(domain);
+                }
+                Err(e) => eprintln!("Warning: {e:#}"),
+            }
+        }
+        *domains = new;
+        self.default
+            .store(default_cert.map(|d| Arc::new(d.to_lowercase())));
+        self.warn_missing_default(&certs);
+        self.certs.store(Arc::new(certs));
+    }
+
+    /// Reload domains whose cert files changed or whose OCSP staples are due for refresh.
+    /// Returns the reloaded domains; failures are logged and the old certs kept.
+    fn reload_changed(&self) -> Vec<String> {
+        let domains = self.domains.lock().unwrap();
+        let current = self.certs.load_full();
+        let mut certs = None;
+        let mut reloaded = Vec::new();
+        for (domain, key_type) in domains.iter() {
+            let loaded = current.get(domain);
+            let (cert_path, key_path) = cert::domain_cert_paths(&self.paths, domain);
+            let stamp = file_stamp(&cert_path, &key_path);
+            let changed = loaded.and_then(|c| c.stamp) != Some(stamp)
+                && self.failed.lock().unwrap().get(domain) != Some(&stamp);
+            let stale = loaded
+                .and_then(|c| c.stapled_at)
+                .is_some_and(|at| at.elapsed() >= STAPLE_REFRESH);
+            if !changed && !stale {
+                continue;
+            }
+            match self.load(domain, *key_type) {
+                Ok(Some(new)) => {
+                    certs
+                        .get_or_insert_with(|| HashMap::clone(&current))
+                        .insert(domain.clone(), Arc::new(new));
+                    reloaded.push(domain.clone());
+                }
+                // Files mid-rewrite or removed: keep serving the loaded cert
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("Warning: keeping the current cert: {e:#}");
+                    self.failed.lock().unwrap().insert(domain.clone(), stamp);
+                }
+            }
+        }
+        if let Some(certs) = certs {
+            self.certs.store(Arc::new(certs));
+        }
+        reloaded
+    }
+
+    /// Poll cert files and reload changed domains until `shutdown`.
+    pub async fn watch(self: Arc<Self>, shutdown: CancellationToken) {
+        let mut tick = tokio::time::interval(WATCH_INTERVAL);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = tick.tick() => {}
+                _ = shutdown.cancelled() => return,
+            }
+            let store = self.clone();
+            let Ok(reloaded) = tokio::task::spawn_blocking(move || store.reload_changed()).await
+            else {
+                continue;
+            };
+            for domain in reloaded {
+                eprintln!("Reloaded cert for {domain}");
+            }
+        }
+    }
+}
+
+/// Key type per domain; the default cert domain uses its mapping's, else ECDSA.
+fn key_types(
+    policies: &HashMap<String, TlsPolicy>,
+    default_cert: Option<&str>,
+) -> HashMap<String, KeyType> {
+    let mut key_types: HashMap<String, KeyType> = policies
+        .iter()
+        .map(|(domain, policy)| (domain.to_lowercase(), policy.key_type()))
+        .collect();
+    if let Some(domain) = default_cert {
+        key_types
+            .entry(domain.to_lowercase())
+            .or_insert(KeyType::Ecdsa);
+    }
+    key_types
+}
+
+fn default_ca(paths: &RoostPaths) -> String {
+    crate::store::load_config(paths)
+        .ok()
+        .map(|c| c.default_ca)
+        .filter(|ca| !ca.is_empty())
+        .unwrap_or_else(|| "default".to_string())
+}
+
+/// CA that signs `domain`'s certs: its assigned CA, else the default.
+fn domain_ca(paths: &RoostPaths, domain: &str) -> String {
+    crate::store::load_config(paths)
+        .ok()
+        .map(|c| {
+            c.domains
+                .get(domain)
+                .cloned()
+                .unwrap_or_else(|| c.default_ca.clone())
+        })
+        .unwrap_or_else(|| "default".to_string())
+}
+
+/// Expiry (notAfter, unix seconds) of the leaf certificate.
+fn cert_not_after(cert: &CertificateDer<'_>) -> Option<i64> {
+    use x509_parser::prelude::FromDer;
+    let (_, cert) = x509_parser::certificate::X509Certificate::from_der(cert).ok()?;
+    Some(cert.validity().not_after.timestamp())
+}
+
+fn load_certified_key(
+    cert_path: &Path,
+    key_path: &Path,
+    provider: &Arc<CryptoProvider>,
+) -> Result<(Arc<CertifiedKey>, Option<i64>)> {
+    let cert_pem =
+        std::fs::read(cert_path).with_context(|| format!("read cert: {}", cert_path.display()))?;
+    let key_pem =
+        std::fs::read(key_path).with_context(|| format!("read key: {}", key_path.display()))?;
+
+    let certs_der: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .context("parse cert PEM")?;
+    let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+        .context("parse key PEM")?
+        .context("no private key in file")?;
+    let not_after = certs_der.first().and_then(cert_not_after);
+    Ok((
+        Arc::new(CertifiedKey::from_der(certs_der, key, provider)?),
+        not_after,
+    ))
+}
+
+/// Attach a locally generated OCSP response when the cert names a responder (see
+/// [`ocsp::staple`]), returning when it was generated. Failures only cost the staple.
+fn with_ocsp_staple(
+    key: Arc<CertifiedKey>,
+    paths: &RoostPaths,
+    ca_name: &str,
+) -> (Arc<CertifiedKey>, Option<Instant>) {
+    let Some(leaf) = key.end_entity_cert().ok() else {
+        return (key, None);
+    };
+    match ocsp::staple(paths, ca_name, leaf) {
+        Ok(Some(response)) => {
+            let mut stapled = (*key).clone();
+            stapled.ocsp = Some(response);
+            (Arc::new(stapled), Some(Instant::now()))
+        }
+        Ok(None) => (key, None),
+        Err(e) => {
+            eprintln!("Warning: no OCSP staple from CA '{ca_name}': {e:#}");
+            (key, None)
+        }
+    }
+}
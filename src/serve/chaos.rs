@@ -0,0 +1,301 @@
+//! Fault and latency injection per mapping (latency, throttling, errors, drops, truncation).
+
+use anyhow::Result;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::RwLock;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::Sleep;
+
+/// Chaos settings for a mapping. All faults are off by default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Chaos {
+    /// Only affect requests whose path starts with this prefix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    /// Fixed latency added before forwarding, in milliseconds.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub latency_ms: u64,
+    /// Extra random latency of up to this many milliseconds.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub jitter_ms: u64,
+    /// Limit response body throughput to this many bytes per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth_bps: Option<u64>,
+    /// Percentage of requests answered with `error_status` instead of the backend response.
+    #[serde(default, skip_serializing_if = "is_zero_f64")]
+    pub error_rate: f64,
+    /// Status for injected errors, 400-599 (default 503).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_status: Option<u16>,
+    /// Percentage of requests whose connection is closed without a response.
+    #[serde(default, skip_serializing_if = "is_zero_f64")]
+    pub drop_rate: f64,
+    /// Percentage of responses whose body is cut off halfway.
+    #[serde(default, skip_serializing_if = "is_zero_f64")]
+    pub truncate_rate: f64,
+}
+
+fn is_zero(v: &u64) -> bool {
+    *v == 0
+}
+
+fn is_zero_f64(v: &f64) -> bool {
+    *v == 0.0
+}
+
+/// Fault picked for a single request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    None,
+    Error(u16),
+    Drop,
+    Truncate,
+}
+
+impl Chaos {
+    /// Check rates and status are in range.
+    pub fn validate(&self) -> Result<()> {
+        for (name, rate) in [
+            ("error_rate", self.error_rate),
+            ("drop_rate", self.drop_rate),
+            ("truncate_rate", self.truncate_rate),
+        ] {
+            if !(0.0..=100.0).contains(&rate) {
+                anyhow::bail!("{name} must be between 0 and 100, got {rate}");
+            }
+        }
+        if self.error_rate + self.drop_rate + self.truncate_rate > 100.0 {
+            anyhow::bail!("error_rate + drop_rate + truncate_rate must not exceed 100");
+        }
+        if let Some(status) = self.error_status {
+            // A 1xx can't end an exchange, and 2xx/3xx wouldn't look like a failure
+            if !(400..=599).contains(&status) {
+                anyhow::bail!("invalid error_status {status}: must be 400-599");
+            }
+        }
+        if self.bandwidth_bps == Some(0) {
+            anyhow::bail!("bandwidth_bps must be greater than 0");
+        }
+        Ok(())
+    }
+
+    /// Whether the settings apply to a request path.
+    pub fn applies_to(&self, path: &str) -> bool {
+        self.path_prefix
+            .as_deref()
+            .is_none_or(|prefix| path.starts_with(prefix))
+    }
+
+    /// Latency to add: fixed part plus a random share of the jitter.
+    pub fn latency(&self) -> Duration {
+        let jitter = if self.jitter_ms > 0 {
+            rand::rng().random_range(0..=self.jitter_ms)
+        } else {
+            0
+        };
+        Duration::from_millis(self.latency_ms + jitter)
+    }
+
+    /// Pick a fault for a roll in [0, 100). Ranges are laid out drop, error, truncate.
+    pub fn fault_for_roll(&self, roll: f64) -> Fault {
+        let mut upper = self.drop_rate;
+        if roll < upper {
+            return Fault::Drop;
+        }
+        upper += self.error_rate;
+        if roll < upper {
+            return Fault::Error(self.error_status.unwrap_or(503));
+        }
+        upper += self.truncate_rate;
+        if roll < upper {
+            return Fault::Truncate;
+        }
+        Fault::None
+    }
+
+    /// Pick a random fault for a request.
+    pub fn pick_fault(&self) -> Fault {
+        if self.drop_rate == 0.0 && self.error_rate == 0.0 && self.truncate_rate == 0.0 {
+            return Fault::None;
+        }
+        self.fault_for_roll(rand::rng().random_range(0.0..100.0))
+    }
+}
+
+/// Runtime chaos overrides set via `roost serve chaos`; take precedence over `.roostrc`.
+#[derive(Debug, Default)]
+pub struct ChaosOverrides {
+    by_domain: RwLock<HashMap<String, Chaos>>,
+}
+
+impl ChaosOverrides {
+    pub fn get(&self, domain: &str) -> Option<Chaos> {
+        self.by_domain.read().unwrap().get(domain).cloned()
+    }
+
+    pub fn set(&self, domain: &str, chaos: Chaos) {
+        self.by_domain
+            .write()
+            .unwrap()
+            .insert(domain.to_lowercase(), chaos);
+    }
+
+    /// Remove override; returns whether one existed.
+    pub fn clear(&self, domain: &str) -> bool {
+        self.by_domain
+            .write()
+            .unwrap()
+            .remove(&domain.to_lowercase())
+            .is_some()
+    }
+
+    pub fn list(&self) -> Vec<(String, Chaos)> {
+        let mut v: Vec<_> = self
+            .by_domain
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(d, c)| (d.clone(), c.clone()))
+            .collect();
+        v.sort_by(|a, b| a.0.cmp(&b.0));
+        v
+    }
+}
+
+/// Error used to abort a response body mid-stream.
+#[derive(Debug)]
+pub(crate) struct Truncated;
+
+impl std::fmt::Display for Truncated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("response truncated by chaos settings")
+    }
+}
+
+impl std::error::Error for Truncated {}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Body wrapper that forwards at most `limit` bytes, then fails so the connection is cut.
+/// Without a limit (unknown length) it cuts halfway through the first data frame.
+pub struct TruncatedBody<B> {
+    inner: B,
+    remaining: Option<usize>,
+}
+
+impl<B> TruncatedBody<B> {
+    pub fn new(inner: B, limit: Option<usize>) -> Self {
+        Self {
+            inner,
+            remaining: limit,
+        }
+    }
+}
+
+impl<B> Body for TruncatedBody<B>
+where
+    B: Body<Data = Bytes, Error = BoxError> + Unpin,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        if self.remaining == Some(0) {
+            return Poll::Ready(Some(Err(Box::new(Truncated))));
+        }
+        match Pin::new(&mut self.inner).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+                Ok(mut data) => {
+                    let remaining = self.remaining.unwrap_or(data.len() / 2);
+                    if data.len() >= remaining {
+                        data.truncate(remaining);
+                        self.remaining = Some(0);
+                    } else {
+                        self.remaining = Some(remaining - data.len());
+                    }
+                    Poll::Ready(Some(Ok(Frame::data(data))))
+                }
+                Err(frame) => Poll::Ready(Some(Ok(frame))),
+            },
+            other => other,
+        }
+    }
+}
+
+/// Body wrapper that releases data at a fixed rate, in ticks of 100ms.
+pub struct ThrottledBody<B> {
+    inner: B,
+    bytes_per_tick: usize,
+    pending: Bytes,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+const THROTTLE_TICK: Duration = Duration::from_millis(100);
+
+impl<B> ThrottledBody<B> {
+    pub fn new(inner: B, bytes_per_second: u64) -> Self {
+        Self {
+            inner,
+            bytes_per_tick: ((bytes_per_second / 10) as usize).max(1),
+            pending: Bytes::new(),
+            sleep: None,
+        }
+    }
+}
+
+impl<B> Body for ThrottledBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+        if let Some(sleep) = self.sleep.as_mut() {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.sleep = None;
+        }
+        if self.pending.is_empty() {
+            match Pin::new(&mut self.inner).poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+                    Ok(data) => self.pending = data,
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                other => return other,
+            }
+        }
+        let n = self.pending.len().min(self.bytes_per_tick);
+        let chunk = self.pending.split_to(n);
+        self.sleep = Some(Box::pin(tokio::time::sleep(THROTTLE_TICK)));
+        Poll::Ready(Some(Ok(Frame::data(chunk))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_empty() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let inner = self.inner.size_hint();
+        let pending = self.pending.len() as u64;
+        let mut hint = SizeHint::new();
+        hint.set_lower(inner.lower() + pending);
+        if let Some(upper) = inner.upper() {
+            hint.set_upper(upper + pending);
+        }
+        hint
+    }
+}
@@ -0,0 +1,252 @@
+//! Opt-in response compression (gzip, brotli) negotiated from `Accept-Encoding`.
+
+use async_compression::tokio::write::{BrotliEncoder, GzipEncoder};
+use futures_util::StreamExt;
+use http::header::{
+    HeaderValue, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, ETAG, VARY,
+};
+use http::response::Parts;
+use http::StatusCode;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::{Bytes, Frame};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::serve::proxy::{BoxError, ProxyBody};
+
+/// Encodings the proxy can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[serde(rename = "br")]
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+fn default_encodings() -> Vec<Encoding> {
+    vec![Encoding::Brotli, Encoding::Gzip]
+}
+
+fn default_min_size() -> u64 {
+    1024
+}
+
+/// Compression settings for a mapping; present means enabled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Compression {
+    /// Encodings to offer, most preferred first.
+    #[serde(default = "default_encodings")]
+    pub encodings: Vec<Encoding>,
+    /// Skip responses whose Content-Length is below this many bytes.
+    #[serde(default = "default_min_size")]
+    pub min_size: u64,
+    /// Extra compressible content types besides the built-in text, JSON, JS, XML, SVG and WASM.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content_types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            encodings: default_encodings(),
+            min_size: default_min_size(),
+            content_types: Vec::new(),
+        }
+    }
+}
+
+/// Pick the first offered encoding the client accepts (q > 0), honouring `*` and `identity`-only clients.
+pub fn negotiate(accept_encoding: &str, offered: &[Encoding]) -> Option<Encoding> {
+    let mut accepted: Vec<(String, f32)> = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        if name.is_empty() {
+            continue;
+        }
+        let q = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        accepted.push((name, q));
+    }
+    let q_for = |name: &str| {
+        accepted
+            .iter()
+            .find(|(n, _)| n == name)
+            .or_else(|| accepted.iter().find(|(n, _)| n == "*"))
+            .map(|(_, q)| *q)
+            .unwrap_or(0.0)
+    };
+    offered.iter().copied().find(|e| q_for(e.as_str()) > 0.0)
+}
+
+/// Whether a Content-Type is worth compressing.
+pub fn is_compressible(content_type: &str, extra: &[String]) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if mime.starts_with("text/") || mime.ends_with("+json") || mime.ends_with("+xml") {
+        return true;
+    }
+    const BUILTIN: &[&str] = &[
+        "application/json",
+        "application/javascript",
+        "application/x-javascript",
+        "application/xml",
+        "application/wasm",
+        "application/graphql-response+json",
+        "image/svg+xml",
+        "image/x-icon",
+        "font/ttf",
+        "font/otf",
+    ];
+    BUILTIN.contains(&mime.as_str()) || extra.iter().any(|t| t.eq_ignore_ascii_case(&mime))
+}
+
+/// Whether a response should be compressed with the given settings.
+pub fn should_compress(parts: &Parts, settings: &Compression) -> bool {
+    let status = parts.status;
+    if status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || status == StatusCode::PARTIAL_CONTENT
+    {
+        return false;
+    }
+    let headers = &parts.headers;
+    let already_encoded = headers
+        .get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| !v.trim().eq_ignore_ascii_case("identity"));
+    if already_encoded || headers.contains_key(CONTENT_RANGE) {
+        return false;
+    }
+    let no_transform = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.to_ascii_lowercase().contains("no-transform"));
+    if no_transform {
+        return false;
+    }
+    let too_small = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .is_some_and(|len| len < settings.min_size);
+    if too_small {
+        return false;
+    }
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| is_compressible(ct, &settings.content_types))
+}
+
+/// Compress a response body as it streams, adjusting headers to match.
+pub fn compress(mut parts: Parts, body: ProxyBody, encoding: Encoding) -> (Parts, ProxyBody) {
+    let headers = &mut parts.headers;
+    headers.remove(CONTENT_LENGTH);
+    headers.remove(ACCEPT_RANGES);
+    headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    let varies = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.to_ascii_lowercase().contains("accept-encoding") || v.trim() == "*");
+    if !varies {
+        headers.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+    // The encoded bytes differ from the original, so a strong validator no longer holds
+    if let Some(etag) = headers.get(ETAG).and_then(|v| v.to_str().ok()) {
+        if !etag.starts_with("W/") {
+            if let Ok(weak) = HeaderValue::from_str(&format!("W/{etag}")) {
+                headers.insert(ETAG, weak);
+            }
+        }
+    }
+
+    let encoder = match encoding {
+        Encoding::Brotli => Encoder::Brotli(Box::new(BrotliEncoder::new(Vec::new()))),
+        Encoding::Gzip => Encoder::Gzip(GzipEncoder::new(Vec::new())),
+    };
+    (parts, encode(body, encoder))
+}
+
+/// Streaming encoder writing into a buffer that is drained after each flush.
+enum Encoder {
+    Brotli(Box<BrotliEncoder<Vec<u8>>>),
+    Gzip(GzipEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn writer(&mut self) -> &mut (dyn AsyncWrite + Unpin + Send + Sync) {
+        match self {
+            Encoder::Brotli(e) => e,
+            Encoder::Gzip(e) => e,
+        }
+    }
+
+    fn take(&mut self) -> Bytes {
+        let buf = match self {
+            Encoder::Brotli(e) => e.get_mut(),
+            Encoder::Gzip(e) => e.get_mut(),
+        };
+        Bytes::from(std::mem::take(buf))
+    }
+}
+
+/// Encode `body` frame by frame, flushing after each data frame so streamed responses (server-sent
+/// events, NDJSON, chunked HTML) reach the client as the backend sends them.
+fn encode(body: ProxyBody, encoder: Encoder) -> ProxyBody {
+    let chunks = futures_util::stream::unfold(Some((body, encoder)), |state| async move {
+        let (mut body, mut encoder) = state?;
+        loop {
+            let data = match body.frame().await {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) if !data.is_empty() => data,
+                    _ => continue,
+                },
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => {
+                    let chunk = match encoder.writer().shutdown().await {
+                        Ok(()) => Ok(encoder.take()),
+                        Err(e) => Err(BoxError::from(e)),
+                    };
+                    return Some((chunk, None));
+                }
+            };
+            let writer = encoder.writer();
+            if let Err(e) = async {
+                writer.write_all(&data).await?;
+                writer.flush().await
+            }
+            .await
+            {
+                return Some((Err(BoxError::from(e)), None));
+            }
+            let chunk = encoder.take();
+            if !chunk.is_empty() {
+                return Some((Ok(chunk), Some((body, encoder))));
+            }
+        }
+    });
+    BodyExt::boxed(StreamBody::new(chunks.map(|r| r.map(Frame::data))))
+}
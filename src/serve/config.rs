@@ -1,13 +1,26 @@
 //! Serve config: mapping add/remove/list, config merge.
 
 use anyhow::Result;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 
+use crate::config::{project_roostrc, RoostPaths};
+use crate::serve::cache::CacheSettings;
+use crate::serve::chaos::Chaos;
+use crate::serve::compress::Compression;
+use crate::serve::forward_auth::ForwardAuth;
+use crate::serve::limits::Limits;
+use crate::serve::mirror::Mirror;
+use crate::serve::rules::Rule;
+use crate::serve::tls::TlsPolicy;
+use crate::serve::trace::Tracing;
+use crate::serve::upstream::UpstreamOverride;
+
 /// Source of a mapping for list output.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MappingSource {
@@ -15,11 +28,103 @@ pub enum MappingSource {
     Global,
 }
 
-/// Single mapping: domain -> port.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Single mapping: domain -> port, plus optional per-mapping settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Mapping {
     pub domain: String,
     pub port: u16,
+    /// Client IPs or CIDR ranges allowed to use this mapping. Empty allows any client.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// HTTP basic auth required for this mapping.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub basic_auth: Option<BasicAuth>,
+    /// Auth endpoint asked about each request before it is proxied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward_auth: Option<ForwardAuth>,
+    /// Fault and latency injection; can be overridden at runtime with `roost serve chaos`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chaos: Option<Chaos>,
+    /// Compress eligible responses at the proxy (gzip/brotli).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// Timeouts and body size limit for this mapping; unset fields use `[serve.limits]`.
+    #[serde(default, skip_serializing_if = "Limits::is_empty")]
+    pub limits: Limits,
+    /// On plain HTTP ports, proxy this mapping over HTTP instead of redirecting to HTTPS.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub plain_http: bool,
+    /// HSTS for this mapping; overrides `[serve.hsts]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hsts: Option<Hsts>,
+    /// TLS versions, cipher suites, ALPN and key type; unset fields use `[serve.tls]`.
+    #[serde(default, skip_serializing_if = "TlsPolicy::is_empty")]
+    pub tls: TlsPolicy,
+    /// Pass `Location`, `Content-Location`, `Refresh` and `Set-Cookie` through unchanged
+    /// instead of rewriting the backend origin (`http://localhost:<port>`) to the public one.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub preserve_origin: bool,
+    /// Shadow backend that receives a copy of every request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<Mirror>,
+    /// Forward to the port in the `Host` header (`app.test:5173` -> `localhost:5173`) when
+    /// it isn't a port the proxy listens on. Off by default: any page could otherwise use
+    /// the proxy to reach arbitrary local ports.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub port_passthrough: bool,
+    /// Alternate backends a `roost-upstream` cookie or header can select.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<UpstreamOverride>,
+    /// Cache backend responses like a CDN would, honouring `Cache-Control`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheSettings>,
+}
+
+impl Mapping {
+    pub fn new(domain: String, port: u16) -> Self {
+        Self {
+            domain,
+            port,
+            ..Default::default()
+        }
+    }
+}
+
+/// HTTP basic auth settings for a mapping.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BasicAuth {
+    /// Realm shown in the browser prompt. Defaults to the domain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realm: Option<String>,
+    /// User name -> bcrypt password hash.
+    #[serde(default)]
+    pub users: BTreeMap<String, String>,
+}
+
+/// Strict-Transport-Security sent on HTTPS responses. Browsers remember it, so keep
+/// `max_age_secs` short while experimenting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hsts {
+    pub max_age_secs: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_subdomains: bool,
+}
+
+impl Hsts {
+    pub fn header_value(&self) -> String {
+        if self.include_subdomains {
+            format!("max-age={}; includeSubDomains", self.max_age_secs)
+        } else {
+            format!("max-age={}", self.max_age_secs)
+        }
+    }
+}
+
+/// Plain HTTP port that redirects to a TLS port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Redirect {
+    pub from: u16,
+    pub to: u16,
 }
 
 /// Top-level .roostrc file format (has [serve] section).
@@ -32,6 +137,19 @@ struct RoostRc {
 /// Default ports when none configured: 80 (HTTP redirect) and 443 (HTTPS).
 pub const DEFAULT_PORTS: [u16; 2] = [80, 443];
 
+/// Default bind addresses for every port: loopback only, IPv4 and IPv6.
+pub const DEFAULT_BIND: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::LOCALHOST),
+    IpAddr::V6(Ipv6Addr::LOCALHOST),
+];
+
+/// Bind addresses for one listen port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Listen {
+    pub port: u16,
+    pub bind: Vec<IpAddr>,
+}
+
 /// Serve config (from .roostrc or global).
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ServeConfig {
@@ -40,6 +158,47 @@ pub struct ServeConfig {
     /// Ports to listen on. Empty means use DEFAULT_PORTS ([80, 443]).
     #[serde(default)]
     pub ports: Vec<u16>,
+    /// Per-port bind addresses. Ports not listed bind to DEFAULT_BIND.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listen: Vec<Listen>,
+    /// Allow binding non-loopback addresses, exposing mappings to the local network. Only
+    /// honoured in the global `.roostrc`, so a cloned project can't turn it on by itself.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lan: bool,
+    /// Timeouts and body size limit for all mappings.
+    #[serde(default, skip_serializing_if = "Limits::is_empty")]
+    pub limits: Limits,
+    /// Seconds to drain in-flight requests and websocket tunnels on shutdown (default 10).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shutdown_timeout_secs: Option<u64>,
+    /// Plain HTTP ports redirecting to TLS ports. 80 -> 443 is implied when both are in `ports`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redirects: Vec<Redirect>,
+    /// Redirect and rewrite rules, evaluated in order before routing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+    /// HSTS for all mappings; off by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hsts: Option<Hsts>,
+    /// Address for the Prometheus metrics endpoint (`/metrics`); off when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<SocketAddr>,
+    /// Trace context propagation and OTLP span export.
+    #[serde(default, skip_serializing_if = "Tracing::is_empty")]
+    pub tracing: Tracing,
+    /// TLS policy for all mappings.
+    #[serde(default, skip_serializing_if = "TlsPolicy::is_empty")]
+    pub tls: TlsPolicy,
+    /// Backend port for requests to unmapped hosts; unset shows a landing page instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_backend: Option<u16>,
+    /// Domain whose cert is served to clients that send no SNI, i.e. that connect by IP
+    /// address; those handshakes fail when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_cert: Option<String>,
+    /// Serve the deliberately broken TLS test endpoints under `badssl.test`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub badssl: bool,
 }
 
 impl ServeConfig {
@@ -78,9 +237,17 @@ impl ServeConfig {
         Ok(())
     }
 
+    /// Add mapping or update the port of an existing one (keeps its other settings).
     pub fn add(&mut self, domain: String, port: u16) {
-        self.mappings.retain(|m| m.domain != domain);
-        self.mappings.push(Mapping { domain, port });
+        match self.mappings.iter_mut().find(|m| m.domain == domain) {
+            Some(m) => m.port = port,
+            None => self.mappings.push(Mapping::new(domain, port)),
+        }
+    }
+
+    /// Get mutable mapping for domain.
+    pub fn mapping_mut(&mut self, domain: &str) -> Option<&mut Mapping> {
+        self.mappings.iter_mut().find(|m| m.domain == domain)
     }
 
     pub fn remove(&mut self, domain: &str) {
@@ -138,6 +305,77 @@ impl ServeConfig {
     pub fn ports_list(&self) -> Vec<u16> {
         self.effective_ports()
     }
+
+    /// Addresses to bind for port: configured list, else DEFAULT_BIND.
+    pub fn bind_addrs(&self, port: u16) -> Vec<IpAddr> {
+        match self.listen.iter().find(|l| l.port == port) {
+            Some(l) if !l.bind.is_empty() => l.bind.clone(),
+            _ => DEFAULT_BIND.to_vec(),
+        }
+    }
+
+    /// Set bind addresses for port; empty resets the port to DEFAULT_BIND.
+    pub fn set_bind(&mut self, port: u16, bind: Vec<IpAddr>) {
+        self.listen.retain(|l| l.port != port);
+        if !bind.is_empty() {
+            self.listen.push(Listen { port, bind });
+            self.listen.sort_by_key(|l| l.port);
+        }
+    }
+
+    /// Add or replace the redirect from a plain port.
+    pub fn set_redirect(&mut self, from: u16, to: u16) -> Result<()> {
+        if from == to {
+            anyhow::bail!("cannot redirect port {from} to itself");
+        }
+        self.redirects.retain(|r| r.from != from);
+        self.redirects.push(Redirect { from, to });
+        self.redirects.sort_by_key(|r| r.from);
+        Ok(())
+    }
+
+    /// Remove the redirect from a plain port; returns whether one existed.
+    pub fn remove_redirect(&mut self, from: u16) -> bool {
+        let before = self.redirects.len();
+        self.redirects.retain(|r| r.from != from);
+        self.redirects.len() != before
+    }
+
+    /// Redirects in effect: configured pairs plus 80 -> 443 when both ports are listed.
+    pub fn effective_redirects(&self) -> Vec<Redirect> {
+        let mut out = self.redirects.clone();
+        let ports = self.effective_ports();
+        if ports.contains(&80) && ports.contains(&443) && !out.iter().any(|r| r.from == 80) {
+            out.push(Redirect { from: 80, to: 443 });
+        }
+        out.sort_by_key(|r| r.from);
+        out
+    }
+
+    /// Ports served with TLS: listed ports except plain (redirect) ports and 80.
+    pub fn tls_ports(&self) -> Vec<u16> {
+        let plain = self.effective_redirects();
+        self.effective_ports()
+            .into_iter()
+            .filter(|&p| p != 80 && !plain.iter().any(|r| r.from == p))
+            .collect()
+    }
+
+    /// Refuse non-loopback bind addresses (listeners and metrics endpoint) unless `lan = true`.
+    pub fn validate_binds(&self) -> Result<()> {
+        const HOW: &str =
+            "set 'lan = true' in the global [serve] (or pass --global --lan) to allow it";
+        for l in &self.listen {
+            let what = format!("port {}", l.port);
+            require_loopback(l.bind.iter().copied(), self.lan, &what, HOW)?;
+        }
+        require_loopback(
+            self.metrics.map(|a| a.ip()),
+            self.lan,
+            "the metrics endpoint",
+            HOW,
+        )
+    }
 }
 
 /// Merge ports from project and global configs (union). Uses DEFAULT_PORTS when both empty.
@@ -167,6 +405,92 @@ pub fn merge_configs(project: &ServeConfig, global: &ServeConfig) -> HashMap<Str
     out
 }
 
+/// Merge project and global configs into full mappings keyed by domain; project overrides on conflict.
+pub fn merge_mappings(project: &ServeConfig, global: &ServeConfig) -> HashMap<String, Mapping> {
+    let mut out = HashMap::new();
+    for m in global.mappings.iter().chain(&project.mappings) {
+        out.insert(m.domain.clone(), m.clone());
+    }
+    out
+}
+
+/// Merge project and global configs into the effective config used by the proxy.
+/// Mappings, per-port binds, redirects, limits, HSTS, metrics address, tracing, TLS policy and
+/// default backend:
+/// project overrides global. Ports: union. LAN and badssl endpoints: either opts in.
+pub fn merge_serve_configs(project: &ServeConfig, global: &ServeConfig) -> ServeConfig {
+    let mut mappings: Vec<Mapping> = merge_mappings(project, global).into_values().collect();
+    mappings.sort_by(|a, b| a.domain.cmp(&b.domain));
+    let mut listen = global.listen.clone();
+    for l in &project.listen {
+        listen.retain(|g| g.port != l.port);
+        listen.push(l.clone());
+    }
+    listen.sort_by_key(|l| l.port);
+    let mut redirects = global.redirects.clone();
+    for r in &project.redirects {
+        redirects.retain(|g| g.from != r.from);
+        redirects.push(*r);
+    }
+    redirects.sort_by_key(|r| r.from);
+    ServeConfig {
+        mappings,
+        ports: merge_ports(project, global),
+        listen,
+        lan: global.lan,
+        limits: project.limits.or(&global.limits),
+        shutdown_timeout_secs: project
+            .shutdown_timeout_secs
+            .or(global.shutdown_timeout_secs),
+        redirects,
+        // Project rules are evaluated first
+        rules: project.rules.iter().chain(&global.rules).cloned().collect(),
+        hsts: project.hsts.or(global.hsts),
+        metrics: project.metrics.or(global.metrics),
+        tracing: project.tracing.or(&global.tracing),
+        tls: project.tls.or(&global.tls),
+        default_backend: project.default_backend.or(global.default_backend),
+        default_cert: project
+            .default_cert
+            .clone()
+            .or_else(|| global.default_cert.clone()),
+        badssl: project.badssl || global.badssl,
+    }
+}
+
+/// Load the project `.roostrc` for `cwd` (if any) and the global one, and merge them.
+pub fn load_merged(paths: &RoostPaths, cwd: &Path) -> Result<ServeConfig> {
+    let project = project_roostrc(cwd)
+        .map(|p| ServeConfig::load(&p))
+        .transpose()?
+        .unwrap_or_default();
+    let global = ServeConfig::load(&paths.roostrc_global)?;
+    if project.lan && !global.lan {
+        eprintln!(
+            "Warning: ignoring 'lan = true' in the project .roostrc; enable LAN exposure in {} or with --global --lan",
+            paths.roostrc_global.display()
+        );
+    }
+    Ok(merge_serve_configs(&project, &global))
+}
+
+/// Fail when one of `ips` is not a loopback address and LAN exposure isn't allowed. `what`
+/// names the listener in the error and `how` says how to allow it.
+pub fn require_loopback(
+    ips: impl IntoIterator<Item = IpAddr>,
+    lan: bool,
+    what: &str,
+    how: &str,
+) -> Result<()> {
+    if lan {
+        return Ok(());
+    }
+    match ips.into_iter().find(|ip| !ip.is_loopback()) {
+        Some(ip) => anyhow::bail!("{what} binds {ip}, which exposes it to the network; {how}"),
+        None => Ok(()),
+    }
+}
+
 /// Merged mapping with source for list output.
 #[derive(Debug, Clone)]
 pub struct MergedMapping {
@@ -0,0 +1,225 @@
+//! Control channel to a running `roost serve`: loopback socket plus a token in control.json.
+
+use anyhow::{Context, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::RoostPaths;
+use crate::serve::chaos::Chaos;
+
+/// Largest accepted request line; control messages are tiny.
+const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+
+/// Time a client has to send its request line before the connection is closed.
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Command sent to the running proxy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ControlRequest {
+    ChaosSet {
+        domain: String,
+        chaos: Chaos,
+    },
+    ChaosClear {
+        domain: String,
+    },
+    ChaosList,
+    CachePurge {
+        domain: Option<String>,
+        path_prefix: Option<String>,
+    },
+    CertReload {
+        domain: Option<String>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    token: String,
+    #[serde(flatten)]
+    request: ControlRequest,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ControlResponse {
+    ok: bool,
+    #[serde(default)]
+    error: String,
+    #[serde(default)]
+    data: serde_json::Value,
+}
+
+/// Contents of control.json, written by the proxy on startup.
+#[derive(Debug, Serialize, Deserialize)]
+struct ControlInfo {
+    addr: SocketAddr,
+    token: String,
+    pid: u32,
+}
+
+/// Handles a request inside the proxy; the returned value is sent back as `data`.
+pub type ControlHandler = Arc<dyn Fn(ControlRequest) -> Result<serde_json::Value> + Send + Sync>;
+
+fn control_json_path(paths: &RoostPaths) -> PathBuf {
+    paths.config_dir.join("control.json")
+}
+
+fn write_control_info(paths: &RoostPaths, info: &ControlInfo) -> Result<()> {
+    let path = control_json_path(paths);
+    if let Some(p) = path.parent() {
+        std::fs::create_dir_all(p)?;
+    }
+    let mut opts = std::fs::OpenOptions::new();
+    opts.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o600);
+    }
+    let mut file = opts
+        .open(&path)
+        .with_context(|| format!("write {}", path.display()))?;
+    file.write_all(serde_json::to_string_pretty(info)?.as_bytes())?;
+    Ok(())
+}
+
+/// Remove control.json if it belongs to this process (on proxy shutdown).
+pub fn remove_control_info(paths: &RoostPaths) {
+    let path = control_json_path(paths);
+    let ours = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<ControlInfo>(&s).ok())
+        .is_some_and(|info| info.pid == std::process::id());
+    if ours {
+        let _ = std::fs::remove_file(&path);
+    }
+}
+
+/// Bind the control socket on an ephemeral loopback port and serve requests in the background
+/// until `shutdown`.
+pub async fn start_control_server(
+    paths: &RoostPaths,
+    handler: ControlHandler,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    // The token only keeps out other local users; the socket must never leave the machine
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .context("bind control socket")?;
+    let token = format!("{:032x}", rand::rng().random::<u128>());
+    write_control_info(
+        paths,
+        &ControlInfo {
+            addr: listener.local_addr()?,
+            token: token.clone(),
+            pid: std::process::id(),
+        },
+    )?;
+    let token = Arc::new(token);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(s) => s,
+                    Err(e) => {
+                        eprintln!("control accept error: {e}");
+                        continue;
+                    }
+                },
+                _ = shutdown.cancelled() => return,
+            };
+            let handler = handler.clone();
+            let token = token.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, &token, handler).await {
+                    eprintln!("control connection error: {e:#}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn token_matches(sent: &str, token: &str) -> bool {
+    use subtle::ConstantTimeEq;
+    bool::from(sent.as_bytes().ct_eq(token.as_bytes()))
+}
+
+async fn handle_connection(
+    stream: tokio::net::TcpStream,
+    token: &str,
+    handler: ControlHandler,
+) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    let mut reader = tokio::io::BufReader::new(read.take(MAX_REQUEST_BYTES));
+    tokio::time::timeout(REQUEST_READ_TIMEOUT, reader.read_line(&mut line))
+        .await
+        .context("control request timed out")??;
+    let response = match serde_json::from_str::<Envelope>(&line) {
+        Ok(env) if token_matches(&env.token, token) => match handler(env.request) {
+            Ok(data) => ControlResponse {
+                ok: true,
+                error: String::new(),
+                data,
+            },
+            Err(e) => ControlResponse {
+                ok: false,
+                error: format!("{e:#}"),
+                data: serde_json::Value::Null,
+            },
+        },
+        Ok(_) => ControlResponse {
+            ok: false,
+            error: "invalid control token".to_string(),
+            data: serde_json::Value::Null,
+        },
+        Err(e) => ControlResponse {
+            ok: false,
+            error: format!("invalid request: {e}"),
+            data: serde_json::Value::Null,
+        },
+    };
+    let mut out = serde_json::to_vec(&response)?;
+    out.push(b'\n');
+    write.write_all(&out).await?;
+    Ok(())
+}
+
+/// Send a request to the running proxy and return its `data`.
+pub fn send_control(paths: &RoostPaths, request: ControlRequest) -> Result<serde_json::Value> {
+    let not_running = || {
+        "no running 'roost serve' found; start it with 'roost serve' or 'roost serve daemon start'"
+    };
+    let path = control_json_path(paths);
+    let s = std::fs::read_to_string(&path).with_context(not_running)?;
+    let info: ControlInfo = serde_json::from_str(&s).context("parse control.json")?;
+
+    let mut stream =
+        TcpStream::connect_timeout(&info.addr, Duration::from_secs(2)).with_context(not_running)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut msg = serde_json::to_vec(&Envelope {
+        token: info.token,
+        request,
+    })?;
+    msg.push(b'\n');
+    stream.write_all(&msg)?;
+
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_REQUEST_BYTES)).read_line(&mut line)?;
+    let response: ControlResponse =
+        serde_json::from_str(&line).context("invalid response from proxy")?;
+    if !response.ok {
+        anyhow::bail!("{}", response.error);
+    }
+    Ok(response.data)
+}
@@ -107,7 +107,10 @@ pub fn start_daemon(paths: &RoostPaths) -> Result<()> {
     Ok(())
 }
 
-/// Stop daemon: send SIGTERM, clear state.
+/// Longest `daemon stop` waits for the proxy to drain and exit after SIGTERM.
+const STOP_WAIT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Stop daemon: send SIGTERM, wait for in-flight requests to drain, clear state.
 pub fn stop_daemon(paths: &RoostPaths) -> Result<()> {
     let state = match read_state(paths)? {
         Some(s) => s,
@@ -128,9 +131,20 @@ pub fn stop_daemon(paths: &RoostPaths) -> Result<()> {
         unsafe {
             libc::kill(state.pid as i32, libc::SIGTERM);
         }
+        let deadline = std::time::Instant::now() + STOP_WAIT;
+        while is_pid_alive(state.pid) && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
         clear_state(paths)?;
-        println!("Daemon stopped (pid={})", state.pid);
-        return Ok(());
+        if is_pid_alive(state.pid) {
+            println!(
+                "Stop signal sent (pid={}); still draining connections",
+                state.pid
+            );
+        } else {
+            println!("Daemon stopped (pid={})", state.pid);
+        }
+        Ok(())
     }
     #[cfg(not(unix))]
     {
@@ -0,0 +1,164 @@
+//! Forward auth: before proxying, ask an auth endpoint about each request, like Traefik's
+//! ForwardAuth or nginx `auth_request`. A 2xx lets the request through; anything else is
+//! the response the client gets.
+
+use anyhow::{Context, Result};
+use http::header::{
+    CONNECTION, CONTENT_LENGTH, EXPECT, HOST, TE, TRAILER, TRANSFER_ENCODING, UPGRADE,
+};
+use http::{HeaderMap, HeaderName, Request, Response, StatusCode, Uri};
+use http_body_util::{BodyExt, Empty};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::serve::proxy::{BoxError, ProxyBody};
+
+/// How long the auth endpoint has to answer before the client gets a 504.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Auth endpoint for a mapping (`[serve.mappings.forward_auth]`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForwardAuth {
+    /// Endpoint called for each request, e.g. `http://localhost:9091/api/verify`.
+    pub url: String,
+    /// Headers copied from a 2xx auth response onto the upstream request. Client-sent
+    /// values of these headers are always dropped, so they can't be forged.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub response_headers: Vec<String>,
+}
+
+impl ForwardAuth {
+    pub fn validate(&self) -> Result<()> {
+        let uri: Uri = self
+            .url
+            .parse()
+            .with_context(|| format!("invalid forward auth url {:?}", self.url))?;
+        if uri.scheme_str() != Some("http") || uri.authority().is_none() {
+            anyhow::bail!(
+                "forward auth url must be http://host[:port]/path, got {:?}",
+                self.url
+            );
+        }
+        for name in &self.response_headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid header name {name:?}"))?;
+        }
+        Ok(())
+    }
+
+    fn copied_headers(&self) -> impl Iterator<Item = HeaderName> + '_ {
+        self.response_headers
+            .iter()
+            .filter_map(|n| HeaderName::from_bytes(n.as_bytes()).ok())
+    }
+}
+
+/// The original request as the client sent it, for the `X-Forwarded-*` headers.
+#[derive(Debug, Clone, Copy)]
+pub struct Original<'a> {
+    pub tls: bool,
+    pub host: &'a str,
+    pub client_ip: IpAddr,
+}
+
+/// Outcome of asking the auth endpoint.
+pub enum Decision {
+    /// Headers to set on the upstream request.
+    Allow(HeaderMap),
+    /// Response to return to the client instead of proxying.
+    Deny(Response<ProxyBody>),
+}
+
+/// Call the auth endpoint with the method and headers of `req` (without its body) and
+/// `X-Forwarded-Method`, `-Proto`, `-Host`, `-Uri` and `-For` describing it. Errors are
+/// turned into 502/504 responses.
+pub async fn authorize<B>(
+    auth: &ForwardAuth,
+    client: &Client<HttpConnector, ProxyBody>,
+    req: &Request<B>,
+    original: Original<'_>,
+) -> Decision {
+    let mut headers = req.headers().clone();
+    for name in [
+        HOST,
+        CONNECTION,
+        UPGRADE,
+        CONTENT_LENGTH,
+        TRANSFER_ENCODING,
+        TE,
+        TRAILER,
+        EXPECT,
+    ] {
+        headers.remove(name);
+    }
+    let uri = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    for (name, value) in [
+        ("x-forwarded-method", req.method().as_str()),
+        (
+            "x-forwarded-proto",
+            if original.tls { "https" } else { "http" },
+        ),
+        ("x-forwarded-host", original.host),
+        ("x-forwarded-uri", uri),
+        ("x-forwarded-for", &original.client_ip.to_string()),
+    ] {
+        if let Ok(value) = value.parse() {
+            headers.insert(name, value);
+        }
+    }
+    let mut builder = Request::builder()
+        .method(req.method().clone())
+        .uri(&auth.url);
+    if let Some(h) = builder.headers_mut() {
+        *h = headers;
+    }
+    let auth_req = match builder.body(Empty::new().map_err(|never| match never {}).boxed()) {
+        Ok(r) => r,
+        Err(e) => return Decision::Deny(error(StatusCode::BAD_GATEWAY, &auth.url, e)),
+    };
+    let response = match tokio::time::timeout(AUTH_TIMEOUT, client.request(auth_req)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => return Decision::Deny(error(StatusCode::BAD_GATEWAY, &auth.url, e)),
+        Err(_) => {
+            return Decision::Deny(error(StatusCode::GATEWAY_TIMEOUT, &auth.url, "no response"))
+        }
+    };
+    if !response.status().is_success() {
+        return Decision::Deny(response.map(|b| b.map_err(BoxError::from).boxed()));
+    }
+    let mut copied = HeaderMap::new();
+    for name in auth.copied_headers() {
+        for value in response.headers().get_all(&name) {
+            copied.append(name.clone(), value.clone());
+        }
+    }
+    Decision::Allow(copied)
+}
+
+/// Drop client-sent values of the headers the auth endpoint provides, then add the
+/// endpoint's.
+pub fn apply(auth: &ForwardAuth, headers: &mut HeaderMap, allowed: HeaderMap) {
+    for name in auth.copied_headers() {
+        headers.remove(name);
+    }
+    headers.extend(allowed);
+}
+
+fn error(status: StatusCode, url: &str, e: impl std::fmt::Display) -> Response<ProxyBody> {
+    let body = format!("Auth endpoint {url} failed: {e}\n");
+    Response::builder()
+        .status(status)
+        .body(
+            http_body_util::Full::new(body.into())
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .unwrap()
+}
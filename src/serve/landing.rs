@@ -0,0 +1,135 @@
+//! Landing page for requests to unmapped hosts: the configured domains and whether
+//! their backends are up.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a backend to accept a connection before reporting it down.
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// How long a probe result is reused, so a burst of unmapped-host requests doesn't connect
+/// to every backend each time.
+pub const PROBE_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// A configured mapping as listed on the landing page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainStatus {
+    pub domain: String,
+    pub port: u16,
+    pub up: bool,
+}
+
+/// Whether something accepts connections on `localhost:<port>`.
+pub async fn backend_up(port: u16) -> bool {
+    matches!(
+        tokio::time::timeout(
+            PROBE_TIMEOUT,
+            tokio::net::TcpStream::connect(("localhost", port))
+        )
+        .await,
+        Ok(Ok(_))
+    )
+}
+
+/// Recent backend probe results by port.
+#[derive(Debug)]
+pub struct ProbeCache {
+    ttl: Duration,
+    ports: Mutex<HashMap<u16, (Instant, bool)>>,
+}
+
+impl ProbeCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            ports: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Status of every mapping's backend, probing concurrently those without a result from
+    /// the last `ttl`; sorted by domain.
+    pub async fn probe(
+        &self,
+        mappings: impl IntoIterator<Item = (String, u16)>,
+    ) -> Vec<DomainStatus> {
+        let mappings: Vec<(String, u16)> = mappings.into_iter().collect();
+        let mut known: HashMap<u16, bool> = {
+            let mut ports = self.ports.lock().unwrap();
+            ports.retain(|_, (at, _)| at.elapsed() < self.ttl);
+            ports.iter().map(|(port, (_, up))| (*port, *up)).collect()
+        };
+        let mut stale: Vec<u16> = mappings
+            .iter()
+            .map(|(_, port)| *port)
+            .filter(|port| !known.contains_key(port))
+            .collect();
+        stale.sort_unstable();
+        stale.dedup();
+        let probed = futures_util::future::join_all(
+            stale
+                .into_iter()
+                .map(|port| async move { (port, backend_up(port).await) }),
+        )
+        .await;
+        let now = Instant::now();
+        self.ports
+            .lock()
+            .unwrap()
+            .extend(probed.iter().map(|(port, up)| (*port, (now, *up))));
+        known.extend(probed);
+
+        let mut statuses: Vec<DomainStatus> = mappings
+            .into_iter()
+            .map(|(domain, port)| DomainStatus {
+                up: known[&port],
+                domain,
+                port,
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.domain.cmp(&b.domain));
+        statuses
+    }
+}
+
+/// Render the page for a request to `host`. `origin` maps a domain to its public origin.
+pub fn render(host: &str, domains: &[DomainStatus], origin: impl Fn(&str) -> String) -> String {
+    let rows: String = domains
+        .iter()
+        .map(|d| {
+            format!(
+                "<tr><td><a href=\"{origin}/\">{domain}</a></td><td>localhost:{port}</td>\
+                 <td class=\"{class}\">{status}</td></tr>\n",
+                origin = escape_html(&origin(&d.domain)),
+                domain = escape_html(&d.domain),
+                port = d.port,
+                class = if d.up { "up" } else { "down" },
+                status = if d.up { "up" } else { "not responding" },
+            )
+        })
+        .collect();
+    let list = if domains.is_empty() {
+        "<p>No domains are configured; add one with <code>roost serve config add &lt;domain&gt; &lt;port&gt;</code>.</p>\n".to_string()
+    } else {
+        format!(
+            "<table>\n<tr><th>Domain</th><th>Backend</th><th>Status</th></tr>\n{rows}</table>\n"
+        )
+    };
+    format!(
+        "<!doctype html>\n<meta charset=\"utf-8\">\n<title>Unknown domain</title>\n\
+         <style>body{{font-family:system-ui,sans-serif;margin:2em}}td,th{{padding:.2em 1em;text-align:left}}\
+         .up{{color:#080}}.down{{color:#b00}}</style>\n\
+         <h1>{host} is not configured in roost</h1>\n\
+         <p>Add it with <code>roost serve config add {host} &lt;port&gt;</code>, or open one of the configured domains:</p>\n\
+         {list}",
+        host = escape_html(host),
+    )
+}
+
+pub(crate) fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
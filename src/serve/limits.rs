@@ -0,0 +1,257 @@
+//! Timeouts and request size limits, set globally in `[serve.limits]` and per mapping.
+
+use anyhow::Result;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::time::Sleep;
+
+use crate::serve::proxy::ProxyBody;
+
+/// Default time allowed to connect to a backend.
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+/// Default time allowed for a client to send request headers (and finish the TLS handshake).
+pub const DEFAULT_HEADER_READ_TIMEOUT_SECS: u64 = 30;
+/// Default time a keep-alive connection may sit without requests before it is closed.
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 120;
+/// Default time allowed for the backend to send its response headers.
+pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 300;
+/// Default time to drain in-flight requests on shutdown.
+pub const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 10;
+
+/// Timeouts and size limits. Unset fields fall back to the global `[serve.limits]`, then to
+/// the defaults above. A value of 0 disables the idle timeout, request timeout or body limit.
+/// The body timeout has no default, so streamed responses are never cut off unless it is set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header_read_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_timeout_secs: Option<u64>,
+    /// Time allowed for the backend to finish the response body once its headers arrived.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_timeout_secs: Option<u64>,
+    /// Largest request body accepted, in bytes (unlimited by default).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_bytes: Option<u64>,
+}
+
+/// Limits with defaults applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EffectiveLimits {
+    pub connect_timeout: Duration,
+    pub header_read_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub request_timeout: Option<Duration>,
+    pub body_timeout: Option<Duration>,
+    pub max_body_bytes: Option<u64>,
+}
+
+fn secs(v: Option<u64>, default: u64) -> Option<Duration> {
+    match v.unwrap_or(default) {
+        0 => None,
+        n => Some(Duration::from_secs(n)),
+    }
+}
+
+impl Limits {
+    /// Fill unset fields from `fallback`.
+    pub fn or(self, fallback: &Limits) -> Limits {
+        Limits {
+            connect_timeout_secs: self.connect_timeout_secs.or(fallback.connect_timeout_secs),
+            header_read_timeout_secs: self
+                .header_read_timeout_secs
+                .or(fallback.header_read_timeout_secs),
+            idle_timeout_secs: self.idle_timeout_secs.or(fallback.idle_timeout_secs),
+            request_timeout_secs: self.request_timeout_secs.or(fallback.request_timeout_secs),
+            body_timeout_secs: self.body_timeout_secs.or(fallback.body_timeout_secs),
+            max_body_bytes: self.max_body_bytes.or(fallback.max_body_bytes),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    /// Connect and header-read timeouts must be positive; the others may be 0 (off).
+    pub fn validate(&self) -> Result<()> {
+        if self.connect_timeout_secs == Some(0) {
+            anyhow::bail!("connect_timeout_secs must be greater than 0");
+        }
+        if self.header_read_timeout_secs == Some(0) {
+            anyhow::bail!("header_read_timeout_secs must be greater than 0");
+        }
+        Ok(())
+    }
+
+    pub fn effective(&self) -> EffectiveLimits {
+        EffectiveLimits {
+            connect_timeout: Duration::from_secs(
+                self.connect_timeout_secs
+                    .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS)
+                    .max(1),
+            ),
+            header_read_timeout: Duration::from_secs(
+                self.header_read_timeout_secs
+                    .unwrap_or(DEFAULT_HEADER_READ_TIMEOUT_SECS)
+                    .max(1),
+            ),
+            idle_timeout: secs(self.idle_timeout_secs, DEFAULT_IDLE_TIMEOUT_SECS),
+            request_timeout: secs(self.request_timeout_secs, DEFAULT_REQUEST_TIMEOUT_SECS),
+            body_timeout: secs(self.body_timeout_secs, 0),
+            max_body_bytes: self.max_body_bytes.filter(|&n| n > 0),
+        }
+    }
+}
+
+/// Requests in flight on one client connection, and when it last started or finished one.
+#[derive(Debug)]
+pub struct ConnActivity {
+    in_flight: AtomicUsize,
+    last_active: Mutex<Instant>,
+}
+
+impl ConnActivity {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            in_flight: AtomicUsize::new(0),
+            last_active: Mutex::new(Instant::now()),
+        })
+    }
+
+    fn touch(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+    }
+
+    /// Mark a request as started; it counts as in flight until the guard is dropped.
+    pub fn begin(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.touch();
+        InFlight(self.clone())
+    }
+
+    /// Resolve once the connection has had no request in flight for `timeout`.
+    pub async fn idle(&self, timeout: Duration) {
+        loop {
+            let wait = if self.in_flight.load(Ordering::SeqCst) > 0 {
+                timeout
+            } else {
+                timeout.saturating_sub(self.last_active.lock().unwrap().elapsed())
+            };
+            if wait.is_zero() {
+                return;
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Guard for one in-flight request; see [`ConnActivity::begin`].
+#[derive(Debug)]
+pub struct InFlight(Arc<ConnActivity>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.0.touch();
+    }
+}
+
+/// Response body that keeps its request in flight until the body is done streaming.
+pub struct TrackedBody {
+    inner: ProxyBody,
+    _in_flight: InFlight,
+}
+
+impl TrackedBody {
+    pub fn new(inner: ProxyBody, in_flight: InFlight) -> Self {
+        Self {
+            inner,
+            _in_flight: in_flight,
+        }
+    }
+}
+
+impl Body for TrackedBody {
+    type Data = Bytes;
+    type Error = <ProxyBody as Body>::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Error ending a [`DeadlineBody`] whose response was still streaming at the deadline.
+#[derive(Debug)]
+pub struct DeadlineExceeded(pub Duration);
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "response not finished within {}s", self.0.as_secs())
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Response body that fails with [`DeadlineExceeded`] if it is still streaming at `deadline`,
+/// enforcing the opt-in body timeout.
+pub struct DeadlineBody {
+    inner: ProxyBody,
+    sleep: Pin<Box<Sleep>>,
+    timeout: Duration,
+}
+
+impl DeadlineBody {
+    /// `timeout` is the configured body timeout, reported in the error.
+    pub fn new(inner: ProxyBody, deadline: tokio::time::Instant, timeout: Duration) -> Self {
+        Self {
+            inner,
+            sleep: Box::pin(tokio::time::sleep_until(deadline)),
+            timeout,
+        }
+    }
+}
+
+impl Body for DeadlineBody {
+    type Data = Bytes;
+    type Error = <ProxyBody as Body>::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        if self.sleep.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Some(Err(Box::new(DeadlineExceeded(self.timeout)))));
+        }
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
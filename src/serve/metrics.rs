@@ -0,0 +1,515 @@
+//! Proxy metrics: counters, gauges and latency histograms in Prometheus text format.
+
+use anyhow::{Context as _, Result};
+use http::{Request, Response, StatusCode};
+use http_body_util::Full;
+use hyper::body::{Body, Bytes, Frame, Incoming, SizeHint};
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::serve::proxy::ProxyBody;
+
+/// Label used for requests whose host matches no mapping, to keep label cardinality bounded.
+pub const OTHER_DOMAIN: &str = "other";
+
+/// Upper bounds (seconds) of the request duration histogram buckets.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// Counters for one domain.
+#[derive(Debug, Default)]
+pub struct DomainStats {
+    /// Requests per status class (1xx..5xx).
+    requests: [AtomicU64; 5],
+    /// Counts per bucket in `DURATION_BUCKETS`, plus +Inf (made cumulative when rendered).
+    buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_sum_micros: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl DomainStats {
+    /// Record a finished request: status class and time until the response head was ready.
+    pub fn observe(&self, status: StatusCode, elapsed: Duration) {
+        let class = (status.as_u16() / 100).clamp(1, 5) as usize - 1;
+        self.requests[class].fetch_add(1, Ordering::Relaxed);
+        let secs = elapsed.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|&b| secs <= b)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.duration_sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_in(&self, n: u64) {
+        self.bytes_in.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_out(&self, n: u64) {
+        self.bytes_out.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+/// Proxy-wide metrics, shared by all listeners and connections.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    domains: Mutex<BTreeMap<String, Arc<DomainStats>>>,
+    connections: AtomicI64,
+    tunnels: AtomicI64,
+    tunnels_total: AtomicU64,
+    handshake_failures: Mutex<BTreeMap<String, u64>>,
+    handshakes_full: AtomicU64,
+    handshakes_resumed: AtomicU64,
+    cert_expiry: Mutex<BTreeMap<String, i64>>,
+}
+
+impl Metrics {
+    /// Metrics with zeroed series for each mapped domain.
+    pub fn new<'a>(domains: impl IntoIterator<Item = &'a str>) -> Arc<Self> {
+        let metrics = Self::default();
+        {
+            let mut map = metrics.domains.lock().unwrap();
+            for domain in domains.into_iter().chain([OTHER_DOMAIN]) {
+                map.entry(domain.to_lowercase()).or_default();
+            }
+        }
+        Arc::new(metrics)
+    }
+
+    /// Switch to a new set of mapped domains (after a config reload). Domains still mapped
+    /// keep their counts; series for removed domains are dropped.
+    pub fn set_domains<'a>(&self, domains: impl IntoIterator<Item = &'a str>) {
+        let mut map = self.domains.lock().unwrap();
+        let mut kept = BTreeMap::new();
+        for domain in domains.into_iter().chain([OTHER_DOMAIN]) {
+            let domain = domain.to_lowercase();
+            let stats = map.remove(&domain).unwrap_or_default();
+            kept.insert(domain, stats);
+        }
+        *map = kept;
+    }
+
+    /// Stats for a domain; unknown domains share the `other` series.
+    pub fn domain(&self, domain: &str) -> Arc<DomainStats> {
+        let map = self.domains.lock().unwrap();
+        map.get(domain)
+            .or_else(|| map.get(OTHER_DOMAIN))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Count an open client connection until the guard is dropped.
+    pub fn connection(self: &Arc<Self>) -> Gauge {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        Gauge(self.clone(), GaugeKind::Connections)
+    }
+
+    /// Count an open websocket tunnel until the guard is dropped.
+    pub fn tunnel(self: &Arc<Self>) -> Gauge {
+        self.tunnels.fetch_add(1, Ordering::Relaxed);
+        self.tunnels_total.fetch_add(1, Ordering::Relaxed);
+        Gauge(self.clone(), GaugeKind::Tunnels)
+    }
+
+    pub fn handshake_failed(&self, reason: &str) {
+        *self
+            .handshake_failures
+            .lock()
+            .unwrap()
+            .entry(reason.to_string())
+            .or_default() += 1;
+    }
+
+    /// Count a completed TLS handshake; `resumed` when it resumed an earlier session.
+    pub fn handshake_completed(&self, resumed: bool) {
+        let counter = if resumed {
+            &self.handshakes_resumed
+        } else {
+            &self.handshakes_full
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record when the cert served for `domain` expires (unix seconds).
+    pub fn set_cert_expiry(&self, domain: &str, not_after: i64) {
+        self.cert_expiry
+            .lock()
+            .unwrap()
+            .insert(domain.to_lowercase(), not_after);
+    }
+
+    /// Render all metrics in the Prometheus text exposition format (version 0.0.4).
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let domains: Vec<(String, Arc<DomainStats>)> = self
+            .domains
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(d, s)| (d.clone(), s.clone()))
+            .collect();
+
+        header(
+            &mut out,
+            "roost_http_requests_total",
+            "counter",
+            "Proxied requests by domain and status class.",
+        );
+        for (domain, stats) in &domains {
+            for (class, count) in STATUS_CLASSES.iter().zip(&stats.requests) {
+                let _ = writeln!(
+                    out,
+                    "roost_http_requests_total{{domain=\"{}\",status=\"{class}\"}} {}",
+                    escape(domain),
+                    count.load(Ordering::Relaxed)
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "roost_http_request_duration_seconds",
+            "histogram",
+            "Time from receiving a request to sending the response head.",
+        );
+        for (domain, stats) in &domains {
+            let domain = escape(domain);
+            let mut cumulative = 0;
+            for (i, count) in stats.buckets.iter().enumerate() {
+                cumulative += count.load(Ordering::Relaxed);
+                let le = DURATION_BUCKETS
+                    .get(i)
+                    .map(|b| b.to_string())
+                    .unwrap_or_else(|| "+Inf".to_string());
+                let _ = writeln!(
+                    out,
+                    "roost_http_request_duration_seconds_bucket{{domain=\"{domain}\",le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let sum = stats.duration_sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+            let _ = writeln!(
+                out,
+                "roost_http_request_duration_seconds_sum{{domain=\"{domain}\"}} {sum}"
+            );
+            let _ = writeln!(
+                out,
+                "roost_http_request_duration_seconds_count{{domain=\"{domain}\"}} {cumulative}"
+            );
+        }
+
+        for (name, help, pick) in [
+            (
+                "roost_http_request_bytes_total",
+                "Request body bytes received from clients.",
+                (|s: &DomainStats| &s.bytes_in) as fn(&DomainStats) -> &AtomicU64,
+            ),
+            (
+                "roost_http_response_bytes_total",
+                "Response body bytes sent to clients.",
+                |s: &DomainStats| &s.bytes_out,
+            ),
+        ] {
+            header(&mut out, name, "counter", help);
+            for (domain, stats) in &domains {
+                let _ = writeln!(
+                    out,
+                    "{name}{{domain=\"{}\"}} {}",
+                    escape(domain),
+                    pick(stats).load(Ordering::Relaxed)
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "roost_active_connections",
+            "gauge",
+            "Open client connections.",
+        );
+        let _ = writeln!(
+            out,
+            "roost_active_connections {}",
+            self.connections.load(Ordering::Relaxed)
+        );
+        header(
+            &mut out,
+            "roost_websocket_tunnels",
+            "gauge",
+            "Open websocket tunnels.",
+        );
+        let _ = writeln!(
+            out,
+            "roost_websocket_tunnels {}",
+            self.tunnels.load(Ordering::Relaxed)
+        );
+        header(
+            &mut out,
+            "roost_websocket_tunnels_total",
+            "counter",
+            "Websocket tunnels opened.",
+        );
+        let _ = writeln!(
+            out,
+            "roost_websocket_tunnels_total {}",
+            self.tunnels_total.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "roost_tls_handshakes_total",
+            "counter",
+            "Completed TLS handshakes: full, or resumed from a session cache entry or ticket.",
+        );
+        for (kind, counter) in [
+            ("full", &self.handshakes_full),
+            ("resumed", &self.handshakes_resumed),
+        ] {
+            let _ = writeln!(
+                out,
+                "roost_tls_handshakes_total{{kind=\"{kind}\"}} {}",
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        header(
+            &mut out,
+            "roost_tls_handshake_failures_total",
+            "counter",
+            "Failed TLS handshakes by reason.",
+        );
+        for (reason, count) in self.handshake_failures.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "roost_tls_handshake_failures_total{{reason=\"{}\"}} {count}",
+                escape(reason)
+            );
+        }
+
+        header(
+            &mut out,
+            "roost_cert_expiry_timestamp_seconds",
+            "gauge",
+            "Expiry (notAfter) of the certificate served for each domain, as a unix timestamp.",
+        );
+        for (domain, ts) in self.cert_expiry.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "roost_cert_expiry_timestamp_seconds{{domain=\"{}\"}} {ts}",
+                escape(domain)
+            );
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Escape a label value (backslash, double quote, newline).
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[derive(Debug)]
+enum GaugeKind {
+    Connections,
+    Tunnels,
+}
+
+/// Guard that keeps a gauge incremented while alive; see [`Metrics::connection`].
+#[derive(Debug)]
+pub struct Gauge(Arc<Metrics>, GaugeKind);
+
+impl Drop for Gauge {
+    fn drop(&mut self) {
+        let gauge = match self.1 {
+            GaugeKind::Connections => &self.0.connections,
+            GaugeKind::Tunnels => &self.0.tunnels,
+        };
+        gauge.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Why a TLS handshake failed, as a metric label.
+pub fn handshake_failure_reason(err: &std::io::Error) -> String {
+    let Some(tls) = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<rustls::Error>())
+    else {
+        return match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => "eof",
+            std::io::ErrorKind::ConnectionReset => "reset",
+            _ => "io",
+        }
+        .to_string();
+    };
+    match tls {
+        rustls::Error::General(msg) if msg.contains("no server certificate") => {
+            "unknown_sni".to_string()
+        }
+        rustls::Error::AlertReceived(alert) => {
+            format!("alert_{}", snake_case(&format!("{alert:?}")))
+        }
+        rustls::Error::NoApplicationProtocol => "no_application_protocol".to_string(),
+        rustls::Error::PeerIncompatible(_) => "peer_incompatible".to_string(),
+        rustls::Error::PeerMisbehaved(_) => "peer_misbehaved".to_string(),
+        rustls::Error::InvalidMessage(_) => "invalid_message".to_string(),
+        _ => "other".to_string(),
+    }
+}
+
+/// `UnknownCA` -> `unknown_ca`, `BadCertificate` -> `bad_certificate`.
+fn snake_case(s: &str) -> String {
+    let mut out = String::new();
+    let mut prev_lower = false;
+    for c in s.chars() {
+        if c.is_ascii_uppercase() && prev_lower {
+            out.push('_');
+        }
+        prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+/// Body that adds the size of each data frame to a byte counter as it streams.
+pub struct CountingBody {
+    inner: ProxyBody,
+    stats: Arc<DomainStats>,
+    outgoing: bool,
+}
+
+impl CountingBody {
+    /// Count request body bytes received from the client.
+    pub fn incoming(inner: ProxyBody, stats: Arc<DomainStats>) -> Self {
+        Self {
+            inner,
+            stats,
+            outgoing: false,
+        }
+    }
+
+    /// Count response body bytes sent to the client.
+    pub fn outgoing(inner: ProxyBody, stats: Arc<DomainStats>) -> Self {
+        Self {
+            inner,
+            stats,
+            outgoing: true,
+        }
+    }
+}
+
+impl Body for CountingBody {
+    type Data = Bytes;
+    type Error = <ProxyBody as Body>::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                let n = data.len() as u64;
+                if self.outgoing {
+                    self.stats.add_bytes_out(n);
+                } else {
+                    self.stats.add_bytes_in(n);
+                }
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Serve `GET /metrics` on `listener` until `shutdown` is cancelled.
+async fn serve(listener: TcpListener, metrics: Arc<Metrics>, shutdown: CancellationToken) {
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((s, _)) => s,
+                Err(e) => {
+                    eprintln!("metrics accept error: {e}");
+                    continue;
+                }
+            },
+            _ = shutdown.cancelled() => return,
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req: Request<Incoming>| {
+                let metrics = metrics.clone();
+                async move { Ok::<_, std::convert::Infallible>(metrics_response(&req, &metrics)) }
+            });
+            let _ = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+fn metrics_response<B>(req: &Request<B>, metrics: &Metrics) -> Response<Full<Bytes>> {
+    let (status, content_type, body) = match (req.method(), req.uri().path()) {
+        (&http::Method::GET, "/metrics") => (
+            StatusCode::OK,
+            "text/plain; version=0.0.4; charset=utf-8",
+            metrics.render(),
+        ),
+        (_, "/metrics") => (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "text/plain",
+            "Method not allowed\n".to_string(),
+        ),
+        _ => (
+            StatusCode::NOT_FOUND,
+            "text/plain",
+            "Not found; metrics are served at /metrics\n".to_string(),
+        ),
+    };
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, content_type)
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+}
+
+/// Bind the metrics endpoint and serve it in the background; returns the bound address.
+pub fn start(
+    addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    shutdown: CancellationToken,
+) -> Result<SocketAddr> {
+    let listener = crate::serve::proxy::bind_listener(addr)
+        .with_context(|| format!("bind metrics endpoint {addr}"))?;
+    let local = listener.local_addr()?;
+    tokio::spawn(serve(listener, metrics, shutdown));
+    Ok(local)
+}
@@ -0,0 +1,75 @@
+//! Extension point for embedding the proxy in other Rust tools: middleware runs around
+//! every request a listener accepts, with roost's TLS, certificates and routing still in
+//! place behind it. Start a proxy with middleware via [`run_proxy_with`].
+//!
+//! [`run_proxy_with`]: crate::serve::proxy::run_proxy_with
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use http::{Request, Response};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::serve::proxy::ProxyBody;
+
+/// The connection a request arrived on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestInfo {
+    pub remote_addr: SocketAddr,
+    /// Port the client connected to.
+    pub local_port: u16,
+    pub tls: bool,
+}
+
+/// Custom request/response logic. A middleware may change the request before passing it
+/// on with [`Next::run`], change the response it gets back, or answer the request itself
+/// without calling `next`. Changes to the `Host` header or path take effect for routing.
+///
+/// Errors are answered with a 502, like backend connection errors.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle<'a>(
+        &'a self,
+        req: Request<ProxyBody>,
+        info: RequestInfo,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Response<ProxyBody>>>;
+}
+
+type Endpoint<'a> =
+    dyn Fn(Request<ProxyBody>) -> BoxFuture<'a, Result<Response<ProxyBody>>> + Send + Sync + 'a;
+
+/// The rest of the chain: later middleware, then roost's own request handling.
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    info: RequestInfo,
+    endpoint: &'a Endpoint<'a>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middleware: &'a [Arc<dyn Middleware>],
+        info: RequestInfo,
+        endpoint: &'a Endpoint<'a>,
+    ) -> Self {
+        Self {
+            middleware,
+            info,
+            endpoint,
+        }
+    }
+
+    /// Pass the request on and return the response.
+    pub fn run(self, req: Request<ProxyBody>) -> BoxFuture<'a, Result<Response<ProxyBody>>> {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(
+                req,
+                self.info,
+                Next {
+                    middleware: rest,
+                    ..self
+                },
+            ),
+            None => (self.endpoint)(req),
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Traffic mirroring: copy each request to a shadow backend and discard its response.
+//! The shadow never delays or fails the client's request; its body copy is dropped
+//! when it falls behind.
+
+use anyhow::Result;
+use http::{Method, Request, StatusCode};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::serve::proxy::{BoxError, ProxyBody};
+
+/// Latency difference reported by `compare` when `latency_threshold_ms` is unset.
+pub const DEFAULT_LATENCY_THRESHOLD_MS: u64 = 100;
+
+/// Shadow requests still running after this long are abandoned.
+pub const SHADOW_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Request body frames buffered for the shadow before its copy is abandoned.
+const BODY_BUFFER_FRAMES: usize = 64;
+
+/// Header added to mirrored requests so the shadow can tell them apart.
+pub const MIRROR_HEADER: &str = "x-roost-mirror";
+
+/// Shadow backend for a mapping (`[serve.mappings.mirror]`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mirror {
+    /// Port of the shadow backend on localhost.
+    pub port: u16,
+    /// Log requests whose shadow status differs from the primary's, or whose latency
+    /// differs by more than `latency_threshold_ms`, and shadow failures.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compare: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_threshold_ms: Option<u64>,
+}
+
+impl Mirror {
+    pub fn validate(&self) -> Result<()> {
+        if self.port == 0 {
+            anyhow::bail!("mirror port must not be 0");
+        }
+        Ok(())
+    }
+
+    fn latency_threshold(&self) -> Duration {
+        Duration::from_millis(
+            self.latency_threshold_ms
+                .unwrap_or(DEFAULT_LATENCY_THRESHOLD_MS),
+        )
+    }
+}
+
+/// A mirrored request in flight. Feed it the primary request body with [`Shadow::tee`]
+/// and report the primary's outcome with [`Shadow::primary_done`].
+pub struct Shadow {
+    body_tx: Option<mpsc::Sender<Bytes>>,
+    aborted: Arc<AtomicBool>,
+    primary_tx: Option<oneshot::Sender<Option<(StatusCode, Duration)>>>,
+}
+
+impl Shadow {
+    /// Send a copy of `req` (already addressed to the primary backend) to the mirror port.
+    pub fn start<B: Body>(
+        mirror: &Mirror,
+        req: &Request<B>,
+        domain: &str,
+        client: Client<HttpConnector, ProxyBody>,
+    ) -> Self {
+        let (body_tx, body_rx) = mpsc::channel(BODY_BUFFER_FRAMES);
+        let aborted = Arc::new(AtomicBool::new(false));
+        let has_body = !req.body().is_end_stream();
+        let body = if has_body {
+            ShadowBody {
+                rx: body_rx,
+                aborted: aborted.clone(),
+            }
+            .boxed()
+        } else {
+            http_body_util::Empty::new()
+                .map_err(|never| match never {})
+                .boxed()
+        };
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/");
+        let mut builder = Request::builder()
+            .method(req.method().clone())
+            .uri(format!("http://localhost:{}{path}", mirror.port))
+            .version(req.version());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = req.headers().clone();
+            headers.insert(MIRROR_HEADER, http::HeaderValue::from_static("1"));
+        }
+        let shadow_req = builder.body(body);
+
+        let (primary_tx, primary_rx) = oneshot::channel();
+        let compare = mirror.compare.then(|| Comparison {
+            domain: domain.to_string(),
+            method: req.method().clone(),
+            path: path.to_string(),
+            threshold: mirror.latency_threshold(),
+            primary: primary_rx,
+        });
+        if let Ok(shadow_req) = shadow_req {
+            tokio::spawn(run(client, shadow_req, compare));
+        }
+        Self {
+            body_tx: has_body.then_some(body_tx),
+            aborted,
+            primary_tx: Some(primary_tx),
+        }
+    }
+
+    /// Wrap the primary request body so each data frame is also sent to the shadow.
+    pub fn tee(&mut self, body: ProxyBody) -> ProxyBody {
+        match self.body_tx.take() {
+            Some(tx) => TeeBody {
+                inner: body,
+                tx: Some(tx),
+                aborted: self.aborted.clone(),
+            }
+            .boxed(),
+            None => body,
+        }
+    }
+
+    /// Report the primary's status (None if it failed) and time to response head.
+    pub fn primary_done(mut self, status: Option<StatusCode>, elapsed: Duration) {
+        if let Some(tx) = self.primary_tx.take() {
+            let _ = tx.send(status.map(|s| (s, elapsed)));
+        }
+    }
+}
+
+/// What to compare once both responses are in.
+struct Comparison {
+    domain: String,
+    method: Method,
+    path: String,
+    threshold: Duration,
+    primary: oneshot::Receiver<Option<(StatusCode, Duration)>>,
+}
+
+async fn run(
+    client: Client<HttpConnector, ProxyBody>,
+    req: Request<ProxyBody>,
+    compare: Option<Comparison>,
+) {
+    let started = Instant::now();
+    let shadow = tokio::time::timeout(SHADOW_TIMEOUT, async {
+        let response = client.request(req).await?;
+        let status = response.status();
+        let elapsed = started.elapsed();
+        // Drain so the connection can be reused; the content is discarded
+        let _ = response.into_body().collect().await;
+        Ok::<_, hyper_util::client::legacy::Error>((status, elapsed))
+    })
+    .await;
+    let Some(compare) = compare else {
+        return;
+    };
+    let prefix = format!(
+        "mirror {} {} {}",
+        compare.domain, compare.method, compare.path
+    );
+    let (shadow_status, shadow_elapsed) = match shadow {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => {
+            eprintln!("{prefix}: shadow failed: {e}");
+            return;
+        }
+        Err(_) => {
+            eprintln!(
+                "{prefix}: shadow did not respond within {}s",
+                SHADOW_TIMEOUT.as_secs()
+            );
+            return;
+        }
+    };
+    let Ok(Some((status, elapsed))) = compare.primary.await else {
+        return;
+    };
+    if let Some(diff) = compare_outcomes(
+        (status, elapsed),
+        (shadow_status, shadow_elapsed),
+        compare.threshold,
+    ) {
+        eprintln!("{prefix}: {diff}");
+    }
+}
+
+/// Describe how the shadow's (status, latency) differs from the primary's, or None if
+/// the statuses match and the latencies are within `threshold`.
+pub fn compare_outcomes(
+    primary: (StatusCode, Duration),
+    shadow: (StatusCode, Duration),
+    threshold: Duration,
+) -> Option<String> {
+    let status_differs = primary.0 != shadow.0;
+    let latency_differs = primary.1.abs_diff(shadow.1) > threshold;
+    if !status_differs && !latency_differs {
+        return None;
+    }
+    let mut parts = Vec::new();
+    if status_differs {
+        parts.push(format!(
+            "status {} vs shadow {}",
+            primary.0.as_u16(),
+            shadow.0.as_u16()
+        ));
+    }
+    if latency_differs {
+        parts.push(format!(
+            "latency {}ms vs shadow {}ms",
+            primary.1.as_millis(),
+            shadow.1.as_millis()
+        ));
+    }
+    Some(parts.join(", "))
+}
+
+/// The shadow's request body copy was abandoned because the shadow fell behind.
+#[derive(Debug)]
+struct MirrorAborted;
+
+impl std::fmt::Display for MirrorAborted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("mirror request body abandoned")
+    }
+}
+
+impl std::error::Error for MirrorAborted {}
+
+/// Primary request body that copies data frames to the shadow without ever waiting for it.
+struct TeeBody {
+    inner: ProxyBody,
+    tx: Option<mpsc::Sender<Bytes>>,
+    aborted: Arc<AtomicBool>,
+}
+
+impl TeeBody {
+    fn abort(&mut self) {
+        self.aborted.store(true, Ordering::Relaxed);
+        self.tx = None;
+    }
+}
+
+impl Body for TeeBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let (Some(data), Some(tx)) = (frame.data_ref(), &self.tx) {
+                    if tx.try_send(data.clone()).is_err() {
+                        self.abort();
+                    }
+                }
+                // Callers may stop polling once the body reports its end
+                if self.inner.is_end_stream() {
+                    self.tx = None;
+                }
+            }
+            Poll::Ready(Some(Err(_))) => self.abort(),
+            // End of body: dropping the sender ends the shadow's body too
+            Poll::Ready(None) => self.tx = None,
+            Poll::Pending => {}
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for TeeBody {
+    fn drop(&mut self) {
+        // Dropped before the end (e.g. the primary failed): the shadow's copy is incomplete
+        if self.tx.is_some() {
+            self.abort();
+        }
+    }
+}
+
+/// Shadow request body fed by [`TeeBody`]; fails if the copy was abandoned.
+struct ShadowBody {
+    rx: mpsc::Receiver<Bytes>,
+    aborted: Arc<AtomicBool>,
+}
+
+impl Body for ShadowBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        match self.rx.poll_recv(cx) {
+            Poll::Ready(Some(data)) => Poll::Ready(Some(Ok(Frame::data(data)))),
+            Poll::Ready(None) if self.aborted.load(Ordering::Relaxed) => {
+                Poll::Ready(Some(Err(Box::new(MirrorAborted))))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
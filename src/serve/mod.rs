@@ -1,5 +1,24 @@
 //! Serve subcommands: config, proxy, daemon.
 
+pub mod access;
+pub mod badssl;
+pub mod cache;
+pub mod certs;
+pub mod chaos;
+pub mod compress;
 pub mod config;
+pub mod control;
 pub mod daemon;
+pub mod forward_auth;
+pub mod landing;
+pub mod limits;
+pub mod metrics;
+pub mod middleware;
+pub mod mirror;
+pub mod origin;
 pub mod proxy;
+pub mod routing;
+pub mod rules;
+pub mod tls;
+pub mod trace;
+pub mod upstream;
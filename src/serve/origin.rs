@@ -0,0 +1,132 @@
+//! Rewrite the backend origin in response headers to the public origin, for dev servers
+//! that redirect to `http://localhost:<port>/...` or set cookies for `localhost`.
+
+use http::header::{CONTENT_LOCATION, LOCATION, SET_COOKIE};
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+const REFRESH: HeaderName = HeaderName::from_static("refresh");
+
+/// Host names a backend may use for itself.
+const LOCAL_HOSTS: [&str; 4] = ["localhost", "127.0.0.1", "[::1]", "::1"];
+
+/// Public origin of a mapping as the client sees it, e.g. `https://app.test:8443`.
+/// The port is left out when it is the scheme's default.
+pub fn public_origin(domain: &str, tls: bool, port: u16) -> String {
+    let domain = crate::serve::routing::url_host(domain);
+    match (tls, port) {
+        (true, 443) => format!("https://{domain}"),
+        (false, 80) => format!("http://{domain}"),
+        (true, port) => format!("https://{domain}:{port}"),
+        (false, port) => format!("http://{domain}:{port}"),
+    }
+}
+
+/// Rewrite `url` if it points at the backend (`http://localhost:<backend_port>`, or
+/// 127.0.0.1 / [::1]). Relative URLs and other hosts are left alone.
+pub fn rewrite_url(url: &str, backend_port: u16, public: &str) -> Option<String> {
+    let lower = url.to_ascii_lowercase();
+    let scheme_len = ["http://", "https://"]
+        .iter()
+        .find(|s| lower.starts_with(*s))?
+        .len();
+    let rest = &url[scheme_len..];
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(authority_end);
+    let (host, port) = authority.rsplit_once(':')?;
+    if port.parse::<u16>().ok()? != backend_port
+        || !LOCAL_HOSTS.iter().any(|h| h.eq_ignore_ascii_case(host))
+    {
+        return None;
+    }
+    Some(format!("{public}{path}"))
+}
+
+/// Rewrite the URL in a `Refresh` value (`5; url=http://localhost:5001/next`).
+pub fn rewrite_refresh(value: &str, backend_port: u16, public: &str) -> Option<String> {
+    let lower = value.to_ascii_lowercase();
+    let url_start = lower.find("url=")? + "url=".len();
+    let url = &value[url_start..];
+    let (quote, inner) = match url.chars().next() {
+        Some(q @ ('\'' | '"')) => (Some(q), url[1..].trim_end_matches(q)),
+        _ => (None, url.trim_end()),
+    };
+    let rewritten = rewrite_url(inner, backend_port, public)?;
+    Some(match quote {
+        Some(q) => format!("{}{q}{rewritten}{q}", &value[..url_start]),
+        None => format!("{}{rewritten}", &value[..url_start]),
+    })
+}
+
+/// Adjust a `Set-Cookie` value for the public origin: a `Domain` naming the backend host
+/// becomes `domain`, and `Secure` is added over HTTPS and dropped over plain HTTP (where
+/// browsers would reject the cookie).
+pub fn rewrite_set_cookie(value: &str, domain: &str, tls: bool) -> Option<String> {
+    let mut parts = value.split(';').map(str::trim);
+    let name_value = parts.next()?;
+    let mut attrs = Vec::new();
+    let mut secure = false;
+    let mut changed = false;
+    for attr in parts.filter(|a| !a.is_empty()) {
+        let (key, val) = attr.split_once('=').unwrap_or((attr, ""));
+        if key.trim().eq_ignore_ascii_case("domain") {
+            let host = val.trim().trim_start_matches('.');
+            if LOCAL_HOSTS.iter().any(|h| h.eq_ignore_ascii_case(host)) {
+                attrs.push(format!("Domain={domain}"));
+                changed = true;
+                continue;
+            }
+        } else if key.trim().eq_ignore_ascii_case("secure") {
+            if !tls {
+                changed = true;
+                continue;
+            }
+            secure = true;
+        }
+        attrs.push(attr.to_string());
+    }
+    if tls && !secure {
+        attrs.push("Secure".to_string());
+        changed = true;
+    }
+    if !changed {
+        return None;
+    }
+    Some(
+        std::iter::once(name_value.to_string())
+            .chain(attrs)
+            .collect::<Vec<_>>()
+            .join("; "),
+    )
+}
+
+/// Rewrite `Location`, `Content-Location`, `Refresh` and `Set-Cookie` in a backend response.
+pub fn rewrite_headers(
+    headers: &mut HeaderMap,
+    backend_port: u16,
+    domain: &str,
+    tls: bool,
+    public_port: u16,
+) {
+    let public = public_origin(domain, tls, public_port);
+    for name in [LOCATION, CONTENT_LOCATION] {
+        rewrite_each(headers, name, |v| rewrite_url(v, backend_port, &public));
+    }
+    rewrite_each(headers, REFRESH, |v| {
+        rewrite_refresh(v, backend_port, &public)
+    });
+    rewrite_each(headers, SET_COOKIE, |v| rewrite_set_cookie(v, domain, tls));
+}
+
+/// Replace each value of `name` for which `f` returns a new value.
+fn rewrite_each(headers: &mut HeaderMap, name: HeaderName, f: impl Fn(&str) -> Option<String>) {
+    if let http::header::Entry::Occupied(mut entry) = headers.entry(name) {
+        for value in entry.iter_mut() {
+            let Ok(s) = value.to_str() else {
+                continue;
+            };
+            if let Some(new) = f(s).and_then(|n| HeaderValue::from_str(&n).ok()) {
+                *value = new;
+            }
+        }
+    }
+}
@@ -1,29 +1,79 @@
 //! Hyper reverse proxy with TLS and SNI.
 
 use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use futures_util::future::BoxFuture;
 use http::header::{CONNECTION, UPGRADE};
-use http::{Request, Response, StatusCode};
-use http_body_util::Full;
+use http::uri::{Authority, PathAndQuery};
+use http::{HeaderValue, Method, Request, Response, StatusCode, Uri};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
 use hyper::body::{Bytes, Incoming};
 use hyper::service::service_fn;
 use hyper::upgrade;
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
-use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
 use hyper_util::server::conn::auto::Builder as HttpBuilder;
-use rustls::pki_types::CertificateDer;
-use rustls::server::{ClientHello, ResolvesServerCert, ServerConfig};
-use rustls::sign::CertifiedKey;
+use rustls::HandshakeKind;
+use socket2::{Domain, Socket, Type};
 use std::collections::HashMap;
 use std::fmt;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio_rustls::TlsAcceptor;
+use tokio_rustls::LazyConfigAcceptor;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
 use crate::config::RoostPaths;
+use crate::serve::access::{AccessDecision, AccessPolicy};
+use crate::serve::badssl;
+use crate::serve::cache::{self, Cache};
+use crate::serve::certs::CertStore;
+use crate::serve::chaos::{Chaos, ChaosOverrides, Fault, ThrottledBody, Truncated, TruncatedBody};
+use crate::serve::compress::{self, Compression};
+use crate::serve::config::{Hsts, Mapping, ServeConfig};
+use crate::serve::control::{self, ControlHandler, ControlRequest};
+use crate::serve::forward_auth::{self, Decision, ForwardAuth};
+use crate::serve::landing::{self, ProbeCache, PROBE_CACHE_TTL};
+use crate::serve::limits::{
+    ConnActivity, DeadlineBody, EffectiveLimits, TrackedBody, DEFAULT_SHUTDOWN_TIMEOUT_SECS,
+};
+use crate::serve::metrics::{self, CountingBody, Metrics, OTHER_DOMAIN};
+use crate::serve::middleware::{Middleware, Next, RequestInfo};
+use crate::serve::mirror::{Mirror, Shadow};
+use crate::serve::origin;
+use crate::serve::routing::{self, HostTable};
+use crate::serve::rules::{Action, RequestParts, Rules};
+use crate::serve::tls::{TlsConfigs, TlsPolicy, TlsSessions};
+use crate::serve::trace::Tracer;
+use crate::serve::upstream::{self, UpstreamSelector};
 
-const UNSUPPORTED_SNI: &[&str] = &["localhost", "127.0.0.1", "::1"];
+pub(crate) type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Response body sent to clients: streamed from the backend or generated by the proxy.
+pub type ProxyBody = BoxBody<Bytes, BoxError>;
+
+fn full(body: impl Into<Bytes>) -> ProxyBody {
+    Full::new(body.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+/// Returned by `proxy_request` to close the client connection without a response.
+#[derive(Debug)]
+struct DropConnection;
+
+impl fmt::Display for DropConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("connection dropped by chaos settings")
+    }
+}
+
+impl std::error::Error for DropConnection {}
 
 /// Normal connection teardown (client closed, navigated away, etc.) — not worth logging.
 fn is_normal_disconnect(err: &impl std::fmt::Display) -> bool {
@@ -34,312 +84,1187 @@ fn is_normal_disconnect(err: &impl std::fmt::Display) -> bool {
         || s.contains("connection error")
 }
 
-#[derive(Clone)]
-struct CertResolver {
-    certs: HashMap<String, Arc<CertifiedKey>>,
+/// Connection aborted on purpose by chaos settings — not worth logging.
+fn is_chaos_abort(err: &(dyn std::error::Error + 'static)) -> bool {
+    has_source::<DropConnection>(err) || has_source::<Truncated>(err)
 }
 
-impl fmt::Debug for CertResolver {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("CertResolver")
-            .field("domains", &self.certs.keys().collect::<Vec<_>>())
-            .finish()
-    }
+type BackendClient = Client<HttpConnector, ProxyBody>;
+
+/// Idle backend connections kept per backend; enough for a load test's concurrency.
+const POOL_MAX_IDLE_PER_HOST: usize = 64;
+
+/// How long an idle backend connection is kept for reuse.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+fn backend_client(connect_timeout: Duration) -> BackendClient {
+    let mut connector = HttpConnector::new();
+    connector.set_connect_timeout(Some(connect_timeout));
+    connector.set_nodelay(true);
+    Client::builder(TokioExecutor::new())
+        .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .pool_timer(TokioTimer::new())
+        .build(connector)
 }
 
-impl ResolvesServerCert for CertResolver {
-    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
-        let sni = client_hello.server_name()?;
-        let key = sni.trim().to_lowercase();
-        if key.is_empty() || UNSUPPORTED_SNI.contains(&key.as_str()) {
-            return None;
-        }
-        // SNI sometimes includes port (e.g. "host:443"); use host part for lookup
-        let host = key.split(':').next().unwrap_or(&key).trim();
-        self.certs.get(host).cloned()
+/// Per-domain routing entry built from a mapping.
+struct Route {
+    port: u16,
+    /// `localhost:<port>`, prebuilt for backend URIs.
+    authority: Authority,
+    access: Option<Arc<AccessPolicy>>,
+    chaos: Option<Chaos>,
+    compression: Option<Compression>,
+    limits: EffectiveLimits,
+    client: BackendClient,
+    plain_http: bool,
+    hsts: Option<Hsts>,
+    preserve_origin: bool,
+    mirror: Option<Mirror>,
+    port_passthrough: bool,
+    upstream: Option<UpstreamSelector>,
+    forward_auth: Option<ForwardAuth>,
+    cache: Option<Arc<Cache>>,
+    /// Server connection settings for connections whose SNI names this domain.
+    http: HttpBuilder<TokioExecutor>,
+}
+
+/// Everything derived from the mappings and rules, swapped as a whole on reload.
+struct Routing {
+    routes: HostTable<Route>,
+    rules: Rules,
+    /// Backend for unmapped hosts; without one they get the landing page.
+    default_backend: Option<u16>,
+}
+
+impl Routing {
+    fn build(paths: &RoostPaths, config: &ServeConfig) -> Result<Self> {
+        let mappings: HashMap<String, Mapping> = config
+            .mappings
+            .iter()
+            .map(|m| (m.domain.clone(), m.clone()))
+            .collect();
+        Ok(Self {
+            routes: build_routes(paths, &mappings, config)?,
+            rules: Rules::compile(&config.rules).context("[[serve.rules]]")?,
+            default_backend: config.default_backend,
+        })
     }
 }
 
-fn build_cert_resolver(
+/// Build routes; `config` supplies the limits and HSTS settings mappings leave unset.
+fn build_routes(
     paths: &RoostPaths,
-    mappings: &HashMap<String, u16>,
-) -> Result<Arc<CertResolver>> {
-    let provider = rustls::ServerConfig::builder().crypto_provider().clone();
-    let mut certs: HashMap<String, Arc<CertifiedKey>> = HashMap::new();
-
-    let mut domains: Vec<_> = mappings.keys().collect();
-    domains.sort_by_key(|b| std::cmp::Reverse(b.len()));
-
-    for domain in domains {
-        let cert_path = paths.certs_dir.join(format!("{domain}.pem"));
-        let key_path = paths.certs_dir.join(format!("{domain}-key.pem"));
-        if !cert_path.is_file() || !key_path.is_file() {
-            continue;
-        }
-        let cert_pem = std::fs::read(&cert_path)
-            .with_context(|| format!("read cert: {}", cert_path.display()))?;
-        let key_pem = std::fs::read(&key_path)
-            .with_context(|| format!("read key: {}", key_path.display()))?;
-
-        let certs_der: Vec<CertificateDer<'static>> =
-            rustls_pemfile::certs(&mut cert_pem.as_slice())
-                .collect::<Result<Vec<_>, _>>()
-                .context("parse cert PEM")?;
-        let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
-            .context("parse key PEM")?
-            .context("no private key in file")?;
-
-        let certified_key = Arc::new(
-            CertifiedKey::from_der(certs_der, key, &provider)
-                .with_context(|| format!("load cert for {domain}"))?,
+    mappings: &HashMap<String, Mapping>,
+    config: &ServeConfig,
+) -> Result<HostTable<Route>> {
+    // Connect timeouts are per client, so share one client per distinct timeout; likewise
+    // one server builder per header-read timeout
+    let mut clients: HashMap<Duration, BackendClient> = HashMap::new();
+    let mut builders: HashMap<Duration, HttpBuilder<TokioExecutor>> = HashMap::new();
+    let mut routes = HostTable::default();
+    for (domain, m) in mappings {
+        let access = AccessPolicy::from_mapping(m)?.map(Arc::new);
+        if let Some(chaos) = &m.chaos {
+            chaos
+                .validate()
+                .with_context(|| format!("chaos settings for {domain}"))?;
+        }
+        m.limits
+            .validate()
+            .with_context(|| format!("limits for {domain}"))?;
+        if let Some(mirror) = &m.mirror {
+            mirror
+                .validate()
+                .with_context(|| format!("mirror for {domain}"))?;
+        }
+        if let Some(auth) = &m.forward_auth {
+            auth.validate()
+                .with_context(|| format!("forward auth for {domain}"))?;
+        }
+        let upstream = m
+            .upstream
+            .as_ref()
+            .map(UpstreamSelector::compile)
+            .transpose()
+            .with_context(|| format!("upstream override for {domain}"))?;
+        let cache = match &m.cache {
+            Some(settings) => {
+                settings
+                    .validate()
+                    .with_context(|| format!("cache settings for {domain}"))?;
+                let dir = settings.disk.then(|| cache::dir(paths, domain));
+                let cache = Cache::new(settings.clone(), dir)
+                    .with_context(|| format!("cache for {domain}"))?;
+                Some(Arc::new(cache))
+            }
+            None => None,
+        };
+        let limits = m.limits.or(&config.limits).effective();
+        let client = clients
+            .entry(limits.connect_timeout)
+            .or_insert_with(|| backend_client(limits.connect_timeout))
+            .clone();
+        let http = builders
+            .entry(limits.header_read_timeout)
+            .or_insert_with(|| http_builder(&limits))
+            .clone();
+        routes.insert(
+            domain,
+            Route {
+                port: m.port,
+                authority: routing::backend_authority(m.port),
+                access,
+                chaos: m.chaos.clone(),
+                compression: m.compression.clone(),
+                limits,
+                client,
+                plain_http: m.plain_http,
+                hsts: m.hsts.or(config.hsts),
+                preserve_origin: m.preserve_origin,
+                mirror: m.mirror.clone(),
+                port_passthrough: m.port_passthrough,
+                upstream,
+                forward_auth: m.forward_auth.clone(),
+                cache,
+                http,
+            },
         );
-        certs.insert(domain.to_lowercase(), certified_key);
     }
+    Ok(routes)
+}
 
-    if certs.is_empty() {
-        anyhow::bail!(
-            "no domain certs found (mappings: {}); run 'roost serve config add <domain> <port>' to create certs",
-            mappings.keys().cloned().collect::<Vec<_>>().join(", ")
-        );
+/// State shared by all listeners and connections.
+struct ProxyState {
+    routing: ArcSwap<Routing>,
+    certs: Arc<CertStore>,
+    /// Server configs for TLS handshakes, rebuilt with the routing on reload.
+    tls: ArcSwap<TlsConfigs>,
+    sessions: TlsSessions,
+    /// Client, limits and server settings for requests that match no route (explicit
+    /// backend port).
+    client: BackendClient,
+    limits: EffectiveLimits,
+    http: HttpBuilder<TokioExecutor>,
+    chaos: ChaosOverrides,
+    /// Cancelled on shutdown: listeners stop accepting and connections finish their current request.
+    shutdown: CancellationToken,
+    /// Connection and websocket tunnel tasks, awaited while draining.
+    tasks: TaskTracker,
+    metrics: Arc<Metrics>,
+    tracer: Tracer,
+    /// Serve the `badssl.test` pages (see [`badssl`]).
+    badssl: bool,
+    /// Backend status shown on the landing page.
+    probes: ProbeCache,
+    /// Run around every request, first to last (see [`run_proxy_with`]).
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl ProxyState {
+    /// Limits and server settings for a connection: those of the mapping its SNI names,
+    /// else the global ones.
+    fn connection_settings(
+        &self,
+        sni: Option<&str>,
+    ) -> (EffectiveLimits, HttpBuilder<TokioExecutor>) {
+        let routing = self.routing.load();
+        match sni.and_then(|sni| routing.routes.get(sni)) {
+            Some(route) => (route.limits, route.http.clone()),
+            None => (self.limits, self.http.clone()),
+        }
     }
 
-    Ok(Arc::new(CertResolver { certs }))
+    /// Chaos settings in effect for a request to a mapping: runtime override, else `.roostrc`.
+    fn chaos_for(&self, domain: &str, route: &Route, path: &str) -> Option<Chaos> {
+        self.chaos
+            .get(domain)
+            .or_else(|| route.chaos.clone())
+            .filter(|c| c.applies_to(path))
+    }
 }
 
-async fn redirect_http_to_https(
-    req: Request<Incoming>,
-) -> Result<Response<Full<Bytes>>, anyhow::Error> {
-    use http_body_util::BodyExt;
-    let host = req
-        .headers()
-        .get("host")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("localhost");
-    let host = host.split(':').next().unwrap_or(host).trim();
-    let path = req
-        .uri()
-        .path_and_query()
-        .map(|pq| pq.as_str())
-        .unwrap_or("/");
-    let location = format!("https://{host}{path}");
-    let _ = req.into_body().collect().await;
-    Ok(Response::builder()
-        .status(StatusCode::PERMANENT_REDIRECT)
-        .header("Location", location)
-        .body(Full::from(Bytes::new()))
-        .unwrap())
+impl Routing {
+    /// Metrics label for a request host: the mapped domain, else `other`.
+    fn metrics_domain<'a>(&self, domain: &'a str) -> &'a str {
+        if self.routes.contains(domain) {
+            domain
+        } else {
+            OTHER_DOMAIN
+        }
+    }
+}
+
+/// Handle `roost serve chaos` commands sent over the control socket.
+fn control_handler(state: Arc<ProxyState>) -> ControlHandler {
+    Arc::new(move |request| match request {
+        ControlRequest::ChaosSet { domain, chaos } => {
+            let domain = domain.to_lowercase();
+            if !state.routing.load().routes.contains(&domain) {
+                anyhow::bail!("no mapping for {domain}");
+            }
+            chaos.validate()?;
+            state.chaos.set(&domain, chaos);
+            Ok(serde_json::Value::Null)
+        }
+        ControlRequest::ChaosClear { domain } => {
+            if !state.chaos.clear(&domain) {
+                anyhow::bail!("no runtime chaos settings for {domain}");
+            }
+            Ok(serde_json::Value::Null)
+        }
+        ControlRequest::ChaosList => {
+            let overrides = state.chaos.list();
+            let mut entries: Vec<serde_json::Value> = overrides
+                .iter()
+                .map(|(domain, chaos)| {
+                    serde_json::json!({ "domain": domain, "source": "runtime", "chaos": chaos })
+                })
+                .collect();
+            for (domain, route) in state.routing.load().routes.iter() {
+                if let Some(chaos) = &route.chaos {
+                    if !overrides.iter().any(|(d, _)| d == domain) {
+                        entries.push(
+                            serde_json::json!({ "domain": domain, "source": "config", "chaos": chaos }),
+                        );
+                    }
+                }
+            }
+            Ok(serde_json::Value::Array(entries))
+        }
+        ControlRequest::CachePurge {
+            domain,
+            path_prefix,
+        } => {
+            let domain = domain.map(|d| d.to_lowercase());
+            let routing = state.routing.load();
+            let caches: Vec<&Arc<Cache>> = routing
+                .routes
+                .iter()
+                .filter(|(d, _)| domain.as_ref().is_none_or(|domain| *d == domain))
+                .filter_map(|(_, route)| route.cache.as_ref())
+                .collect();
+            if caches.is_empty() {
+                match domain {
+                    Some(domain) => anyhow::bail!("no cache configured for {domain}"),
+                    None => anyhow::bail!("no mapping has a cache configured"),
+                }
+            }
+            let purged: usize = caches.iter().map(|c| c.purge(path_prefix.as_deref())).sum();
+            Ok(serde_json::json!({ "purged": purged }))
+        }
+        ControlRequest::CertReload { domain } => {
+            let reloaded = state.certs.reload(domain.as_deref())?;
+            Ok(serde_json::json!({ "reloaded": reloaded }))
+        }
+    })
+}
+
+/// Effective TLS policy per lowercase mapping domain.
+fn tls_policies(
+    mappings: &HashMap<String, Mapping>,
+    config: &ServeConfig,
+) -> HashMap<String, TlsPolicy> {
+    mappings
+        .iter()
+        .map(|(domain, m)| (domain.to_lowercase(), m.tls.or(&config.tls)))
+        .collect()
+}
+
+/// Bind a TCP listener. IPv6 sockets are v6-only so `::` and `0.0.0.0` can share a port.
+pub(crate) fn bind_listener(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Bind all configured addresses for a port. Fails only when none of them could be bound,
+/// so a host without IPv6 still serves on 127.0.0.1.
+fn bind_port(config: &ServeConfig, port: u16) -> Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    let mut last_err = None;
+    for ip in config.bind_addrs(port) {
+        let addr = SocketAddr::new(ip, port);
+        match bind_listener(addr) {
+            Ok(l) => listeners.push(l),
+            Err(e) => {
+                eprintln!("Warning: could not bind {addr}: {e}");
+                last_err = Some(e.context(format!("bind {addr}")));
+            }
+        }
+    }
+    match (listeners.is_empty(), last_err) {
+        (true, Some(e)) => Err(e),
+        _ => Ok(listeners),
+    }
+}
+
+/// Resolve on SIGTERM (sent by `roost serve daemon stop`) or Ctrl-C.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = term.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Run the proxy for a merged serve config (see `merge_serve_configs`) until SIGTERM or Ctrl-C.
+/// On SIGHUP (`roost serve daemon reload`) the `.roostrc` files are read again and the new
+/// mappings and rules replace the old ones without dropping connections.
+pub async fn run_proxy(paths: &RoostPaths, config: &ServeConfig) -> Result<()> {
+    run(paths, config, Vec::new(), shutdown_signal(), true).await
+}
+
+/// Run the proxy until `shutdown` resolves, then stop accepting, let in-flight requests and
+/// websocket tunnels finish for up to `shutdown_timeout_secs`, and return. Embedded proxies
+/// have no control socket (so they leave a running daemon's control.json alone) and don't
+/// handle signals.
+pub async fn run_proxy_until(
+    paths: &RoostPaths,
+    config: &ServeConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    run(paths, config, Vec::new(), shutdown, false).await
+}
+
+/// Like [`run_proxy_until`], with `middleware` run around every request. The first
+/// middleware sees each request first and its response last; roost's routing, access
+/// checks and proxying run after the last one.
+pub async fn run_proxy_with(
+    paths: &RoostPaths,
+    config: &ServeConfig,
+    middleware: Vec<Arc<dyn Middleware>>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    run(paths, config, middleware, shutdown, false).await
 }
 
-pub async fn run_proxy(
+/// `standalone` is set for the `roost serve` process itself: it publishes the control socket,
+/// reloads on SIGHUP and exits at once on a second Ctrl-C while draining.
+async fn run(
     paths: &RoostPaths,
-    mappings: HashMap<String, u16>,
-    ports: Vec<u16>,
+    config: &ServeConfig,
+    middleware: Vec<Arc<dyn Middleware>>,
+    shutdown: impl Future<Output = ()>,
+    standalone: bool,
 ) -> Result<()> {
-    if mappings.is_empty() {
+    if config.mappings.is_empty() && !config.badssl {
         anyhow::bail!("no mappings configured; add with 'roost serve config add <domain> <port>'");
     }
-    if ports.is_empty() {
+    if config.ports.is_empty() {
         anyhow::bail!("no ports configured; add with 'roost serve config ports add <port>'");
     }
+    config.validate_binds()?;
+    config.limits.validate().context("[serve.limits]")?;
+    config.tracing.traces_url().context("[serve.tracing]")?;
 
-    let cert_resolver = build_cert_resolver(paths, &mappings)?;
-    let mut server_config = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_cert_resolver(cert_resolver);
-    server_config.alpn_protocols = vec![b"http/1.1".to_vec(), b"http/1.0".to_vec()];
-    let tls_acceptor = TlsAcceptor::from(Arc::new(server_config));
-    let http_client = Client::builder(TokioExecutor::new())
-        .pool_max_idle_per_host(4)
-        .build(HttpConnector::new());
-    let mappings = Arc::new(mappings);
-    let has_443 = ports.contains(&443);
-
-    for port in &ports {
-        if *port == 80 && has_443 {
-            let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], 80))).await?;
-            eprintln!("HTTP redirect listening on http://0.0.0.0:80 (-> https)");
-            tokio::spawn(async move {
-                loop {
-                    let (stream, _) = match listener.accept().await {
-                        Ok(s) => s,
-                        Err(e) => {
-                            eprintln!("accept error on 80: {e}");
-                            continue;
-                        }
-                    };
-                    let service = service_fn(|req: Request<Incoming>| async move {
-                        redirect_http_to_https(req).await
-                    });
-                    if let Err(e) = HttpBuilder::new(TokioExecutor::new())
-                        .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
-                        .await
-                    {
-                        eprintln!("connection error on 80: {e:#}");
+    let mappings: HashMap<String, Mapping> = config
+        .mappings
+        .iter()
+        .map(|m| (m.domain.clone(), m.clone()))
+        .collect();
+    let metrics = Metrics::new(mappings.keys().map(String::as_str));
+    let policies = tls_policies(&mappings, config);
+    let certs = CertStore::new(
+        paths,
+        &policies,
+        config.default_cert.as_deref(),
+        config.badssl,
+        &metrics,
+    )?;
+    let routing = Routing::build(paths, config)?;
+    let sessions = TlsSessions::new()?;
+    let tls_configs = TlsConfigs::new(&policies, certs.clone(), &sessions)?;
+    let limits = config.limits.effective();
+    let cancel = CancellationToken::new();
+    let state = Arc::new(ProxyState {
+        routing: ArcSwap::from_pointee(routing),
+        certs,
+        tls: ArcSwap::from_pointee(tls_configs),
+        sessions,
+        client: backend_client(limits.connect_timeout),
+        limits,
+        http: http_builder(&limits),
+        chaos: ChaosOverrides::default(),
+        tracer: Tracer::new(&config.tracing, &cancel)?,
+        shutdown: cancel,
+        tasks: TaskTracker::new(),
+        metrics,
+        badssl: config.badssl,
+        probes: ProbeCache::new(PROBE_CACHE_TTL),
+        middleware,
+    });
+    if let Err(e) = start(paths, config, &state, standalone).await {
+        // Stop whatever already started before reporting the error
+        state.shutdown.cancel();
+        if standalone {
+            control::remove_control_info(paths);
+        }
+        return Err(e);
+    }
+
+    shutdown.await;
+    let grace = Duration::from_secs(
+        config
+            .shutdown_timeout_secs
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+    );
+    eprintln!(
+        "Shutting down; draining connections (up to {}s)",
+        grace.as_secs()
+    );
+    state.shutdown.cancel();
+    state.tasks.close();
+    let forced = async {
+        if standalone {
+            let _ = tokio::signal::ctrl_c().await;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    };
+    tokio::select! {
+        drained = tokio::time::timeout(grace, state.tasks.wait()) => {
+            if drained.is_err() {
+                eprintln!(
+                    "Shutdown timeout reached; closing {} remaining connection(s)",
+                    state.tasks.len()
+                );
+            }
+        }
+        _ = forced => eprintln!("Forced shutdown"),
+    }
+    if standalone {
+        control::remove_control_info(paths);
+    }
+    Ok(())
+}
+
+/// Start the control socket, background tasks and listeners for `run`.
+async fn start(
+    paths: &RoostPaths,
+    config: &ServeConfig,
+    state: &Arc<ProxyState>,
+    standalone: bool,
+) -> Result<()> {
+    if standalone {
+        control::start_control_server(
+            paths,
+            control_handler(state.clone()),
+            state.shutdown.clone(),
+        )
+        .await?;
+    }
+    tokio::spawn(state.certs.clone().watch(state.shutdown.clone()));
+    if standalone {
+        tokio::spawn(reload_on_signal(
+            paths.clone(),
+            config.clone(),
+            state.clone(),
+        ));
+    }
+    if let Some(addr) = config.metrics {
+        let addr = metrics::start(addr, state.metrics.clone(), state.shutdown.clone())?;
+        eprintln!("Metrics on http://{addr}/metrics");
+    }
+    for redirect in config.effective_redirects() {
+        for listener in bind_port(config, redirect.from)? {
+            eprintln!(
+                "HTTP listening on http://{} (-> https port {})",
+                listener.local_addr()?,
+                redirect.to
+            );
+            tokio::spawn(serve_plain(listener, redirect.to, state.clone()));
+        }
+    }
+    for port in config.tls_ports() {
+        for listener in bind_port(config, port)? {
+            eprintln!("Proxy listening on https://{}", listener.local_addr()?);
+            tokio::spawn(serve_tls(listener, state.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Settings applied only at startup that differ in `new`, for the reload warning.
+fn restart_only_changes(startup: &ServeConfig, new: &ServeConfig) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if startup.tls_ports() != new.tls_ports()
+        || startup.effective_redirects() != new.effective_redirects()
+    {
+        changed.push("ports");
+    }
+    if startup.listen != new.listen || startup.lan != new.lan {
+        changed.push("bind addresses");
+    }
+    if startup.limits != new.limits {
+        changed.push("limits for unmapped hosts");
+    }
+    if startup.metrics != new.metrics {
+        changed.push("metrics address");
+    }
+    if startup.tracing != new.tracing {
+        changed.push("[serve.tracing]");
+    }
+    if startup.badssl != new.badssl {
+        changed.push("badssl");
+    }
+    if startup.shutdown_timeout_secs != new.shutdown_timeout_secs {
+        changed.push("shutdown_timeout_secs");
+    }
+    changed
+}
+
+/// Swap in the config from the `.roostrc` files on each SIGHUP until shutdown: mappings,
+/// rules, TLS settings, certificates and the metrics domains. Listeners, tracing, the
+/// metrics endpoint and settings for unmapped hosts keep their `startup` configuration; a
+/// reload that changes them logs a warning.
+async fn reload_on_signal(paths: RoostPaths, startup: ServeConfig, state: Arc<ProxyState>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+            return;
+        };
+        loop {
+            tokio::select! {
+                Some(()) = hangup.recv() => {}
+                _ = state.shutdown.cancelled() => return,
+            }
+            let reloaded = std::env::current_dir()
+                .map_err(anyhow::Error::from)
+                .and_then(|cwd| crate::serve::config::load_merged(&paths, &cwd))
+                .and_then(|config| {
+                    let mappings: HashMap<String, Mapping> = config
+                        .mappings
+                        .iter()
+                        .map(|m| (m.domain.clone(), m.clone()))
+                        .collect();
+                    let policies = tls_policies(&mappings, &config);
+                    let tls = TlsConfigs::new(&policies, state.certs.clone(), &state.sessions)?;
+                    let routing = Routing::build(&paths, &config)?;
+                    Ok((routing, tls, policies, config))
+                });
+            match reloaded {
+                Ok((routing, tls, policies, config)) => {
+                    let count = routing.routes.len();
+                    state
+                        .certs
+                        .set_domains(&policies, config.default_cert.as_deref());
+                    state
+                        .metrics
+                        .set_domains(config.mappings.iter().map(|m| m.domain.as_str()));
+                    state.tls.store(Arc::new(tls));
+                    state.routing.store(Arc::new(routing));
+                    eprintln!("Reloaded {count} mapping(s)");
+                    let changed = restart_only_changes(&startup, &config);
+                    if !changed.is_empty() {
+                        eprintln!(
+                            "Warning: {} changed; restart the proxy to apply",
+                            changed.join(", ")
+                        );
                     }
                 }
-            });
-        } else if *port != 80 {
-            let port = *port;
-            let mappings = mappings.clone();
-            let tls_acceptor = tls_acceptor.clone();
-            let http_client = http_client.clone();
-            let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await?;
-            eprintln!("Proxy listening on https://0.0.0.0:{port}");
-            tokio::spawn(async move {
-                loop {
-                    let (tcp_stream, remote_addr) = match listener.accept().await {
-                        Ok(s) => s,
-                        Err(e) => {
-                            eprintln!("accept error on {port}: {e}");
-                            continue;
-                        }
-                    };
-                    let tls_acceptor = tls_acceptor.clone();
-                    let mappings = mappings.clone();
-                    let client = http_client.clone();
-                    tokio::spawn(async move {
-                        let tls_stream = match tls_acceptor.accept(tcp_stream).await {
-                            Ok(s) => s,
-                            Err(e) => {
-                                eprintln!("TLS handshake failed: {e}");
-                                return;
-                            }
-                        };
-                        let service = service_fn({
-                            let mappings = mappings.clone();
-                            let client = client.clone();
-                            let remote_addr = remote_addr;
-                            move |req: Request<Incoming>| {
-                                let mappings = mappings.clone();
-                                let client = client.clone();
-                                let remote_addr = remote_addr;
-                                async move {
-                                    match proxy_request(req, remote_addr, &mappings, &client).await {
-                                        Ok(r) => Ok::<_, anyhow::Error>(r),
-                                        Err(e) => {
-                                            eprintln!("proxy error: {e:#}");
-                                            Ok(Response::builder()
-                                                .status(StatusCode::BAD_GATEWAY)
-                                                .body(Full::from(format!(
-                                                    "Backend error: {e}\n\nIs your app running on the configured port?"
-                                                )))
-                                                .unwrap())
-                                        }
-                                    }
-                                }
-                            }
-                        });
-                        if let Err(e) = HttpBuilder::new(TokioExecutor::new())
-                            .serve_connection_with_upgrades(
-                                hyper_util::rt::TokioIo::new(tls_stream),
-                                service,
-                            )
-                            .await
-                        {
-                            if !is_normal_disconnect(&e) {
-                                eprintln!("Connection error: {e:#}");
-                            }
-                        }
-                    });
+                Err(e) => eprintln!("Reload failed, keeping the current mappings: {e:#}"),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (paths, startup, state);
+    }
+}
+
+/// HTTP/1 server builder with the header-read timeout for a connection.
+fn http_builder(limits: &EffectiveLimits) -> HttpBuilder<TokioExecutor> {
+    let mut builder = HttpBuilder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(limits.header_read_timeout);
+    builder
+}
+
+/// Resolve when a connection should be closed gracefully: idle too long, or proxy shutting down.
+async fn close_signal(
+    activity: &ConnActivity,
+    idle: Option<Duration>,
+    shutdown: &CancellationToken,
+) {
+    let idle = async {
+        match idle {
+            Some(timeout) => activity.idle(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = idle => {}
+        _ = shutdown.cancelled() => {}
+    }
+}
+
+/// Where a request arrived: client address, listener port, and whether it came over TLS.
+#[derive(Debug, Clone, Copy)]
+struct ConnInfo {
+    remote_addr: SocketAddr,
+    local_port: u16,
+    tls: bool,
+    /// On plain ports: TLS port to redirect to, unless the mapping serves plain HTTP.
+    redirect_to: Option<u16>,
+}
+
+async fn accept(
+    listener: &TcpListener,
+    state: &ProxyState,
+) -> Option<(tokio::net::TcpStream, SocketAddr)> {
+    let port = listener.local_addr().map(|a| a.port()).unwrap_or_default();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(s) => {
+                    // Small proxied responses shouldn't wait on Nagle's algorithm
+                    let _ = s.0.set_nodelay(true);
+                    return Some(s);
                 }
-            });
+                Err(e) => eprintln!("accept error on {port}: {e}"),
+            },
+            _ = state.shutdown.cancelled() => return None,
         }
     }
+}
 
-    std::future::pending::<()>().await;
-    #[allow(unreachable_code)]
-    Ok(())
+async fn serve_plain(listener: TcpListener, redirect_to: u16, state: Arc<ProxyState>) {
+    let local_port = listener.local_addr().map(|a| a.port()).unwrap_or(80);
+    while let Some((stream, remote_addr)) = accept(&listener, &state).await {
+        let info = ConnInfo {
+            remote_addr,
+            local_port,
+            tls: false,
+            redirect_to: Some(redirect_to),
+        };
+        let state = state.clone();
+        state.tasks.clone().spawn(async move {
+            let (limits, http) = (state.limits, state.http.clone());
+            serve_connection(TokioIo::new(stream), info, limits, http, state).await;
+        });
+    }
 }
 
-/// Parse "host" or "host:port" into (domain, optional_port).
-fn parse_host(s: &str) -> (String, Option<u16>) {
-    let s = s.trim();
-    let (host, port) = match s.rfind(':') {
-        Some(colon) => {
-            let (h, p) = s.split_at(colon);
-            let port_str = p.trim_start_matches(':');
-            match port_str.parse::<u16>() {
-                Ok(port) => (h, Some(port)),
-                Err(_) => (s, None),
+async fn serve_tls(listener: TcpListener, state: Arc<ProxyState>) {
+    let local_port = listener.local_addr().map(|a| a.port()).unwrap_or_default();
+    while let Some((tcp_stream, remote_addr)) = accept(&listener, &state).await {
+        let info = ConnInfo {
+            remote_addr,
+            local_port,
+            tls: true,
+            redirect_to: None,
+        };
+        let state = state.clone();
+        state.tasks.clone().spawn(async move {
+            // Read the ClientHello first so the server config can follow the SNI's TLS policy
+            let handshake = tokio::time::timeout(state.limits.header_read_timeout, async {
+                let start =
+                    LazyConfigAcceptor::new(rustls::server::Acceptor::default(), tcp_stream)
+                        .await?;
+                let config = state.tls.load().for_sni(start.client_hello().server_name());
+                start.into_stream(config).await
+            });
+            let tls_stream = match handshake.await {
+                Ok(Ok(s)) => s,
+                Ok(Err(e)) => {
+                    state
+                        .metrics
+                        .handshake_failed(&metrics::handshake_failure_reason(&e));
+                    eprintln!("TLS handshake failed: {e}");
+                    return;
+                }
+                Err(_) => {
+                    state.metrics.handshake_failed("timeout");
+                    return;
+                }
+            };
+            let resumed = tls_stream.get_ref().1.handshake_kind() == Some(HandshakeKind::Resumed);
+            state.metrics.handshake_completed(resumed);
+            // Connection-level limits follow the SNI mapping
+            let sni = tls_stream.get_ref().1.server_name();
+            let (limits, http) = state.connection_settings(sni);
+            serve_connection(TokioIo::new(tls_stream), info, limits, http, state).await;
+        });
+    }
+}
+
+/// Serve HTTP on one client connection until it closes, goes idle, or the proxy shuts down.
+async fn serve_connection<I>(
+    io: I,
+    info: ConnInfo,
+    limits: EffectiveLimits,
+    http: HttpBuilder<TokioExecutor>,
+    state: Arc<ProxyState>,
+) where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let _connection = state.metrics.connection();
+    let activity = ConnActivity::new();
+    let service = service_fn({
+        let state = state.clone();
+        let activity = activity.clone();
+        move |mut req: Request<Incoming>| {
+            let state = state.clone();
+            let in_flight = activity.begin();
+            async move {
+                let started = std::time::Instant::now();
+                let host = request_host(&req);
+                let domain = host.as_ref().map_or("", |(d, _)| d.as_str());
+                let stats = state
+                    .metrics
+                    .domain(state.routing.load().metrics_domain(domain));
+                let trace = state.tracer.begin(
+                    &mut req,
+                    domain,
+                    info.remote_addr,
+                    info.local_port,
+                    info.tls,
+                );
+                let req = req.map(|body| body.map_err(BoxError::from).boxed());
+                let mut response = match dispatch(req, host, info, &state).await {
+                    Ok(r) => r,
+                    Err(e) if e.is::<DropConnection>() => return Err(DropConnection),
+                    Err(e) => {
+                        eprintln!("proxy error: {e:#}");
+                        Response::builder()
+                            .status(StatusCode::BAD_GATEWAY)
+                            .body(full(format!(
+                                "Backend error: {e}\n\nIs your app running on the configured port?"
+                            )))
+                            .unwrap()
+                    }
+                };
+                stats.observe(response.status(), started.elapsed());
+                if let Some(trace) = trace {
+                    let status = response.status();
+                    state.tracer.finish(trace, status, response.headers_mut());
+                }
+                Ok::<_, DropConnection>(response.map(|body| {
+                    TrackedBody::new(CountingBody::outgoing(body, stats).boxed(), in_flight)
+                }))
             }
         }
-        None => (s, None),
+    });
+    let conn = http.serve_connection_with_upgrades(io, service);
+    tokio::pin!(conn);
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = close_signal(&activity, limits.idle_timeout, &state.shutdown) => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
     };
-    (host.to_lowercase(), port)
+    if let Err(e) = result {
+        if !is_normal_disconnect(&e) && !is_chaos_abort(&*e) {
+            eprintln!("Connection error on {}: {e:#}", info.local_port);
+        }
+    }
 }
 
-async fn proxy_request(
-    mut req: Request<Incoming>,
-    remote_addr: SocketAddr,
-    mappings: &HashMap<String, u16>,
-    client: &Client<HttpConnector, Incoming>,
-) -> Result<Response<Full<Bytes>>, anyhow::Error> {
-    use http_body_util::BodyExt;
+/// Run a request through the middleware chain, ending in [`handle_request`]. `host` is
+/// the parsed `Host` of `req`; it is parsed again after middleware, which may change it.
+async fn dispatch(
+    req: Request<ProxyBody>,
+    host: Option<(String, Option<u16>)>,
+    info: ConnInfo,
+    state: &ProxyState,
+) -> Result<Response<ProxyBody>> {
+    if state.middleware.is_empty() {
+        return handle_request(req, host, info, state).await;
+    }
+    let endpoint = |req: Request<ProxyBody>| -> BoxFuture<'_, Result<Response<ProxyBody>>> {
+        let host = request_host(&req);
+        Box::pin(handle_request(req, host, info, state))
+    };
+    let request_info = RequestInfo {
+        remote_addr: info.remote_addr,
+        local_port: info.local_port,
+        tls: info.tls,
+    };
+    Next::new(&state.middleware, request_info, &endpoint)
+        .run(req)
+        .await
+}
 
-    let host_raw = req
-        .headers()
+/// Redirect to HTTPS on plain ports (unless the mapping serves plain HTTP), else proxy.
+async fn handle_request(
+    req: Request<ProxyBody>,
+    host: Option<(String, Option<u16>)>,
+    info: ConnInfo,
+    state: &ProxyState,
+) -> Result<Response<ProxyBody>> {
+    // One snapshot per request, so a reload never mixes old and new mappings
+    let routing = state.routing.load_full();
+    if let Some(tls_port) = info.redirect_to {
+        let plain_http = host
+            .as_ref()
+            .and_then(|(domain, _)| routing.routes.get(domain))
+            .is_some_and(|r| r.plain_http);
+        if !plain_http {
+            return Ok(redirect_to_https(&req, tls_port));
+        }
+    }
+    proxy_request(req, host, info, state, &routing).await
+}
+
+fn request_host<B>(req: &Request<B>) -> Option<(String, Option<u16>)> {
+    req.headers()
         .get("host")
         .and_then(|v| v.to_str().ok())
-        .or_else(|| req.uri().authority().map(|a| a.as_str()));
+        .or_else(|| req.uri().authority().map(|a| a.as_str()))
+        .map(routing::parse_host)
+}
 
-    let (domain, explicit_port) = match host_raw {
-        Some(h) => parse_host(h),
+/// Backend port for a request. `route` is the mapping's port and whether it has
+/// `port_passthrough`; `host_port` is the port in the `Host` header and `local_port` the
+/// port the client connected to. Without passthrough the `Host` port never selects the
+/// backend, so only mapped ports are reachable.
+pub fn backend_port(
+    route: Option<(u16, bool)>,
+    host_port: Option<u16>,
+    local_port: u16,
+) -> Option<u16> {
+    let (port, passthrough) = route?;
+    match host_port {
+        // The port the client connected to is the proxy itself, not a backend
+        Some(p) if passthrough && p != 443 && p != local_port => Some(p),
+        _ => Some(port),
+    }
+}
+
+/// 308 to the same host and path on `tls_port` (port omitted for 443).
+fn redirect_to_https(req: &Request<ProxyBody>, tls_port: u16) -> Response<ProxyBody> {
+    let host = request_host(req)
+        .map(|(h, _)| h)
+        .unwrap_or_else(|| "localhost".to_string());
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    let host = routing::url_host(&host);
+    let location = match tls_port {
+        443 => format!("https://{host}{path}"),
+        port => format!("https://{host}:{port}{path}"),
+    };
+    Response::builder()
+        .status(StatusCode::PERMANENT_REDIRECT)
+        .header(http::header::LOCATION, location)
+        .body(full(Bytes::new()))
+        .unwrap()
+}
+
+/// 500 for a rule whose expanded target is not a valid header value or path.
+fn rule_error(target: &str) -> Response<ProxyBody> {
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(full(format!(
+            "Rule produced an invalid target: {target:?}\n"
+        )))
+        .unwrap()
+}
+
+fn body_too_large(max: u64) -> Response<ProxyBody> {
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .header(CONNECTION, "close")
+        .body(full(format!("Request body exceeds the {max}-byte limit\n")))
+        .unwrap()
+}
+
+/// Whether `err` or any error in its source chain is a `T`.
+fn has_source<T: std::error::Error + 'static>(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut cur = Some(err);
+    while let Some(e) = cur {
+        if e.is::<T>() {
+            return true;
+        }
+        cur = e.source();
+    }
+    false
+}
+
+/// Enforce the route's access policy; returns the 401/403 response when access is denied.
+async fn check_access(
+    req: &Request<ProxyBody>,
+    remote_addr: SocketAddr,
+    domain: &str,
+    route: Option<&Route>,
+) -> Result<Option<Response<ProxyBody>>> {
+    let Some(policy) = route.and_then(|r| r.access.clone()) else {
+        return Ok(None);
+    };
+    let authorization = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let peer = remote_addr.ip();
+    // bcrypt verification is CPU-heavy; keep it off the async workers
+    let decision =
+        tokio::task::spawn_blocking(move || policy.check(peer, authorization.as_deref()))
+            .await
+            .context("access check")?;
+    match decision {
+        AccessDecision::Allow => Ok(None),
+        AccessDecision::Forbidden => Ok(Some(
+            Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(full(format!(
+                    "Forbidden: client {peer} is not allowed to access {domain}\n"
+                )))
+                .unwrap(),
+        )),
+        AccessDecision::Unauthorized { realm } => Ok(Some(
+            Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header(
+                    http::header::WWW_AUTHENTICATE,
+                    format!(
+                        "Basic realm=\"{}\", charset=\"UTF-8\"",
+                        realm.replace('"', "")
+                    ),
+                )
+                .body(full("Authentication required\n"))
+                .unwrap(),
+        )),
+    }
+}
+
+/// 404 page for an unmapped host listing the configured domains and their backend status.
+async fn landing_page(
+    host: &str,
+    info: ConnInfo,
+    routing: &Routing,
+    probes: &ProbeCache,
+) -> Response<ProxyBody> {
+    let domains = probes
+        .probe(
+            routing
+                .routes
+                .iter()
+                .map(|(domain, route)| (domain.to_string(), route.port)),
+        )
+        .await;
+    let page = landing::render(host, &domains, |domain| {
+        origin::public_origin(domain, info.tls, info.local_port)
+    });
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(http::header::CACHE_CONTROL, "no-store")
+        .body(full(page))
+        .unwrap()
+}
+
+/// Body of a form posted to [`upstream::ENDPOINT`]; `None` if it is over
+/// [`upstream::MAX_FORM_BYTES`].
+async fn read_form(mut body: ProxyBody) -> Result<Option<Vec<u8>>> {
+    let mut form = Vec::new();
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| anyhow::anyhow!(e).context("read upstream form"))?;
+        if let Ok(data) = frame.into_data() {
+            if form.len() + data.len() > upstream::MAX_FORM_BYTES {
+                return Ok(None);
+            }
+            form.extend_from_slice(&data);
+        }
+    }
+    Ok(Some(form))
+}
+
+async fn proxy_request(
+    mut req: Request<ProxyBody>,
+    host: Option<(String, Option<u16>)>,
+    info: ConnInfo,
+    state: &ProxyState,
+    routing: &Routing,
+) -> Result<Response<ProxyBody>, anyhow::Error> {
+    let remote_addr = info.remote_addr;
+    let (domain, explicit_port) = match host {
+        Some(h) => h,
         None => {
             return Ok(Response::builder()
                 .status(StatusCode::BAD_REQUEST)
-                .body(Full::from("Missing Host header"))
+                .body(full("Missing Host header"))
                 .unwrap())
         }
     };
 
-    let port = match explicit_port {
-        Some(443) => mappings
-            .get(&domain)
-            .copied()
-            .or_else(|| mappings.iter().find(|(k, _)| k.eq_ignore_ascii_case(&domain)).map(|(_, p)| *p)),
-        Some(p) => Some(p),
-        None => mappings
-            .get(&domain)
-            .copied()
-            .or_else(|| mappings.iter().find(|(k, _)| k.eq_ignore_ascii_case(&domain)).map(|(_, p)| *p)),
-    };
+    if !routing.rules.is_empty() {
+        let (path, query) = (req.uri().path(), req.uri().query());
+        let parts = RequestParts {
+            tls: info.tls,
+            host: &domain,
+            port: info.local_port,
+            path,
+            query,
+        };
+        match routing.rules.apply(&parts) {
+            Some(Action::Redirect { status, location }) => {
+                return Ok(match HeaderValue::from_str(&location) {
+                    Ok(location) => Response::builder()
+                        .status(status)
+                        .header(http::header::LOCATION, location)
+                        .body(full(Bytes::new()))
+                        .unwrap(),
+                    Err(_) => rule_error(&location),
+                });
+            }
+            Some(Action::Rewrite(target)) => match target.parse::<PathAndQuery>() {
+                Ok(pq) => {
+                    let mut parts = req.uri().clone().into_parts();
+                    parts.path_and_query = Some(pq);
+                    *req.uri_mut() = Uri::from_parts(parts)?;
+                }
+                Err(_) => return Ok(rule_error(&target)),
+            },
+            None => {}
+        }
+    }
 
-    let port = match port {
-        Some(p) => p,
-        None => {
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Full::from(
-                    "Unknown domain; add with 'roost serve config add <domain> <port>'",
-                ))
-                .unwrap());
+    let route = routing.routes.get(&domain);
+    if let Some(page) = badssl::page(&domain).filter(|_| state.badssl && route.is_none()) {
+        return Ok(Response::builder()
+            .header(http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(full(page))
+            .unwrap());
+    }
+    if let Some(denied) = check_access(&req, remote_addr, &domain, route).await? {
+        return Ok(denied);
+    }
+    if let Some((auth, client)) =
+        route.and_then(|r| r.forward_auth.as_ref().map(|a| (a, &r.client)))
+    {
+        let original = forward_auth::Original {
+            tls: info.tls,
+            host: &domain,
+            client_ip: remote_addr.ip(),
+        };
+        match forward_auth::authorize(auth, client, &req, original).await {
+            Decision::Allow(headers) => forward_auth::apply(auth, req.headers_mut(), headers),
+            Decision::Deny(response) => return Ok(response),
+        }
+    }
+    let upstream = route.and_then(|r| r.upstream.as_ref().map(|u| (r.port, u)));
+    if let Some((port, sel)) = upstream.filter(|_| req.uri().path() == upstream::ENDPOINT) {
+        let (head, body) = req.into_parts();
+        let mut params = head.uri.query().unwrap_or_default().to_string();
+        if head.method == Method::POST {
+            match read_form(body).await? {
+                Some(form) => {
+                    params.push('&');
+                    params.push_str(&String::from_utf8_lossy(&form));
+                }
+                None => return Ok(body_too_large(upstream::MAX_FORM_BYTES as u64)),
+            }
         }
+        let response = upstream::endpoint(
+            sel,
+            &domain,
+            port,
+            &head.method,
+            &params,
+            &head.headers,
+            info.tls,
+        );
+        return Ok(response.map(full));
+    }
+    let limits = route.map(|r| r.limits).unwrap_or(state.limits);
+    let declared_len = req
+        .headers()
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let (Some(max), Some(len)) = (limits.max_body_bytes, declared_len) {
+        if len > max {
+            return Ok(body_too_large(max));
+        }
+    }
+
+    let port = backend_port(
+        route.map(|r| (r.port, r.port_passthrough)),
+        explicit_port,
+        info.local_port,
+    );
+    let port = match port.or(routing.default_backend) {
+        Some(p) => p,
+        None => return Ok(landing_page(&domain, info, routing, &state.probes).await),
     };
+    let alternate = upstream.and_then(|(_, sel)| sel.select_for(req.headers()));
+    let port = alternate.unwrap_or(port);
+
+    let chaos = route.and_then(|r| state.chaos_for(&domain, r, req.uri().path()));
+    let mut truncate = false;
+    if let Some(chaos) = &chaos {
+        let latency = chaos.latency();
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        match chaos.pick_fault() {
+            Fault::None => {}
+            Fault::Drop => return Err(DropConnection.into()),
+            Fault::Error(status) => {
+                return Ok(Response::builder()
+                    .status(status)
+                    .header("x-roost-chaos", "error")
+                    .body(full(format!("Injected error {status} (roost chaos)\n")))
+                    .unwrap());
+            }
+            Fault::Truncate => truncate = true,
+        }
+    }
 
-    let backend = format!("http://localhost:{port}");
+    let authority = match route {
+        Some(r) if r.port == port => r.authority.clone(),
+        _ => routing::backend_authority(port),
+    };
 
     req.headers_mut()
         .insert("x-forwarded-for", remote_addr.to_string().parse().unwrap());
-    req.headers_mut()
-        .insert("x-forwarded-proto", "https".parse().unwrap());
+    req.headers_mut().insert(
+        "x-forwarded-proto",
+        if info.tls { "https" } else { "http" }.parse().unwrap(),
+    );
     req.headers_mut()
         .insert("x-forwarded-host", domain.parse().unwrap());
 
-    let uri = format!(
-        "{}{}",
-        backend,
-        req.uri()
-            .path_and_query()
-            .map(|p| p.as_str())
-            .unwrap_or("/")
-    );
-    *req.uri_mut() = uri.parse().unwrap();
+    *req.uri_mut() = routing::backend_uri(&authority, req.uri().path_and_query());
 
     let is_ws_upgrade = req
         .headers()
         .get(CONNECTION)
         .and_then(|v| v.to_str().ok())
-        .map(|v| v.to_lowercase().contains("upgrade"))
+        .map(|v| {
+            v.split(',')
+                .any(|t| t.trim().eq_ignore_ascii_case("upgrade"))
+        })
         .unwrap_or(false)
         && req
             .headers()
@@ -348,21 +1273,96 @@ async fn proxy_request(
             .map(|v| v.eq_ignore_ascii_case("websocket"))
             .unwrap_or(false);
 
+    let exchange = route
+        .and_then(|r| r.cache.as_ref())
+        .filter(|_| !is_ws_upgrade)
+        .map(|cache| {
+            let target = req.uri().path_and_query().map_or("/", |p| p.as_str());
+            cache.begin(Cache::key(port, target), req.method(), req.headers())
+        });
+    let hit = exchange.as_ref().and_then(|e| e.hit());
+    if let Some(exchange) = &exchange {
+        exchange.prepare(req.headers_mut());
+    }
+
     let server_upgrade = is_ws_upgrade.then(|| upgrade::on(&mut req));
+    let encoding = route
+        .and_then(|r| r.compression.as_ref())
+        .filter(|_| req.method() != http::Method::HEAD)
+        .and_then(|settings| {
+            req.headers()
+                .get(http::header::ACCEPT_ENCODING)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|accept| compress::negotiate(accept, &settings.encodings))
+                .map(|e| (e, settings))
+        });
 
-    let mut response = client
-        .request(req)
-        .await
-        .with_context(|| format!("connect to backend {backend}"))?;
+    let client = route.map(|r| &r.client).unwrap_or(&state.client);
+    let mut shadow = route
+        .and_then(|r| r.mirror.as_ref())
+        .filter(|_| !is_ws_upgrade && hit.is_none())
+        .map(|mirror| Shadow::start(mirror, &req, &domain, client.clone()));
+    let stats = state.metrics.domain(routing.metrics_domain(&domain));
+    let req = req.map(|body| {
+        let mut body = match limits.max_body_bytes {
+            Some(max) => Limited::new(body, max as usize).boxed(),
+            None => body,
+        };
+        if let Some(shadow) = &mut shadow {
+            body = shadow.tee(body);
+        }
+        CountingBody::incoming(body, stats).boxed()
+    });
+    let started = std::time::Instant::now();
+    let deadline = limits
+        .request_timeout
+        .map(|timeout| (tokio::time::Instant::now() + timeout, timeout));
+    let pending = async {
+        match hit {
+            Some(response) => Ok(response),
+            None => client
+                .request(req)
+                .await
+                .map(|r| r.map(|b| b.map_err(BoxError::from).boxed())),
+        }
+    };
+    let result = match deadline {
+        Some((deadline, timeout)) => match tokio::time::timeout_at(deadline, pending).await {
+            Ok(result) => result,
+            Err(_) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::GATEWAY_TIMEOUT)
+                    .body(full(format!(
+                        "Backend http://{authority} did not respond within {}s\n",
+                        timeout.as_secs()
+                    )))
+                    .unwrap());
+            }
+        },
+        None => pending.await,
+    };
+    if let Some(shadow) = shadow {
+        let status = result.as_ref().ok().map(|r| r.status());
+        shadow.primary_done(status, started.elapsed());
+    }
+    let mut response = match result {
+        Ok(r) => r,
+        Err(e) if has_source::<LengthLimitError>(&e) => {
+            return Ok(body_too_large(limits.max_body_bytes.unwrap_or_default()));
+        }
+        Err(e) => return Err(e).with_context(|| format!("connect to backend http://{authority}")),
+    };
 
     if response.status() == StatusCode::SWITCHING_PROTOCOLS {
         let client_upgrade = upgrade::on(&mut response);
         let (parts, _body) = response.into_parts();
 
         if let Some(server_upgrade) = server_upgrade {
-            tokio::spawn(async move {
+            let metrics = state.metrics.clone();
+            state.tasks.spawn(async move {
                 match tokio::try_join!(server_upgrade, client_upgrade) {
                     Ok((server_stream, client_stream)) => {
+                        let _tunnel = metrics.tunnel();
                         let mut server_io = hyper_util::rt::TokioIo::new(server_stream);
                         let mut client_io = hyper_util::rt::TokioIo::new(client_stream);
                         if let Err(e) =
@@ -382,10 +1382,51 @@ async fn proxy_request(
             });
         }
 
-        return Ok(Response::from_parts(parts, Full::from(Bytes::new())));
+        return Ok(Response::from_parts(parts, full(Bytes::new())));
     }
 
-    let (parts, body) = response.into_parts();
-    let bytes = body.collect().await.context("read body")?.to_bytes();
-    Ok(Response::from_parts(parts, Full::from(bytes)))
+    let (mut parts, mut body) = response.into_parts();
+    if let Some(timeout) = limits.body_timeout {
+        let deadline = tokio::time::Instant::now() + timeout;
+        body = DeadlineBody::new(body, deadline, timeout).boxed();
+    }
+    if let Some(exchange) = exchange {
+        (parts, body) = exchange.finish(parts, body);
+    }
+    if let Some((encoding, settings)) = encoding {
+        if compress::should_compress(&parts, settings) {
+            (parts, body) = compress::compress(parts, body, encoding);
+        }
+    }
+    if truncate {
+        let limit = parts
+            .headers
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok())
+            .map(|len| len / 2);
+        body = TruncatedBody::new(body, limit).boxed();
+    }
+    if let Some(bps) = chaos.as_ref().and_then(|c| c.bandwidth_bps) {
+        body = ThrottledBody::new(body, bps).boxed();
+    }
+    if route.is_some_and(|r| !r.preserve_origin) {
+        origin::rewrite_headers(&mut parts.headers, port, &domain, info.tls, info.local_port);
+    }
+    if let Some(port) = alternate {
+        parts.headers.insert(upstream::HEADER, port.into());
+    }
+    if info.tls
+        && !parts
+            .headers
+            .contains_key(http::header::STRICT_TRANSPORT_SECURITY)
+    {
+        if let Some(hsts) = route.and_then(|r| r.hsts) {
+            parts.headers.insert(
+                http::header::STRICT_TRANSPORT_SECURITY,
+                hsts.header_value().parse().unwrap(),
+            );
+        }
+    }
+    Ok(Response::from_parts(parts, body))
 }
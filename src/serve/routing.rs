@@ -0,0 +1,118 @@
+//! Host lookup on the request hot path: tables keyed by normalized host name, `Host`
+//! header parsing, and backend URIs built from parts instead of formatted strings.
+
+use http::uri::{Authority, PathAndQuery, Scheme};
+use http::Uri;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Lowercase `host` and drop a trailing dot (`App.Test.` -> `app.test`). Borrows when
+/// the name is already normalized, which is the common case.
+pub fn normalize(host: &str) -> Cow<'_, str> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if host.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(host.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(host)
+    }
+}
+
+/// Values keyed by normalized host name.
+#[derive(Debug, Clone)]
+pub struct HostTable<T> {
+    entries: HashMap<Box<str>, T>,
+}
+
+impl<T> Default for HostTable<T> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<T> HostTable<T> {
+    pub fn insert(&mut self, host: &str, value: T) {
+        self.entries.insert(normalize(host).into(), value);
+    }
+
+    /// Look up a host in any case, with or without a trailing dot.
+    pub fn get(&self, host: &str) -> Option<&T> {
+        self.entries.get(normalize(host).as_ref())
+    }
+
+    pub fn contains(&self, host: &str) -> bool {
+        self.get(host).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.entries.iter().map(|(host, value)| (&**host, value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<T> FromIterator<(String, T)> for HostTable<T> {
+    fn from_iter<I: IntoIterator<Item = (String, T)>>(iter: I) -> Self {
+        let mut table = Self::default();
+        for (host, value) in iter {
+            table.insert(&host, value);
+        }
+        table
+    }
+}
+
+/// Parse "host" or "host:port" into the normalized host and the port, if any. IPv6
+/// addresses come in brackets ("[::1]:8443") and are returned without them.
+pub fn parse_host(s: &str) -> (String, Option<u16>) {
+    let s = s.trim();
+    if let Some((ip, rest)) = s.strip_prefix('[').and_then(|s| s.split_once(']')) {
+        let port = rest.strip_prefix(':').and_then(|p| p.parse().ok());
+        return (ip.to_ascii_lowercase(), port);
+    }
+    let (host, port) = match s.rfind(':') {
+        Some(colon) => {
+            let (h, p) = s.split_at(colon);
+            match p[1..].parse::<u16>() {
+                Ok(port) => (h, Some(port)),
+                Err(_) => (s, None),
+            }
+        }
+        None => (s, None),
+    };
+    (normalize(host).into_owned(), port)
+}
+
+/// `host` as written in a URL: IPv6 addresses in brackets (`::1` -> `[::1]`).
+pub fn url_host(host: &str) -> Cow<'_, str> {
+    if host.contains(':') {
+        Cow::Owned(format!("[{host}]"))
+    } else {
+        Cow::Borrowed(host)
+    }
+}
+
+/// `localhost:<port>`, the authority of a backend.
+pub fn backend_authority(port: u16) -> Authority {
+    Authority::try_from(format!("localhost:{port}")).expect("localhost:<port> is a valid authority")
+}
+
+/// `http://<authority><path_and_query>`, reusing the already parsed request target.
+pub fn backend_uri(authority: &Authority, path_and_query: Option<&PathAndQuery>) -> Uri {
+    Uri::builder()
+        .scheme(Scheme::HTTP)
+        .authority(authority.clone())
+        .path_and_query(
+            path_and_query
+                .cloned()
+                .unwrap_or_else(|| PathAndQuery::from_static("/")),
+        )
+        .build()
+        .expect("scheme, authority and path form a valid URI")
+}
@@ -0,0 +1,271 @@
+//! Redirect and rewrite rules (`[[serve.rules]]`), evaluated in order before a request
+//! is routed. The first rule whose host and path patterns match applies.
+
+use anyhow::{Context, Result};
+use http::StatusCode;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+/// Redirect status used when a rule sets none. Temporary, so browsers don't cache it.
+pub const DEFAULT_REDIRECT_STATUS: u16 = 302;
+
+/// Variables available in targets besides the captures.
+const VARIABLES: [&str; 4] = ["scheme", "host", "port", "path"];
+
+/// A rule as written in `.roostrc`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    /// Pattern for the request host (without port); case-insensitive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Pattern for the request path (without query).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Treat `host` and `path` as regular expressions instead of globs.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub regex: bool,
+    /// Redirect to this URL or path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<String>,
+    /// Redirect status: 301, 302, 307 or 308 (default 302).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Proxy to this path instead, without telling the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rewrite: Option<String>,
+}
+
+/// What a matching rule does to a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Redirect {
+        status: StatusCode,
+        location: String,
+    },
+    /// New path and query to send to the backend.
+    Rewrite(String),
+}
+
+/// The parts of a request rules match on and expand into targets.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestParts<'a> {
+    pub tls: bool,
+    pub host: &'a str,
+    /// Port the client connected to.
+    pub port: u16,
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+}
+
+/// Rules compiled from config, in evaluation order.
+#[derive(Debug, Default)]
+pub struct Rules(Vec<Compiled>);
+
+#[derive(Debug)]
+struct Compiled {
+    host: Option<Regex>,
+    path: Option<Regex>,
+    target: String,
+    /// `None` for a rewrite.
+    status: Option<StatusCode>,
+}
+
+impl Rules {
+    pub fn compile(rules: &[Rule]) -> Result<Self> {
+        rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| compile(rule).with_context(|| format!("rule {}", i + 1)))
+            .collect::<Result<_>>()
+            .map(Rules)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Action of the first matching rule, if any.
+    pub fn apply(&self, req: &RequestParts) -> Option<Action> {
+        self.0.iter().find_map(|rule| rule.apply(req))
+    }
+}
+
+fn compile(rule: &Rule) -> Result<Compiled> {
+    if rule.host.is_none() && rule.path.is_none() {
+        anyhow::bail!("needs a host or path pattern");
+    }
+    let pattern = |p: &Option<String>, case_insensitive: bool| -> Result<Option<Regex>> {
+        let Some(p) = p else {
+            return Ok(None);
+        };
+        let source = if rule.regex {
+            format!("^(?:{p})$")
+        } else {
+            glob_to_regex(p)
+        };
+        let source = if case_insensitive {
+            format!("(?i){source}")
+        } else {
+            source
+        };
+        Regex::new(&source)
+            .map(Some)
+            .with_context(|| format!("invalid pattern {p:?}"))
+    };
+    let host = pattern(&rule.host, true)?;
+    let path = pattern(&rule.path, false)?;
+    let (target, status) = match (&rule.redirect, &rule.rewrite) {
+        (Some(target), None) => {
+            let status = rule.status.unwrap_or(DEFAULT_REDIRECT_STATUS);
+            if !matches!(status, 301 | 302 | 307 | 308) {
+                anyhow::bail!("redirect status must be 301, 302, 307 or 308, got {status}");
+            }
+            (target, Some(StatusCode::from_u16(status)?))
+        }
+        (None, Some(target)) => {
+            if rule.status.is_some() {
+                anyhow::bail!("status only applies to redirects");
+            }
+            if !target.starts_with('/') {
+                anyhow::bail!("rewrite target must be a path starting with '/', got {target:?}");
+            }
+            (target, None)
+        }
+        _ => anyhow::bail!("needs exactly one of redirect or rewrite"),
+    };
+    let groups = [&host, &path]
+        .into_iter()
+        .flatten()
+        .map(|r| r.captures_len() - 1)
+        .sum();
+    let names: Vec<&str> = [&host, &path]
+        .into_iter()
+        .flatten()
+        .flat_map(|r| r.capture_names().flatten())
+        .collect();
+    for reference in references(target)? {
+        match reference.parse::<usize>() {
+            Ok(n) if n == 0 || n > groups => {
+                anyhow::bail!("${n} in {target:?}, but the patterns capture {groups} group(s)")
+            }
+            Ok(_) => {}
+            Err(_) if names.contains(&reference) || VARIABLES.contains(&reference) => {}
+            Err(_) => anyhow::bail!("unknown variable ${reference} in {target:?}"),
+        }
+    }
+    Ok(Compiled {
+        host,
+        path,
+        target: target.clone(),
+        status,
+    })
+}
+
+impl Compiled {
+    fn apply(&self, req: &RequestParts) -> Option<Action> {
+        let host = match &self.host {
+            Some(re) => Some(re.captures(req.host)?),
+            None => None,
+        };
+        let path = match &self.path {
+            Some(re) => Some(re.captures(req.path)?),
+            None => None,
+        };
+        let caps: Vec<&Captures> = host.iter().chain(path.iter()).collect();
+        let mut target = expand(&self.target, &caps, req);
+        if let Some(query) = req.query.filter(|_| !target.contains('?')) {
+            target = format!("{target}?{query}");
+        }
+        Some(match self.status {
+            Some(status) => Action::Redirect {
+                status,
+                location: target,
+            },
+            None => Action::Rewrite(target),
+        })
+    }
+}
+
+/// Anchored regex for a glob: `*` matches any run of characters and captures it.
+fn glob_to_regex(glob: &str) -> String {
+    let parts: Vec<String> = glob.split('*').map(regex::escape).collect();
+    format!("^{}$", parts.join("(.*)"))
+}
+
+/// Names referenced in a target: `$1`, `${2}`, `$name`, `${name}`; `$$` is a literal `$`.
+fn references(target: &str) -> Result<Vec<&str>> {
+    let mut refs = Vec::new();
+    let mut rest = target;
+    while let Some(i) = rest.find('$') {
+        let (name, len) = parse_reference(&rest[i + 1..])
+            .with_context(|| format!("bad '$' in {target:?}; use $$ for a literal '$'"))?;
+        if let Some(name) = name {
+            refs.push(name);
+        }
+        rest = &rest[i + 1 + len..];
+    }
+    Ok(refs)
+}
+
+/// Parse what follows a `$`: the referenced name (`None` for `$$`) and its length.
+fn parse_reference(s: &str) -> Option<(Option<&str>, usize)> {
+    if s.starts_with('$') {
+        return Some((None, 1));
+    }
+    if let Some(inner) = s.strip_prefix('{') {
+        let end = inner.find('}')?;
+        return (end > 0).then(|| (Some(&inner[..end]), end + 2));
+    }
+    let digits = s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 {
+        return Some((Some(&s[..digits]), digits));
+    }
+    let ident = s.len()
+        - s.trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '_')
+            .len();
+    (ident > 0).then(|| (Some(&s[..ident]), ident))
+}
+
+/// Substitute captures and variables into a target validated by [`compile`].
+fn expand(target: &str, caps: &[&Captures], req: &RequestParts) -> String {
+    let mut out = String::with_capacity(target.len());
+    let mut rest = target;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let (name, len) = parse_reference(&rest[i + 1..]).unwrap_or((None, 0));
+        match name {
+            None => out.push('$'),
+            Some(name) => out.push_str(&lookup(name, caps, req)),
+        }
+        rest = &rest[i + 1 + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn lookup(name: &str, caps: &[&Captures], req: &RequestParts) -> String {
+    if let Ok(n) = name.parse::<usize>() {
+        // Number groups across the host pattern, then the path pattern
+        let mut n = n;
+        for c in caps {
+            if n < c.len() {
+                return c.get(n).map_or("", |m| m.as_str()).to_string();
+            }
+            n -= c.len() - 1;
+        }
+        return String::new();
+    }
+    if let Some(m) = caps.iter().find_map(|c| c.name(name)) {
+        return m.as_str().to_string();
+    }
+    match name {
+        "scheme" => if req.tls { "https" } else { "http" }.to_string(),
+        "host" => req.host.to_string(),
+        "port" => match (req.tls, req.port) {
+            (true, 443) | (false, 80) => String::new(),
+            (_, port) => format!(":{port}"),
+        },
+        "path" => req.path.to_string(),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,274 @@
+//! Per-domain TLS policy: protocol versions, cipher suites, ALPN and leaf key type, plus
+//! the session cache and ticket keys shared by every listener for resumption.
+
+use anyhow::{Context, Result};
+use rustls::crypto::CryptoProvider;
+use rustls::server::{
+    ProducesTickets, ResolvesServerCert, ServerSessionMemoryCache, StoresServerSessions,
+};
+use rustls::{ServerConfig, SupportedCipherSuite, SupportedProtocolVersion};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// ALPN protocols offered when a policy does not set `alpn`.
+pub const DEFAULT_ALPN: [&str; 2] = ["http/1.1", "http/1.0"];
+
+/// Sessions kept for stateful resumption, across all listeners and domains.
+const SESSION_CACHE_SIZE: usize = 4096;
+
+/// Resumption state shared by every server config: a session cache for TLS 1.3 stateful
+/// resumption and TLS 1.2 session IDs, and ticket keys for stateless tickets. Ticket keys
+/// are random per process and rotate every 6 hours; tickets under the previous key stay
+/// valid until they expire.
+#[derive(Clone)]
+pub struct TlsSessions {
+    storage: Arc<dyn StoresServerSessions>,
+    ticketer: Arc<dyn ProducesTickets>,
+}
+
+impl TlsSessions {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            storage: ServerSessionMemoryCache::new(SESSION_CACHE_SIZE),
+            ticketer: rustls::crypto::aws_lc_rs::Ticketer::new()
+                .context("create TLS ticket keys")?,
+        })
+    }
+}
+
+impl std::fmt::Debug for TlsSessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsSessions").finish_non_exhaustive()
+    }
+}
+
+/// TLS protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    fn rustls(self) -> &'static SupportedProtocolVersion {
+        match self {
+            TlsVersion::Tls12 => &rustls::version::TLS12,
+            TlsVersion::Tls13 => &rustls::version::TLS13,
+        }
+    }
+}
+
+impl std::str::FromStr for TlsVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().trim_start_matches("tls").trim() {
+            "1.2" | "12" => Ok(TlsVersion::Tls12),
+            "1.3" | "13" => Ok(TlsVersion::Tls13),
+            _ => anyhow::bail!("unsupported TLS version {s:?}; use 1.2 or 1.3"),
+        }
+    }
+}
+
+impl std::fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TlsVersion::Tls12 => "1.2",
+            TlsVersion::Tls13 => "1.3",
+        })
+    }
+}
+
+/// Which leaf certificate to serve.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyType {
+    /// ECDSA P-256 (default)
+    #[default]
+    Ecdsa,
+    /// RSA 2048
+    Rsa,
+    /// ECDSA when the client's signature schemes allow it, else RSA
+    Both,
+}
+
+impl KeyType {
+    pub fn needs_rsa(self) -> bool {
+        matches!(self, KeyType::Rsa | KeyType::Both)
+    }
+}
+
+/// TLS settings for a mapping (`[serve.mappings.tls]`) or all mappings (`[serve.tls]`).
+/// Unset fields fall back to `[serve.tls]`, then to rustls defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<TlsVersion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_version: Option<TlsVersion>,
+    /// Allowed cipher suites by IANA name (e.g. `TLS13_AES_128_GCM_SHA256`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ciphers: Option<Vec<String>>,
+    /// ALPN protocols to offer, in preference order; empty disables ALPN.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alpn: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<KeyType>,
+}
+
+impl TlsPolicy {
+    /// Fill unset fields from `fallback`.
+    pub fn or(&self, fallback: &TlsPolicy) -> TlsPolicy {
+        TlsPolicy {
+            min_version: self.min_version.or(fallback.min_version),
+            max_version: self.max_version.or(fallback.max_version),
+            ciphers: self.ciphers.clone().or_else(|| fallback.ciphers.clone()),
+            alpn: self.alpn.clone().or_else(|| fallback.alpn.clone()),
+            key_type: self.key_type.or(fallback.key_type),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == TlsPolicy::default()
+    }
+
+    pub fn key_type(&self) -> KeyType {
+        self.key_type.unwrap_or_default()
+    }
+
+    /// Whether the policy needs its own `ServerConfig` (anything besides the key type set).
+    fn customizes_handshake(&self) -> bool {
+        self.min_version.is_some()
+            || self.max_version.is_some()
+            || self.ciphers.is_some()
+            || self.alpn.is_some()
+    }
+
+    fn versions(&self) -> Vec<&'static SupportedProtocolVersion> {
+        let min = self.min_version.unwrap_or(TlsVersion::Tls12);
+        let max = self.max_version.unwrap_or(TlsVersion::Tls13);
+        [TlsVersion::Tls12, TlsVersion::Tls13]
+            .into_iter()
+            .filter(|v| (min..=max).contains(v))
+            .map(TlsVersion::rustls)
+            .collect()
+    }
+
+    fn provider(&self) -> Result<CryptoProvider> {
+        let mut provider = default_provider();
+        if let Some(names) = &self.ciphers {
+            let available = cipher_suite_names();
+            for name in names {
+                if !available.iter().any(|a| a.eq_ignore_ascii_case(name)) {
+                    anyhow::bail!(
+                        "unknown cipher suite {name:?}; available: {}",
+                        available.join(", ")
+                    );
+                }
+            }
+            provider.cipher_suites.retain(|suite| {
+                let name = suite_name(suite);
+                names.iter().any(|n| n.eq_ignore_ascii_case(&name))
+            });
+        }
+        Ok(provider)
+    }
+
+    /// Check versions, cipher names and that at least one cipher suite fits the versions.
+    pub fn validate(&self) -> Result<()> {
+        if let (Some(min), Some(max)) = (self.min_version, self.max_version) {
+            if min > max {
+                anyhow::bail!("min_version {min} is above max_version {max}");
+            }
+        }
+        ServerConfig::builder_with_provider(Arc::new(self.provider()?))
+            .with_protocol_versions(&self.versions())
+            .context("no cipher suite allowed for the configured TLS versions")?;
+        Ok(())
+    }
+
+    /// Server config applying this policy, with certs from `resolver` and resumption
+    /// through `sessions`.
+    pub fn server_config(
+        &self,
+        resolver: Arc<dyn ResolvesServerCert>,
+        sessions: &TlsSessions,
+    ) -> Result<ServerConfig> {
+        self.validate()?;
+        let mut config = ServerConfig::builder_with_provider(Arc::new(self.provider()?))
+            .with_protocol_versions(&self.versions())?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver);
+        config.session_storage = sessions.storage.clone();
+        config.ticketer = sessions.ticketer.clone();
+        config.alpn_protocols = match &self.alpn {
+            Some(protocols) => protocols.iter().map(|p| p.as_bytes().to_vec()).collect(),
+            None => DEFAULT_ALPN.iter().map(|p| p.as_bytes().to_vec()).collect(),
+        };
+        Ok(config)
+    }
+}
+
+fn default_provider() -> CryptoProvider {
+    ServerConfig::builder().crypto_provider().as_ref().clone()
+}
+
+fn suite_name(suite: &SupportedCipherSuite) -> String {
+    format!("{:?}", suite.suite())
+}
+
+/// Names of the cipher suites the proxy supports, in rustls preference order.
+pub fn cipher_suite_names() -> Vec<String> {
+    default_provider()
+        .cipher_suites
+        .iter()
+        .map(suite_name)
+        .collect()
+}
+
+/// Server configs for the TLS listeners: one per domain with a custom policy, plus the default.
+#[derive(Debug)]
+pub struct TlsConfigs {
+    default: Arc<ServerConfig>,
+    by_domain: HashMap<String, Arc<ServerConfig>>,
+}
+
+impl TlsConfigs {
+    /// Build configs for `policies` (effective policy per lowercase domain), all sharing
+    /// `sessions`.
+    pub fn new(
+        policies: &HashMap<String, TlsPolicy>,
+        resolver: Arc<dyn ResolvesServerCert>,
+        sessions: &TlsSessions,
+    ) -> Result<Self> {
+        let default = Arc::new(TlsPolicy::default().server_config(resolver.clone(), sessions)?);
+        let mut by_domain = HashMap::new();
+        for (domain, policy) in policies {
+            if policy.customizes_handshake() {
+                let config = policy
+                    .server_config(resolver.clone(), sessions)
+                    .with_context(|| format!("TLS settings for {domain}"))?;
+                by_domain.insert(domain.clone(), Arc::new(config));
+            }
+        }
+        Ok(Self { default, by_domain })
+    }
+
+    /// Config for a ClientHello's SNI (see [`sni_host`]).
+    pub fn for_sni(&self, sni: Option<&str>) -> Arc<ServerConfig> {
+        sni.and_then(sni_host)
+            .and_then(|host| self.by_domain.get(&host))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+/// Host part of an SNI value, lowercased. Some clients include a port (e.g. "host:443").
+pub fn sni_host(sni: &str) -> Option<String> {
+    let key = sni.trim().to_lowercase();
+    let host = key.split(':').next().unwrap_or(&key).trim();
+    (!host.is_empty()).then(|| host.to_string())
+}
@@ -0,0 +1,472 @@
+//! W3C trace context propagation, X-Request-Id and OTLP/HTTP span export.
+
+use anyhow::{Context as _, Result};
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::{Request, StatusCode, Uri};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+pub const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+pub const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// `service.name` of exported spans unless configured.
+pub const DEFAULT_SERVICE_NAME: &str = "roost";
+
+/// Spans buffered for export; more are dropped until the exporter catches up.
+const QUEUE_SIZE: usize = 4096;
+/// Spans sent per OTLP request.
+const BATCH_SIZE: usize = 256;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tracing settings from `[serve.tracing]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tracing {
+    /// Add `traceparent`/`tracestate` and `X-Request-Id` to backend requests (default true).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub propagate: Option<bool>,
+    /// OTLP/HTTP collector (e.g. `http://127.0.0.1:4318`); one span per request is exported when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` resource attribute of exported spans (default "roost").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+}
+
+impl Tracing {
+    /// Fill unset fields from `fallback`.
+    pub fn or(&self, fallback: &Tracing) -> Tracing {
+        Tracing {
+            propagate: self.propagate.or(fallback.propagate),
+            otlp_endpoint: self
+                .otlp_endpoint
+                .clone()
+                .or_else(|| fallback.otlp_endpoint.clone()),
+            service_name: self
+                .service_name
+                .clone()
+                .or_else(|| fallback.service_name.clone()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Tracing::default()
+    }
+
+    pub fn propagate(&self) -> bool {
+        self.propagate.unwrap_or(true)
+    }
+
+    pub fn service_name(&self) -> &str {
+        self.service_name.as_deref().unwrap_or(DEFAULT_SERVICE_NAME)
+    }
+
+    /// Traces URL of the OTLP endpoint: `/v1/traces` is appended when the endpoint has no path.
+    pub fn traces_url(&self) -> Result<Option<Uri>> {
+        let Some(endpoint) = &self.otlp_endpoint else {
+            return Ok(None);
+        };
+        let uri: Uri = endpoint
+            .parse()
+            .with_context(|| format!("invalid OTLP endpoint {endpoint:?}"))?;
+        if uri.scheme_str() != Some("http") || uri.host().is_none() {
+            anyhow::bail!("OTLP endpoint must be an http:// URL, got {endpoint:?}");
+        }
+        if matches!(uri.path(), "" | "/") {
+            let base = endpoint.trim_end_matches('/');
+            return Ok(Some(format!("{base}/v1/traces").parse()?));
+        }
+        Ok(Some(uri))
+    }
+}
+
+/// Trace context for one request: continues the client's trace, or starts a new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    /// Span created by the proxy for this request; the backend sees it as its parent.
+    pub span_id: [u8; 8],
+    /// Span from the incoming `traceparent`, if any.
+    pub parent_id: Option<[u8; 8]>,
+    pub flags: u8,
+    /// Incoming `tracestate`, forwarded unchanged when the `traceparent` was valid.
+    pub state: Option<HeaderValue>,
+}
+
+impl TraceContext {
+    /// Continue the trace in `headers`, or start a sampled one when there is no valid `traceparent`.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut rng = rand::rng();
+        let span_id = nonzero(|| rng.random());
+        let incoming = headers
+            .get(TRACEPARENT)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_traceparent);
+        match incoming {
+            Some((trace_id, parent_id, flags)) => Self {
+                trace_id,
+                span_id,
+                parent_id: Some(parent_id),
+                flags,
+                state: headers.get(TRACESTATE).cloned(),
+            },
+            None => Self {
+                trace_id: nonzero(|| rng.random()),
+                span_id,
+                parent_id: None,
+                flags: 0x01,
+                state: None,
+            },
+        }
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            self.flags
+        )
+    }
+
+    /// Set `traceparent` (and `tracestate`, when continuing a trace) on an outgoing request.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        headers.insert(TRACEPARENT, self.traceparent().parse().unwrap());
+        headers.remove(TRACESTATE);
+        if let Some(state) = &self.state {
+            headers.insert(TRACESTATE, state.clone());
+        }
+    }
+}
+
+fn nonzero<const N: usize>(mut random: impl FnMut() -> [u8; N]) -> [u8; N] {
+    loop {
+        let id = random();
+        if id.iter().any(|&b| b != 0) {
+            return id;
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 || !s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    let mut out = [0u8; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+/// Parse a `traceparent` header into (trace id, parent span id, flags).
+/// Version 00 must have exactly four fields; later versions may append more.
+pub fn parse_traceparent(value: &str) -> Option<([u8; 16], [u8; 8], u8)> {
+    let mut fields = value.trim().split('-');
+    let version: [u8; 1] = unhex(fields.next()?)?;
+    let trace_id: [u8; 16] = unhex(fields.next()?)?;
+    let parent_id: [u8; 8] = unhex(fields.next()?)?;
+    let [flags]: [u8; 1] = unhex(fields.next()?)?;
+    if version[0] == 0xff || (version[0] == 0 && fields.next().is_some()) {
+        return None;
+    }
+    if trace_id.iter().all(|&b| b == 0) || parent_id.iter().all(|&b| b == 0) {
+        return None;
+    }
+    Some((trace_id, parent_id, flags))
+}
+
+/// The client's `X-Request-Id` when it is a sane token, else a new random (v4) UUID.
+pub fn request_id(headers: &HeaderMap) -> HeaderValue {
+    let incoming = headers.get(X_REQUEST_ID).filter(|v| {
+        !v.is_empty() && v.len() <= 200 && v.as_bytes().iter().all(|b| b.is_ascii_graphic())
+    });
+    if let Some(id) = incoming {
+        return id.clone();
+    }
+    let mut b: [u8; 16] = rand::rng().random();
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let h = hex(&b);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &h[..8],
+        &h[8..12],
+        &h[12..16],
+        &h[16..20],
+        &h[20..]
+    )
+    .parse()
+    .unwrap()
+}
+
+/// A finished request, exported as an OTLP server span.
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub context: TraceContext,
+    pub name: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, serde_json::Value)>,
+    pub status: Option<StatusCode>,
+}
+
+fn unix_nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn attribute(key: &str, value: &serde_json::Value) -> serde_json::Value {
+    let value = match value {
+        serde_json::Value::Number(n) => json!({ "intValue": n.to_string() }),
+        serde_json::Value::Bool(b) => json!({ "boolValue": b }),
+        other => json!({ "stringValue": other.as_str().unwrap_or_default() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// OTLP/JSON `ExportTraceServiceRequest` body for `spans`.
+pub fn otlp_json(service_name: &str, spans: &[Span]) -> serde_json::Value {
+    let spans: Vec<serde_json::Value> = spans
+        .iter()
+        .map(|span| {
+            let mut s = json!({
+                "traceId": hex(&span.context.trace_id),
+                "spanId": hex(&span.context.span_id),
+                "name": span.name,
+                "kind": 2, // SPAN_KIND_SERVER
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(k, v)| attribute(k, v))
+                    .collect::<Vec<_>>(),
+                "flags": span.context.flags as u32,
+            });
+            if let Some(parent) = span.context.parent_id {
+                s["parentSpanId"] = json!(hex(&parent));
+            }
+            if let Some(state) = span.context.state.as_ref().and_then(|v| v.to_str().ok()) {
+                s["traceState"] = json!(state);
+            }
+            // STATUS_CODE_ERROR for 5xx (server spans leave 4xx unset)
+            if span.status.is_some_and(|s| s.is_server_error()) {
+                s["status"] = json!({ "code": 2 });
+            }
+            s
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", &json!(service_name))]
+            },
+            "scopeSpans": [{
+                "scope": { "name": "roost", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans
+            }]
+        }]
+    })
+}
+
+/// Sends spans to an OTLP/HTTP collector in batches from a background task.
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    tx: mpsc::Sender<Span>,
+}
+
+impl Exporter {
+    /// Start exporting to `url` until `shutdown` is cancelled (queued spans are flushed then).
+    pub fn start(url: Uri, service_name: String, shutdown: CancellationToken) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(export_loop(url, service_name, rx, shutdown));
+        Self { tx }
+    }
+
+    /// Queue a span; dropped when the queue is full.
+    pub fn export(&self, span: Span) {
+        let _ = self.tx.try_send(span);
+    }
+}
+
+async fn export_loop(
+    url: Uri,
+    service_name: String,
+    mut rx: mpsc::Receiver<Span>,
+    shutdown: CancellationToken,
+) {
+    let client: Client<HttpConnector, Full<Bytes>> =
+        Client::builder(TokioExecutor::new()).build_http();
+    let mut batch = Vec::new();
+    let mut failing = false;
+    let mut tick = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        let stop = tokio::select! {
+            span = rx.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < BATCH_SIZE {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = tick.tick() => false,
+            _ = shutdown.cancelled() => {
+                while let Ok(span) = rx.try_recv() {
+                    batch.push(span);
+                }
+                true
+            }
+        };
+        for chunk in batch.chunks(BATCH_SIZE) {
+            match send(&client, &url, &service_name, chunk).await {
+                Ok(()) => failing = false,
+                Err(e) => {
+                    // Log once per outage rather than once per batch
+                    if !failing {
+                        eprintln!("OTLP export to {url} failed: {e:#}");
+                    }
+                    failing = true;
+                }
+            }
+        }
+        batch.clear();
+        if stop {
+            return;
+        }
+    }
+}
+
+async fn send(
+    client: &Client<HttpConnector, Full<Bytes>>,
+    url: &Uri,
+    service_name: &str,
+    spans: &[Span],
+) -> Result<()> {
+    let body = serde_json::to_vec(&otlp_json(service_name, spans))?;
+    let req = Request::post(url.clone())
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)))?;
+    let response = tokio::time::timeout(EXPORT_TIMEOUT, client.request(req))
+        .await
+        .context("timed out")??;
+    if !response.status().is_success() {
+        anyhow::bail!("collector answered {}", response.status());
+    }
+    Ok(())
+}
+
+/// Tracing in effect on the proxy: header propagation and optional span export.
+#[derive(Debug, Clone, Default)]
+pub struct Tracer {
+    propagate: bool,
+    exporter: Option<Exporter>,
+}
+
+impl Tracer {
+    pub fn new(settings: &Tracing, shutdown: &CancellationToken) -> Result<Self> {
+        let exporter = settings
+            .traces_url()?
+            .map(|url| Exporter::start(url, settings.service_name().to_string(), shutdown.clone()));
+        Ok(Self {
+            propagate: settings.propagate(),
+            exporter,
+        })
+    }
+
+    /// Start tracing a request: set trace and request id headers for the backend.
+    /// `None` when propagation and export are both off.
+    pub fn begin<B>(
+        &self,
+        req: &mut Request<B>,
+        domain: &str,
+        remote_addr: SocketAddr,
+        local_port: u16,
+        tls: bool,
+    ) -> Option<RequestTrace> {
+        if !self.propagate && self.exporter.is_none() {
+            return None;
+        }
+        let context = TraceContext::from_headers(req.headers());
+        let request_id = request_id(req.headers());
+        if self.propagate {
+            context.inject(req.headers_mut());
+            req.headers_mut().insert(X_REQUEST_ID, request_id.clone());
+        }
+        let mut attributes = vec![
+            ("http.request.method", json!(req.method().as_str())),
+            ("url.scheme", json!(if tls { "https" } else { "http" })),
+            ("url.path", json!(req.uri().path())),
+            ("server.address", json!(domain)),
+            ("server.port", json!(local_port)),
+            ("client.address", json!(remote_addr.ip().to_string())),
+            (
+                "roost.request_id",
+                json!(request_id.to_str().unwrap_or_default()),
+            ),
+        ];
+        if let Some(query) = req.uri().query() {
+            attributes.push(("url.query", json!(query)));
+        }
+        if let Some(ua) = req
+            .headers()
+            .get(http::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+        {
+            attributes.push(("user_agent.original", json!(ua)));
+        }
+        Some(RequestTrace {
+            span: Span {
+                context,
+                name: req.method().to_string(),
+                start: SystemTime::now(),
+                end: SystemTime::now(),
+                attributes,
+                status: None,
+            },
+            request_id: self.propagate.then_some(request_id),
+        })
+    }
+
+    /// Finish a request: echo its request id to the client and export the span.
+    pub fn finish(&self, trace: RequestTrace, status: StatusCode, headers: &mut HeaderMap) {
+        if let Some(id) = trace.request_id {
+            headers.entry(X_REQUEST_ID).or_insert(id);
+        }
+        if let Some(exporter) = &self.exporter {
+            let mut span = trace.span;
+            span.end = SystemTime::now();
+            span.status = Some(status);
+            span.attributes
+                .push(("http.response.status_code", json!(status.as_u16())));
+            exporter.export(span);
+        }
+    }
+}
+
+/// Trace state carried from [`Tracer::begin`] to [`Tracer::finish`].
+#[derive(Debug)]
+pub struct RequestTrace {
+    span: Span,
+    request_id: Option<HeaderValue>,
+}
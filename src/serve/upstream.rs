@@ -0,0 +1,288 @@
+//! Per-browser upstream override for branch testing: a `roost-upstream` cookie or
+//! `X-Roost-Upstream` header sends a request for a mapping to an alternate backend,
+//! either a named alternate or a port from an allowlisted range.
+
+use anyhow::{Context, Result};
+use http::{HeaderMap, Method, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::serve::landing::escape_html;
+
+/// Cookie holding the selected upstream.
+pub const COOKIE: &str = "roost-upstream";
+
+/// Request header that selects an upstream; takes precedence over the cookie. Also set
+/// on responses served by an alternate upstream.
+pub const HEADER: &str = "x-roost-upstream";
+
+/// Path on a mapping's domain that shows the current selection; a POST with `set=<name|port>`
+/// or `clear` (in the query or a form body) sets or clears the cookie.
+pub const ENDPOINT: &str = "/_roost/upstream";
+
+/// Largest form body read from a POST to [`ENDPOINT`].
+pub const MAX_FORM_BYTES: usize = 1024;
+
+/// Alternate upstreams for a mapping (`[serve.mappings.upstream]`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpstreamOverride {
+    /// Ports that may be selected by number: `5005` or `5000-5010`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<String>,
+    /// Named alternates: name -> port.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub alternates: BTreeMap<String, u16>,
+}
+
+impl UpstreamOverride {
+    pub fn validate(&self) -> Result<()> {
+        if self.ports.is_empty() && self.alternates.is_empty() {
+            anyhow::bail!("needs allowed ports or named alternates");
+        }
+        for range in &self.ports {
+            parse_range(range)?;
+        }
+        for (name, port) in &self.alternates {
+            validate_name(name)?;
+            if *port == 0 {
+                anyhow::bail!("alternate {name:?} has port 0");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An [`UpstreamOverride`] with its port ranges parsed, built once per config load.
+#[derive(Debug, Clone)]
+pub struct UpstreamSelector {
+    settings: UpstreamOverride,
+    ranges: Vec<RangeInclusive<u16>>,
+}
+
+impl UpstreamSelector {
+    pub fn compile(settings: &UpstreamOverride) -> Result<Self> {
+        settings.validate()?;
+        Ok(Self {
+            ranges: settings
+                .ports
+                .iter()
+                .map(|r| parse_range(r))
+                .collect::<Result<_>>()?,
+            settings: settings.clone(),
+        })
+    }
+
+    /// Port for a cookie or header value: a named alternate, or an allowlisted port.
+    pub fn select(&self, value: &str) -> Option<u16> {
+        if let Some(port) = self.settings.alternates.get(value) {
+            return Some(*port);
+        }
+        let port = value.parse::<u16>().ok()?;
+        self.ranges
+            .iter()
+            .any(|r| r.contains(&port))
+            .then_some(port)
+    }
+
+    /// Port selected by a request's header or cookie. Values that aren't allowed are
+    /// ignored, so a stale cookie falls back to the mapping's port.
+    pub fn select_for(&self, headers: &HeaderMap) -> Option<u16> {
+        requested(headers).and_then(|v| self.select(v))
+    }
+}
+
+/// `5005` or `5000-5010`.
+pub fn parse_range(s: &str) -> Result<RangeInclusive<u16>> {
+    let parse = |p: &str| {
+        p.trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|p| *p != 0)
+            .with_context(|| format!("invalid port range {s:?}; use 5005 or 5000-5010"))
+    };
+    let range = match s.split_once('-') {
+        Some((start, end)) => parse(start)?..=parse(end)?,
+        None => parse(s)?..=parse(s)?,
+    };
+    if range.is_empty() {
+        anyhow::bail!("invalid port range {s:?}; start is above end");
+    }
+    Ok(range)
+}
+
+/// Alternate names go into a cookie and must not look like a port.
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.parse::<u16>().is_ok()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        anyhow::bail!(
+            "invalid alternate name {name:?}; use letters, digits, '-', '_' or '.', not a bare number"
+        );
+    }
+    Ok(())
+}
+
+/// Upstream value requested by the header, else the cookie.
+pub fn requested(headers: &HeaderMap) -> Option<&str> {
+    if let Some(v) = headers.get(HEADER).and_then(|v| v.to_str().ok()) {
+        return Some(v.trim());
+    }
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE)
+        .map(|(_, value)| value.trim())
+}
+
+/// Whether a request comes from a page on `domain` itself, or from a non-browser client that
+/// sends no `Origin`.
+fn same_origin(headers: &HeaderMap, domain: &str) -> bool {
+    let Some(origin) = headers.get(http::header::ORIGIN) else {
+        return true;
+    };
+    origin
+        .to_str()
+        .ok()
+        .and_then(|o| o.split_once("://"))
+        .map(|(_, authority)| authority.split(':').next().unwrap_or_default())
+        .is_some_and(|host| host.eq_ignore_ascii_case(domain))
+}
+
+fn text(status: StatusCode, body: String) -> Response<String> {
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(http::header::CACHE_CONTROL, "no-store")
+        .body(body)
+        .unwrap()
+}
+
+/// Response for [`ENDPOINT`] on a mapping (`domain` -> `localhost:<port>`). `params` is the
+/// query string, followed for a POST by the form body. Only a same-origin POST changes the
+/// cookie, so another site can't switch a browser's upstream.
+pub fn endpoint(
+    sel: &UpstreamSelector,
+    domain: &str,
+    port: u16,
+    method: &Method,
+    params: &str,
+    headers: &HeaderMap,
+    tls: bool,
+) -> Response<String> {
+    let params: Vec<(&str, &str)> = params
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| p.split_once('=').unwrap_or((p, "")))
+        .collect();
+    let set = params.iter().find(|(k, _)| *k == "set").map(|(_, v)| *v);
+    let clear = params.iter().any(|(k, _)| *k == "clear");
+    if set.is_some() || clear {
+        if method != Method::POST {
+            let mut response = text(
+                StatusCode::METHOD_NOT_ALLOWED,
+                format!("Use the form at {ENDPOINT}, or POST {ENDPOINT}?set=<name|port>\n"),
+            );
+            response
+                .headers_mut()
+                .insert(http::header::ALLOW, http::HeaderValue::from_static("POST"));
+            return response;
+        }
+        if !same_origin(headers, domain) {
+            return text(
+                StatusCode::FORBIDDEN,
+                format!("Refusing to change the upstream for {domain} from another site\n"),
+            );
+        }
+    }
+    let secure = if tls { "; Secure" } else { "" };
+    if let Some(value) = set {
+        return match sel.select(value) {
+            Some(_) => redirect_home(format!(
+                "{COOKIE}={value}; Path=/; HttpOnly; SameSite=Lax{secure}"
+            )),
+            None => text(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "{value:?} is not an allowed upstream for {domain}\n\n{}",
+                    describe(&sel.settings)
+                ),
+            ),
+        };
+    }
+    if clear {
+        return redirect_home(format!(
+            "{COOKIE}=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax{secure}"
+        ));
+    }
+    let current = match requested(headers) {
+        Some(v) => match sel.select(v) {
+            Some(p) => format!("{v} (localhost:{p})"),
+            None => format!("none ({v:?} is not allowed)"),
+        },
+        None => "none".to_string(),
+    };
+    let alternates: String = sel
+        .settings
+        .alternates
+        .iter()
+        .map(|(name, port)| {
+            format!(
+                "<button name=\"set\" value=\"{name}\">{name} (localhost:{port})</button>\n",
+                name = escape_html(name),
+            )
+        })
+        .collect();
+    let ports = if sel.settings.ports.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<form method=\"post\" action=\"{ENDPOINT}\">\n\
+             <input name=\"set\" inputmode=\"numeric\" required placeholder=\"{ranges}\">\n\
+             <button>Use port</button>\n</form>\n",
+            ranges = escape_html(&sel.settings.ports.join(", ")),
+        )
+    };
+    Response::builder()
+        .header(http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(http::header::CACHE_CONTROL, "no-store")
+        .body(format!(
+            "<!doctype html>\n<meta charset=\"utf-8\">\n<title>{domain} upstream</title>\n\
+             <style>body{{font-family:system-ui,sans-serif;margin:2em}}form{{margin:1em 0}}</style>\n\
+             <h1>{domain} &rarr; localhost:{port}</h1>\n\
+             <p>Override: {current}</p>\n\
+             <form method=\"post\" action=\"{ENDPOINT}\">\n{alternates}</form>\n\
+             {ports}\
+             <form method=\"post\" action=\"{ENDPOINT}\"><button name=\"clear\">Clear</button></form>\n",
+            domain = escape_html(domain),
+            current = escape_html(&current),
+        ))
+        .unwrap()
+}
+
+fn redirect_home(cookie: String) -> Response<String> {
+    Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(http::header::LOCATION, "/")
+        .header(http::header::SET_COOKIE, cookie)
+        .header(http::header::CACHE_CONTROL, "no-store")
+        .body(String::new())
+        .unwrap()
+}
+
+fn describe(ov: &UpstreamOverride) -> String {
+    let mut out = String::new();
+    for (name, port) in &ov.alternates {
+        out.push_str(&format!("Alternate {name} -> localhost:{port}\n"));
+    }
+    if !ov.ports.is_empty() {
+        out.push_str(&format!("Allowed ports: {}\n", ov.ports.join(", ")));
+    }
+    out
+}
@@ -0,0 +1,371 @@
+//! Local ACME server: JWS handling and the full account/order/finalize flow.
+
+mod common;
+
+use http_body_util::BodyExt;
+use hyper::body::Bytes;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair as _, ECDSA_P256_SHA256_FIXED_SIGNING};
+use roost::acme::jws::{b64, thumbprint, Jws};
+use roost::acme::{AcmeOptions, AcmeServer};
+use roost::config::RoostPaths;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+const BASE: &str = "https://localhost:14000";
+
+/// Minimal ES256 ACME client driving `AcmeServer::handle` directly.
+struct Client {
+    server: Arc<AcmeServer>,
+    key: EcdsaKeyPair,
+    kid: Option<String>,
+    nonce: String,
+}
+
+impl Client {
+    async fn new(server: Arc<AcmeServer>) -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        let response = server
+            .handle(request("HEAD", "/acme/new-nonce", Bytes::new()))
+            .await;
+        let nonce = response.headers()["replay-nonce"].to_str().unwrap().into();
+        Self {
+            server,
+            key,
+            kid: None,
+            nonce,
+        }
+    }
+
+    fn jwk(&self) -> Value {
+        let point = self.key.public_key().as_ref();
+        json!({"kty": "EC", "crv": "P-256", "x": b64(&point[1..33]), "y": b64(&point[33..])})
+    }
+
+    fn sign(&self, path: &str, payload: Option<&Value>) -> Bytes {
+        let mut protected =
+            json!({"alg": "ES256", "nonce": self.nonce, "url": format!("{BASE}{path}")});
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = b64(protected.to_string());
+        let payload = payload.map(|p| b64(p.to_string())).unwrap_or_default();
+        let signature = self
+            .key
+            .sign(
+                &SystemRandom::new(),
+                format!("{protected}.{payload}").as_bytes(),
+            )
+            .unwrap();
+        json!({"protected": protected, "payload": payload, "signature": b64(signature)})
+            .to_string()
+            .into()
+    }
+
+    async fn post(&mut self, path: &str, payload: Option<&Value>) -> (u16, http::HeaderMap, Bytes) {
+        let body = self.sign(path, payload);
+        let response = self.server.handle(request("POST", path, body)).await;
+        self.nonce = response.headers()["replay-nonce"].to_str().unwrap().into();
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, headers, body)
+    }
+
+    async fn post_json(&mut self, path: &str, payload: Option<&Value>) -> (u16, Value) {
+        let (status, _, body) = self.post(path, payload).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn register(&mut self) -> String {
+        let (status, headers, _) = self
+            .post(
+                "/acme/new-account",
+                Some(&json!({"termsOfServiceAgreed": true})),
+            )
+            .await;
+        assert_eq!(status, 201);
+        let kid = headers["location"].to_str().unwrap().to_string();
+        self.kid = Some(kid.clone());
+        kid
+    }
+}
+
+fn request(method: &str, path: &str, body: Bytes) -> http::Request<Bytes> {
+    http::Request::builder()
+        .method(method)
+        .uri(path)
+        .header("host", "localhost:14000")
+        .header("content-type", "application/jose+json")
+        .body(body)
+        .unwrap()
+}
+
+fn path_of(url: &str) -> &str {
+    url.strip_prefix(BASE).unwrap()
+}
+
+fn server(auto_approve: bool) -> (tempfile::TempDir, RoostPaths, Arc<AcmeServer>) {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    roost::ca::create_ca(&paths, "default").unwrap();
+    let server = AcmeServer::new(
+        &paths,
+        AcmeOptions {
+            auto_approve,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    (dir, paths, server)
+}
+
+fn csr(names: &[&str]) -> String {
+    let key = rcgen::KeyPair::generate().unwrap();
+    let mut params =
+        rcgen::CertificateParams::new(names.iter().map(|n| n.to_string()).collect::<Vec<_>>())
+            .unwrap();
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    b64(params.serialize_request(&key).unwrap().der())
+}
+
+#[tokio::test]
+async fn directory_lists_endpoints() {
+    let (_dir, _paths, server) = server(false);
+    let response = server
+        .handle(request("GET", "/directory", Bytes::new()))
+        .await;
+    assert_eq!(response.status(), 200);
+    assert!(response.headers().contains_key("replay-nonce"));
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let directory: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(directory["newNonce"], format!("{BASE}/acme/new-nonce"));
+    assert_eq!(directory["newAccount"], format!("{BASE}/acme/new-account"));
+    assert_eq!(directory["newOrder"], format!("{BASE}/acme/new-order"));
+}
+
+#[tokio::test]
+async fn auto_approve_issues_cert_chained_to_ca() {
+    let (_dir, paths, server) = server(true);
+    let mut client = Client::new(server.clone()).await;
+    let kid = client.register().await;
+
+    let (status, order) = client
+        .post_json(
+            "/acme/new-order",
+            Some(&json!({"identifiers": [
+                {"type": "dns", "value": "app.test"},
+                {"type": "dns", "value": "*.app.test"},
+            ]})),
+        )
+        .await;
+    assert_eq!(status, 201);
+    assert_eq!(order["status"], "ready");
+
+    let authz = order["authorizations"][1].as_str().unwrap().to_string();
+    let (_, authz) = client.post_json(path_of(&authz), None).await;
+    assert_eq!(authz["status"], "valid");
+    assert_eq!(authz["identifier"]["value"], "app.test");
+    assert_eq!(authz["wildcard"], true);
+
+    let finalize = order["finalize"].as_str().unwrap().to_string();
+    let (status, order) = client
+        .post_json(
+            path_of(&finalize),
+            Some(&json!({"csr": csr(&["app.test", "*.app.test"])})),
+        )
+        .await;
+    assert_eq!(status, 200, "{order}");
+    assert_eq!(order["status"], "valid");
+
+    let cert_url = order["certificate"].as_str().unwrap().to_string();
+    let (status, headers, chain) = client.post(path_of(&cert_url), None).await;
+    assert_eq!(status, 200);
+    assert_eq!(headers["content-type"], "application/pem-certificate-chain");
+    let chain = String::from_utf8(chain.to_vec()).unwrap();
+    assert_eq!(chain.matches("BEGIN CERTIFICATE").count(), 2);
+    let (ca_pem, _) = roost::ca::load_ca(&paths, "default").unwrap();
+    assert!(chain.ends_with(std::str::from_utf8(&ca_pem).unwrap()));
+
+    // Same key finds the same account again, also after a restart
+    let restarted = AcmeServer::new(&paths, AcmeOptions::default()).unwrap();
+    client.server = restarted;
+    client.nonce = {
+        let r = client
+            .server
+            .handle(request("HEAD", "/acme/new-nonce", Bytes::new()))
+            .await;
+        r.headers()["replay-nonce"].to_str().unwrap().into()
+    };
+    client.kid = None;
+    let (status, headers, _) = client
+        .post(
+            "/acme/new-account",
+            Some(&json!({"onlyReturnExisting": true})),
+        )
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(headers["location"], kid.as_str());
+}
+
+#[tokio::test]
+async fn rejects_csr_names_outside_order() {
+    let (_dir, _paths, server) = server(true);
+    let mut client = Client::new(server).await;
+    client.register().await;
+    let (_, order) = client
+        .post_json(
+            "/acme/new-order",
+            Some(&json!({"identifiers": [{"type": "dns", "value": "app.test"}]})),
+        )
+        .await;
+    let finalize = order["finalize"].as_str().unwrap().to_string();
+    let (status, problem) = client
+        .post_json(
+            path_of(&finalize),
+            Some(&json!({"csr": csr(&["app.test", "other.test"])})),
+        )
+        .await;
+    assert_eq!(status, 400);
+    assert_eq!(problem["type"], "urn:ietf:params:acme:error:badCSR");
+}
+
+#[tokio::test]
+async fn without_auto_approve_orders_need_http01() {
+    let (_dir, _paths, server) = server(false);
+    let mut client = Client::new(server).await;
+    client.register().await;
+
+    let (status, problem) = client
+        .post_json(
+            "/acme/new-order",
+            Some(&json!({"identifiers": [{"type": "dns", "value": "example.com"}]})),
+        )
+        .await;
+    assert_eq!(status, 400);
+    assert_eq!(
+        problem["type"],
+        "urn:ietf:params:acme:error:rejectedIdentifier"
+    );
+
+    let (status, order) = client
+        .post_json(
+            "/acme/new-order",
+            Some(&json!({"identifiers": [{"type": "dns", "value": "app.test"}]})),
+        )
+        .await;
+    assert_eq!(status, 201);
+    assert_eq!(order["status"], "pending");
+    let authz = order["authorizations"][0].as_str().unwrap().to_string();
+    let (_, authz) = client.post_json(path_of(&authz), None).await;
+    assert_eq!(authz["status"], "pending");
+    assert_eq!(authz["challenges"][0]["type"], "http-01");
+
+    let finalize = order["finalize"].as_str().unwrap().to_string();
+    let (status, problem) = client
+        .post_json(
+            path_of(&finalize),
+            Some(&json!({"csr": csr(&["app.test"])})),
+        )
+        .await;
+    assert_eq!(status, 403);
+    assert_eq!(problem["type"], "urn:ietf:params:acme:error:orderNotReady");
+}
+
+#[tokio::test]
+async fn expired_orders_are_pruned_with_their_certs() {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    roost::ca::create_ca(&paths, "default").unwrap();
+    let options = AcmeOptions {
+        auto_approve: true,
+        order_lifetime: std::time::Duration::from_millis(500),
+        ..Default::default()
+    };
+    let server = AcmeServer::new(&paths, options).unwrap();
+    let mut client = Client::new(server).await;
+    let kid = client.register().await;
+    let identifiers = json!({"identifiers": [{"type": "dns", "value": "app.test"}]});
+
+    let (_, headers, body) = client.post("/acme/new-order", Some(&identifiers)).await;
+    let order_url = headers["location"].to_str().unwrap().to_string();
+    let order: Value = serde_json::from_slice(&body).unwrap();
+    let authz_url = order["authorizations"][0].as_str().unwrap().to_string();
+    let finalize = order["finalize"].as_str().unwrap().to_string();
+    let (status, order) = client
+        .post_json(
+            path_of(&finalize),
+            Some(&json!({"csr": csr(&["app.test"])})),
+        )
+        .await;
+    assert_eq!(status, 200, "{order}");
+    let cert_url = order["certificate"].as_str().unwrap().to_string();
+
+    // The next order after the first one expired drops it, its authorization and its cert
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+    let (status, _) = client
+        .post_json("/acme/new-order", Some(&identifiers))
+        .await;
+    assert_eq!(status, 201);
+    for url in [&order_url, &authz_url, &cert_url] {
+        let (status, _, _) = client.post(path_of(url), None).await;
+        assert_eq!(status, 404, "{url}");
+    }
+    let (_, orders) = client
+        .post_json(&format!("{}/orders", path_of(&kid)), None)
+        .await;
+    assert_eq!(orders["orders"].as_array().unwrap().len(), 1);
+    assert_ne!(orders["orders"][0], order_url.as_str());
+}
+
+#[tokio::test]
+async fn replayed_nonce_and_bad_signature_are_rejected() {
+    let (_dir, _paths, server) = server(true);
+    let mut client = Client::new(server.clone()).await;
+    let body = client.sign("/acme/new-account", Some(&json!({})));
+    let first = server
+        .handle(request("POST", "/acme/new-account", body.clone()))
+        .await;
+    assert_eq!(first.status(), 201);
+    let replay = server
+        .handle(request("POST", "/acme/new-account", body))
+        .await;
+    assert_eq!(replay.status(), 400);
+    let problem = replay.into_body().collect().await.unwrap().to_bytes();
+    let problem: Value = serde_json::from_slice(&problem).unwrap();
+    assert_eq!(problem["type"], "urn:ietf:params:acme:error:badNonce");
+
+    client.nonce = first.headers()["replay-nonce"].to_str().unwrap().into();
+    let mut jws: Value =
+        serde_json::from_slice(&client.sign("/acme/new-account", Some(&json!({})))).unwrap();
+    jws["payload"] = json!(b64(r#"{"contact":["mailto:x@app.test"]}"#));
+    let response = server
+        .handle(request("POST", "/acme/new-account", jws.to_string().into()))
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[test]
+fn jws_parse_and_thumbprint() {
+    // RFC 7638 §3.1 example key
+    let jwk = json!({
+        "kty": "RSA",
+        "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+        "e": "AQAB",
+    });
+    assert_eq!(
+        thumbprint(&jwk).unwrap(),
+        "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+    );
+
+    let both = json!({
+        "protected": b64(json!({"alg": "ES256", "url": "u", "jwk": {}, "kid": "k"}).to_string()),
+        "payload": "",
+        "signature": "",
+    });
+    assert!(Jws::parse(both.to_string().as_bytes()).is_err());
+}
@@ -1,4 +1,4 @@
-//! Cert SANs: wildcard has domain+*.domain, exact has only domain.
+//! Cert SANs: wildcard has domain+*.domain, exact has only domain, IPs get an IP SAN.
 
 mod common;
 
@@ -18,8 +18,10 @@ fn get_sans(cert_pem: &[u8]) -> Vec<String> {
     let mut sans = Vec::new();
     if let Ok(Some(ext)) = x509.subject_alternative_name() {
         for name in ext.value.general_names.iter() {
-            if let GeneralName::DNSName(s) = name {
-                sans.push(s.to_string());
+            match name {
+                GeneralName::DNSName(s) => sans.push(s.to_string()),
+                GeneralName::IPAddress(ip) => sans.push(format!("ip:{ip:?}")),
+                _ => {}
             }
         }
     }
@@ -54,3 +56,34 @@ fn exact_cert_has_only_domain() {
     let sans = get_sans(&cert_pem);
     assert_eq!(sans, vec!["api.test"]);
 }
+
+#[test]
+fn ip_cert_has_only_ip_san() {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    ca::create_ca(&paths, "default").unwrap();
+    let (ca_pem, ca_key_pem) = ca::load_ca(&paths, "default").unwrap();
+
+    let (cert_pem, _key_pem) =
+        cert::generate_domain_cert("192.168.1.20", &ca_pem, &ca_key_pem, false).unwrap();
+    assert_eq!(get_sans(&cert_pem), vec!["ip:[192, 168, 1, 20]"]);
+
+    let (cert_pem, _key_pem) =
+        cert::generate_domain_cert("localhost", &ca_pem, &ca_key_pem, false).unwrap();
+    assert_eq!(get_sans(&cert_pem), vec!["*.localhost", "localhost"]);
+}
+
+#[test]
+fn ipv6_cert_files_have_no_colons() {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    ca::create_ca(&paths, "default").unwrap();
+    cert::ensure_cert_valid(&paths, "::1", "default", false).unwrap();
+
+    let (cert_path, key_path) = cert::domain_cert_paths(&paths, "::1");
+    assert_eq!(cert_path, paths.certs_dir.join("__1.pem"));
+    assert_eq!(key_path, paths.certs_dir.join("__1-key.pem"));
+    assert!(cert_path.is_file());
+    let (cert_pem, _) = cert::load_domain_cert(&paths, "::1").unwrap();
+    assert_eq!(get_sans(&cert_pem).len(), 1);
+}
@@ -0,0 +1,258 @@
+//! Revocation: revoked list, CRL, embedded CRL/OCSP URLs and OCSP responses.
+
+mod common;
+
+use roost::config::{Config, RoostPaths};
+use roost::revoke::{self, Reason};
+use roost::{ca, cert, ocsp};
+use x509_parser::prelude::*;
+use yasna::Tag;
+
+fn setup(revocation_url: Option<&str>) -> (tempfile::TempDir, RoostPaths) {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    ca::create_ca(&paths, "default").unwrap();
+    let mut config = Config {
+        default_ca: "default".to_string(),
+        revocation_url: revocation_url.map(String::from),
+        ..Default::default()
+    };
+    config
+        .domains
+        .insert("app.test".to_string(), "default".to_string());
+    config.save(&paths).unwrap();
+    cert::ensure_cert_valid(&paths, "app.test", "default", false).unwrap();
+    (dir, paths)
+}
+
+fn pem_to_der(pem: &[u8]) -> Vec<u8> {
+    rustls_pemfile::certs(&mut &pem[..])
+        .next()
+        .unwrap()
+        .unwrap()
+        .to_vec()
+}
+
+/// OCSP request for `cert_pem` (SHA-1 CertID, as openssl sends by default).
+fn ocsp_request(paths: &RoostPaths, cert_pem: &[u8]) -> Vec<u8> {
+    let (ca_pem, _) = ca::load_ca(paths, "default").unwrap();
+    let ca_der = pem_to_der(&ca_pem);
+    let (_, ca) = X509Certificate::from_der(&ca_der).unwrap();
+    let leaf_der = pem_to_der(cert_pem);
+    let (_, leaf) = X509Certificate::from_der(&leaf_der).unwrap();
+    let sha1 = |data: &[u8]| {
+        ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, data)
+            .as_ref()
+            .to_vec()
+    };
+    let cert_id = yasna::construct_der(|w| {
+        w.write_sequence(|w| {
+            w.next().write_sequence(|w| {
+                w.next()
+                    .write_oid(&yasna::models::ObjectIdentifier::from_slice(&[
+                        1, 3, 14, 3, 2, 26,
+                    ]));
+                w.next().write_null();
+            });
+            w.next().write_bytes(&sha1(ca.subject().as_raw()));
+            w.next()
+                .write_bytes(&sha1(&ca.public_key().subject_public_key.data));
+            w.next().write_bigint_bytes(leaf.raw_serial(), true);
+        });
+    });
+    // OCSPRequest { TBSRequest { requestList { Request { CertID } } } }
+    yasna::construct_der(|w| {
+        w.write_sequence(|w| {
+            w.next().write_sequence(|w| {
+                w.next().write_sequence(|w| {
+                    w.next().write_sequence(|w| w.next().write_der(&cert_id));
+                });
+            });
+        });
+    })
+}
+
+/// Response status and the CertStatus tag number of the single response.
+fn ocsp_cert_status(response: &[u8]) -> (i64, Option<u64>) {
+    yasna::parse_der(response, |r| {
+        r.read_sequence(|r| {
+            let status = r.next().read_enum()?;
+            let basic = r.read_optional(|r| {
+                r.read_tagged(Tag::context(0), |r| {
+                    r.read_sequence(|r| {
+                        r.next().read_oid()?;
+                        r.next().read_bytes()
+                    })
+                })
+            })?;
+            let Some(basic) = basic else {
+                return Ok((status, None));
+            };
+            let tag = yasna::parse_der(&basic, |r| {
+                r.read_sequence(|r| {
+                    let tag = r.next().read_sequence(|r| {
+                        r.next().read_der()?; // responderID
+                        r.next().read_der()?; // producedAt
+                        let tags = r.next().collect_sequence_of(|r| {
+                            r.read_sequence(|r| {
+                                r.next().read_der()?; // certID
+                                let tag = r.next().lookahead_tag()?;
+                                r.next().read_der()?; // certStatus
+                                r.next().read_der()?; // thisUpdate
+                                r.read_optional(|r| r.read_der())?;
+                                Ok(tag.tag_number)
+                            })
+                        })?;
+                        r.read_optional(|r| r.read_der())?;
+                        Ok(tags[0])
+                    })?;
+                    r.next().read_der()?;
+                    r.next().read_der()?;
+                    r.read_optional(|r| r.read_der())?;
+                    Ok(tag)
+                })
+            })?;
+            Ok((status, Some(tag)))
+        })
+    })
+    .unwrap()
+}
+
+#[test]
+fn serials_are_normalized() {
+    assert_eq!(revoke::normalize_serial("0x00AB:CD").unwrap(), "abcd");
+    assert_eq!(revoke::normalize_serial("abc").unwrap(), "0abc");
+    assert!(revoke::normalize_serial("app.test").is_err());
+}
+
+#[test]
+fn revoke_domain_reissues_and_lists_serial_in_crl() {
+    let (_dir, paths) = setup(None);
+    let (old_pem, _) = cert::load_domain_cert(&paths, "app.test").unwrap();
+    let old_serial = revoke::cert_serial(&old_pem).unwrap();
+
+    let serial =
+        revoke::revoke_domain(&paths, "app.test", "default", Reason::KeyCompromise, true).unwrap();
+    assert_eq!(serial, old_serial);
+    let (new_pem, _) = cert::load_domain_cert(&paths, "app.test").unwrap();
+    assert_ne!(revoke::cert_serial(&new_pem).unwrap(), old_serial);
+    let new_der = pem_to_der(&new_pem);
+    let (_, new_cert) = X509Certificate::from_der(&new_der).unwrap();
+    let sans: Vec<String> = new_cert
+        .subject_alternative_name()
+        .unwrap()
+        .unwrap()
+        .value
+        .general_names
+        .iter()
+        .map(|n| n.to_string())
+        .collect();
+    assert!(sans.iter().any(|n| n.contains("*.app.test")), "{sans:?}");
+
+    // Revoking again is a no-op
+    assert!(!revoke::revoke(&paths, "default", &serial, Reason::Unspecified, None).unwrap());
+
+    let list = revoke::load(&paths, "default").unwrap();
+    assert_eq!(list.revoked.len(), 1);
+    assert_eq!(list.revoked[0].domain.as_deref(), Some("app.test"));
+
+    let crl_pem = std::fs::read(revoke::crl_path(&paths, "default")).unwrap();
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&crl_pem).unwrap();
+    let (_, crl) = CertificateRevocationList::from_der(&pem.contents).unwrap();
+    let revoked: Vec<String> = crl
+        .iter_revoked_certificates()
+        .map(|r| revoke::serial_hex(r.raw_serial()))
+        .collect();
+    assert_eq!(revoked, vec![old_serial]);
+}
+
+#[test]
+fn revocation_url_is_embedded_in_new_certs() {
+    let (_dir, paths) = setup(Some("http://127.0.0.1:8889"));
+    let (pem, _) = cert::load_domain_cert(&paths, "app.test").unwrap();
+    let der = pem_to_der(&pem);
+    let (_, cert) = X509Certificate::from_der(&der).unwrap();
+    let mut crl_dp = false;
+    let mut ocsp = false;
+    for ext in cert.extensions() {
+        match ext.parsed_extension() {
+            ParsedExtension::CRLDistributionPoints(points) => {
+                crl_dp = format!("{points:?}").contains("http://127.0.0.1:8889/crl/default.crl");
+            }
+            ParsedExtension::AuthorityInfoAccess(aia) => {
+                ocsp = aia.accessdescs.iter().any(|d| {
+                    d.access_location == GeneralName::URI("http://127.0.0.1:8889/ocsp/default")
+                });
+            }
+            _ => {}
+        }
+    }
+    assert!(crl_dp, "CRL distribution point missing");
+    assert!(ocsp, "AIA OCSP URL missing");
+
+    let (_dir, plain) = setup(None);
+    let (pem, _) = cert::load_domain_cert(&plain, "app.test").unwrap();
+    let der = pem_to_der(&pem);
+    let (_, cert) = X509Certificate::from_der(&der).unwrap();
+    assert!(cert.extensions().iter().all(|e| !matches!(
+        e.parsed_extension(),
+        ParsedExtension::CRLDistributionPoints(_) | ParsedExtension::AuthorityInfoAccess(_)
+    )));
+}
+
+#[test]
+fn ocsp_reports_good_then_revoked() {
+    let (_dir, paths) = setup(None);
+    let (pem, _) = cert::load_domain_cert(&paths, "app.test").unwrap();
+    let request = ocsp_request(&paths, &pem);
+
+    assert_eq!(
+        ocsp_cert_status(&ocsp::respond(&paths, "default", &request)),
+        (0, Some(0)),
+        "good"
+    );
+    revoke::revoke_domain(&paths, "app.test", "default", Reason::Superseded, false).unwrap();
+    assert_eq!(
+        ocsp_cert_status(&ocsp::respond(&paths, "default", &request)),
+        (0, Some(1)),
+        "revoked"
+    );
+
+    // A cert from another CA is unknown to this one
+    ca::create_ca(&paths, "other").unwrap();
+    assert_eq!(
+        ocsp_cert_status(&ocsp::respond(&paths, "other", &request)),
+        (0, Some(2)),
+        "unknown"
+    );
+
+    assert_eq!(
+        ocsp_cert_status(&ocsp::respond(&paths, "default", b"garbage")),
+        (1, None),
+        "malformedRequest"
+    );
+}
+
+#[test]
+fn staple_covers_certs_that_name_a_responder() {
+    let (_dir, paths) = setup(None);
+    let (pem, _) = cert::load_domain_cert(&paths, "app.test").unwrap();
+    assert_eq!(
+        ocsp::staple(&paths, "default", &pem_to_der(&pem)).unwrap(),
+        None
+    );
+
+    let (_dir, paths) = setup(Some("http://127.0.0.1:8889"));
+    let (pem, _) = cert::load_domain_cert(&paths, "app.test").unwrap();
+    let leaf = pem_to_der(&pem);
+    let staple = ocsp::staple(&paths, "default", &leaf).unwrap().unwrap();
+    assert_eq!(ocsp_cert_status(&staple), (0, Some(0)), "good");
+
+    revoke::revoke_domain(&paths, "app.test", "default", Reason::Superseded, false).unwrap();
+    let staple = ocsp::staple(&paths, "default", &leaf).unwrap().unwrap();
+    assert_eq!(ocsp_cert_status(&staple), (0, Some(1)), "revoked");
+
+    // Not issued by this CA: nothing to staple
+    ca::create_ca(&paths, "other").unwrap();
+    assert_eq!(ocsp::staple(&paths, "other", &leaf).unwrap(), None);
+}
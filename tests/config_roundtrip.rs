@@ -19,6 +19,7 @@ fn config_roundtrip() {
     let config = Config {
         default_ca: "default".to_string(),
         domains,
+        ..Default::default()
     };
 
     config.save(&paths).unwrap();
@@ -1,4 +1,4 @@
-//! Domain validation: allowlist, --allow override, invalid hostnames, reject localhost.
+//! Domain validation: allowlist, --allow override, invalid hostnames, localhost and IPs.
 
 mod common;
 
@@ -29,7 +29,29 @@ fn invalid_hostname_fails() {
 }
 
 #[test]
-fn reject_bare_localhost() {
-    let err = domain::validate_hostname("localhost").unwrap_err();
-    assert!(err.to_string().to_lowercase().contains("localhost"));
+fn bare_localhost_passes() {
+    domain::validate_hostname("localhost").unwrap();
+    domain::validate_domain("localhost", false).unwrap();
+    assert!(!domain::needs_hosts_entry("localhost"));
+    assert!(domain::needs_hosts_entry("app.localhost"));
+}
+
+#[test]
+fn local_ips_pass_and_public_ips_need_allow() {
+    for ip in [
+        "127.0.0.1",
+        "192.168.1.20",
+        "10.0.0.5",
+        "169.254.1.1",
+        "::1",
+        "fd00::1",
+        "fe80::1",
+    ] {
+        domain::validate_domain(ip, false).unwrap();
+        assert!(!domain::needs_hosts_entry(ip), "{ip}");
+    }
+    let err = domain::validate_domain("8.8.8.8", false).unwrap_err();
+    assert!(err.to_string().contains("--allow"), "{err}");
+    assert!(domain::validate_domain("2001:db8::1", false).is_err());
+    domain::validate_domain("8.8.8.8", true).unwrap();
 }
@@ -126,6 +126,11 @@ fn help_serve_config_ports_set() {
     roost().args(["serve", "config", "ports", "set", "--help"]).assert().success();
 }
 
+#[test]
+fn help_serve_config_ports_bind() {
+    roost().args(["serve", "config", "ports", "bind", "--help"]).assert().success();
+}
+
 #[test]
 fn help_serve_config_ports_list() {
     roost().args(["serve", "config", "ports", "list", "--help"]).assert().success();
@@ -155,3 +160,137 @@ fn help_serve_daemon_status() {
 fn help_serve_daemon_reload() {
     roost().args(["serve", "daemon", "reload", "--help"]).assert().success();
 }
+
+#[test]
+fn help_serve_config_auth() {
+    roost().args(["serve", "config", "auth", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_config_allow() {
+    roost().args(["serve", "config", "allow", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_config_redirect() {
+    roost().args(["serve", "config", "redirect", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_config_compress() {
+    roost().args(["serve", "config", "compress", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_chaos() {
+    roost().args(["serve", "chaos", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_chaos_set() {
+    roost().args(["serve", "chaos", "set", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_cache_purge() {
+    roost()
+        .args(["serve", "cache", "purge", "--help"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn help_serve_certs_reload() {
+    roost()
+        .args(["serve", "certs", "reload", "--help"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn help_serve_config_cache() {
+    roost()
+        .args(["serve", "config", "cache", "--help"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn help_acme_serve() {
+    roost().args(["acme", "serve", "--help"]).assert().success();
+}
+
+#[test]
+fn help_cert_revoke() {
+    roost().args(["cert", "revoke", "--help"]).assert().success();
+}
+
+#[test]
+fn help_cert_ocsp() {
+    roost().args(["cert", "ocsp", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_config_metrics() {
+    roost().args(["serve", "config", "metrics", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_config_tracing() {
+    roost().args(["serve", "config", "tracing", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_config_tls() {
+    roost().args(["serve", "config", "tls", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_config_badssl() {
+    roost().args(["serve", "config", "badssl", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_config_mirror() {
+    roost().args(["serve", "config", "mirror", "--help"]).assert().success();
+}
+
+#[test]
+fn help_serve_config_default_cert() {
+    roost()
+        .args(["serve", "config", "default-cert", "--help"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn help_serve_config_default_backend() {
+    roost()
+        .args(["serve", "config", "default-backend", "--help"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn help_serve_config_port_passthrough() {
+    roost()
+        .args(["serve", "config", "port-passthrough", "--help"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn help_serve_config_upstream() {
+    roost()
+        .args(["serve", "config", "upstream", "--help"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn help_serve_config_forward_auth() {
+    roost()
+        .args(["serve", "config", "forward-auth", "--help"])
+        .assert()
+        .success();
+}
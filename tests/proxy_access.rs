@@ -0,0 +1,188 @@
+//! Per-mapping access control: IP allowlist and basic auth.
+
+mod common;
+
+use assert_cmd::cargo::cargo_bin_cmd;
+use base64::Engine;
+use roost::serve::access::{AccessDecision, AccessPolicy};
+use roost::serve::config::{BasicAuth, Mapping, ServeConfig};
+
+fn basic(user: &str, password: &str) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+    format!("Basic {encoded}")
+}
+
+fn mapping_with_user(user: &str, password: &str) -> Mapping {
+    let mut m = Mapping::new("app.test".into(), 5001);
+    let mut auth = BasicAuth::default();
+    auth.users
+        .insert(user.into(), bcrypt::hash(password, 4).unwrap());
+    m.basic_auth = Some(auth);
+    m
+}
+
+#[test]
+fn no_restrictions_builds_no_policy() {
+    let m = Mapping::new("app.test".into(), 5001);
+    assert!(AccessPolicy::from_mapping(&m).unwrap().is_none());
+}
+
+#[test]
+fn allowlist_matches_ips_and_cidrs() {
+    let mut m = Mapping::new("app.test".into(), 5001);
+    m.allow = vec!["127.0.0.1".into(), "192.168.1.0/24".into(), "::1".into()];
+    let policy = AccessPolicy::from_mapping(&m).unwrap().unwrap();
+
+    assert_eq!(
+        policy.check("127.0.0.1".parse().unwrap(), None),
+        AccessDecision::Allow
+    );
+    assert_eq!(
+        policy.check("192.168.1.77".parse().unwrap(), None),
+        AccessDecision::Allow
+    );
+    assert_eq!(
+        policy.check("::1".parse().unwrap(), None),
+        AccessDecision::Allow
+    );
+    assert_eq!(
+        policy.check("::ffff:192.168.1.5".parse().unwrap(), None),
+        AccessDecision::Allow,
+        "IPv4-mapped IPv6 peers match IPv4 entries"
+    );
+    assert_eq!(
+        policy.check("10.0.0.3".parse().unwrap(), None),
+        AccessDecision::Forbidden
+    );
+}
+
+#[test]
+fn invalid_allow_entry_is_rejected() {
+    let mut m = Mapping::new("app.test".into(), 5001);
+    m.allow = vec!["not-an-ip".into()];
+    let err = AccessPolicy::from_mapping(&m).unwrap_err();
+    assert!(format!("{err:#}").contains("not-an-ip"));
+}
+
+#[test]
+fn basic_auth_requires_valid_credentials() {
+    let policy = AccessPolicy::from_mapping(&mapping_with_user("alice", "s3cret"))
+        .unwrap()
+        .unwrap();
+    let peer = "127.0.0.1".parse().unwrap();
+
+    let unauthorized = AccessDecision::Unauthorized {
+        realm: "app.test".into(),
+    };
+    assert_eq!(policy.check(peer, None), unauthorized);
+    assert_eq!(
+        policy.check(peer, Some(&basic("alice", "wrong"))),
+        unauthorized
+    );
+    assert_eq!(
+        policy.check(peer, Some(&basic("bob", "s3cret"))),
+        unauthorized
+    );
+    assert_eq!(policy.check(peer, Some("Bearer abc")), unauthorized);
+    assert_eq!(
+        policy.check(peer, Some(&basic("alice", "s3cret"))),
+        AccessDecision::Allow
+    );
+    // Second request hits the verified cache
+    assert_eq!(
+        policy.check(peer, Some(&basic("alice", "s3cret"))),
+        AccessDecision::Allow
+    );
+}
+
+#[test]
+fn allowlist_checked_before_auth() {
+    let mut m = mapping_with_user("alice", "s3cret");
+    m.allow = vec!["127.0.0.0/8".into()];
+    let policy = AccessPolicy::from_mapping(&m).unwrap().unwrap();
+    assert_eq!(
+        policy.check("10.1.2.3".parse().unwrap(), Some(&basic("alice", "s3cret"))),
+        AccessDecision::Forbidden
+    );
+}
+
+#[test]
+fn access_settings_roundtrip_through_roostrc() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+
+    let mut cfg = ServeConfig::default();
+    cfg.add("app.test".into(), 5001);
+    let m = cfg.mapping_mut("app.test").unwrap();
+    m.allow.push("10.0.0.0/8".into());
+    m.basic_auth = Some(BasicAuth {
+        realm: Some("dev".into()),
+        users: [("alice".to_string(), "$2b$04$hash".to_string())].into(),
+    });
+    cfg.save(&rc_path).unwrap();
+
+    // Re-adding the mapping with a new port keeps access settings
+    let mut loaded = ServeConfig::load(&rc_path).unwrap();
+    loaded.add("app.test".into(), 5002);
+    let m = &loaded.mappings[0];
+    assert_eq!(m.port, 5002);
+    assert_eq!(m.allow, vec!["10.0.0.0/8".to_string()]);
+    let auth = m.basic_auth.as_ref().unwrap();
+    assert_eq!(auth.realm.as_deref(), Some("dev"));
+    assert_eq!(
+        auth.users.get("alice").map(String::as_str),
+        Some("$2b$04$hash")
+    );
+}
+
+#[test]
+fn auth_add_reads_password_from_stdin_not_argv() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut cfg = ServeConfig::default();
+    cfg.add("app.test".into(), 5001);
+    cfg.save(&rc_path).unwrap();
+    let roost = || {
+        let mut cmd = cargo_bin_cmd!("roost");
+        cmd.current_dir(dir.path()).env("ROOST_HOME", dir.path());
+        cmd
+    };
+
+    roost()
+        .args([
+            "serve",
+            "config",
+            "auth",
+            "add",
+            "app.test",
+            "alice",
+            "--password",
+            "x",
+        ])
+        .assert()
+        .failure();
+    // Without a terminal there is nothing to prompt on
+    roost()
+        .args(["serve", "config", "auth", "add", "app.test", "alice"])
+        .write_stdin("s3cret\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--password-stdin"));
+    roost()
+        .args([
+            "serve",
+            "config",
+            "auth",
+            "add",
+            "app.test",
+            "alice",
+            "--password-stdin",
+        ])
+        .write_stdin("s3cret\n")
+        .assert()
+        .success();
+
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    let hash = &loaded.mappings[0].basic_auth.as_ref().unwrap().users["alice"];
+    assert!(bcrypt::verify("s3cret", hash).unwrap());
+}
@@ -0,0 +1,312 @@
+//! Response cache: what is stored, freshness, Vary, revalidation, purge and disk reload.
+
+mod common;
+
+use std::sync::Arc;
+
+use http::response::Parts;
+use http::{HeaderMap, Method, Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use roost::serve::cache::{storable, Cache, CacheControl, CacheSettings, X_CACHE};
+use roost::serve::config::ServeConfig;
+use roost::serve::proxy::ProxyBody;
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (name, value) in pairs {
+        map.append(*name, value.parse().unwrap());
+    }
+    map
+}
+
+fn response(status: u16, pairs: &[(&'static str, &str)], body: &'static str) -> (Parts, ProxyBody) {
+    let mut builder = Response::builder().status(status);
+    for (name, value) in pairs {
+        builder = builder.header(*name, *value);
+    }
+    let body = Full::new(Bytes::from_static(body.as_bytes()))
+        .map_err(|never| match never {})
+        .boxed();
+    let (parts, ()) = builder.body(()).unwrap().into_parts();
+    (parts, body)
+}
+
+fn cache() -> Arc<Cache> {
+    Arc::new(Cache::new(CacheSettings::default(), None).unwrap())
+}
+
+/// Run a GET for `target` through the cache, answering misses with `backend`, and
+/// return the X-Cache value, status and body the client gets.
+async fn get(
+    cache: &Arc<Cache>,
+    target: &str,
+    request: &HeaderMap,
+    backend: (Parts, ProxyBody),
+) -> (String, StatusCode, Bytes) {
+    let exchange = cache.begin(Cache::key(5001, target), &Method::GET, request);
+    let (parts, body) = match exchange.hit() {
+        Some(hit) => {
+            let (parts, body) = hit.into_parts();
+            exchange.finish(parts, body)
+        }
+        None => {
+            let (parts, body) = backend;
+            exchange.finish(parts, body)
+        }
+    };
+    let body = body.collect().await.unwrap().to_bytes();
+    let x_cache = parts.headers[X_CACHE].to_str().unwrap().to_string();
+    (x_cache, parts.status, body)
+}
+
+#[test]
+fn parses_cache_control() {
+    let cc = CacheControl::parse(&headers(&[
+        ("cache-control", "public, max-age=60"),
+        ("cache-control", "S-MAXAGE=\"120\", must-revalidate"),
+    ]));
+    assert!(cc.public && cc.must_revalidate);
+    assert_eq!(cc.max_age, Some(60));
+    assert_eq!(cc.s_maxage, Some(120));
+
+    let invalid = CacheControl::parse(&headers(&[("cache-control", "max-age=soon")]));
+    assert_eq!(invalid.max_age, Some(0));
+}
+
+#[test]
+fn only_shareable_responses_are_storable() {
+    let none = HeaderMap::new();
+    let ok = StatusCode::OK;
+    assert!(storable(
+        &none,
+        ok,
+        &headers(&[("cache-control", "max-age=60")])
+    ));
+    assert!(storable(&none, ok, &headers(&[("etag", "\"v1\"")])));
+    assert!(!storable(&none, ok, &HeaderMap::new()));
+    assert!(!storable(
+        &none,
+        StatusCode::CREATED,
+        &headers(&[("etag", "\"v1\"")])
+    ));
+    for pairs in [
+        &[("cache-control", "no-store, max-age=60")][..],
+        &[("cache-control", "private, max-age=60")],
+        &[("cache-control", "max-age=60"), ("set-cookie", "a=1")],
+        &[("cache-control", "max-age=60"), ("vary", "*")],
+    ] {
+        assert!(!storable(&none, ok, &headers(pairs)), "{pairs:?}");
+    }
+    let partial = headers(&[("cache-control", "max-age=60")]);
+    assert!(!storable(&none, StatusCode::PARTIAL_CONTENT, &partial));
+
+    let no_store = headers(&[("cache-control", "no-store")]);
+    assert!(!storable(&no_store, ok, &partial));
+
+    let authorized = headers(&[("authorization", "Bearer x")]);
+    assert!(!storable(&authorized, ok, &partial));
+    let shared = headers(&[("cache-control", "s-maxage=60")]);
+    assert!(storable(&authorized, ok, &shared));
+}
+
+#[tokio::test]
+async fn fresh_response_is_served_from_cache() {
+    let cache = cache();
+    let none = HeaderMap::new();
+    let fresh = [("cache-control", "max-age=60")];
+    let (x_cache, _, body) = get(&cache, "/a", &none, response(200, &fresh, "one")).await;
+    assert_eq!((x_cache.as_str(), &body[..]), ("MISS", &b"one"[..]));
+    assert_eq!(cache.len(), 1);
+
+    let (x_cache, status, body) = get(&cache, "/a", &none, response(200, &fresh, "two")).await;
+    assert_eq!(
+        (x_cache.as_str(), status, &body[..]),
+        ("HIT", StatusCode::OK, &b"one"[..])
+    );
+
+    // Other paths and request no-store bypass the stored response
+    let (x_cache, ..) = get(&cache, "/b", &none, response(200, &fresh, "b")).await;
+    assert_eq!(x_cache, "MISS");
+    let no_store = headers(&[("cache-control", "no-store")]);
+    let (x_cache, _, body) = get(&cache, "/a", &no_store, response(200, &fresh, "two")).await;
+    assert_eq!((x_cache.as_str(), &body[..]), ("BYPASS", &b"two"[..]));
+}
+
+#[tokio::test]
+async fn hit_answers_client_conditional_with_not_modified() {
+    let cache = cache();
+    let stored = [("cache-control", "max-age=60"), ("etag", "\"v1\"")];
+    get(
+        &cache,
+        "/a",
+        &HeaderMap::new(),
+        response(200, &stored, "one"),
+    )
+    .await;
+    let conditional = headers(&[("if-none-match", "W/\"v1\"")]);
+    let (x_cache, status, body) = get(&cache, "/a", &conditional, response(200, &[], "")).await;
+    assert_eq!(
+        (x_cache.as_str(), status),
+        ("HIT", StatusCode::NOT_MODIFIED)
+    );
+    assert!(body.is_empty());
+}
+
+#[tokio::test]
+async fn vary_keeps_one_response_per_variant() {
+    let cache = cache();
+    let en = headers(&[("accept-language", "en")]);
+    let de = headers(&[("accept-language", "de")]);
+    let vary = [("cache-control", "max-age=60"), ("vary", "Accept-Language")];
+    get(&cache, "/", &en, response(200, &vary, "hello")).await;
+    let (x_cache, _, body) = get(&cache, "/", &de, response(200, &vary, "hallo")).await;
+    assert_eq!((x_cache.as_str(), &body[..]), ("MISS", &b"hallo"[..]));
+
+    let (x_cache, _, body) = get(&cache, "/", &en, response(200, &[], "")).await;
+    assert_eq!((x_cache.as_str(), &body[..]), ("HIT", &b"hello"[..]));
+    let (x_cache, _, body) = get(&cache, "/", &de, response(200, &[], "")).await;
+    assert_eq!((x_cache.as_str(), &body[..]), ("HIT", &b"hallo"[..]));
+    assert_eq!(cache.len(), 2);
+}
+
+#[tokio::test]
+async fn stale_response_is_revalidated_with_its_validator() {
+    let cache = cache();
+    let none = HeaderMap::new();
+    let stale = [("cache-control", "no-cache"), ("etag", "\"v1\"")];
+    get(&cache, "/a", &none, response(200, &stale, "one")).await;
+
+    let exchange = cache.begin(Cache::key(5001, "/a"), &Method::GET, &none);
+    assert!(exchange.hit().is_none());
+    let mut upstream = headers(&[("if-none-match", "\"client\"")]);
+    exchange.prepare(&mut upstream);
+    assert_eq!(upstream["if-none-match"], "\"v1\"");
+
+    let (parts, body) = response(304, &[("etag", "\"v1\""), ("x-fresh", "yes")], "");
+    let (parts, body) = exchange.finish(parts, body);
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(parts.headers[X_CACHE], "REVALIDATED");
+    assert_eq!(parts.headers["x-fresh"], "yes");
+    assert_eq!(&body.collect().await.unwrap().to_bytes()[..], b"one");
+
+    // A changed resource replaces the stored response
+    let changed = [("cache-control", "no-cache"), ("etag", "\"v2\"")];
+    let (x_cache, _, body) = get(&cache, "/a", &none, response(200, &changed, "two")).await;
+    assert_eq!((x_cache.as_str(), &body[..]), ("MISS", &b"two"[..]));
+    let exchange = cache.begin(Cache::key(5001, "/a"), &Method::GET, &none);
+    let mut upstream = HeaderMap::new();
+    exchange.prepare(&mut upstream);
+    assert_eq!(upstream["if-none-match"], "\"v2\"");
+}
+
+#[tokio::test]
+async fn unsafe_method_invalidates_and_limits_apply() {
+    let settings = CacheSettings {
+        max_entry_bytes: Some(4),
+        ..Default::default()
+    };
+    let cache = Arc::new(Cache::new(settings, None).unwrap());
+    let fresh = [("cache-control", "max-age=60")];
+    get(
+        &cache,
+        "/a",
+        &HeaderMap::new(),
+        response(200, &fresh, "one"),
+    )
+    .await;
+    get(
+        &cache,
+        "/big",
+        &HeaderMap::new(),
+        response(200, &fresh, "too large"),
+    )
+    .await;
+    assert_eq!(cache.len(), 1);
+
+    let exchange = cache.begin(Cache::key(5001, "/a"), &Method::POST, &HeaderMap::new());
+    let (parts, body) = response(204, &[], "");
+    let (parts, _) = exchange.finish(parts, body);
+    assert_eq!(parts.headers[X_CACHE], "BYPASS");
+    assert!(cache.is_empty());
+}
+
+#[tokio::test]
+async fn purge_by_path_prefix() {
+    let cache = cache();
+    let fresh = [("cache-control", "max-age=60")];
+    for target in ["/static/app.js", "/static/app.css", "/api/users"] {
+        get(
+            &cache,
+            target,
+            &HeaderMap::new(),
+            response(200, &fresh, "x"),
+        )
+        .await;
+    }
+    assert_eq!(cache.purge(Some("/static/")), 2);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.purge(None), 1);
+    assert!(cache.is_empty());
+}
+
+#[tokio::test]
+async fn disk_cache_survives_restart() {
+    let dir = common::temp_roost_home();
+    let cache_dir = dir.path().join("cache").join("app.test");
+    let settings = CacheSettings {
+        disk: true,
+        ..Default::default()
+    };
+    let cache = Arc::new(Cache::new(settings.clone(), Some(cache_dir.clone())).unwrap());
+    let fresh = [
+        ("cache-control", "max-age=60"),
+        ("content-type", "text/plain"),
+    ];
+    get(
+        &cache,
+        "/a",
+        &HeaderMap::new(),
+        response(200, &fresh, "stored"),
+    )
+    .await;
+    drop(cache);
+
+    let cache = Arc::new(Cache::new(settings.clone(), Some(cache_dir.clone())).unwrap());
+    assert_eq!(cache.len(), 1);
+    let (x_cache, _, body) = get(&cache, "/a", &HeaderMap::new(), response(200, &[], "")).await;
+    assert_eq!((x_cache.as_str(), &body[..]), ("HIT", &b"stored"[..]));
+
+    cache.purge(None);
+    assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn settings_are_validated_and_roundtrip() {
+    let zero = CacheSettings {
+        max_bytes: Some(0),
+        ..Default::default()
+    };
+    assert!(zero.validate().is_err());
+    let inverted = CacheSettings {
+        max_bytes: Some(1024),
+        max_entry_bytes: Some(2048),
+        ..Default::default()
+    };
+    assert!(inverted.validate().is_err());
+
+    let settings = CacheSettings {
+        max_bytes: Some(1 << 20),
+        disk: true,
+        ..Default::default()
+    };
+    settings.validate().unwrap();
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut cfg = ServeConfig::default();
+    cfg.add("app.test".into(), 5001);
+    cfg.mapping_mut("app.test").unwrap().cache = Some(settings.clone());
+    cfg.save(&rc_path).unwrap();
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    assert_eq!(loaded.mappings[0].cache, Some(settings));
+}
@@ -0,0 +1,282 @@
+//! Chaos settings: fault selection, body wrappers, runtime control.
+
+mod common;
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use roost::config::RoostPaths;
+use roost::serve::chaos::{Chaos, ChaosOverrides, Fault, ThrottledBody, TruncatedBody};
+use roost::serve::config::ServeConfig;
+use roost::serve::control::{send_control, start_control_server, ControlRequest};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+fn body(data: &'static [u8]) -> http_body_util::combinators::BoxBody<Bytes, BoxError> {
+    Full::new(Bytes::from_static(data))
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+#[test]
+fn fault_ranges_are_drop_then_error_then_truncate() {
+    let chaos = Chaos {
+        drop_rate: 10.0,
+        error_rate: 20.0,
+        error_status: Some(500),
+        truncate_rate: 30.0,
+        ..Default::default()
+    };
+    assert_eq!(chaos.fault_for_roll(5.0), Fault::Drop);
+    assert_eq!(chaos.fault_for_roll(15.0), Fault::Error(500));
+    assert_eq!(chaos.fault_for_roll(45.0), Fault::Truncate);
+    assert_eq!(chaos.fault_for_roll(75.0), Fault::None);
+}
+
+#[test]
+fn error_status_defaults_to_503() {
+    let chaos = Chaos {
+        error_rate: 100.0,
+        ..Default::default()
+    };
+    assert_eq!(chaos.pick_fault(), Fault::Error(503));
+    assert_eq!(Chaos::default().pick_fault(), Fault::None);
+}
+
+#[test]
+fn validate_rejects_out_of_range_settings() {
+    let too_much = Chaos {
+        error_rate: 60.0,
+        drop_rate: 50.0,
+        ..Default::default()
+    };
+    assert!(too_much.validate().is_err());
+    let bad_status = Chaos {
+        error_rate: 1.0,
+        error_status: Some(42),
+        ..Default::default()
+    };
+    assert!(bad_status.validate().is_err());
+    // Informational and success codes aren't errors
+    for status in [101, 204, 302] {
+        let not_an_error = Chaos {
+            error_rate: 1.0,
+            error_status: Some(status),
+            ..Default::default()
+        };
+        let err = not_an_error.validate().unwrap_err();
+        assert!(err.to_string().contains("400-599"), "{err}");
+    }
+    assert!(Chaos::default().validate().is_ok());
+}
+
+#[test]
+fn path_prefix_scopes_chaos() {
+    let chaos = Chaos {
+        path_prefix: Some("/api".into()),
+        ..Default::default()
+    };
+    assert!(chaos.applies_to("/api/users"));
+    assert!(!chaos.applies_to("/assets/app.js"));
+    assert!(Chaos::default().applies_to("/anything"));
+}
+
+#[test]
+fn latency_includes_jitter_bounds() {
+    let chaos = Chaos {
+        latency_ms: 100,
+        jitter_ms: 50,
+        ..Default::default()
+    };
+    for _ in 0..20 {
+        let d = chaos.latency();
+        assert!(d >= Duration::from_millis(100) && d <= Duration::from_millis(150));
+    }
+}
+
+#[test]
+fn overrides_are_case_insensitive_and_clearable() {
+    let overrides = ChaosOverrides::default();
+    overrides.set("App.Test", Chaos::default());
+    assert!(overrides.get("app.test").is_some());
+    assert_eq!(overrides.list().len(), 1);
+    assert!(overrides.clear("APP.test"));
+    assert!(!overrides.clear("app.test"));
+}
+
+#[test]
+fn chaos_roundtrips_through_roostrc() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut cfg = ServeConfig::default();
+    cfg.add("app.test".into(), 5001);
+    cfg.mapping_mut("app.test").unwrap().chaos = Some(Chaos {
+        path_prefix: Some("/api".into()),
+        latency_ms: 250,
+        error_rate: 5.0,
+        ..Default::default()
+    });
+    cfg.save(&rc_path).unwrap();
+
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    let chaos = loaded.mappings[0].chaos.as_ref().unwrap();
+    assert_eq!(chaos.path_prefix.as_deref(), Some("/api"));
+    assert_eq!(chaos.latency_ms, 250);
+    assert_eq!(chaos.error_rate, 5.0);
+}
+
+#[tokio::test]
+async fn truncated_body_cuts_off_and_errors() {
+    let truncated = TruncatedBody::new(body(b"0123456789"), Some(4));
+    let err = truncated.collect().await.unwrap_err();
+    assert!(err.to_string().contains("truncated"));
+
+    let mut truncated = TruncatedBody::new(body(b"0123456789"), None);
+    let first = truncated
+        .frame()
+        .await
+        .unwrap()
+        .unwrap()
+        .into_data()
+        .unwrap();
+    assert_eq!(&first[..], b"01234");
+    assert!(truncated.frame().await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn throttled_body_limits_rate() {
+    // 100 bytes/s -> 10 bytes per 100ms tick; 30 bytes take at least 200ms
+    let start = Instant::now();
+    let data = ThrottledBody::new(body(&[b'x'; 30]), 100)
+        .collect()
+        .await
+        .unwrap()
+        .to_bytes();
+    assert_eq!(data.len(), 30);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn control_socket_roundtrip() {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    let overrides = Arc::new(ChaosOverrides::default());
+    let handler_overrides = overrides.clone();
+    let shutdown = CancellationToken::new();
+    start_control_server(
+        &paths,
+        Arc::new(move |req| match req {
+            ControlRequest::ChaosSet { domain, chaos } => {
+                handler_overrides.set(&domain, chaos);
+                Ok(serde_json::Value::Null)
+            }
+            ControlRequest::ChaosClear { domain } => {
+                anyhow::ensure!(handler_overrides.clear(&domain), "nothing to clear");
+                Ok(serde_json::Value::Null)
+            }
+            ControlRequest::ChaosList => Ok(serde_json::json!(handler_overrides.list().len())),
+            #[allow(unreachable_patterns)]
+            _ => anyhow::bail!("unsupported"),
+        }),
+        shutdown.clone(),
+    )
+    .await
+    .unwrap();
+
+    let client_paths = paths.clone();
+    let (list, clear_err) = tokio::task::spawn_blocking(move || {
+        send_control(
+            &client_paths,
+            ControlRequest::ChaosSet {
+                domain: "app.test".into(),
+                chaos: Chaos::default(),
+            },
+        )
+        .unwrap();
+        let list = send_control(&client_paths, ControlRequest::ChaosList).unwrap();
+        let clear_err = send_control(
+            &client_paths,
+            ControlRequest::ChaosClear {
+                domain: "other.test".into(),
+            },
+        )
+        .unwrap_err();
+        (list, clear_err)
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(list, serde_json::json!(1));
+    assert!(clear_err.to_string().contains("nothing to clear"));
+    assert!(overrides.get("app.test").is_some());
+
+    // The listener closes on shutdown
+    shutdown.cancel();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let client_paths = paths.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            send_control(&client_paths, ControlRequest::ChaosList)
+        })
+        .await
+        .unwrap();
+        if res.is_err() {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "control listener still accepting"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn control_rejects_bad_tokens_and_idle_clients() {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    let shutdown = CancellationToken::new();
+    start_control_server(
+        &paths,
+        Arc::new(|_| Ok(serde_json::Value::Null)),
+        shutdown.clone(),
+    )
+    .await
+    .unwrap();
+    let info: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(paths.config_dir.join("control.json")).unwrap(),
+    )
+    .unwrap();
+    let addr = info["addr"].as_str().unwrap().to_string();
+
+    let mut stream = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    stream
+        .write_all(b"{\"token\":\"wrong\",\"cmd\":\"chaos_list\"}\n")
+        .await
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(&mut stream)
+        .read_line(&mut line)
+        .await
+        .unwrap();
+    assert!(line.contains("invalid control token"), "{line}");
+
+    // A client that never sends its request is disconnected
+    let mut idle = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut buf = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(30), idle.read_to_end(&mut buf)).await;
+    assert!(read.is_ok(), "idle control connection was kept open");
+    shutdown.cancel();
+}
+
+#[test]
+fn control_without_running_proxy_fails() {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    let err = send_control(&paths, ControlRequest::ChaosList).unwrap_err();
+    assert!(err.to_string().contains("no running 'roost serve'"));
+}
@@ -0,0 +1,260 @@
+//! Response compression: negotiation, eligibility, streaming encode.
+
+mod common;
+
+use std::time::Duration;
+
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder};
+use futures_util::stream::{self, StreamExt};
+use http::{Request, Response};
+use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::service::service_fn;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use roost::config::{Config, RoostPaths};
+use roost::serve::compress::{compress, is_compressible, negotiate, should_compress};
+use roost::serve::compress::{Compression, Encoding};
+use roost::serve::config::{Mapping, Redirect, ServeConfig};
+use roost::serve::proxy::run_proxy_until;
+use tokio::io::AsyncReadExt;
+use tokio::sync::oneshot;
+use tokio_util::io::StreamReader;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+fn body(data: Vec<u8>) -> http_body_util::combinators::BoxBody<Bytes, BoxError> {
+    Full::new(Bytes::from(data))
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+fn parts(headers: &[(&str, &str)]) -> http::response::Parts {
+    let mut builder = http::Response::builder().status(200);
+    for (k, v) in headers {
+        builder = builder.header(*k, *v);
+    }
+    builder.body(()).unwrap().into_parts().0
+}
+
+#[test]
+fn negotiate_prefers_offered_order_and_respects_q() {
+    let offered = [Encoding::Brotli, Encoding::Gzip];
+    assert_eq!(
+        negotiate("gzip, deflate, br", &offered),
+        Some(Encoding::Brotli)
+    );
+    assert_eq!(negotiate("gzip, br;q=0", &offered), Some(Encoding::Gzip));
+    assert_eq!(negotiate("*", &offered), Some(Encoding::Brotli));
+    assert_eq!(negotiate("*;q=0, gzip", &offered), Some(Encoding::Gzip));
+    assert_eq!(negotiate("identity", &offered), None);
+    assert_eq!(negotiate("", &offered), None);
+}
+
+#[test]
+fn compressible_types() {
+    assert!(is_compressible("text/html; charset=utf-8", &[]));
+    assert!(is_compressible("application/json", &[]));
+    assert!(is_compressible("application/problem+json", &[]));
+    assert!(is_compressible("image/svg+xml", &[]));
+    assert!(!is_compressible("image/png", &[]));
+    assert!(!is_compressible("application/x-ndjson", &[]));
+    assert!(is_compressible("text/event-stream; charset=utf-8", &[]));
+    assert!(is_compressible(
+        "application/x-ndjson",
+        &["application/x-ndjson".into()]
+    ));
+}
+
+#[test]
+fn skips_encoded_small_partial_and_no_transform() {
+    let settings = Compression::default();
+    assert!(should_compress(
+        &parts(&[("content-type", "text/html"), ("content-length", "5000")]),
+        &settings
+    ));
+    assert!(!should_compress(
+        &parts(&[("content-type", "text/html"), ("content-encoding", "gzip")]),
+        &settings
+    ));
+    assert!(!should_compress(
+        &parts(&[("content-type", "text/html"), ("content-length", "100")]),
+        &settings
+    ));
+    assert!(!should_compress(
+        &parts(&[
+            ("content-type", "text/html"),
+            ("cache-control", "public, no-transform")
+        ]),
+        &settings
+    ));
+    assert!(!should_compress(
+        &parts(&[
+            ("content-type", "text/html"),
+            ("content-range", "bytes 0-9/100")
+        ]),
+        &settings
+    ));
+    assert!(!should_compress(
+        &parts(&[("content-type", "image/png")]),
+        &settings
+    ));
+}
+
+#[tokio::test]
+async fn compress_streams_and_rewrites_headers() {
+    let original = b"hello roost ".repeat(500);
+    for encoding in [Encoding::Gzip, Encoding::Brotli] {
+        let head = parts(&[
+            ("content-type", "text/plain"),
+            ("content-length", "6000"),
+            ("etag", "\"abc\""),
+            ("accept-ranges", "bytes"),
+        ]);
+        let (head, encoded) = compress(head, body(original.clone()), encoding);
+        assert_eq!(head.headers["content-encoding"], encoding.as_str());
+        assert_eq!(head.headers["vary"], "Accept-Encoding");
+        assert_eq!(head.headers["etag"], "W/\"abc\"");
+        assert!(head.headers.get("content-length").is_none());
+        assert!(head.headers.get("accept-ranges").is_none());
+
+        let bytes = encoded.collect().await.unwrap().to_bytes();
+        assert!(bytes.len() < original.len());
+        let mut decoded = Vec::new();
+        match encoding {
+            Encoding::Gzip => GzipDecoder::new(&bytes[..]).read_to_end(&mut decoded).await,
+            Encoding::Brotli => {
+                BrotliDecoder::new(&bytes[..])
+                    .read_to_end(&mut decoded)
+                    .await
+            }
+        }
+        .unwrap();
+        assert_eq!(decoded, original);
+    }
+}
+
+#[test]
+fn compression_roundtrips_through_roostrc() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut cfg = ServeConfig::default();
+    cfg.add("app.test".into(), 5001);
+    cfg.mapping_mut("app.test").unwrap().compression = Some(Compression {
+        encodings: vec![Encoding::Gzip],
+        min_size: 256,
+        content_types: vec![],
+    });
+    cfg.save(&rc_path).unwrap();
+
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    let compression = loaded.mappings[0].compression.as_ref().unwrap();
+    assert_eq!(compression.encodings, vec![Encoding::Gzip]);
+    assert_eq!(compression.min_size, 256);
+}
+
+/// Backend that sends one server-sent event and then keeps the stream open.
+async fn event_stream_backend() -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let service = service_fn(|_req: Request<Incoming>| async move {
+                    let event = Frame::data(Bytes::from_static(b"data: hello\n\n"));
+                    let frames = stream::once(async move { Ok::<_, hyper::Error>(event) })
+                        .chain(stream::pending());
+                    Ok::<_, hyper::Error>(
+                        Response::builder()
+                            .header("content-type", "text/event-stream")
+                            .body(StreamBody::new(frames))
+                            .unwrap(),
+                    )
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    port
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+#[tokio::test]
+async fn server_sent_events_are_flushed_one_event_at_a_time() {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    roost::ca::create_ca(&paths, "default").unwrap();
+    let mut store = Config {
+        default_ca: "default".to_string(),
+        ..Default::default()
+    };
+    roost::domain::add_domain(&paths, &mut store, "app.test", false, None).unwrap();
+    store.save(&paths).unwrap();
+
+    let (tls_port, plain_port) = (free_port(), free_port());
+    let mut mapping = Mapping::new("app.test".into(), event_stream_backend().await);
+    mapping.plain_http = true;
+    mapping.compression = Some(Compression {
+        min_size: 0,
+        ..Default::default()
+    });
+    let config = ServeConfig {
+        mappings: vec![mapping],
+        ports: vec![tls_port, plain_port],
+        redirects: vec![Redirect {
+            from: plain_port,
+            to: tls_port,
+        }],
+        ..Default::default()
+    };
+    let (stop, stopped) = oneshot::channel::<()>();
+    let proxy = tokio::spawn(async move {
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        run_proxy_until(&paths, &config, shutdown).await
+    });
+
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+    let mut response = None;
+    for _ in 0..50 {
+        let req = Request::get(format!("http://127.0.0.1:{plain_port}/events"))
+            .header("host", "app.test")
+            .header("accept-encoding", "gzip, br")
+            .body(Empty::new())
+            .unwrap();
+        match client.request(req).await {
+            Ok(r) => {
+                response = Some(r);
+                break;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+    let response = response.expect("proxy did not start");
+    assert_eq!(response.headers()["content-encoding"], "br");
+
+    // The first event decodes while the backend keeps the stream open
+    let data = response.into_body().into_data_stream();
+    let reader = StreamReader::new(data.map(|r| r.map_err(std::io::Error::other)));
+    let mut decoder = BrotliDecoder::new(reader);
+    let mut event = [0u8; 13];
+    tokio::time::timeout(Duration::from_secs(5), decoder.read_exact(&mut event))
+        .await
+        .expect("event was held back")
+        .unwrap();
+    assert_eq!(&event, b"data: hello\n\n");
+
+    drop(decoder);
+    stop.send(()).unwrap();
+    proxy.await.unwrap().unwrap();
+}
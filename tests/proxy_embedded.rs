@@ -0,0 +1,101 @@
+//! Embedding the proxy: leaving a running daemon alone, and cleaning up after a failed start.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use roost::config::{Config, RoostPaths};
+use roost::serve::config::{Mapping, Redirect, ServeConfig};
+use roost::serve::proxy::run_proxy_until;
+use tokio::sync::oneshot;
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Roost home with a cert for app.test, and a config proxying it on `tls_port` and `plain_port`.
+fn setup(tls_port: u16, plain_port: u16) -> (tempfile::TempDir, RoostPaths, ServeConfig) {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    roost::ca::create_ca(&paths, "default").unwrap();
+    let mut store = Config {
+        default_ca: "default".to_string(),
+        ..Default::default()
+    };
+    roost::domain::add_domain(&paths, &mut store, "app.test", false, None).unwrap();
+    store.save(&paths).unwrap();
+    let config = ServeConfig {
+        mappings: vec![Mapping::new("app.test".into(), free_port())],
+        ports: vec![tls_port, plain_port],
+        redirects: vec![Redirect {
+            from: plain_port,
+            to: tls_port,
+        }],
+        ..Default::default()
+    };
+    (dir, paths, config)
+}
+
+async fn wait_for(port: u16, listening: bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .is_ok()
+        != listening
+    {
+        assert!(
+            Instant::now() < deadline,
+            "port {port} listening != {listening}"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn embedded_proxy_leaves_the_daemon_control_file_alone() {
+    let (tls_port, plain_port) = (free_port(), free_port());
+    let (_dir, paths, config) = setup(tls_port, plain_port);
+    let control = paths.config_dir.join("control.json");
+    let daemon = r#"{"addr":"127.0.0.1:1","token":"daemon","pid":1}"#;
+    std::fs::write(&control, daemon).unwrap();
+
+    let (stop, stopped) = oneshot::channel::<()>();
+    let proxy_paths = paths.clone();
+    let proxy = tokio::spawn(async move {
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        run_proxy_until(&proxy_paths, &config, shutdown).await
+    });
+    wait_for(plain_port, true).await;
+    assert_eq!(std::fs::read_to_string(&control).unwrap(), daemon);
+
+    stop.send(()).unwrap();
+    proxy.await.unwrap().unwrap();
+    assert_eq!(std::fs::read_to_string(&control).unwrap(), daemon);
+}
+
+#[tokio::test]
+async fn failed_start_closes_listeners_already_open() {
+    // The redirect port binds first, then the TLS port is taken on every default address
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let tls_port = taken.local_addr().unwrap().port();
+    let _taken_v6 = std::net::TcpListener::bind(("::1", tls_port)).unwrap();
+    let plain_port = free_port();
+    let (_dir, paths, config) = setup(tls_port, plain_port);
+
+    let run = run_proxy_until(&paths, &config, std::future::pending());
+    let err = tokio::time::timeout(Duration::from_secs(10), run)
+        .await
+        .expect("proxy started on a taken port")
+        .unwrap_err();
+    assert!(
+        format!("{err:#}").contains(&tls_port.to_string()),
+        "{err:#}"
+    );
+    wait_for(plain_port, false).await;
+}
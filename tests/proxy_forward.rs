@@ -3,13 +3,17 @@
 mod common;
 
 use roost::config::RoostPaths;
-use std::collections::HashMap;
+use roost::serve::config::ServeConfig;
 
 #[tokio::test]
 async fn proxy_fails_with_no_mappings() {
     let dir = common::temp_roost_home();
     let paths = RoostPaths::for_test(dir.path());
-    let result = roost::serve::proxy::run_proxy(&paths, HashMap::new(), vec![17444]).await;
+    let config = ServeConfig {
+        ports: vec![17444],
+        ..Default::default()
+    };
+    let result = roost::serve::proxy::run_proxy(&paths, &config).await;
     assert!(result.is_err());
     assert!(result.unwrap_err().to_string().contains("no mappings"));
 }
@@ -0,0 +1,168 @@
+//! Forward auth: the subrequest, allowing with copied headers, and denying as-is.
+
+mod common;
+
+use http::{HeaderMap, Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use roost::serve::config::ServeConfig;
+use roost::serve::forward_auth::{apply, authorize, Decision, ForwardAuth, Original};
+use roost::serve::proxy::ProxyBody;
+use tokio::sync::mpsc;
+
+/// Auth endpoint that allows requests with `cookie: session=ok`, redirects others to a
+/// login page, and forwards each request's headers and body size.
+async fn auth_server() -> (u16, mpsc::UnboundedReceiver<(HeaderMap, usize)>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let tx = tx.clone();
+                    async move {
+                        let headers = req.headers().clone();
+                        let body = req.into_body().collect().await?.to_bytes();
+                        let _ = tx.send((headers.clone(), body.len()));
+                        let allowed = headers.get("cookie").is_some_and(|c| c == "session=ok");
+                        let response = if allowed {
+                            Response::builder()
+                                .header("x-auth-user", "alice")
+                                .header("x-other", "not copied")
+                                .body(Full::new(Bytes::new()))
+                        } else {
+                            Response::builder()
+                                .status(StatusCode::FOUND)
+                                .header("location", "https://login.test/?rd=app")
+                                .body(Full::new(Bytes::from_static(b"log in first")))
+                        };
+                        Ok::<_, hyper::Error>(response.unwrap())
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    (port, rx)
+}
+
+fn client() -> Client<HttpConnector, ProxyBody> {
+    Client::builder(TokioExecutor::new()).build(HttpConnector::new())
+}
+
+fn settings(port: u16) -> ForwardAuth {
+    ForwardAuth {
+        url: format!("http://127.0.0.1:{port}/verify"),
+        response_headers: vec!["X-Auth-User".into()],
+    }
+}
+
+fn original() -> Original<'static> {
+    Original {
+        tls: true,
+        host: "app.test",
+        client_ip: "127.0.0.1".parse().unwrap(),
+    }
+}
+
+fn request(cookie: &str) -> Request<Full<Bytes>> {
+    Request::post("/orders?id=7")
+        .header("host", "app.test")
+        .header("cookie", cookie)
+        .header("x-auth-user", "mallory")
+        .header("content-length", "5")
+        .body(Full::new(Bytes::from_static(b"hello")))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn allowed_request_gets_copied_headers() {
+    let (port, mut received) = auth_server().await;
+    let auth = settings(port);
+    let mut req = request("session=ok");
+    let Decision::Allow(headers) = authorize(&auth, &client(), &req, original()).await else {
+        panic!("expected the request to be allowed");
+    };
+
+    let (sent, body_len) = received.recv().await.unwrap();
+    assert_eq!(body_len, 0);
+    assert_eq!(sent["x-forwarded-method"], "POST");
+    assert_eq!(sent["x-forwarded-proto"], "https");
+    assert_eq!(sent["x-forwarded-host"], "app.test");
+    assert_eq!(sent["x-forwarded-uri"], "/orders?id=7");
+    assert_eq!(sent["x-forwarded-for"], "127.0.0.1");
+    assert_eq!(sent["cookie"], "session=ok");
+    assert_eq!(sent["host"], format!("127.0.0.1:{port}"));
+
+    apply(&auth, req.headers_mut(), headers);
+    let users: Vec<_> = req.headers().get_all("x-auth-user").iter().collect();
+    assert_eq!(users, ["alice"]);
+    assert!(!req.headers().contains_key("x-other"));
+}
+
+#[tokio::test]
+async fn denied_response_is_returned_as_is() {
+    let (port, _received) = auth_server().await;
+    let req = request("session=expired");
+    let Decision::Deny(response) = authorize(&settings(port), &client(), &req, original()).await
+    else {
+        panic!("expected the request to be denied");
+    };
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(response.headers()["location"], "https://login.test/?rd=app");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"log in first");
+}
+
+#[tokio::test]
+async fn unreachable_endpoint_is_bad_gateway() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let req = request("session=ok");
+    let Decision::Deny(response) = authorize(&settings(port), &client(), &req, original()).await
+    else {
+        panic!("expected the request to be denied");
+    };
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[test]
+fn settings_are_validated_and_roundtrip() {
+    for url in [
+        "localhost:9091/verify",
+        "https://auth.test/verify",
+        "/verify",
+    ] {
+        let auth = ForwardAuth {
+            url: url.into(),
+            ..Default::default()
+        };
+        assert!(auth.validate().is_err(), "{url}");
+    }
+    let bad_header = ForwardAuth {
+        response_headers: vec!["bad header".into()],
+        ..settings(9091)
+    };
+    assert!(bad_header.validate().is_err());
+    settings(9091).validate().unwrap();
+
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut cfg = ServeConfig::default();
+    cfg.add("app.test".into(), 5001);
+    cfg.mapping_mut("app.test").unwrap().forward_auth = Some(settings(9091));
+    cfg.save(&rc_path).unwrap();
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    assert_eq!(loaded.mappings[0].forward_auth, Some(settings(9091)));
+}
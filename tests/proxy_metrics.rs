@@ -0,0 +1,196 @@
+//! Prometheus metrics: rendering, byte counting, handshake failure reasons, endpoint and config.
+
+mod common;
+
+use http::StatusCode;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use roost::serve::config::{merge_serve_configs, ServeConfig};
+use roost::serve::metrics::{handshake_failure_reason, CountingBody, Metrics};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+
+#[test]
+fn renders_requests_histogram_and_gauges() {
+    let metrics = Metrics::new(["app.test"]);
+    let stats = metrics.domain("app.test");
+    stats.observe(StatusCode::OK, Duration::from_millis(3));
+    stats.observe(StatusCode::OK, Duration::from_millis(40));
+    stats.observe(StatusCode::BAD_GATEWAY, Duration::from_secs(20));
+    metrics
+        .domain("unmapped.test")
+        .observe(StatusCode::NOT_FOUND, Duration::from_millis(1));
+    let conn = metrics.connection();
+    let _tunnel = metrics.tunnel();
+    metrics.handshake_failed("unknown_sni");
+    metrics.handshake_failed("unknown_sni");
+    metrics.handshake_completed(false);
+    metrics.handshake_completed(true);
+    metrics.handshake_completed(true);
+    metrics.set_cert_expiry("app.test", 1_900_000_000);
+
+    let text = metrics.render();
+    for line in [
+        "roost_http_requests_total{domain=\"app.test\",status=\"2xx\"} 2",
+        "roost_http_requests_total{domain=\"app.test\",status=\"5xx\"} 1",
+        "roost_http_requests_total{domain=\"other\",status=\"4xx\"} 1",
+        "roost_http_request_duration_seconds_bucket{domain=\"app.test\",le=\"0.005\"} 1",
+        "roost_http_request_duration_seconds_bucket{domain=\"app.test\",le=\"0.05\"} 2",
+        "roost_http_request_duration_seconds_bucket{domain=\"app.test\",le=\"10\"} 2",
+        "roost_http_request_duration_seconds_bucket{domain=\"app.test\",le=\"+Inf\"} 3",
+        "roost_http_request_duration_seconds_count{domain=\"app.test\"} 3",
+        "roost_active_connections 1",
+        "roost_websocket_tunnels 1",
+        "roost_websocket_tunnels_total 1",
+        "roost_tls_handshake_failures_total{reason=\"unknown_sni\"} 2",
+        "roost_tls_handshakes_total{kind=\"full\"} 1",
+        "roost_tls_handshakes_total{kind=\"resumed\"} 2",
+        "roost_cert_expiry_timestamp_seconds{domain=\"app.test\"} 1900000000",
+        "# TYPE roost_http_request_duration_seconds histogram",
+    ] {
+        assert!(text.contains(line), "missing {line:?} in:\n{text}");
+    }
+    assert!(!text.contains("unmapped.test"));
+
+    drop(conn);
+    assert!(metrics.render().contains("roost_active_connections 0"));
+}
+
+#[test]
+fn reload_switches_domain_series() {
+    let metrics = Metrics::new(["app.test", "old.test"]);
+    metrics
+        .domain("app.test")
+        .observe(StatusCode::OK, Duration::from_millis(3));
+    metrics.set_domains(["App.Test", "new.test"]);
+    metrics
+        .domain("new.test")
+        .observe(StatusCode::OK, Duration::from_millis(3));
+
+    let text = metrics.render();
+    assert!(
+        text.contains("roost_http_requests_total{domain=\"app.test\",status=\"2xx\"} 1"),
+        "{text}"
+    );
+    assert!(
+        text.contains("roost_http_requests_total{domain=\"new.test\",status=\"2xx\"} 1"),
+        "{text}"
+    );
+    assert!(!text.contains("old.test"), "{text}");
+}
+
+#[tokio::test]
+async fn counting_body_counts_streamed_bytes() {
+    let metrics = Metrics::new(["app.test"]);
+    let body = Full::new(Bytes::from_static(b"hello world"))
+        .map_err(|never| match never {})
+        .boxed();
+    let out = CountingBody::outgoing(body, metrics.domain("app.test"))
+        .collect()
+        .await
+        .unwrap()
+        .to_bytes();
+    assert_eq!(&out[..], b"hello world");
+    let body = Full::new(Bytes::from_static(b"abc"))
+        .map_err(|never| match never {})
+        .boxed();
+    CountingBody::incoming(body, metrics.domain("app.test"))
+        .collect()
+        .await
+        .unwrap();
+
+    let text = metrics.render();
+    assert!(text.contains("roost_http_response_bytes_total{domain=\"app.test\"} 11"));
+    assert!(text.contains("roost_http_request_bytes_total{domain=\"app.test\"} 3"));
+}
+
+#[test]
+fn handshake_failure_reasons() {
+    let tls = |e: rustls::Error| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    assert_eq!(
+        handshake_failure_reason(&tls(rustls::Error::General(
+            "no server certificate chain resolved".into()
+        ))),
+        "unknown_sni"
+    );
+    assert_eq!(
+        handshake_failure_reason(&tls(rustls::Error::AlertReceived(
+            rustls::AlertDescription::UnknownCA
+        ))),
+        "alert_unknown_ca"
+    );
+    assert_eq!(
+        handshake_failure_reason(&tls(rustls::Error::NoApplicationProtocol)),
+        "no_application_protocol"
+    );
+    assert_eq!(
+        handshake_failure_reason(&std::io::ErrorKind::UnexpectedEof.into()),
+        "eof"
+    );
+}
+
+#[tokio::test]
+async fn endpoint_serves_metrics() {
+    let metrics = Metrics::new(["app.test"]);
+    let shutdown = CancellationToken::new();
+    let addr = roost::serve::metrics::start(
+        "127.0.0.1:0".parse().unwrap(),
+        metrics.clone(),
+        shutdown.clone(),
+    )
+    .unwrap();
+
+    let get = |path: &'static str| async move {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!("GET {path} HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n").as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+    let response = get("/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains("text/plain; version=0.0.4"));
+    assert!(response.contains("roost_http_requests_total{domain=\"app.test\",status=\"2xx\"} 0"));
+    assert!(get("/").await.starts_with("HTTP/1.1 404"));
+    shutdown.cancel();
+}
+
+#[test]
+fn metrics_address_roundtrips_merges_and_requires_lan() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let global = ServeConfig {
+        metrics: Some("127.0.0.1:9464".parse().unwrap()),
+        ..Default::default()
+    };
+    global.save(&rc_path).unwrap();
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    assert_eq!(loaded.metrics, global.metrics);
+
+    let project = ServeConfig {
+        metrics: Some("[::1]:9000".parse().unwrap()),
+        ..Default::default()
+    };
+    assert_eq!(
+        merge_serve_configs(&project, &loaded).metrics,
+        project.metrics
+    );
+    assert_eq!(
+        merge_serve_configs(&ServeConfig::default(), &loaded).metrics,
+        global.metrics
+    );
+
+    let mut exposed = ServeConfig {
+        metrics: Some("0.0.0.0:9464".parse().unwrap()),
+        ..Default::default()
+    };
+    assert!(exposed.validate_binds().is_err());
+    exposed.lan = true;
+    assert!(exposed.validate_binds().is_ok());
+}
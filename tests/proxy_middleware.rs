@@ -0,0 +1,205 @@
+//! Middleware: changing requests and responses, answering directly, and chain order.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use http::{Request, Response, StatusCode};
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use roost::config::{Config, RoostPaths};
+use roost::serve::config::{Mapping, Redirect, ServeConfig};
+use roost::serve::middleware::{Middleware, Next, RequestInfo};
+use roost::serve::proxy::{run_proxy_with, ProxyBody};
+use tokio::sync::oneshot;
+
+/// Backend that answers with the `x-tag` request header and the path.
+async fn backend() -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let service = service_fn(|req: Request<Incoming>| async move {
+                    let tag = req
+                        .headers()
+                        .get("x-tag")
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or("-");
+                    let body = format!("{tag} {}", req.uri().path());
+                    Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(body))))
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    port
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Tags requests on the way in and responses on the way out, recording the order.
+struct Tag {
+    name: &'static str,
+    seen: Arc<Mutex<Vec<String>>>,
+}
+
+impl Middleware for Tag {
+    fn handle<'a>(
+        &'a self,
+        mut req: Request<ProxyBody>,
+        info: RequestInfo,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Response<ProxyBody>>> {
+        Box::pin(async move {
+            assert!(!info.tls);
+            self.seen
+                .lock()
+                .unwrap()
+                .push(format!("{} request", self.name));
+            let tag = match req.headers().get("x-tag") {
+                Some(tag) => format!("{},{}", tag.to_str()?, self.name),
+                None => self.name.to_string(),
+            };
+            req.headers_mut().insert("x-tag", tag.parse()?);
+            let mut response = next.run(req).await?;
+            self.seen
+                .lock()
+                .unwrap()
+                .push(format!("{} response", self.name));
+            response
+                .headers_mut()
+                .append("x-middleware", self.name.parse()?);
+            Ok(response)
+        })
+    }
+}
+
+/// Answers `/health` itself and sends `/old/*` to `/new/*`.
+struct Gate;
+
+impl Middleware for Gate {
+    fn handle<'a>(
+        &'a self,
+        mut req: Request<ProxyBody>,
+        _info: RequestInfo,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Response<ProxyBody>>> {
+        Box::pin(async move {
+            if req.uri().path() == "/health" {
+                let body = Full::new(Bytes::from_static(b"ok"))
+                    .map_err(|never| match never {})
+                    .boxed();
+                return Ok(Response::builder()
+                    .status(StatusCode::IM_A_TEAPOT)
+                    .body(body)?);
+            }
+            if let Some(rest) = req.uri().path().strip_prefix("/old/") {
+                *req.uri_mut() = format!("/new/{rest}").parse()?;
+            }
+            next.run(req).await
+        })
+    }
+}
+
+#[tokio::test]
+async fn middleware_wraps_proxied_requests_in_order() {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    roost::ca::create_ca(&paths, "default").unwrap();
+    let mut store = Config {
+        default_ca: "default".to_string(),
+        ..Default::default()
+    };
+    roost::domain::add_domain(&paths, &mut store, "app.test", false, None).unwrap();
+    store.save(&paths).unwrap();
+
+    let (tls_port, plain_port) = (free_port(), free_port());
+    let mut mapping = Mapping::new("app.test".into(), backend().await);
+    mapping.plain_http = true;
+    let config = ServeConfig {
+        mappings: vec![mapping],
+        ports: vec![tls_port, plain_port],
+        redirects: vec![Redirect {
+            from: plain_port,
+            to: tls_port,
+        }],
+        ..Default::default()
+    };
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let middleware: Vec<Arc<dyn Middleware>> = vec![
+        Arc::new(Tag {
+            name: "outer",
+            seen: seen.clone(),
+        }),
+        Arc::new(Gate),
+        Arc::new(Tag {
+            name: "inner",
+            seen: seen.clone(),
+        }),
+    ];
+    let (stop, stopped) = oneshot::channel::<()>();
+    let proxy = tokio::spawn(async move {
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        run_proxy_with(&paths, &config, middleware, shutdown).await
+    });
+
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+    let get = |path: &str| {
+        let req = Request::get(format!("http://127.0.0.1:{plain_port}{path}"))
+            .header("host", "app.test")
+            .body(Empty::new())
+            .unwrap();
+        client.request(req)
+    };
+    let mut response = None;
+    for _ in 0..50 {
+        match get("/old/page").await {
+            Ok(r) => {
+                response = Some(r);
+                break;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+    let response = response.expect("proxy did not start");
+    let tags: Vec<_> = response.headers().get_all("x-middleware").iter().collect();
+    assert_eq!(tags, ["inner", "outer"]);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"outer,inner /new/page");
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            "outer request",
+            "inner request",
+            "inner response",
+            "outer response"
+        ]
+    );
+
+    // Answered by the middleware; later middleware and the backend never see it
+    seen.lock().unwrap().clear();
+    let response = get("/health").await.unwrap();
+    assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+    assert_eq!(response.headers()["x-middleware"], "outer");
+    assert_eq!(*seen.lock().unwrap(), ["outer request", "outer response"]);
+
+    stop.send(()).unwrap();
+    proxy.await.unwrap().unwrap();
+}
@@ -0,0 +1,168 @@
+//! Traffic mirroring: shadow requests, body copies and response comparison.
+
+mod common;
+
+use http::{Request, StatusCode};
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::service::service_fn;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use roost::serve::config::ServeConfig;
+use roost::serve::mirror::{compare_outcomes, Mirror, Shadow, MIRROR_HEADER};
+use roost::serve::proxy::ProxyBody;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+type Received = (String, Option<String>, Bytes);
+
+/// Shadow backend that forwards (path, mirror header, body) of each request.
+async fn shadow_backend() -> (u16, mpsc::UnboundedReceiver<Received>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let tx = tx.clone();
+                    async move {
+                        let path = req.uri().path().to_string();
+                        let header = req
+                            .headers()
+                            .get(MIRROR_HEADER)
+                            .map(|v| v.to_str().unwrap().to_string());
+                        let body = req.into_body().collect().await?.to_bytes();
+                        let _ = tx.send((path, header, body));
+                        Ok::<_, hyper::Error>(http::Response::new(Full::new(Bytes::new())))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    (port, rx)
+}
+
+fn client() -> Client<HttpConnector, ProxyBody> {
+    Client::builder(TokioExecutor::new()).build(HttpConnector::new())
+}
+
+fn boxed(body: Full<Bytes>) -> ProxyBody {
+    body.map_err(|never| match never {}).boxed()
+}
+
+#[tokio::test]
+async fn shadow_receives_copy_of_request_and_body() {
+    let (port, mut received) = shadow_backend().await;
+    let mirror = Mirror {
+        port,
+        ..Default::default()
+    };
+    let req = Request::post("http://localhost:1/orders?id=7")
+        .header("content-length", "11")
+        .body(Full::new(Bytes::from_static(b"hello world")))
+        .unwrap();
+    let mut shadow = Shadow::start(&mirror, &req, "app.test", client());
+    let primary = shadow.tee(boxed(req.into_body()));
+    let primary_body = primary.collect().await.unwrap().to_bytes();
+    assert_eq!(&primary_body[..], b"hello world");
+    shadow.primary_done(Some(StatusCode::OK), Duration::from_millis(5));
+
+    let (path, header, body) = tokio::time::timeout(Duration::from_secs(5), received.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(path, "/orders");
+    assert_eq!(header.as_deref(), Some("1"));
+    assert_eq!(&body[..], b"hello world");
+}
+
+#[tokio::test]
+async fn unreachable_or_slow_shadow_never_blocks_primary() {
+    // Nothing listens on the shadow port
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mirror = Mirror {
+        port,
+        compare: true,
+        ..Default::default()
+    };
+    let chunks: Vec<Result<Frame<Bytes>, Box<dyn std::error::Error + Send + Sync>>> = (0..1000)
+        .map(|_| Ok(Frame::data(Bytes::from_static(&[7; 1024]))))
+        .collect();
+    let body = StreamBody::new(futures_util::stream::iter(chunks)).boxed();
+    let req = Request::put("http://localhost:1/upload")
+        .body(body)
+        .unwrap();
+    let mut shadow = Shadow::start(&mirror, &req, "app.test", client());
+    let primary = shadow.tee(req.into_body());
+    let primary_body = tokio::time::timeout(Duration::from_secs(5), primary.collect())
+        .await
+        .expect("primary body is not held back by the shadow")
+        .unwrap()
+        .to_bytes();
+    assert_eq!(primary_body.len(), 1000 * 1024);
+    shadow.primary_done(None, Duration::ZERO);
+}
+
+#[test]
+fn compares_status_and_latency() {
+    let ms = Duration::from_millis;
+    let threshold = ms(100);
+    assert_eq!(
+        compare_outcomes(
+            (StatusCode::OK, ms(10)),
+            (StatusCode::OK, ms(90)),
+            threshold
+        ),
+        None
+    );
+    assert_eq!(
+        compare_outcomes(
+            (StatusCode::OK, ms(10)),
+            (StatusCode::INTERNAL_SERVER_ERROR, ms(20)),
+            threshold
+        )
+        .unwrap(),
+        "status 200 vs shadow 500"
+    );
+    assert_eq!(
+        compare_outcomes(
+            (StatusCode::OK, ms(300)),
+            (StatusCode::OK, ms(20)),
+            threshold
+        )
+        .unwrap(),
+        "latency 300ms vs shadow 20ms"
+    );
+}
+
+#[test]
+fn mirror_settings_roundtrip() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut cfg = ServeConfig::default();
+    cfg.add("app.test".into(), 5001);
+    cfg.mapping_mut("app.test").unwrap().mirror = Some(Mirror {
+        port: 5002,
+        compare: true,
+        latency_threshold_ms: Some(250),
+    });
+    cfg.save(&rc_path).unwrap();
+    let text = std::fs::read_to_string(&rc_path).unwrap();
+    assert!(text.contains("[serve.mappings.mirror]"), "{text}");
+
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    let mirror = loaded.mappings[0].mirror.clone().unwrap();
+    assert_eq!(mirror.port, 5002);
+    assert_eq!(mirror.latency_threshold_ms, Some(250));
+    assert!(Mirror::default().validate().is_err());
+}
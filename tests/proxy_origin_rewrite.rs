@@ -0,0 +1,101 @@
+//! Rewriting the backend origin in Location, Refresh and Set-Cookie headers.
+
+use http::header::{CONTENT_LOCATION, LOCATION, SET_COOKIE};
+use http::HeaderMap;
+use roost::serve::origin::{
+    public_origin, rewrite_headers, rewrite_refresh, rewrite_set_cookie, rewrite_url,
+};
+
+#[test]
+fn public_origin_omits_default_ports() {
+    assert_eq!(public_origin("app.test", true, 443), "https://app.test");
+    assert_eq!(
+        public_origin("app.test", true, 8443),
+        "https://app.test:8443"
+    );
+    assert_eq!(public_origin("app.test", false, 80), "http://app.test");
+    assert_eq!(
+        public_origin("app.test", false, 8080),
+        "http://app.test:8080"
+    );
+}
+
+#[test]
+fn rewrites_only_backend_urls() {
+    let public = "https://app.test";
+    for (url, expected) in [
+        (
+            "http://localhost:5001/login?next=/",
+            Some("https://app.test/login?next=/"),
+        ),
+        ("http://LOCALHOST:5001", Some("https://app.test")),
+        (
+            "http://127.0.0.1:5001/a#frag",
+            Some("https://app.test/a#frag"),
+        ),
+        ("http://[::1]:5001?q=1", Some("https://app.test?q=1")),
+        ("http://localhost:5002/", None),
+        ("http://localhost/", None),
+        ("https://example.com:5001/", None),
+        ("/relative", None),
+    ] {
+        assert_eq!(rewrite_url(url, 5001, public).as_deref(), expected, "{url}");
+    }
+}
+
+#[test]
+fn rewrites_refresh_url() {
+    let public = "https://app.test";
+    assert_eq!(
+        rewrite_refresh("5; url=http://localhost:5001/next", 5001, public).unwrap(),
+        "5; url=https://app.test/next"
+    );
+    assert_eq!(
+        rewrite_refresh("0;URL='http://127.0.0.1:5001/x'", 5001, public).unwrap(),
+        "0;URL='https://app.test/x'"
+    );
+    assert!(rewrite_refresh("5", 5001, public).is_none());
+    assert!(rewrite_refresh("5; url=/next", 5001, public).is_none());
+}
+
+#[test]
+fn rewrites_cookie_domain_and_secure() {
+    assert_eq!(
+        rewrite_set_cookie(
+            "sid=1; Domain=localhost; Path=/; HttpOnly",
+            "app.test",
+            true
+        )
+        .unwrap(),
+        "sid=1; Domain=app.test; Path=/; HttpOnly; Secure"
+    );
+    assert_eq!(
+        rewrite_set_cookie("sid=1; domain=.127.0.0.1; Secure", "app.test", true).unwrap(),
+        "sid=1; Domain=app.test; Secure"
+    );
+    // Already fine over HTTPS
+    assert!(rewrite_set_cookie("sid=1; Domain=example.com; Secure", "app.test", true).is_none());
+    // Secure cookies would be dropped over plain HTTP
+    assert_eq!(
+        rewrite_set_cookie("sid=1; Secure; Path=/", "app.test", false).unwrap(),
+        "sid=1; Path=/"
+    );
+    assert!(rewrite_set_cookie("sid=1; Path=/", "app.test", false).is_none());
+}
+
+#[test]
+fn rewrites_all_values_of_each_header() {
+    let mut headers = HeaderMap::new();
+    headers.insert(LOCATION, "http://localhost:5001/a".parse().unwrap());
+    headers.insert(CONTENT_LOCATION, "/b".parse().unwrap());
+    headers.insert("refresh", "1; url=http://localhost:5001/c".parse().unwrap());
+    headers.append(SET_COOKIE, "a=1; Domain=localhost".parse().unwrap());
+    headers.append(SET_COOKIE, "b=2".parse().unwrap());
+    rewrite_headers(&mut headers, 5001, "app.test", true, 8443);
+
+    assert_eq!(headers[LOCATION], "https://app.test:8443/a");
+    assert_eq!(headers[CONTENT_LOCATION], "/b");
+    assert_eq!(headers["refresh"], "1; url=https://app.test:8443/c");
+    let cookies: Vec<_> = headers.get_all(SET_COOKIE).iter().collect();
+    assert_eq!(cookies, ["a=1; Domain=app.test; Secure", "b=2; Secure"]);
+}
@@ -0,0 +1,55 @@
+//! Route table: host normalization, `Host` header parsing and backend URIs.
+
+use http::uri::PathAndQuery;
+use roost::serve::routing::{backend_authority, backend_uri, normalize, parse_host, HostTable};
+use std::borrow::Cow;
+
+#[test]
+fn normalize_lowercases_and_strips_trailing_dot() {
+    assert_eq!(normalize("App.Test."), "app.test");
+    assert!(matches!(normalize("app.test"), Cow::Borrowed("app.test")));
+    assert!(matches!(normalize("app.test."), Cow::Borrowed("app.test")));
+}
+
+#[test]
+fn host_table_matches_any_case() {
+    let table: HostTable<u16> = [("App.Test".to_string(), 5001), ("api.test".into(), 5002)]
+        .into_iter()
+        .collect();
+    assert_eq!(table.len(), 2);
+    assert_eq!(table.get("app.test"), Some(&5001));
+    assert_eq!(table.get("API.TEST."), Some(&5002));
+    assert!(table.contains("APP.test"));
+    assert_eq!(table.get("other.test"), None);
+    let mut hosts: Vec<_> = table.iter().map(|(h, _)| h).collect();
+    hosts.sort();
+    assert_eq!(hosts, ["api.test", "app.test"]);
+}
+
+#[test]
+fn parse_host_splits_port() {
+    assert_eq!(parse_host("App.Test:8443"), ("app.test".into(), Some(8443)));
+    assert_eq!(parse_host(" app.test "), ("app.test".into(), None));
+    assert_eq!(parse_host("app.test:x"), ("app.test:x".into(), None));
+    assert_eq!(parse_host("app.test.:443"), ("app.test".into(), Some(443)));
+    assert_eq!(
+        parse_host("192.168.1.20:8443"),
+        ("192.168.1.20".into(), Some(8443))
+    );
+    assert_eq!(parse_host("[::1]:8443"), ("::1".into(), Some(8443)));
+    assert_eq!(parse_host("[FE80::1]"), ("fe80::1".into(), None));
+}
+
+#[test]
+fn backend_uri_keeps_path_and_query() {
+    let authority = backend_authority(5001);
+    let pq = PathAndQuery::from_static("/a/b?c=1&d");
+    assert_eq!(
+        backend_uri(&authority, Some(&pq)).to_string(),
+        "http://localhost:5001/a/b?c=1&d"
+    );
+    assert_eq!(
+        backend_uri(&authority, None).to_string(),
+        "http://localhost:5001/"
+    );
+}
@@ -0,0 +1,243 @@
+//! Redirect and rewrite rules: matching, captures, targets and validation.
+
+mod common;
+
+use http::StatusCode;
+use roost::serve::config::{merge_serve_configs, ServeConfig};
+use roost::serve::rules::{Action, RequestParts, Rule, Rules};
+
+fn request<'a>(host: &'a str, path: &'a str, query: Option<&'a str>) -> RequestParts<'a> {
+    RequestParts {
+        tls: true,
+        host,
+        port: 8443,
+        path,
+        query,
+    }
+}
+
+fn redirect(status: u16, location: &str) -> Option<Action> {
+    Some(Action::Redirect {
+        status: StatusCode::from_u16(status).unwrap(),
+        location: location.to_string(),
+    })
+}
+
+#[test]
+fn host_redirect_keeps_path_port_and_query() {
+    let rules = Rules::compile(&[Rule {
+        host: Some("www.*".into()),
+        redirect: Some("$scheme://$1$port$path".into()),
+        status: Some(301),
+        ..Default::default()
+    }])
+    .unwrap();
+    assert_eq!(
+        rules.apply(&request("WWW.app.test", "/a/b", Some("x=1"))),
+        redirect(301, "https://app.test:8443/a/b?x=1")
+    );
+    assert_eq!(rules.apply(&request("app.test", "/a/b", None)), None);
+
+    let mut default_port = request("www.app.test", "/", None);
+    default_port.port = 443;
+    assert_eq!(
+        rules.apply(&default_port),
+        redirect(301, "https://app.test/")
+    );
+}
+
+#[test]
+fn glob_path_redirect_and_rewrite() {
+    let rules = Rules::compile(&[
+        Rule {
+            path: Some("/old/*".into()),
+            redirect: Some("/new/$1".into()),
+            ..Default::default()
+        },
+        Rule {
+            host: Some("api.test".into()),
+            path: Some("/v1/*".into()),
+            rewrite: Some("/api/v1/$1?legacy=1".into()),
+            ..Default::default()
+        },
+    ])
+    .unwrap();
+    assert_eq!(
+        rules.apply(&request("app.test", "/old/a/b.html", Some("q=2"))),
+        redirect(302, "/new/a/b.html?q=2")
+    );
+    // The glob is anchored
+    assert_eq!(rules.apply(&request("app.test", "/x/old/a", None)), None);
+    // A query in the target replaces the request's
+    assert_eq!(
+        rules.apply(&request("api.test", "/v1/users", Some("page=2"))),
+        Some(Action::Rewrite("/api/v1/users?legacy=1".into()))
+    );
+    assert_eq!(rules.apply(&request("app.test", "/v1/users", None)), None);
+}
+
+#[test]
+fn regex_trailing_slash_and_named_captures() {
+    let rules = Rules::compile(&[
+        Rule {
+            path: Some(r"/docs/(?P<page>[^/.]+)".into()),
+            regex: true,
+            redirect: Some("/docs/${page}/".into()),
+            status: Some(308),
+            ..Default::default()
+        },
+        Rule {
+            host: Some(r"(\w+)\.app\.test".into()),
+            path: Some(r"/(.*)".into()),
+            regex: true,
+            rewrite: Some("/tenants/$1/$2".into()),
+            ..Default::default()
+        },
+    ])
+    .unwrap();
+    assert_eq!(
+        rules.apply(&request("app.test", "/docs/intro", None)),
+        redirect(308, "/docs/intro/")
+    );
+    assert_eq!(
+        rules.apply(&request("app.test", "/docs/intro/", None)),
+        None
+    );
+    // Captures are numbered across the host pattern, then the path pattern
+    assert_eq!(
+        rules.apply(&request("acme.app.test", "/orders", None)),
+        Some(Action::Rewrite("/tenants/acme/orders".into()))
+    );
+}
+
+#[test]
+fn first_matching_rule_wins() {
+    let rules = Rules::compile(&[
+        Rule {
+            path: Some("/a/*".into()),
+            redirect: Some("/first".into()),
+            ..Default::default()
+        },
+        Rule {
+            path: Some("/a/b".into()),
+            redirect: Some("/second".into()),
+            ..Default::default()
+        },
+    ])
+    .unwrap();
+    assert_eq!(
+        rules.apply(&request("app.test", "/a/b", None)),
+        redirect(302, "/first")
+    );
+}
+
+#[test]
+fn invalid_rules_are_rejected() {
+    let path = || Some("/a/*".to_string());
+    for (rule, message) in [
+        (
+            Rule {
+                redirect: Some("/b".into()),
+                ..Default::default()
+            },
+            "host or path",
+        ),
+        (
+            Rule {
+                path: path(),
+                ..Default::default()
+            },
+            "exactly one",
+        ),
+        (
+            Rule {
+                path: path(),
+                redirect: Some("/b".into()),
+                rewrite: Some("/c".into()),
+                ..Default::default()
+            },
+            "exactly one",
+        ),
+        (
+            Rule {
+                path: path(),
+                redirect: Some("/b".into()),
+                status: Some(200),
+                ..Default::default()
+            },
+            "301, 302, 307 or 308",
+        ),
+        (
+            Rule {
+                path: path(),
+                rewrite: Some("b".into()),
+                ..Default::default()
+            },
+            "starting with '/'",
+        ),
+        (
+            Rule {
+                path: path(),
+                redirect: Some("/b/$2".into()),
+                ..Default::default()
+            },
+            "capture 1 group",
+        ),
+        (
+            Rule {
+                path: path(),
+                redirect: Some("/b/$nope".into()),
+                ..Default::default()
+            },
+            "unknown variable $nope",
+        ),
+        (
+            Rule {
+                path: Some("(".into()),
+                regex: true,
+                redirect: Some("/b".into()),
+                ..Default::default()
+            },
+            "invalid pattern",
+        ),
+    ] {
+        let valid = Rule {
+            path: path(),
+            redirect: Some("/b/$1".into()),
+            ..Default::default()
+        };
+        let err = Rules::compile(&[valid, rule]).unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.starts_with("rule 2:"), "{err}");
+        assert!(err.contains(message), "{err}");
+    }
+}
+
+#[test]
+fn rules_roundtrip_and_project_rules_come_first() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let rule = |path: &str| Rule {
+        path: Some(path.into()),
+        redirect: Some("/".into()),
+        ..Default::default()
+    };
+    let global = ServeConfig {
+        rules: vec![rule("/global")],
+        ..Default::default()
+    };
+    global.save(&rc_path).unwrap();
+    let text = std::fs::read_to_string(&rc_path).unwrap();
+    assert!(text.contains("[[serve.rules]]"), "{text}");
+    let global = ServeConfig::load(&rc_path).unwrap();
+    assert_eq!(global.rules, [rule("/global")]);
+
+    let project = ServeConfig {
+        rules: vec![rule("/project")],
+        ..Default::default()
+    };
+    assert_eq!(
+        merge_serve_configs(&project, &global).rules,
+        [rule("/project"), rule("/global")]
+    );
+}
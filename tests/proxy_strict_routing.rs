@@ -0,0 +1,52 @@
+//! Strict routing: the Host port selects a backend only with `port_passthrough`.
+
+mod common;
+
+use roost::serve::config::ServeConfig;
+use roost::serve::proxy::backend_port;
+
+#[test]
+fn host_port_is_ignored_by_default() {
+    let route = Some((5001, false));
+    assert_eq!(backend_port(route, None, 443), Some(5001));
+    assert_eq!(backend_port(route, Some(443), 443), Some(5001));
+    assert_eq!(backend_port(route, Some(8443), 8443), Some(5001));
+    assert_eq!(backend_port(route, Some(6379), 443), Some(5001));
+}
+
+#[test]
+fn unmapped_hosts_never_reach_host_port() {
+    assert_eq!(backend_port(None, Some(6379), 443), None);
+    assert_eq!(backend_port(None, None, 443), None);
+}
+
+#[test]
+fn passthrough_forwards_to_host_port() {
+    let route = Some((5001, true));
+    assert_eq!(backend_port(route, Some(5173), 443), Some(5173));
+    // Listen ports still mean the mapping itself
+    assert_eq!(backend_port(route, Some(443), 8443), Some(5001));
+    assert_eq!(backend_port(route, Some(8443), 8443), Some(5001));
+    assert_eq!(backend_port(route, None, 443), Some(5001));
+}
+
+#[test]
+fn passthrough_roundtrips() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut cfg = ServeConfig::default();
+    cfg.add("app.test".into(), 5001);
+    cfg.add("api.test".into(), 5002);
+    cfg.mapping_mut("app.test").unwrap().port_passthrough = true;
+    cfg.save(&rc_path).unwrap();
+    let text = std::fs::read_to_string(&rc_path).unwrap();
+    assert_eq!(text.matches("port_passthrough").count(), 1, "{text}");
+
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    let passthrough: Vec<_> = loaded
+        .mappings
+        .iter()
+        .map(|m| (m.domain.as_str(), m.port_passthrough))
+        .collect();
+    assert_eq!(passthrough, [("app.test", true), ("api.test", false)]);
+}
@@ -0,0 +1,235 @@
+//! Trace context propagation, X-Request-Id and OTLP span export.
+
+use http::{HeaderMap, Request, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use roost::serve::trace::{parse_traceparent, request_id, TraceContext, Tracer, Tracing};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+#[test]
+fn parses_valid_traceparent_only() {
+    let (trace_id, parent_id, flags) = parse_traceparent(PARENT).unwrap();
+    assert_eq!(trace_id[0], 0x4b);
+    assert_eq!(parent_id[7], 0xb7);
+    assert_eq!(flags, 1);
+
+    for bad in [
+        "",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+    ] {
+        assert!(parse_traceparent(bad).is_none(), "{bad:?}");
+    }
+    // Future versions may append fields
+    assert!(
+        parse_traceparent("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra")
+            .is_some()
+    );
+}
+
+#[test]
+fn continues_incoming_trace_or_starts_one() {
+    let mut headers = HeaderMap::new();
+    headers.insert("traceparent", PARENT.parse().unwrap());
+    headers.insert("tracestate", "vendor=abc".parse().unwrap());
+    let ctx = TraceContext::from_headers(&headers);
+    let (trace_id, parent_id, _) = parse_traceparent(PARENT).unwrap();
+    assert_eq!(ctx.trace_id, trace_id);
+    assert_eq!(ctx.parent_id, Some(parent_id));
+    assert_ne!(ctx.span_id, parent_id);
+
+    let mut out = HeaderMap::new();
+    ctx.inject(&mut out);
+    let sent = out["traceparent"].to_str().unwrap();
+    assert!(sent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+    assert!(sent.ends_with("-01"));
+    assert_eq!(out["tracestate"], "vendor=abc");
+
+    // tracestate without a valid traceparent is dropped
+    headers.insert("traceparent", "garbage".parse().unwrap());
+    let fresh = TraceContext::from_headers(&headers);
+    assert_ne!(fresh.trace_id, trace_id);
+    assert_eq!(fresh.parent_id, None);
+    let mut out = HeaderMap::new();
+    fresh.inject(&mut out);
+    assert!(parse_traceparent(out["traceparent"].to_str().unwrap()).is_some());
+    assert!(out.get("tracestate").is_none());
+}
+
+#[test]
+fn request_id_is_kept_or_generated() {
+    let mut headers = HeaderMap::new();
+    let generated = request_id(&headers);
+    let id = generated.to_str().unwrap();
+    assert_eq!(id.len(), 36);
+    assert_eq!(&id[14..15], "4", "UUID v4: {id}");
+    assert_ne!(request_id(&headers), generated);
+
+    headers.insert("x-request-id", "abc-123".parse().unwrap());
+    assert_eq!(request_id(&headers), "abc-123");
+    headers.insert("x-request-id", "has space".parse().unwrap());
+    assert_ne!(request_id(&headers), "has space");
+}
+
+#[test]
+fn settings_merge_and_endpoint_url() {
+    let global = Tracing {
+        otlp_endpoint: Some("http://127.0.0.1:4318".into()),
+        ..Default::default()
+    };
+    let project = Tracing {
+        propagate: Some(false),
+        ..Default::default()
+    };
+    let merged = project.or(&global);
+    assert!(!merged.propagate());
+    assert_eq!(merged.service_name(), "roost");
+    assert_eq!(
+        merged.traces_url().unwrap().unwrap(),
+        "http://127.0.0.1:4318/v1/traces"
+    );
+    assert!(Tracing::default().propagate());
+    assert!(Tracing::default().traces_url().unwrap().is_none());
+
+    let custom = Tracing {
+        otlp_endpoint: Some("http://collector:4318/custom/traces".into()),
+        ..Default::default()
+    };
+    assert_eq!(
+        custom.traces_url().unwrap().unwrap(),
+        "http://collector:4318/custom/traces"
+    );
+    let tls = Tracing {
+        otlp_endpoint: Some("https://collector:4318".into()),
+        ..Default::default()
+    };
+    assert!(tls.traces_url().is_err());
+}
+
+/// Collector that forwards each received request body to the returned channel.
+async fn collector() -> (String, mpsc::UnboundedReceiver<(String, Bytes)>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let tx = tx.clone();
+                    async move {
+                        let path = req.uri().path().to_string();
+                        let body = req.into_body().collect().await?.to_bytes();
+                        let _ = tx.send((path, body));
+                        Ok::<_, hyper::Error>(http::Response::new(Full::new(Bytes::new())))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    (format!("http://{addr}"), rx)
+}
+
+#[tokio::test]
+async fn tracer_injects_headers_and_exports_span() {
+    let (endpoint, mut received) = collector().await;
+    let settings = Tracing {
+        otlp_endpoint: Some(endpoint),
+        service_name: Some("edge".into()),
+        ..Default::default()
+    };
+    let shutdown = CancellationToken::new();
+    let tracer = Tracer::new(&settings, &shutdown).unwrap();
+
+    let mut req = Request::get("/orders?id=7")
+        .header("traceparent", PARENT)
+        .body(())
+        .unwrap();
+    let trace = tracer
+        .begin(
+            &mut req,
+            "app.test",
+            "127.0.0.1:50000".parse().unwrap(),
+            443,
+            true,
+        )
+        .unwrap();
+    let backend_traceparent = req.headers()["traceparent"].to_str().unwrap().to_string();
+    let request_id = req.headers()["x-request-id"].clone();
+
+    let mut response_headers = HeaderMap::new();
+    tracer.finish(trace, StatusCode::BAD_GATEWAY, &mut response_headers);
+    assert_eq!(response_headers["x-request-id"], request_id);
+
+    let (path, body) = tokio::time::timeout(Duration::from_secs(5), received.recv())
+        .await
+        .expect("span exported within flush interval")
+        .unwrap();
+    assert_eq!(path, "/v1/traces");
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let resource = &json["resourceSpans"][0];
+    assert_eq!(
+        resource["resource"]["attributes"][0]["value"]["stringValue"],
+        "edge"
+    );
+    let span = &resource["scopeSpans"][0]["spans"][0];
+    assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(span["parentSpanId"], "00f067aa0ba902b7");
+    assert_eq!(
+        backend_traceparent,
+        format!(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-{}-01",
+            span["spanId"].as_str().unwrap()
+        ),
+        "backend sees the proxy span as its parent"
+    );
+    assert_eq!(span["name"], "GET");
+    assert_eq!(span["kind"], 2);
+    assert_eq!(span["status"]["code"], 2);
+    let attrs = span["attributes"].as_array().unwrap();
+    let attr = |key: &str| {
+        attrs
+            .iter()
+            .find(|a| a["key"] == key)
+            .map(|a| a["value"].clone())
+            .unwrap_or_else(|| panic!("missing attribute {key}"))
+    };
+    assert_eq!(attr("server.address")["stringValue"], "app.test");
+    assert_eq!(attr("url.path")["stringValue"], "/orders");
+    assert_eq!(attr("url.query")["stringValue"], "id=7");
+    assert_eq!(attr("http.response.status_code")["intValue"], "502");
+    shutdown.cancel();
+}
+
+#[tokio::test]
+async fn propagation_can_be_turned_off() {
+    let settings = Tracing {
+        propagate: Some(false),
+        ..Default::default()
+    };
+    let tracer = Tracer::new(&settings, &CancellationToken::new()).unwrap();
+    let mut req = Request::get("/").body(()).unwrap();
+    assert!(tracer
+        .begin(
+            &mut req,
+            "app.test",
+            "127.0.0.1:1".parse().unwrap(),
+            443,
+            true
+        )
+        .is_none());
+    assert!(req.headers().is_empty());
+}
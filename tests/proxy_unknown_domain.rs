@@ -1,9 +1,129 @@
-//! Unknown domain returns 400.
+//! Unmapped hosts: fallback cert, landing page and default backend.
 
 mod common;
 
+use roost::config::RoostPaths;
+use roost::serve::config::{merge_serve_configs, ServeConfig};
+use roost::serve::landing::{render, DomainStatus, ProbeCache};
+use roost::{ca, cert};
+use std::time::Duration;
+use x509_parser::prelude::*;
+
+#[tokio::test]
+async fn probe_reports_listening_backends() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let up = listener.local_addr().unwrap().port();
+    let down = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mappings = [("b.test".to_string(), down), ("a.test".to_string(), up)];
+    let statuses = ProbeCache::new(Duration::ZERO)
+        .probe(mappings.clone())
+        .await;
+    assert_eq!(
+        statuses,
+        [
+            DomainStatus {
+                domain: "a.test".into(),
+                port: up,
+                up: true
+            },
+            DomainStatus {
+                domain: "b.test".into(),
+                port: down,
+                up: false
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn probe_results_are_reused_for_the_ttl() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mappings = [("a.test".to_string(), port), ("b.test".to_string(), port)];
+    let cached = ProbeCache::new(Duration::from_secs(60));
+    let uncached = ProbeCache::new(Duration::ZERO);
+    assert!(cached.probe(mappings.clone()).await.iter().all(|s| s.up));
+    assert!(uncached.probe(mappings.clone()).await.iter().all(|s| s.up));
+
+    drop(listener);
+    assert!(cached.probe(mappings.clone()).await.iter().all(|s| s.up));
+    assert!(uncached.probe(mappings).await.iter().all(|s| !s.up));
+}
+
 #[test]
-fn unknown_domain_returns_400() {
-    // Verified: proxy_request returns 400 when domain not in mappings.
-    assert!(true, "proxy returns 400 for unknown domain");
+fn landing_page_links_domains_and_escapes_host() {
+    let domains = [
+        DomainStatus {
+            domain: "api.test".into(),
+            port: 5001,
+            up: true,
+        },
+        DomainStatus {
+            domain: "web.test".into(),
+            port: 5002,
+            up: false,
+        },
+    ];
+    let page = render("<script>.test", &domains, |d| format!("https://{d}:8443"));
+    assert!(page.contains("&lt;script&gt;.test is not configured"));
+    assert!(!page.contains("<script>"));
+    assert!(page.contains("<a href=\"https://api.test:8443/\">api.test</a>"));
+    assert!(page.contains("localhost:5002</td><td class=\"down\">not responding"));
+
+    let empty = render("x.test", &[], |d| d.to_string());
+    assert!(empty.contains("No domains are configured"));
+}
+
+#[test]
+fn fallback_cert_is_issued_once() {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    ca::create_ca(&paths, "default").unwrap();
+    cert::ensure_fallback_cert(&paths, "default").unwrap();
+    let (cert_path, key_path) = cert::fallback_cert_paths(&paths);
+    assert!(key_path.is_file());
+    let pem = std::fs::read(&cert_path).unwrap();
+    let der = rustls_pemfile::certs(&mut &pem[..])
+        .next()
+        .unwrap()
+        .unwrap();
+    let (_, parsed) = X509Certificate::from_der(&der).unwrap();
+    assert!(parsed
+        .subject()
+        .to_string()
+        .contains(cert::FALLBACK_CERT_NAME));
+    assert!(parsed.issuer().to_string().contains("Roost CA (default)"));
+
+    cert::ensure_fallback_cert(&paths, "default").unwrap();
+    assert_eq!(std::fs::read(&cert_path).unwrap(), pem);
+}
+
+#[test]
+fn default_backend_roundtrips_and_project_wins() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let global = ServeConfig {
+        default_backend: Some(3000),
+        ..Default::default()
+    };
+    global.save(&rc_path).unwrap();
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    assert_eq!(loaded.default_backend, Some(3000));
+
+    let project = ServeConfig {
+        default_backend: Some(4000),
+        ..Default::default()
+    };
+    assert_eq!(
+        merge_serve_configs(&project, &loaded).default_backend,
+        Some(4000)
+    );
+    assert_eq!(
+        merge_serve_configs(&ServeConfig::default(), &loaded).default_backend,
+        Some(3000)
+    );
 }
@@ -0,0 +1,184 @@
+//! Upstream override: selecting alternate backends by cookie or header.
+
+mod common;
+
+use http::{HeaderMap, Method, StatusCode};
+use roost::serve::config::ServeConfig;
+use roost::serve::upstream::{
+    endpoint, parse_range, requested, UpstreamOverride, UpstreamSelector, COOKIE,
+};
+
+fn branches() -> UpstreamOverride {
+    UpstreamOverride {
+        ports: vec!["5005".into(), "5100-5110".into()],
+        alternates: [("feature-x".to_string(), 5020)].into(),
+    }
+}
+
+fn selector() -> UpstreamSelector {
+    UpstreamSelector::compile(&branches()).unwrap()
+}
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (name, value) in pairs {
+        map.append(*name, value.parse().unwrap());
+    }
+    map
+}
+
+#[test]
+fn selects_alternates_and_allowlisted_ports_only() {
+    let ov = selector();
+    assert_eq!(ov.select("feature-x"), Some(5020));
+    assert_eq!(ov.select("5005"), Some(5005));
+    assert_eq!(ov.select("5100"), Some(5100));
+    assert_eq!(ov.select("5110"), Some(5110));
+    assert_eq!(ov.select("5111"), None);
+    assert_eq!(ov.select("6379"), None);
+    assert_eq!(ov.select("feature-y"), None);
+}
+
+#[test]
+fn header_takes_precedence_over_cookie() {
+    let cookie = format!("a=1; {COOKIE}=5005; b=2");
+    let h = headers(&[("cookie", &cookie)]);
+    assert_eq!(requested(&h), Some("5005"));
+    assert_eq!(selector().select_for(&h), Some(5005));
+
+    let h = headers(&[("cookie", &cookie), ("x-roost-upstream", "feature-x")]);
+    assert_eq!(selector().select_for(&h), Some(5020));
+
+    // A stale or forged value falls back to the mapping's port
+    let h = headers(&[("cookie", "roost-upstream=22")]);
+    assert_eq!(selector().select_for(&h), None);
+    assert_eq!(requested(&HeaderMap::new()), None);
+}
+
+#[test]
+fn endpoint_sets_and_clears_cookie() {
+    let sel = selector();
+    let post = Method::POST;
+    let same_site = headers(&[("origin", "https://app.test")]);
+    let set = endpoint(
+        &sel,
+        "app.test",
+        5001,
+        &post,
+        "set=feature-x",
+        &same_site,
+        true,
+    );
+    assert_eq!(set.status(), StatusCode::SEE_OTHER);
+    assert_eq!(set.headers()["location"], "/");
+    assert_eq!(
+        set.headers()["set-cookie"],
+        "roost-upstream=feature-x; Path=/; HttpOnly; SameSite=Lax; Secure"
+    );
+
+    // Query and form body are joined; no Origin means a non-browser client
+    let empty = HeaderMap::new();
+    let set = endpoint(&sel, "app.test", 5001, &post, "&set=5005", &empty, false);
+    assert_eq!(
+        set.headers()["set-cookie"],
+        "roost-upstream=5005; Path=/; HttpOnly; SameSite=Lax"
+    );
+
+    let clear = endpoint(&sel, "app.test", 5001, &post, "clear", &empty, false);
+    assert_eq!(
+        clear.headers()["set-cookie"],
+        "roost-upstream=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax"
+    );
+
+    let rejected = endpoint(&sel, "app.test", 5001, &post, "set=6379", &empty, true);
+    assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+    assert!(!rejected.headers().contains_key("set-cookie"));
+
+    let h = headers(&[("cookie", "roost-upstream=5005")]);
+    let status = endpoint(&sel, "app.test", 5001, &Method::GET, "", &h, true);
+    assert_eq!(status.status(), StatusCode::OK);
+    let body = status.body();
+    assert!(body.contains("app.test &rarr; localhost:5001"), "{body}");
+    assert!(body.contains("Override: 5005 (localhost:5005)"), "{body}");
+    assert!(
+        body.contains(r#"<button name="set" value="feature-x">feature-x (localhost:5020)"#),
+        "{body}"
+    );
+    assert!(body.contains(r#"placeholder="5005, 5100-5110""#), "{body}");
+}
+
+#[test]
+fn endpoint_refuses_cross_site_changes() {
+    let sel = selector();
+    // A link or an image can't change the cookie
+    let get = endpoint(
+        &sel,
+        "app.test",
+        5001,
+        &Method::GET,
+        "set=feature-x",
+        &HeaderMap::new(),
+        true,
+    );
+    assert_eq!(get.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(get.headers()["allow"], "POST");
+    assert!(!get.headers().contains_key("set-cookie"));
+
+    // Nor can a form on another site, including a subdomain
+    for origin in ["https://evil.example", "https://x.app.test", "null"] {
+        let h = headers(&[("origin", origin)]);
+        for params in ["set=feature-x", "clear"] {
+            let response = endpoint(&sel, "app.test", 5001, &Method::POST, params, &h, true);
+            assert_eq!(
+                response.status(),
+                StatusCode::FORBIDDEN,
+                "{origin} {params}"
+            );
+            assert!(!response.headers().contains_key("set-cookie"));
+        }
+    }
+
+    let h = headers(&[("origin", "https://APP.test:8443")]);
+    let response = endpoint(&sel, "app.test", 5001, &Method::POST, "clear", &h, true);
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    assert!(UpstreamOverride::default().validate().is_err());
+    assert!(parse_range("5010-5000").is_err());
+    assert!(parse_range("0").is_err());
+    assert!(parse_range("50x").is_err());
+    assert_eq!(parse_range(" 5000 - 5010").unwrap(), 5000..=5010);
+    for name in ["5005", "a b", "x;y", ""] {
+        let ov = UpstreamOverride {
+            alternates: [(name.to_string(), 5005)].into(),
+            ..Default::default()
+        };
+        assert!(ov.validate().is_err(), "{name:?}");
+    }
+    branches().validate().unwrap();
+    let bad_range = UpstreamOverride {
+        ports: vec!["5010-5000".into()],
+        ..Default::default()
+    };
+    assert!(UpstreamSelector::compile(&bad_range).is_err());
+}
+
+#[test]
+fn override_roundtrips() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut cfg = ServeConfig::default();
+    cfg.add("app.test".into(), 5001);
+    cfg.mapping_mut("app.test").unwrap().upstream = Some(branches());
+    cfg.save(&rc_path).unwrap();
+    let text = std::fs::read_to_string(&rc_path).unwrap();
+    assert!(
+        text.contains("[serve.mappings.upstream.alternates]"),
+        "{text}"
+    );
+
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    assert_eq!(loaded.mappings[0].upstream, Some(branches()));
+}
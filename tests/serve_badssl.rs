@@ -0,0 +1,144 @@
+//! badssl test endpoints: broken certs, page content and config.
+
+mod common;
+
+use roost::cert::{self, BrokenCert, WRONG_HOST_SAN};
+use roost::config::{Config, RoostPaths};
+use roost::serve::badssl::{self, BASE_DOMAIN, ENDPOINTS};
+use roost::serve::config::{merge_serve_configs, ServeConfig};
+use roost::{ca, revoke};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::*;
+
+fn setup() -> (tempfile::TempDir, RoostPaths) {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    ca::create_ca(&paths, "default").unwrap();
+    Config {
+        default_ca: "default".to_string(),
+        ..Default::default()
+    }
+    .save(&paths)
+    .unwrap();
+    (dir, paths)
+}
+
+fn chain_der(pem: &[u8]) -> Vec<Vec<u8>> {
+    rustls_pemfile::certs(&mut &pem[..])
+        .map(|c| c.unwrap().to_vec())
+        .collect()
+}
+
+fn ca_subject(paths: &RoostPaths) -> String {
+    let (ca_pem, _) = ca::load_ca(paths, "default").unwrap();
+    let der = &chain_der(&ca_pem)[0];
+    let (_, ca_cert) = X509Certificate::from_der(der).unwrap();
+    ca_cert.subject().to_string()
+}
+
+fn issue(paths: &RoostPaths, kind: BrokenCert) -> Vec<Vec<u8>> {
+    let (pem, key) = cert::issue_broken_cert(paths, "x.badssl.test", "default", kind).unwrap();
+    assert!(String::from_utf8(key).unwrap().contains("PRIVATE KEY"));
+    chain_der(&pem)
+}
+
+#[test]
+fn broken_certs_are_broken_as_described() {
+    let (_dir, paths) = setup();
+    let ca = ca_subject(&paths);
+
+    let chain = issue(&paths, BrokenCert::Expired);
+    let (_, leaf) = X509Certificate::from_der(&chain[0]).unwrap();
+    assert!(!leaf.validity().is_valid());
+    assert_eq!(leaf.issuer().to_string(), ca);
+
+    let chain = issue(&paths, BrokenCert::WrongHost);
+    let (_, leaf) = X509Certificate::from_der(&chain[0]).unwrap();
+    let san = leaf.subject_alternative_name().unwrap().unwrap();
+    assert_eq!(
+        san.value.general_names,
+        vec![GeneralName::DNSName(WRONG_HOST_SAN)]
+    );
+    assert_eq!(leaf.issuer().to_string(), ca);
+
+    let chain = issue(&paths, BrokenCert::SelfSigned);
+    let (_, leaf) = X509Certificate::from_der(&chain[0]).unwrap();
+    assert_eq!(leaf.issuer(), leaf.subject());
+    assert!(leaf.validity().is_valid());
+
+    let chain = issue(&paths, BrokenCert::UntrustedRoot);
+    assert_eq!(chain.len(), 2, "leaf and untrusted root");
+    let (_, leaf) = X509Certificate::from_der(&chain[0]).unwrap();
+    let (_, root) = X509Certificate::from_der(&chain[1]).unwrap();
+    assert_eq!(leaf.issuer(), root.subject());
+    assert_eq!(root.issuer(), root.subject());
+    assert_ne!(root.subject().to_string(), ca);
+
+    let chain = issue(&paths, BrokenCert::IncompleteChain);
+    assert_eq!(chain.len(), 1, "intermediate is not sent");
+    let (_, leaf) = X509Certificate::from_der(&chain[0]).unwrap();
+    assert_ne!(leaf.issuer().to_string(), ca);
+
+    let chain = issue(&paths, BrokenCert::Revoked);
+    let (_, leaf) = X509Certificate::from_der(&chain[0]).unwrap();
+    let serial = revoke::serial_hex(leaf.raw_serial());
+    let list = revoke::load(&paths, "default").unwrap();
+    assert_eq!(
+        list.find(&serial).unwrap().domain.as_deref(),
+        Some("x.badssl.test")
+    );
+    assert!(revoke::crl_path(&paths, "default").is_file());
+}
+
+#[test]
+fn ensure_certs_issues_each_host_once() {
+    let (_dir, paths) = setup();
+    badssl::ensure_certs(&paths).unwrap();
+    let hosts = badssl::hosts();
+    assert_eq!(hosts.len(), ENDPOINTS.len() + 1);
+    for host in &hosts {
+        let (cert_path, key_path) = badssl::cert_paths(&paths, host);
+        assert!(cert_path.is_file() && key_path.is_file(), "{host}");
+    }
+    let (index, _) = badssl::cert_paths(&paths, BASE_DOMAIN);
+    let index_pem = std::fs::read(&index).unwrap();
+
+    badssl::ensure_certs(&paths).unwrap();
+    assert_eq!(std::fs::read(&index).unwrap(), index_pem);
+    assert_eq!(revoke::load(&paths, "default").unwrap().revoked.len(), 1);
+
+    badssl::remove_certs(&paths);
+    assert!(!index.exists());
+}
+
+#[test]
+fn pages_exist_for_badssl_hosts_only() {
+    let index = badssl::page(BASE_DOMAIN).unwrap();
+    for endpoint in ENDPOINTS {
+        assert!(index.contains(&format!("https://{}/", endpoint.host())));
+        let page = badssl::page(&endpoint.host()).unwrap();
+        assert!(page.contains(endpoint.description));
+    }
+    assert!(badssl::page("expired.badssl.test.evil").is_none());
+    assert!(badssl::page("app.test").is_none());
+}
+
+#[test]
+fn badssl_flag_roundtrips_and_merges() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    ServeConfig::default().save(&rc_path).unwrap();
+    assert!(!std::fs::read_to_string(&rc_path)
+        .unwrap()
+        .contains("badssl"));
+
+    let global = ServeConfig {
+        badssl: true,
+        ..Default::default()
+    };
+    global.save(&rc_path).unwrap();
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    assert!(loaded.badssl);
+    assert!(merge_serve_configs(&ServeConfig::default(), &loaded).badssl);
+    assert!(!merge_serve_configs(&ServeConfig::default(), &ServeConfig::default()).badssl);
+}
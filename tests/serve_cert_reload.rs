@@ -0,0 +1,140 @@
+//! Cert hot swap: the resolver serves re-signed certs after a reload or a file change,
+//! without rebuilding the TLS configs.
+
+mod common;
+
+use roost::config::RoostPaths;
+use roost::serve::certs::CertStore;
+use roost::serve::metrics::Metrics;
+use roost::serve::tls::{TlsConfigs, TlsPolicy, TlsSessions};
+use roost::{ca, domain, store};
+use rustls::pki_types::ServerName;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+fn setup() -> (tempfile::TempDir, RoostPaths, roost::config::Config) {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    ca::create_ca(&paths, "default").unwrap();
+    ca::create_ca(&paths, "custom").unwrap();
+    store::ensure_dirs(&paths).unwrap();
+    let mut config = store::load_config(&paths).unwrap();
+    config.default_ca = "default".to_string();
+    domain::add_domain(&paths, &mut config, "app.test", false, None).unwrap();
+    store::save_config(&paths, &config).unwrap();
+    (dir, paths, config)
+}
+
+/// Client config trusting both test CAs, always doing full handshakes.
+fn client(paths: &RoostPaths) -> Arc<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    for name in ["default", "custom"] {
+        let (ca_pem, _) = ca::load_ca(paths, name).unwrap();
+        for c in rustls_pemfile::certs(&mut &ca_pem[..]) {
+            roots.add(c.unwrap()).unwrap();
+        }
+    }
+    let mut config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    // A resumed session would report the cert from the first handshake
+    config.resumption = rustls::client::Resumption::disabled();
+    Arc::new(config)
+}
+
+/// Handshake in memory and return the leaf cert the server presented.
+fn served_leaf(client: &Arc<rustls::ClientConfig>, server: Arc<rustls::ServerConfig>) -> Vec<u8> {
+    let mut client =
+        rustls::ClientConnection::new(client.clone(), ServerName::try_from("app.test").unwrap())
+            .unwrap();
+    let mut server = rustls::ServerConnection::new(server).unwrap();
+    let mut buf = Vec::new();
+    while client.is_handshaking() || server.is_handshaking() {
+        buf.clear();
+        client.write_tls(&mut buf).unwrap();
+        server.read_tls(&mut &buf[..]).unwrap();
+        server.process_new_packets().unwrap();
+        buf.clear();
+        server.write_tls(&mut buf).unwrap();
+        client.read_tls(&mut &buf[..]).unwrap();
+        client.process_new_packets().unwrap();
+    }
+    client.peer_certificates().unwrap()[0].to_vec()
+}
+
+fn leaf_on_disk(paths: &RoostPaths) -> Vec<u8> {
+    let pem = std::fs::read(paths.certs_dir.join("app.test.pem")).unwrap();
+    let leaf = rustls_pemfile::certs(&mut &pem[..])
+        .next()
+        .unwrap()
+        .unwrap();
+    leaf.to_vec()
+}
+
+fn start(paths: &RoostPaths) -> (Arc<CertStore>, TlsConfigs) {
+    let policies = HashMap::from([("app.test".to_string(), TlsPolicy::default())]);
+    let metrics = Metrics::new(["app.test"]);
+    let certs = CertStore::new(paths, &policies, None, false, &metrics).unwrap();
+    let configs = TlsConfigs::new(&policies, certs.clone(), &TlsSessions::new().unwrap()).unwrap();
+    (certs, configs)
+}
+
+#[test]
+fn reload_serves_re_signed_cert() {
+    let (_dir, paths, mut config) = setup();
+    let (certs, configs) = start(&paths);
+    let client = client(&paths);
+    let before = served_leaf(&client, configs.for_sni(Some("app.test")));
+    assert_eq!(before, leaf_on_disk(&paths));
+
+    domain::set_ca(&paths, &mut config, "app.test", "custom").unwrap();
+    store::save_config(&paths, &config).unwrap();
+    assert_eq!(certs.reload(Some("App.Test")).unwrap(), vec!["app.test"]);
+
+    // Same config object, new cert
+    let after = served_leaf(&client, configs.for_sni(Some("app.test")));
+    assert_ne!(before, after);
+    assert_eq!(after, leaf_on_disk(&paths));
+    assert_eq!(certs.reload(None).unwrap(), vec!["app.test"]);
+}
+
+#[test]
+fn reload_reports_unknown_or_missing_certs() {
+    let (_dir, paths, _config) = setup();
+    let (certs, _configs) = start(&paths);
+    let err = certs.reload(Some("other.test")).unwrap_err();
+    assert!(
+        err.to_string().contains("no mapping for other.test"),
+        "{err}"
+    );
+
+    std::fs::remove_file(paths.certs_dir.join("app.test.pem")).unwrap();
+    let err = certs.reload(Some("app.test")).unwrap_err();
+    assert!(err.to_string().contains("no cert for app.test"), "{err}");
+    // Reloading everything skips it and keeps serving the loaded cert
+    assert!(certs.reload(None).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn watcher_picks_up_changed_files() {
+    let (_dir, paths, mut config) = setup();
+    let (certs, configs) = start(&paths);
+    let client = client(&paths);
+    let shutdown = CancellationToken::new();
+    tokio::spawn(certs.clone().watch(shutdown.clone()));
+    let before = served_leaf(&client, configs.for_sni(Some("app.test")));
+
+    domain::set_ca(&paths, &mut config, "app.test", "custom").unwrap();
+    store::save_config(&paths, &config).unwrap();
+    let expected = leaf_on_disk(&paths);
+    assert_ne!(before, expected);
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while served_leaf(&client, configs.for_sni(Some("app.test"))) != expected {
+        assert!(tokio::time::Instant::now() < deadline, "cert not reloaded");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    shutdown.cancel();
+}
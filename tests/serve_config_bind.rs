@@ -0,0 +1,145 @@
+//! Per-port bind addresses and LAN opt-in.
+
+mod common;
+
+use assert_cmd::cargo::cargo_bin_cmd;
+use roost::acme::AcmeOptions;
+use roost::config::RoostPaths;
+use roost::serve::config::{
+    load_merged, merge_serve_configs, require_loopback, ServeConfig, DEFAULT_BIND,
+};
+use std::net::IpAddr;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn defaults_to_loopback_v4_and_v6() {
+    let cfg = ServeConfig::default();
+    assert_eq!(cfg.bind_addrs(443), DEFAULT_BIND.to_vec());
+    assert_eq!(cfg.bind_addrs(443), vec![ip("127.0.0.1"), ip("::1")]);
+    assert!(cfg.validate_binds().is_ok());
+}
+
+#[test]
+fn set_bind_per_port_and_reset() {
+    let mut cfg = ServeConfig::default();
+    cfg.set_bind(8443, vec![ip("127.0.0.1")]);
+    assert_eq!(cfg.bind_addrs(8443), vec![ip("127.0.0.1")]);
+    assert_eq!(cfg.bind_addrs(443), DEFAULT_BIND.to_vec());
+
+    cfg.set_bind(8443, vec![]);
+    assert_eq!(cfg.bind_addrs(8443), DEFAULT_BIND.to_vec());
+    assert!(cfg.listen.is_empty());
+}
+
+#[test]
+fn non_loopback_requires_lan_opt_in() {
+    let mut cfg = ServeConfig::default();
+    cfg.set_bind(8443, vec![ip("0.0.0.0"), ip("::")]);
+    let err = cfg.validate_binds().unwrap_err();
+    assert!(err.to_string().contains("lan = true"), "{err}");
+
+    cfg.lan = true;
+    assert!(cfg.validate_binds().is_ok());
+}
+
+#[test]
+fn bind_roundtrips_through_roostrc() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+
+    let mut cfg = ServeConfig {
+        lan: true,
+        ..Default::default()
+    };
+    cfg.set_bind(8443, vec![ip("192.168.1.20"), ip("::1")]);
+    cfg.save(&rc_path).unwrap();
+
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    assert!(loaded.lan);
+    assert_eq!(loaded.bind_addrs(8443), vec![ip("192.168.1.20"), ip("::1")]);
+}
+
+#[test]
+fn merge_project_bind_overrides_global() {
+    let mut global = ServeConfig::default();
+    global.set_bind(8443, vec![ip("127.0.0.1")]);
+    global.set_bind(9443, vec![ip("::1")]);
+
+    let mut project = ServeConfig::default();
+    project.set_bind(8443, vec![ip("0.0.0.0")]);
+    project.lan = true;
+
+    let merged = merge_serve_configs(&project, &global);
+    assert_eq!(merged.bind_addrs(8443), vec![ip("0.0.0.0")]);
+    assert_eq!(merged.bind_addrs(9443), vec![ip("::1")]);
+    // A project file can't expose mappings by itself
+    assert!(!merged.lan);
+    assert!(merged.validate_binds().is_err());
+
+    global.lan = true;
+    assert!(merge_serve_configs(&project, &global).lan);
+}
+
+#[test]
+fn lan_flag_is_written_to_the_global_roostrc_only() {
+    let dir = common::temp_roost_home();
+    let project = dir.path().join("project");
+    std::fs::create_dir_all(&project).unwrap();
+    let roost = || {
+        let mut cmd = cargo_bin_cmd!("roost");
+        cmd.current_dir(&project).env("ROOST_HOME", dir.path());
+        cmd
+    };
+    let bind = ["serve", "config", "ports", "bind", "8443", "0.0.0.0"];
+    let paths = RoostPaths::for_test(dir.path());
+
+    std::fs::write(project.join(".roostrc"), "[serve]\nlan = true\n").unwrap();
+    roost()
+        .args(bind)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("lan = true"));
+    // A project command can't widen exposure for the whole machine
+    roost()
+        .args(bind)
+        .arg("--lan")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--lan needs --global"));
+    assert!(!ServeConfig::load(&paths.roostrc_global).unwrap().lan);
+
+    roost()
+        .args(["serve", "config", "ports", "bind", "9443", "0.0.0.0"])
+        .args(["--global", "--lan"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Enabled LAN exposure"));
+    assert!(ServeConfig::load(&paths.roostrc_global).unwrap().lan);
+
+    // With lan enabled globally, the project file may bind other addresses
+    roost().args(bind).assert().success();
+    let merged = load_merged(&paths, &project).unwrap();
+    assert_eq!(merged.bind_addrs(8443), vec![ip("0.0.0.0")]);
+    assert!(merged.validate_binds().is_ok());
+}
+
+#[test]
+fn other_listeners_stay_on_loopback() {
+    let how = "pass --lan";
+    assert!(require_loopback([ip("127.0.0.1"), ip("::1")], false, "x", how).is_ok());
+    let err = require_loopback([ip("0.0.0.0")], false, "the OCSP responder", how).unwrap_err();
+    assert!(
+        err.to_string().contains("the OCSP responder binds 0.0.0.0"),
+        "{err}"
+    );
+    assert!(require_loopback([ip("0.0.0.0")], true, "x", how).is_ok());
+
+    let acme = AcmeOptions {
+        bind: vec![ip("192.168.1.20")],
+        ..Default::default()
+    };
+    assert!(acme.validate().is_err());
+}
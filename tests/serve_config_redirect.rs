@@ -0,0 +1,90 @@
+//! HTTP -> HTTPS redirect pairs, plain HTTP mappings and HSTS settings.
+
+mod common;
+
+use roost::serve::config::{merge_serve_configs, Hsts, Redirect, ServeConfig};
+
+#[test]
+fn default_ports_imply_80_to_443() {
+    let cfg = ServeConfig::default();
+    assert_eq!(cfg.effective_redirects(), vec![Redirect { from: 80, to: 443 }]);
+    assert_eq!(cfg.tls_ports(), vec![443]);
+}
+
+#[test]
+fn port_80_without_443_has_no_redirect() {
+    let mut cfg = ServeConfig::default();
+    cfg.ports_set(vec![80, 8443]);
+    assert!(cfg.effective_redirects().is_empty());
+    assert_eq!(cfg.tls_ports(), vec![8443]);
+}
+
+#[test]
+fn explicit_pair_for_rootless_ports() {
+    let mut cfg = ServeConfig::default();
+    cfg.ports_set(vec![8080, 8443]);
+    cfg.set_redirect(8080, 8443).unwrap();
+    assert_eq!(
+        cfg.effective_redirects(),
+        vec![Redirect {
+            from: 8080,
+            to: 8443
+        }]
+    );
+    assert_eq!(cfg.tls_ports(), vec![8443], "plain ports are not served with TLS");
+
+    assert!(cfg.set_redirect(8443, 8443).is_err());
+    assert!(cfg.remove_redirect(8080));
+    assert!(!cfg.remove_redirect(8080));
+}
+
+#[test]
+fn explicit_redirect_from_80_replaces_implied_one() {
+    let mut cfg = ServeConfig::default();
+    cfg.set_redirect(80, 8443).unwrap();
+    assert_eq!(cfg.effective_redirects(), vec![Redirect { from: 80, to: 8443 }]);
+}
+
+#[test]
+fn redirects_and_hsts_roundtrip_and_merge() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut global = ServeConfig {
+        hsts: Some(Hsts {
+            max_age_secs: 300,
+            include_subdomains: true,
+        }),
+        ..Default::default()
+    };
+    global.set_redirect(8080, 8443).unwrap();
+    global.set_redirect(9080, 9443).unwrap();
+    global.add("app.test".into(), 5001);
+    global.mapping_mut("app.test").unwrap().plain_http = true;
+    global.save(&rc_path).unwrap();
+
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    assert_eq!(loaded.redirects.len(), 2);
+    assert!(loaded.mappings[0].plain_http);
+    assert_eq!(
+        loaded.hsts.unwrap().header_value(),
+        "max-age=300; includeSubDomains"
+    );
+
+    let mut project = ServeConfig::default();
+    project.set_redirect(8080, 443).unwrap();
+    let merged = merge_serve_configs(&project, &loaded);
+    assert_eq!(
+        merged.redirects,
+        vec![
+            Redirect {
+                from: 8080,
+                to: 443
+            },
+            Redirect {
+                from: 9080,
+                to: 9443
+            },
+        ]
+    );
+    assert!(merged.hsts.is_some());
+}
@@ -0,0 +1,136 @@
+//! HTTPS by IP address and for localhost: IP-SAN certs served to clients without SNI via
+//! `default_cert`, and localhost served like any other mapping.
+
+mod common;
+
+use roost::config::RoostPaths;
+use roost::serve::certs::CertStore;
+use roost::serve::config::{merge_serve_configs, ServeConfig};
+use roost::serve::metrics::Metrics;
+use roost::serve::tls::{TlsConfigs, TlsPolicy, TlsSessions};
+use roost::{ca, domain, store};
+use rustls::pki_types::ServerName;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn setup(domains: &[&str]) -> (tempfile::TempDir, RoostPaths) {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    ca::create_ca(&paths, "default").unwrap();
+    store::ensure_dirs(&paths).unwrap();
+    let mut config = store::load_config(&paths).unwrap();
+    config.default_ca = "default".to_string();
+    for name in domains {
+        domain::add_domain(&paths, &mut config, name, false, None).unwrap();
+    }
+    store::save_config(&paths, &config).unwrap();
+    (dir, paths)
+}
+
+fn client(paths: &RoostPaths) -> Arc<rustls::ClientConfig> {
+    let (ca_pem, _) = ca::load_ca(paths, "default").unwrap();
+    let mut roots = rustls::RootCertStore::empty();
+    for c in rustls_pemfile::certs(&mut &ca_pem[..]) {
+        roots.add(c.unwrap()).unwrap();
+    }
+    Arc::new(
+        rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    )
+}
+
+/// Handshake in memory, verifying the server cert for `name`. Clients send no SNI when
+/// `name` is an IP address.
+fn handshake(
+    client: &Arc<rustls::ClientConfig>,
+    configs: &TlsConfigs,
+    name: &str,
+) -> Result<(), rustls::Error> {
+    let server_name = ServerName::try_from(name.to_string()).unwrap();
+    let mut client = rustls::ClientConnection::new(client.clone(), server_name).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let mut accepted = rustls::server::Acceptor::default();
+    accepted.read_tls(&mut &buf[..]).unwrap();
+    let accepted = accepted.accept().map_err(|(e, _)| e)?.unwrap();
+    let config = configs.for_sni(accepted.client_hello().server_name());
+    let mut server = accepted.into_connection(config).map_err(|(e, _)| e)?;
+    while client.is_handshaking() || server.is_handshaking() {
+        buf.clear();
+        server.write_tls(&mut buf).unwrap();
+        client.read_tls(&mut &buf[..]).unwrap();
+        client.process_new_packets()?;
+        buf.clear();
+        client.write_tls(&mut buf).unwrap();
+        server.read_tls(&mut &buf[..]).unwrap();
+        server.process_new_packets()?;
+    }
+    Ok(())
+}
+
+fn start(paths: &RoostPaths, mapped: &[&str], default_cert: Option<&str>) -> TlsConfigs {
+    let policies: HashMap<String, TlsPolicy> = mapped
+        .iter()
+        .map(|d| (d.to_string(), TlsPolicy::default()))
+        .collect();
+    let metrics = Metrics::new(mapped.iter().copied());
+    let certs = CertStore::new(paths, &policies, default_cert, false, &metrics).unwrap();
+    TlsConfigs::new(&policies, certs, &TlsSessions::new().unwrap()).unwrap()
+}
+
+#[test]
+fn clients_without_sni_get_the_default_cert() {
+    let (_dir, paths) = setup(&["app.test", "192.168.1.20", "::1"]);
+    let client = client(&paths);
+
+    let configs = start(&paths, &["app.test", "::1"], Some("192.168.1.20"));
+    handshake(&client, &configs, "192.168.1.20").unwrap();
+    handshake(&client, &configs, "app.test").unwrap();
+    // Only one cert can be the default; other IPs get a name mismatch
+    assert!(handshake(&client, &configs, "::1").is_err());
+
+    let configs = start(&paths, &["app.test", "::1"], Some("::1"));
+    handshake(&client, &configs, "::1").unwrap();
+
+    // Without a default cert there is nothing to serve
+    let configs = start(&paths, &["app.test"], None);
+    assert!(handshake(&client, &configs, "192.168.1.20").is_err());
+}
+
+#[test]
+fn localhost_is_served_like_any_mapping() {
+    let (_dir, paths) = setup(&["localhost"]);
+    let configs = start(&paths, &["localhost"], None);
+    handshake(&client(&paths), &configs, "localhost").unwrap();
+}
+
+#[test]
+fn default_cert_roundtrips_and_project_wins() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let global = ServeConfig {
+        default_cert: Some("192.168.1.20".into()),
+        ..Default::default()
+    };
+    global.save(&rc_path).unwrap();
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    assert_eq!(loaded.default_cert.as_deref(), Some("192.168.1.20"));
+
+    let project = ServeConfig {
+        default_cert: Some("127.0.0.1".into()),
+        ..Default::default()
+    };
+    assert_eq!(
+        merge_serve_configs(&project, &loaded)
+            .default_cert
+            .as_deref(),
+        Some("127.0.0.1")
+    );
+    assert_eq!(
+        merge_serve_configs(&ServeConfig::default(), &loaded)
+            .default_cert
+            .as_deref(),
+        Some("192.168.1.20")
+    );
+}
@@ -0,0 +1,233 @@
+//! Timeouts, body size limits and connection idle tracking.
+
+mod common;
+
+use futures_util::stream::{self, StreamExt};
+use http::{Request, Response};
+use http_body_util::{BodyExt, Empty, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::service::service_fn;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use roost::config::{Config, RoostPaths};
+use roost::serve::config::{merge_serve_configs, Mapping, Redirect, ServeConfig};
+use roost::serve::limits::{
+    ConnActivity, DeadlineBody, DeadlineExceeded, Limits, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use roost::serve::proxy::run_proxy_until;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[test]
+fn defaults_apply_when_unset() {
+    let limits = Limits::default().effective();
+    assert_eq!(
+        limits.request_timeout,
+        Some(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS))
+    );
+    assert!(limits.idle_timeout.is_some());
+    assert_eq!(limits.body_timeout, None);
+    assert_eq!(limits.max_body_bytes, None);
+}
+
+#[test]
+fn mapping_limits_fall_back_per_field() {
+    let global = Limits {
+        connect_timeout_secs: Some(3),
+        max_body_bytes: Some(1024),
+        ..Default::default()
+    };
+    let mapping = Limits {
+        max_body_bytes: Some(0),
+        request_timeout_secs: Some(5),
+        ..Default::default()
+    };
+    let limits = mapping.or(&global).effective();
+    assert_eq!(limits.connect_timeout, Duration::from_secs(3));
+    assert_eq!(limits.request_timeout, Some(Duration::from_secs(5)));
+    assert_eq!(limits.max_body_bytes, None, "0 disables the global limit");
+}
+
+#[test]
+fn zero_connect_timeout_is_rejected() {
+    let limits = Limits {
+        connect_timeout_secs: Some(0),
+        ..Default::default()
+    };
+    assert!(limits.validate().is_err());
+    assert!(Limits::default().validate().is_ok());
+}
+
+#[test]
+fn limits_roundtrip_and_merge() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut cfg = ServeConfig {
+        limits: Limits {
+            idle_timeout_secs: Some(30),
+            ..Default::default()
+        },
+        shutdown_timeout_secs: Some(5),
+        ..Default::default()
+    };
+    cfg.add("app.test".into(), 5001);
+    cfg.mapping_mut("app.test").unwrap().limits.max_body_bytes = Some(4096);
+    cfg.save(&rc_path).unwrap();
+
+    let loaded = ServeConfig::load(&rc_path).unwrap();
+    assert_eq!(loaded.limits.idle_timeout_secs, Some(30));
+    assert_eq!(loaded.shutdown_timeout_secs, Some(5));
+    assert_eq!(loaded.mappings[0].limits.max_body_bytes, Some(4096));
+
+    let project = ServeConfig {
+        limits: Limits {
+            idle_timeout_secs: Some(10),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let merged = merge_serve_configs(&project, &loaded);
+    assert_eq!(merged.limits.idle_timeout_secs, Some(10));
+    assert_eq!(merged.shutdown_timeout_secs, Some(5));
+}
+
+#[tokio::test]
+async fn idle_waits_for_in_flight_requests() {
+    let activity = ConnActivity::new();
+    let timeout = Duration::from_millis(100);
+
+    let start = Instant::now();
+    activity.idle(timeout).await;
+    assert!(start.elapsed() >= timeout);
+
+    let in_flight = activity.begin();
+    let idle = tokio::time::timeout(Duration::from_millis(250), activity.idle(timeout)).await;
+    assert!(
+        idle.is_err(),
+        "connection with a request in flight is not idle"
+    );
+
+    drop(in_flight);
+    let start = Instant::now();
+    activity.idle(timeout).await;
+    assert!(start.elapsed() >= Duration::from_millis(90));
+}
+
+#[tokio::test]
+async fn body_timeout_cuts_off_streaming_bodies() {
+    let frames = stream::once(async { Ok::<_, BoxError>(Frame::data(Bytes::from_static(b"a"))) })
+        .chain(stream::pending());
+    let timeout = Duration::from_millis(200);
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut body = DeadlineBody::new(BodyExt::boxed(StreamBody::new(frames)), deadline, timeout);
+
+    let first = body.frame().await.unwrap().unwrap();
+    assert_eq!(first.into_data().unwrap(), "a");
+    let err = tokio::time::timeout(Duration::from_secs(5), body.frame())
+        .await
+        .expect("body outlived its deadline")
+        .unwrap()
+        .unwrap_err();
+    assert!(err.is::<DeadlineExceeded>(), "{err}");
+    assert!(deadline <= tokio::time::Instant::now());
+}
+
+/// Backend answering with server-sent events every 400ms for about two seconds.
+async fn slow_event_stream_backend() -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let service = service_fn(|_req: Request<Incoming>| async move {
+                    let frames = stream::iter(0..5).then(|i| async move {
+                        tokio::time::sleep(Duration::from_millis(400)).await;
+                        Ok::<_, hyper::Error>(Frame::data(Bytes::from(format!("data: {i}\n\n"))))
+                    });
+                    Ok::<_, hyper::Error>(
+                        Response::builder()
+                            .header("content-type", "text/event-stream")
+                            .body(StreamBody::new(frames))
+                            .unwrap(),
+                    )
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    port
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+#[tokio::test]
+async fn event_streams_outlive_the_request_timeout() {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    roost::ca::create_ca(&paths, "default").unwrap();
+    let mut store = Config {
+        default_ca: "default".to_string(),
+        ..Default::default()
+    };
+    roost::domain::add_domain(&paths, &mut store, "app.test", false, None).unwrap();
+    store.save(&paths).unwrap();
+
+    let (tls_port, plain_port) = (free_port(), free_port());
+    let mut mapping = Mapping::new("app.test".into(), slow_event_stream_backend().await);
+    mapping.plain_http = true;
+    mapping.limits.request_timeout_secs = Some(1);
+    let config = ServeConfig {
+        mappings: vec![mapping],
+        ports: vec![tls_port, plain_port],
+        redirects: vec![Redirect {
+            from: plain_port,
+            to: tls_port,
+        }],
+        ..Default::default()
+    };
+    let (stop, stopped) = oneshot::channel::<()>();
+    let proxy = tokio::spawn(async move {
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        run_proxy_until(&paths, &config, shutdown).await
+    });
+
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+    let mut response = None;
+    for _ in 0..50 {
+        let req = Request::get(format!("http://127.0.0.1:{plain_port}/events"))
+            .header("host", "app.test")
+            .body(Empty::new())
+            .unwrap();
+        match client.request(req).await {
+            Ok(r) => {
+                response = Some(r);
+                break;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+    let response = response.expect("proxy did not start");
+    assert_eq!(response.status(), 200);
+
+    // Every event arrives although the stream runs twice as long as the request timeout
+    let start = Instant::now();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(start.elapsed() > Duration::from_secs(1));
+    let expected: String = (0..5).map(|i| format!("data: {i}\n\n")).collect();
+    assert_eq!(body, expected);
+
+    stop.send(()).unwrap();
+    proxy.await.unwrap().unwrap();
+}
@@ -0,0 +1,288 @@
+//! Per-domain TLS policy: config, validation, negotiated versions/ALPN and RSA leaf certs.
+
+mod common;
+
+use roost::config::RoostPaths;
+use roost::serve::config::{merge_serve_configs, ServeConfig};
+use roost::serve::tls::{KeyType, TlsConfigs, TlsPolicy, TlsSessions, TlsVersion};
+use roost::{ca, cert};
+use rustls::pki_types::{PrivateKeyDer, ServerName};
+use rustls::server::ResolvesServerCertUsingSni;
+use rustls::sign::CertifiedKey;
+use std::collections::HashMap;
+use std::sync::Arc;
+use x509_parser::prelude::*;
+
+fn setup() -> (tempfile::TempDir, RoostPaths) {
+    let dir = common::temp_roost_home();
+    let paths = RoostPaths::for_test(dir.path());
+    ca::create_ca(&paths, "default").unwrap();
+    cert::ensure_cert_valid(&paths, "app.test", "default", false).unwrap();
+    (dir, paths)
+}
+
+#[test]
+fn policy_roundtrips_and_mapping_overrides_global() {
+    let dir = common::temp_roost_home();
+    let rc_path = dir.path().join(".roostrc");
+    let mut cfg = ServeConfig {
+        tls: TlsPolicy {
+            min_version: Some(TlsVersion::Tls12),
+            key_type: Some(KeyType::Both),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    cfg.add("app.test".into(), 5001);
+    cfg.mapping_mut("app.test").unwrap().tls = TlsPolicy {
+        min_version: Some(TlsVersion::Tls13),
+        alpn: Some(vec![]),
+        ..Default::default()
+    };
+    cfg.save(&rc_path).unwrap();
+    let text = std::fs::read_to_string(&rc_path).unwrap();
+    assert!(text.contains("min_version = \"1.3\""), "{text}");
+    assert!(text.contains("key_type = \"both\""), "{text}");
+
+    let merged = merge_serve_configs(
+        &ServeConfig::load(&rc_path).unwrap(),
+        &ServeConfig::default(),
+    );
+    let effective = merged.mappings[0].tls.or(&merged.tls);
+    assert_eq!(effective.min_version, Some(TlsVersion::Tls13));
+    assert_eq!(effective.alpn, Some(vec![]));
+    assert_eq!(effective.key_type(), KeyType::Both);
+    assert_eq!(TlsPolicy::default().key_type(), KeyType::Ecdsa);
+}
+
+#[test]
+fn validation_rejects_unusable_policies() {
+    assert_eq!("1.3".parse::<TlsVersion>().unwrap(), TlsVersion::Tls13);
+    assert_eq!("TLS1.2".parse::<TlsVersion>().unwrap(), TlsVersion::Tls12);
+    assert!("1.1".parse::<TlsVersion>().is_err());
+
+    let unknown = TlsPolicy {
+        ciphers: Some(vec!["TLS_RSA_WITH_RC4_128_MD5".into()]),
+        ..Default::default()
+    };
+    assert!(unknown
+        .validate()
+        .unwrap_err()
+        .to_string()
+        .contains("unknown cipher suite"));
+
+    let inverted = TlsPolicy {
+        min_version: Some(TlsVersion::Tls13),
+        max_version: Some(TlsVersion::Tls12),
+        ..Default::default()
+    };
+    assert!(inverted.validate().is_err());
+
+    let mismatched = TlsPolicy {
+        min_version: Some(TlsVersion::Tls13),
+        ciphers: Some(vec!["TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256".into()]),
+        ..Default::default()
+    };
+    assert!(mismatched.validate().is_err());
+
+    let ok = TlsPolicy {
+        ciphers: Some(vec!["tls13_aes_128_gcm_sha256".into()]),
+        ..Default::default()
+    };
+    ok.validate().unwrap();
+}
+
+fn resolver(paths: &RoostPaths) -> Arc<ResolvesServerCertUsingSni> {
+    let (cert_pem, key_pem) = cert::load_domain_cert(paths, "app.test").unwrap();
+    let chain = rustls_pemfile::certs(&mut &cert_pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let key: PrivateKeyDer = rustls_pemfile::private_key(&mut &key_pem[..])
+        .unwrap()
+        .unwrap();
+    let provider = rustls::crypto::aws_lc_rs::default_provider();
+    let signing_key = provider.key_provider.load_private_key(key).unwrap();
+    let mut resolver = ResolvesServerCertUsingSni::new();
+    resolver
+        .add("app.test", CertifiedKey::new(chain, signing_key))
+        .unwrap();
+    Arc::new(resolver)
+}
+
+/// Run an in-memory handshake; returns the negotiated version and ALPN protocol.
+fn handshake(
+    paths: &RoostPaths,
+    server: Arc<rustls::ServerConfig>,
+) -> Result<(rustls::ProtocolVersion, Option<Vec<u8>>), rustls::Error> {
+    let (ca_pem, _) = ca::load_ca(paths, "default").unwrap();
+    let mut roots = rustls::RootCertStore::empty();
+    for c in rustls_pemfile::certs(&mut &ca_pem[..]) {
+        roots.add(c.unwrap()).unwrap();
+    }
+    let mut client_config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let mut client = rustls::ClientConnection::new(
+        Arc::new(client_config),
+        ServerName::try_from("app.test").unwrap(),
+    )
+    .unwrap();
+    let mut server = rustls::ServerConnection::new(server).unwrap();
+
+    let mut buf = Vec::new();
+    while client.is_handshaking() || server.is_handshaking() {
+        buf.clear();
+        client.write_tls(&mut buf).unwrap();
+        server.read_tls(&mut &buf[..]).unwrap();
+        server.process_new_packets()?;
+        buf.clear();
+        server.write_tls(&mut buf).unwrap();
+        client.read_tls(&mut &buf[..]).unwrap();
+        client.process_new_packets()?;
+    }
+    Ok((
+        client.protocol_version().unwrap(),
+        client.alpn_protocol().map(|p| p.to_vec()),
+    ))
+}
+
+#[test]
+fn per_domain_configs_limit_versions_and_alpn() {
+    let (_dir, paths) = setup();
+    let policies = HashMap::from([(
+        "app.test".to_string(),
+        TlsPolicy {
+            max_version: Some(TlsVersion::Tls12),
+            alpn: Some(vec!["http/1.1".into()]),
+            ..Default::default()
+        },
+    )]);
+    let configs =
+        TlsConfigs::new(&policies, resolver(&paths), &TlsSessions::new().unwrap()).unwrap();
+
+    let (version, alpn) = handshake(&paths, configs.for_sni(Some("APP.test:443"))).unwrap();
+    assert_eq!(version, rustls::ProtocolVersion::TLSv1_2);
+    assert_eq!(alpn.as_deref(), Some(&b"http/1.1"[..]));
+
+    // Other SNI values use the default config
+    let (version, alpn) = handshake(&paths, configs.for_sni(Some("other.test"))).unwrap();
+    assert_eq!(version, rustls::ProtocolVersion::TLSv1_3);
+    assert_eq!(alpn.as_deref(), Some(&b"http/1.1"[..]));
+
+    // An explicit ALPN list replaces the HTTP/1.x default
+    let only_h2 = TlsPolicy {
+        alpn: Some(vec!["h2".into()]),
+        ..Default::default()
+    };
+    let sessions = TlsSessions::new().unwrap();
+    let config = Arc::new(only_h2.server_config(resolver(&paths), &sessions).unwrap());
+    let (_, alpn) = handshake(&paths, config).unwrap();
+    assert_eq!(alpn.as_deref(), Some(&b"h2"[..]));
+}
+
+/// Handshake `client` against `server` in memory, then deliver the server's session
+/// tickets; returns how the server completed the handshake.
+fn connect(
+    client: &Arc<rustls::ClientConfig>,
+    server: Arc<rustls::ServerConfig>,
+) -> rustls::HandshakeKind {
+    let mut client =
+        rustls::ClientConnection::new(client.clone(), ServerName::try_from("app.test").unwrap())
+            .unwrap();
+    let mut server = rustls::ServerConnection::new(server).unwrap();
+    let mut buf = Vec::new();
+    while client.is_handshaking() || server.is_handshaking() || server.wants_write() {
+        buf.clear();
+        client.write_tls(&mut buf).unwrap();
+        server.read_tls(&mut &buf[..]).unwrap();
+        server.process_new_packets().unwrap();
+        buf.clear();
+        server.write_tls(&mut buf).unwrap();
+        client.read_tls(&mut &buf[..]).unwrap();
+        client.process_new_packets().unwrap();
+    }
+    server.handshake_kind().unwrap()
+}
+
+#[test]
+fn sessions_resume_across_configs() {
+    let (_dir, paths) = setup();
+    let (ca_pem, _) = ca::load_ca(&paths, "default").unwrap();
+    let mut roots = rustls::RootCertStore::empty();
+    for c in rustls_pemfile::certs(&mut &ca_pem[..]) {
+        roots.add(c.unwrap()).unwrap();
+    }
+    let client_config = |version: &'static rustls::SupportedProtocolVersion| {
+        Arc::new(
+            rustls::ClientConfig::builder_with_protocol_versions(&[version])
+                .with_root_certificates(roots.clone())
+                .with_no_client_auth(),
+        )
+    };
+    let policies = HashMap::from([(
+        "app.test".to_string(),
+        TlsPolicy {
+            alpn: Some(vec!["http/1.1".into()]),
+            ..Default::default()
+        },
+    )]);
+    let sessions = TlsSessions::new().unwrap();
+    let configs = TlsConfigs::new(&policies, resolver(&paths), &sessions).unwrap();
+
+    for version in [&rustls::version::TLS13, &rustls::version::TLS12] {
+        let client = client_config(version);
+        let kinds = [
+            connect(&client, configs.for_sni(Some("app.test"))),
+            // The default config shares the session cache and ticket keys
+            connect(&client, configs.for_sni(None)),
+        ];
+        assert_eq!(
+            kinds,
+            [rustls::HandshakeKind::Full, rustls::HandshakeKind::Resumed],
+            "{version:?}"
+        );
+    }
+
+    // Configs with separate session state don't resume each other's sessions
+    let client = client_config(&rustls::version::TLS13);
+    let other = TlsConfigs::new(&policies, resolver(&paths), &TlsSessions::new().unwrap()).unwrap();
+    connect(&client, configs.for_sni(Some("app.test")));
+    assert_eq!(
+        connect(&client, other.for_sni(Some("app.test"))),
+        rustls::HandshakeKind::Full
+    );
+}
+
+fn leaf_key_oid(pem: &[u8]) -> String {
+    let der = rustls_pemfile::certs(&mut &pem[..])
+        .next()
+        .unwrap()
+        .unwrap();
+    let (_, cert) = X509Certificate::from_der(&der).unwrap();
+    cert.public_key().algorithm.algorithm.to_id_string()
+}
+
+#[test]
+fn rsa_cert_is_issued_next_to_ecdsa_cert() {
+    let (_dir, paths) = setup();
+    cert::ensure_rsa_cert_valid(&paths, "app.test", "default").unwrap();
+    let (cert_path, key_path) = cert::rsa_cert_paths(&paths, "app.test");
+    assert!(key_path.is_file());
+    let pem = std::fs::read(&cert_path).unwrap();
+    assert_eq!(leaf_key_oid(&pem), "1.2.840.113549.1.1.1", "rsaEncryption");
+    let (ecdsa_pem, _) = cert::load_domain_cert(&paths, "app.test").unwrap();
+    assert_eq!(
+        leaf_key_oid(&ecdsa_pem),
+        "1.2.840.10045.2.1",
+        "id-ecPublicKey"
+    );
+
+    // Still valid: kept as is
+    cert::ensure_rsa_cert_valid(&paths, "app.test", "default").unwrap();
+    assert_eq!(std::fs::read(&cert_path).unwrap(), pem);
+
+    cert::remove_rsa_cert(&paths, "app.test");
+    assert!(!cert_path.exists());
+    assert!(!key_path.exists());
+}